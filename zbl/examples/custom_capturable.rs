@@ -0,0 +1,80 @@
+use std::sync::mpsc::Receiver;
+
+use zbl::{
+    capturable::{client_box, create_capture_item_for_monitor, never_closing_notification_channel},
+    windows::{
+        core::Result,
+        Graphics::Capture::GraphicsCaptureItem,
+        Win32::{
+            Foundation::POINT,
+            Graphics::{
+                Direct3D11::D3D11_BOX,
+                Gdi::{MonitorFromPoint, HMONITOR, MONITOR_DEFAULTTOPRIMARY},
+            },
+            UI::WindowsAndMessaging::GetCursorPos,
+        },
+    },
+    Capturable, Capture, Display, GrabStatus,
+};
+
+/// The monitor which contained the mouse cursor at the moment of creation.
+struct MonitorUnderCursor {
+    display: Display,
+}
+
+impl MonitorUnderCursor {
+    fn new() -> Result<Self> {
+        let mut cursor = POINT::default();
+        let handle: HMONITOR = unsafe {
+            GetCursorPos(&mut cursor as *mut _).ok()?;
+            MonitorFromPoint(cursor, MONITOR_DEFAULTTOPRIMARY)
+        };
+        Ok(Self {
+            display: Display::new(handle)?,
+        })
+    }
+}
+
+impl Capturable for MonitorUnderCursor {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        create_capture_item_for_monitor(self.display.handle)
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        let (w, h) = self.display.get_virtual_size();
+        Ok(client_box(0, 0, w as u32, h as u32))
+    }
+
+    fn get_close_notification_channel(&self) -> Receiver<()> {
+        never_closing_notification_channel()
+    }
+
+    fn get_raw_handle(&self) -> isize {
+        self.display.handle.0
+    }
+}
+
+fn main() {
+    zbl::init();
+
+    let target = MonitorUnderCursor::new().expect("failed to find monitor under cursor");
+    println!("capturing {}", target.display.display_name);
+
+    let mut capture = Capture::new(Box::new(target), false).expect("failed to initialize capture");
+    capture.start().expect("failed to start capture");
+
+    for _ in 0..10 {
+        match capture.grab().expect("failed to get frame") {
+            GrabStatus::Frame(frame) => println!(
+                "got {}x{} frame, row pitch = {}",
+                frame.width(),
+                frame.height(),
+                frame.row_pitch()
+            ),
+            GrabStatus::Closed => break,
+            _ => {}
+        }
+    }
+
+    capture.stop().expect("failed to stop capture");
+}
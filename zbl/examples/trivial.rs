@@ -2,7 +2,7 @@ use std::time::Instant;
 
 use clap::Parser;
 use opencv::{highgui, prelude::*};
-use zbl::{display::Display, Capturable, Capture, Frame, Window};
+use zbl::{display::Display, Capturable, Capture, GrabStatus, Window};
 
 #[derive(Parser, Debug)]
 #[clap(version)]
@@ -41,31 +41,33 @@ fn main() {
     let mut tt = 0f32;
     loop {
         let t = Instant::now();
-        if let Some(Frame { texture, ptr }) = capture.grab().expect("failed to get frame") {
-            let mat = unsafe {
-                Mat::new_size_with_data(
-                    opencv::core::Size::new(texture.desc.Width as i32, texture.desc.Height as i32),
-                    opencv::core::CV_8UC4,
-                    ptr.pData,
-                    ptr.RowPitch as usize,
-                )
+        match capture.grab().expect("failed to get frame") {
+            GrabStatus::Frame(frame) => {
+                let mat = unsafe {
+                    Mat::new_size_with_data(
+                        opencv::core::Size::new(frame.width() as i32, frame.height() as i32),
+                        opencv::core::CV_8UC4,
+                        frame.as_bytes().as_ptr() as *mut _,
+                        frame.row_pitch() as usize,
+                    )
+                }
+                .expect("failed to convert to opencv frame");
+                let t = Instant::now() - t;
+                cnt += 1;
+                tt += t.as_secs_f32();
+                if (Instant::now() - start).as_secs() != prev {
+                    println!("averaging {} fps", 1f32 / (tt / cnt as f32));
+                    cnt = 0;
+                    tt = 0f32;
+                    prev = (Instant::now() - start).as_secs();
+                }
+                highgui::imshow("Test", &mat).expect("failed to show frame");
+                if highgui::wait_key(8).expect("failed to wait user input") != -1 {
+                    break;
+                }
             }
-            .expect("failed to convert to opencv frame");
-            let t = Instant::now() - t;
-            cnt += 1;
-            tt += t.as_secs_f32();
-            if (Instant::now() - start).as_secs() != prev {
-                println!("averaging {} fps", 1f32 / (tt / cnt as f32));
-                cnt = 0;
-                tt = 0f32;
-                prev = (Instant::now() - start).as_secs();
-            }
-            highgui::imshow("Test", &mat).expect("failed to show frame");
-            if highgui::wait_key(8).expect("failed to wait user input") != -1 {
-                break;
-            }
-        } else {
-            break;
+            GrabStatus::Closed => break,
+            _ => continue,
         }
     }
 
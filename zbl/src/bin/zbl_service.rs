@@ -0,0 +1,18 @@
+//! Runs `zbl::service::Service`, listening on the address given as the only argument
+//! (`127.0.0.1:7878` by default).
+
+use zbl::service::Service;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+fn main() {
+    zbl::init();
+
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    println!("listening on {}", address);
+    Service::new()
+        .serve(&address)
+        .expect("failed to run the service");
+}
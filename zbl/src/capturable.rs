@@ -0,0 +1,288 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        RwLock,
+    },
+    time::Duration,
+};
+
+use lazy_static::lazy_static;
+use windows::{
+    core::Result,
+    Graphics::Capture::GraphicsCaptureItem,
+    Win32::{
+        Foundation::{HWND, POINT},
+        Graphics::{Direct3D11::D3D11_BOX, Gdi::HMONITOR},
+        System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
+        UI::{
+            Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
+            WindowsAndMessaging::{EVENT_OBJECT_DESTROY, WINEVENT_OUTOFCONTEXT},
+        },
+    },
+};
+
+use crate::{Capture, Display, Image, Window};
+
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref OBJECT_DESTROYED_USER_DATA: RwLock<HashMap<isize, (isize, SyncSender<()>)>> =
+        Default::default();
+    static ref NEVER_CLOSING_SENDERS: RwLock<Vec<SyncSender<()>>> = Default::default();
+}
+
+/// Something that can be captured by `Capture`.
+///
+/// This is the extension point for custom capture targets: `Window` and `Display` are implemented
+/// on top of it, and nothing in `Capture` relies on the concrete type. The helper functions in this
+/// module cover the platform plumbing needed by most implementations.
+///
+/// Capturables are `Send`, so that they can be handed to the thread a capture is built on (see
+/// `CaptureThread`).
+pub trait Capturable: Send {
+    /// Create a `GraphicsCaptureItem` for this target.
+    ///
+    /// This is called once when the capture is created, and again every time the frame pool has to
+    /// be recreated (e.g. after the target was resized), so it should be cheap and must not cache
+    /// stale items. See `create_capture_item_for_window` and `create_capture_item_for_monitor`.
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem>;
+
+    /// Region of the captured surface that should be copied into a frame, in texture coordinates.
+    ///
+    /// The box must fit inside the surface produced by the capture item. Use `client_box` to
+    /// build one from a rectangle.
+    fn get_client_box(&self) -> Result<D3D11_BOX>;
+
+    /// Channel which receives a message (or gets disconnected) once the target is gone and the
+    /// capture should stop.
+    ///
+    /// Use `window_close_notification_channel` for window-like targets and
+    /// `never_closing_notification_channel` for targets which live as long as the capture does.
+    fn get_close_notification_channel(&self) -> Receiver<()>;
+
+    /// Raw handle of the target (`HWND`, `HMONITOR`, ...), mostly useful for logging and bindings.
+    fn get_raw_handle(&self) -> isize;
+
+    /// Position of the top-left corner of the client box on the virtual screen, if known. Used by
+    /// processing stages which draw screen-space things (like the cursor) onto frames.
+    fn get_screen_origin(&self) -> Option<POINT> {
+        None
+    }
+
+    /// Refresh rate (in Hz) of the monitor showing the target, if known. Used to pace the capture and
+    /// to size the frame pool.
+    fn refresh_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Whether the target is currently visible on screen, i.e. whether it is expected to produce
+    /// frames. Used by the capture watchdog.
+    fn is_visible(&self) -> bool {
+        true
+    }
+
+    /// Cheap check whether the target still exists, done before every grab by captures with
+    /// `CaptureConfig::revalidate_target`. Targets which are not alive are treated as closed.
+    fn is_alive(&self) -> bool {
+        true
+    }
+
+    /// Whether the target is minimized. Minimized targets do not produce frames, so `Capture::grab`
+    /// reports `GrabStatus::Minimized` instead of waiting for one.
+    fn is_minimized(&self) -> bool {
+        false
+    }
+
+    /// Whether the target is hidden from the user even though it is not minimized, e.g. because it
+    /// is completely covered by other windows or on another virtual desktop.
+    /// Windows.Graphics.Capture keeps producing frames for such targets. Used by captures with
+    /// `CaptureConfig::pause_when_occluded`.
+    fn is_occluded(&self) -> bool {
+        false
+    }
+
+    /// Look for a new target which should take over after this one has been closed (e.g. the window
+    /// of a restarted application). Used by captures with auto-reattach enabled, which call this
+    /// repeatedly until a replacement is found or they give up.
+    ///
+    /// `existing` starts out as `None` for every closed target, and is kept between the calls for
+    /// it. Implementations can record there the (raw handles of the) targets which existed when
+    /// this one was closed, so that only targets which have appeared since are taken as
+    /// replacements.
+    ///
+    /// By default targets cannot be replaced.
+    fn find_replacement(
+        &self,
+        _existing: &mut Option<HashSet<isize>>,
+    ) -> Option<Box<dyn Capturable>> {
+        None
+    }
+
+    /// Called a few times a second while grabbing. Returning a new target makes the capture switch
+    /// over to it right away (e.g. `FollowMouse` moving to the monitor the cursor is on), in which
+    /// case `Capture::grab()` returns `GrabStatus::Recreated`.
+    ///
+    /// By default targets never change.
+    fn retarget(&self) -> Option<Box<dyn Capturable>> {
+        None
+    }
+
+    /// Check whether the target can be captured at all, before any capture resources are created.
+    fn validate(&self) -> crate::Result<()> {
+        Ok(())
+    }
+
+    /// Capture a single frame of the target, downscaled on the GPU to fit into a `max_size` square
+    /// while preserving the aspect ratio. Targets smaller than that are not upscaled.
+    ///
+    /// This is meant for previews (e.g. in a window picker): the session only lives for the
+    /// duration of the call. Returns `None` if the target did not produce a frame within a second,
+    /// e.g. because it is minimized.
+    fn thumbnail(&self, max_size: u32) -> crate::Result<Option<Image>>
+    where
+        Self: Clone + Sized + 'static,
+    {
+        let client_box = self.get_client_box()?;
+        let width = client_box.right - client_box.left;
+        let height = client_box.bottom - client_box.top;
+        let scale = (max_size as f32 / width.max(height).max(1) as f32).min(1.0);
+        let mut capture = Capture::builder(Box::new(self.clone()))
+            .output_size(
+                ((width as f32 * scale).round() as u32).max(1),
+                ((height as f32 * scale).round() as u32).max(1),
+            )
+            .grab_timeout(THUMBNAIL_TIMEOUT)
+            .build()?;
+        let image = capture
+            .request_frame()?
+            .frame()
+            .map(|frame| frame.to_image());
+        Ok(image)
+    }
+
+    /// The target as a `Window`, if it is one.
+    fn as_window(&self) -> Option<&Window> {
+        None
+    }
+
+    /// The target as a `Display`, if it is one.
+    fn as_display(&self) -> Option<&Display> {
+        None
+    }
+}
+
+/// Create a capture item for a top-level window.
+pub fn create_capture_item_for_window(handle: HWND) -> Result<GraphicsCaptureItem> {
+    let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+    unsafe { interop.CreateForWindow(handle) }
+}
+
+/// Create a capture item for a monitor.
+pub fn create_capture_item_for_monitor(handle: HMONITOR) -> Result<GraphicsCaptureItem> {
+    let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
+    unsafe { interop.CreateForMonitor(handle) }
+}
+
+/// Build a single-slice `D3D11_BOX` from a rectangle in texture coordinates.
+pub fn client_box(left: u32, top: u32, width: u32, height: u32) -> D3D11_BOX {
+    D3D11_BOX {
+        left,
+        top,
+        front: 0,
+        right: left + width,
+        bottom: top + height,
+        back: 1,
+    }
+}
+
+extern "system" fn object_destroyed_cb(
+    this: HWINEVENTHOOK,
+    _: u32,
+    handle: HWND,
+    id_object: i32,
+    id_child: i32,
+    _: u32,
+    _: u32,
+) {
+    if id_object == 0 && id_child == 0 && handle != HWND::default() {
+        let has_been_closed = if let Ok(handles) = OBJECT_DESTROYED_USER_DATA.read() {
+            if let Some((window_handle, tx)) = handles.get(&this.0) {
+                if *window_handle == handle.0 {
+                    tx.send(()).ok();
+                    true
+                } else {
+                    false
+                }
+            } else {
+                false
+            }
+        } else {
+            // TODO is that correct?
+            true
+        };
+
+        if has_been_closed {
+            unsafe { UnhookWinEvent(this) };
+        }
+    }
+}
+
+/// Close notification channel which fires when the given window is destroyed.
+///
+/// The notification is delivered through an out-of-context WinEvent hook installed on the
+/// calling thread.
+pub fn window_close_notification_channel(handle: HWND) -> Receiver<()> {
+    let (sender, receiver) = sync_channel(1);
+    let hook_id = unsafe {
+        SetWinEventHook(
+            EVENT_OBJECT_DESTROY,
+            EVENT_OBJECT_DESTROY,
+            None,
+            Some(object_destroyed_cb),
+            // TODO filtering by process id does not always catch the moment when the window is closed
+            // why? aren't windows bound to their process ids?
+            // moreover, for explorer windows even that does not work.
+            // need some more realiable and simpler way to track window closing
+            0,
+            0,
+            WINEVENT_OUTOFCONTEXT,
+        )
+    };
+    if let Ok(mut handles) = OBJECT_DESTROYED_USER_DATA.write() {
+        handles.insert(hook_id.0, (handle.0, sender));
+    }
+    receiver
+}
+
+/// Close notification channel which never fires.
+///
+/// Every call returns a channel of its own. The sending half is kept alive until the receiver is
+/// dropped, so that the receiver does not get disconnected, which would stop the capture.
+pub fn never_closing_notification_channel() -> Receiver<()> {
+    // with a rendezvous channel, probing the senders below cannot queue a message; it could only
+    // be delivered to a receiver blocked in `recv()`, while `Capture` just polls
+    let (sender, receiver) = sync_channel(0);
+    let mut senders = NEVER_CLOSING_SENDERS.write().unwrap();
+    senders.retain(|sender| !matches!(sender.try_send(()), Err(TrySendError::Disconnected(_))));
+    senders.push(sender);
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::TryRecvError;
+
+    use super::*;
+
+    #[test]
+    fn never_closing_channels_are_independent() {
+        let first = never_closing_notification_channel();
+        let second = never_closing_notification_channel();
+        drop(never_closing_notification_channel());
+        // creating a channel prunes the senders of dropped ones, which must not touch the others
+        drop(never_closing_notification_channel());
+        assert_eq!(first.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(second.try_recv(), Err(TryRecvError::Empty));
+    }
+}
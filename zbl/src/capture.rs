@@ -1,42 +1,657 @@
-use std::sync::mpsc::{sync_channel, Receiver, TryRecvError, TrySendError};
+use std::{
+    cell::Cell,
+    mem::size_of,
+    os::windows::io::{AsRawHandle, RawHandle},
+    path::Path,
+    sync::{
+        atomic::{AtomicI64, AtomicIsize, AtomicU64, Ordering},
+        mpsc::{sync_channel, Receiver, TryRecvError, TrySendError},
+        Arc,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use windows::{
-    core::{IInspectable, Interface, Result},
-    Foundation::TypedEventHandler,
+    core::{IInspectable, Interface, Result, GUID, PCWSTR},
+    Foundation::{EventRegistrationToken, TypedEventHandler},
     Graphics::{
-        Capture::{Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureSession},
+        Capture::{
+            Direct3D11CaptureFrame, Direct3D11CaptureFramePool, GraphicsCaptureItem,
+            GraphicsCaptureSession,
+        },
         DirectX::{Direct3D11::IDirect3DDevice, DirectXPixelFormat},
         SizeInt32,
     },
-    Win32::Graphics::Direct3D11::{
-        ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BOX, D3D11_MAPPED_SUBRESOURCE,
-        D3D11_TEXTURE2D_DESC,
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, HWND, POINT, RECT},
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11Multithread, ID3D11Resource,
+                ID3D11Texture2D, D3D11_BOX, D3D11_MAPPED_SUBRESOURCE, D3D11_TEXTURE2D_DESC,
+            },
+            Dxgi::Common::{
+                DXGI_FORMAT, DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R16G16B16A16_FLOAT,
+            },
+        },
+        System::Threading::{CreateEventW, ResetEvent, SetEvent},
     },
 };
 
 use crate::{
+    capturable::client_box,
+    config::{
+        AlphaMode, CaptureBuilder, CaptureConfig, CaptureRate, Insets, OutputFormat, ScaleMode,
+        TransientRetry,
+    },
+    error::{is_transient, Context, Error},
+    event::{CaptureEvent, EventSender},
+    frame_callback::{FrameArrivedCallback, FrameCallbackSlot},
+    frame_stats::{FrameAnalyzer, FrameStats},
+    gpu_timer::GpuTimer,
+    hang::HangDetector,
+    image::{write_dds, Color, Image},
+    input::{self, InputEvent},
+    observer::{DropReason, FrameInfo, Observer, Observers},
+    pipeline::{
+        alpha::AlphaConversion, create_work_texture, format::FormatConversion, scale::Scaler,
+        Stage, StageContext,
+    },
+    power::PowerState,
     staging_texture::StagingTexture,
-    util::{create_d3d_device, create_direct3d_device, get_dxgi_interface_from_object},
+    util::{create_d3d_device, create_direct3d_device, get_dxgi_interface_from_object, Rect},
+    window::{get_frame_bounds, is_shown},
     Capturable,
 };
 
+const REATTACH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const POWER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const OCCLUSION_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const VIRTUAL_DESKTOP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const RETARGET_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Input events buffered between grabs, see `CaptureConfig::annotate_input`. Further events are
+/// dropped until the next grab.
+const INPUT_BUFFER_SIZE: usize = 4096;
+
+/// Value of `SessionHandlers::min_frame_interval` making the frame arrived handler drop all frames.
+const PAUSED: i64 = i64::MAX;
+
 pub struct Frame<'a> {
     pub texture: &'a StagingTexture,
     pub ptr: D3D11_MAPPED_SUBRESOURCE,
+    /// Time at which the frame was produced, in 100ns units (see
+    /// `Direct3D11CaptureFrame::SystemRelativeTime`).
+    pub timestamp: i64,
+    /// The frame returned by the previous grab, if changes are tracked (see
+    /// `CaptureConfig::track_changes`).
+    pub previous: Option<&'a Image>,
+    /// Input events which happened after the previous frame and up to this one, oldest first, if
+    /// input is annotated (see `CaptureConfig::annotate_input`).
+    pub input_events: &'a [InputEvent],
+    /// Luminance histogram and average color of the frame as returned (after all stages and
+    /// scaling), if enabled (see `CaptureConfig::frame_stats`). Also available when black frames
+    /// are detected (see `CaptureConfig::hang_detection`).
+    pub stats: Option<&'a FrameStats>,
+    /// Frame bounds of the tracked window (see `CaptureConfig::track_window`) relative to the
+    /// top-left corner of the captured area, before scaling to `CaptureConfig::output_size`. Not
+    /// clipped to the frame. `None` if no window is tracked, if it is not shown, or if the target
+    /// does not know where it is on the screen.
+    pub tracked_window: Option<Rect>,
+}
+
+impl<'a> Frame<'a> {
+    /// Width of the frame in pixels.
+    pub fn width(&self) -> u32 {
+        self.texture.desc.Width
+    }
+
+    /// Height of the frame in pixels.
+    pub fn height(&self) -> u32 {
+        self.texture.desc.Height
+    }
+
+    /// Length of a single row of the frame in bytes. This can be larger than `width() * 4`.
+    pub fn row_pitch(&self) -> u32 {
+        self.ptr.RowPitch
+    }
+
+    /// Pixel format of the frame, see `CaptureConfig::output_format`. This is either
+    /// `DXGI_FORMAT_B8G8R8A8_UNORM` or `DXGI_FORMAT_R10G10B10A2_UNORM`, both 32 bits per pixel.
+    pub fn pixel_format(&self) -> DXGI_FORMAT {
+        self.texture.desc.Format
+    }
+
+    /// Copy the frame into tightly packed RGBA rows with 16 bits per channel, e.g. for consumers
+    /// of 10-bit frames which do not want to unpack them. Channels are scaled to the full 16-bit
+    /// range.
+    pub fn to_rgba16(&self) -> Vec<u16> {
+        let mut data = Vec::with_capacity(self.width() as usize * self.height() as usize * 4);
+        let ten_bit = self.pixel_format() == DXGI_FORMAT_R10G10B10A2_UNORM;
+        for y in 0..self.height() {
+            for pixel in self.row(y).chunks_exact(4) {
+                if ten_bit {
+                    let pixel = u32::from_le_bytes(pixel.try_into().unwrap());
+                    let expand = |shift: u32| {
+                        let value = ((pixel >> shift) & 0x3ff) as u16;
+                        (value << 6) | (value >> 4)
+                    };
+                    data.extend_from_slice(&[
+                        expand(0),
+                        expand(10),
+                        expand(20),
+                        (pixel >> 30) as u16 * 0x5555,
+                    ]);
+                } else {
+                    let [b, g, r, a] =
+                        [pixel[0], pixel[1], pixel[2], pixel[3]].map(|c| c as u16 * 257);
+                    data.extend_from_slice(&[r, g, b, a]);
+                }
+            }
+        }
+        data
+    }
+
+    /// Raw frame data: `height()` rows of `row_pitch()` bytes each.
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.row_pitch() as usize * self.height() as usize;
+        // the texture is mapped for as long as it is borrowed by this frame, and can only be
+        // written to by the next `Capture::grab()`, which cannot be called while the frame is alive
+        unsafe { std::slice::from_raw_parts(self.ptr.pData as *const u8, len) }
+    }
+
+    /// Raw data of the `y`-th row of the frame, without padding.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.row_pitch() as usize;
+        let len = self.width() as usize * 4;
+        &self.as_bytes()[start..start + len]
+    }
+
+    /// Tiles of `tile_size` x `tile_size` pixels which differ from the previous frame, in frame
+    /// coordinates. Tiles at the right and bottom edges are clipped to the frame.
+    ///
+    /// All tiles are reported if there is nothing to compare to, i.e. for the first frame, after
+    /// the frame size has changed, or if `CaptureConfig::track_changes` is disabled.
+    pub fn changed_tiles(&self, tile_size: u32) -> Vec<RECT> {
+        let tile_size = tile_size.max(1);
+        let (width, height) = (self.width(), self.height());
+        let previous = self
+            .previous
+            .filter(|previous| previous.width == width && previous.height == height);
+
+        let mut tiles = Vec::new();
+        for top in (0..height).step_by(tile_size as usize) {
+            let bottom = (top + tile_size).min(height);
+            for left in (0..width).step_by(tile_size as usize) {
+                let right = (left + tile_size).min(width);
+                let bytes = left as usize * 4..right as usize * 4;
+                let changed = match previous {
+                    Some(previous) => (top..bottom)
+                        .any(|y| self.row(y)[bytes.clone()] != previous.row(y)[bytes.clone()]),
+                    None => true,
+                };
+                if changed {
+                    tiles.push(RECT {
+                        left: left as i32,
+                        top: top as i32,
+                        right: right as i32,
+                        bottom: bottom as i32,
+                    });
+                }
+            }
+        }
+        tiles
+    }
+
+    /// Save the frame as an uncompressed DDS texture in its pixel format (see `pixel_format()`),
+    /// e.g. to load it as an asset with DirectXTex or a game engine. Unlike `to_image()`, this
+    /// keeps all bits of 10-bit frames.
+    pub fn save_dds(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let rows = (0..self.height()).map(|y| self.row(y));
+        write_dds(path, self.width(), self.height(), self.pixel_format(), rows)
+    }
+
+    /// Copy the frame to CPU memory, so that it can outlive the next `Capture::grab()`.
+    pub fn to_image(&self) -> Image {
+        Image::from_frame(self)
+    }
+
+    /// Place the frame on the clipboard as a bitmap.
+    pub fn copy_to_clipboard(&self) -> Result<()> {
+        self.to_image().copy_to_clipboard()
+    }
+}
+
+/// Outcome of `Capture::grab()`.
+pub enum GrabStatus<'a> {
+    /// A new frame has been captured.
+    Frame(Frame<'a>),
+    /// The target has been closed or the capture has been stopped. No more frames will arrive.
+    Closed,
+    /// The target is minimized and does not produce frames at the moment.
+    Minimized,
+    /// No frame has arrived within the grab timeout (see `CaptureConfig::grab_timeout`).
+    Timeout,
+    /// The frame pool and session have been recreated (by the watchdog or after reattaching to a
+    /// new target). Frames grabbed after this may have a different size.
+    Recreated,
+}
+
+impl<'a> GrabStatus<'a> {
+    /// Get the frame, if any.
+    pub fn frame(self) -> Option<Frame<'a>> {
+        match self {
+            GrabStatus::Frame(frame) => Some(frame),
+            _ => None,
+        }
+    }
+
+    /// Whether the capture is over, i.e. `grab()` should not be called anymore.
+    pub fn is_closed(&self) -> bool {
+        matches!(self, GrabStatus::Closed)
+    }
+}
+
+/// The `start` / `grab` / `stop` interface shared by `Capture` and `FakeCapture`, so that code
+/// consuming frames can be tested without a desktop session.
+pub trait FrameSource {
+    /// Start producing frames.
+    fn start(&mut self) -> Result<()>;
+
+    /// Get the next frame, see `Capture::grab`.
+    fn grab(&mut self) -> crate::Result<GrabStatus>;
+
+    /// Stop producing frames. `grab()` returns `GrabStatus::Closed` afterwards.
+    fn stop(&mut self) -> Result<()>;
+
+    /// Append a processing stage, see `Capture::add_stage`.
+    fn add_stage(&mut self, stage: Box<dyn Stage>);
+
+    /// Get the D3D11 device frames are produced on.
+    fn d3d_device(&self) -> &ID3D11Device;
+}
+
+/// Memory held by a capture in bytes, see `Capture::memory_usage()`.
+///
+/// These are estimates computed from the sizes of the resources, which drivers may pad or place
+/// differently. Resources owned by stages are not included.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryUsage {
+    /// Buffers of the frame pool, which the system renders frames into.
+    pub frame_pool: u64,
+    /// Staging textures frames are read back through. Drivers usually keep these in system
+    /// memory.
+    pub staging: u64,
+    /// Textures for stages, scaling, and alpha and format conversion.
+    pub processing: u64,
+    /// CPU memory: the copy of the previous frame (see `CaptureConfig::track_changes`) and
+    /// buffered input events.
+    pub cpu: u64,
+}
+
+impl MemoryUsage {
+    /// Memory held by the GPU (or its driver) for the capture.
+    pub fn gpu(&self) -> u64 {
+        self.frame_pool + self.staging + self.processing
+    }
+
+    pub fn total(&self) -> u64 {
+        self.gpu() + self.cpu
+    }
 }
 
+/// Size of a pixel in bytes, for the formats used by captures.
+fn bytes_per_pixel(format: DXGI_FORMAT) -> u64 {
+    if format == DXGI_FORMAT_R16G16B16A16_FLOAT {
+        8
+    } else {
+        4
+    }
+}
+
+fn texture_bytes(texture: &ID3D11Texture2D) -> u64 {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { texture.GetDesc(&mut desc) };
+    desc.Width as u64 * desc.Height as u64 * bytes_per_pixel(desc.Format)
+}
+
+/// Pixel format of the frame pool for `format`, see `OutputFormat::capture_format`.
+fn frame_pool_format(format: OutputFormat) -> DirectXPixelFormat {
+    match format {
+        OutputFormat::Bgra8 => DirectXPixelFormat::B8G8R8A8UIntNormalized,
+        OutputFormat::Rgb10A2 | OutputFormat::Hdr10 => DirectXPixelFormat::R16G16B16A16Float,
+    }
+}
+
+/// Outcome of waiting for a frame from the frame pool.
+enum Received {
+    Frame(Direct3D11CaptureFrame),
+    /// No new frame has been queued, but a pipelined grab has copied one before.
+    PendingIsLatest,
+    Nothing(GrabStatus<'static>),
+}
+
+/// Outcome of `Capture::grab_into` and `Capture::grab_with`.
+pub(crate) enum GpuGrab {
+    /// A frame of `width` x `height` has been copied, cropped to `copied_width` x
+    /// `copied_height`.
+    Copied {
+        width: u32,
+        height: u32,
+        copied_width: u32,
+        copied_height: u32,
+        timestamp: i64,
+    },
+    Nothing(GrabStatus<'static>),
+}
+
+/// Manual-reset event which is signaled while there are frames waiting to be grabbed, or when the
+/// capture item has been closed.
+struct FrameSignal {
+    event: HANDLE,
+    pending: AtomicIsize,
+}
+
+impl FrameSignal {
+    fn new() -> Result<Self> {
+        let event = unsafe { CreateEventW(None, true, false, PCWSTR::null())? };
+        Ok(Self {
+            event,
+            pending: AtomicIsize::new(0),
+        })
+    }
+
+    fn frame_arrived(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        unsafe { SetEvent(self.event) };
+    }
+
+    fn frame_taken(&self) {
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        unsafe { ResetEvent(self.event) };
+        // a frame may have arrived between the decrement and the reset
+        if self.pending.load(Ordering::SeqCst) > 0 {
+            unsafe { SetEvent(self.event) };
+        }
+    }
+
+    /// Forget about pending frames, e.g. when the frame pool they came from is gone.
+    fn clear(&self) {
+        self.pending.store(0, Ordering::SeqCst);
+        unsafe { ResetEvent(self.event) };
+    }
+
+    fn set(&self) {
+        unsafe { SetEvent(self.event) };
+    }
+}
+
+impl Drop for FrameSignal {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.event) };
+    }
+}
+
+/// Frame pool and capture session for a single capture item.
+struct Session {
+    capture_item: GraphicsCaptureItem,
+    frame_pool: Direct3D11CaptureFramePool,
+    frame_pool_size: i32,
+    session: GraphicsCaptureSession,
+    frame_source: Receiver<Option<Direct3D11CaptureFrame>>,
+    frame_arrived_token: EventRegistrationToken,
+    closed_token: EventRegistrationToken,
+    closed: Cell<bool>,
+}
+
+/// Number of buffers in the frame pool. A single buffer is enough at 60 Hz, but high refresh rate
+/// displays produce frames faster than they can be consumed from one.
+fn frame_pool_size(refresh_rate: Option<u32>) -> i32 {
+    let refresh_rate = refresh_rate.unwrap_or(60).max(1);
+    ((refresh_rate + 59) / 60).clamp(1, 4) as i32
+}
+
+/// Minimal time between delivered frames in 100ns units (same as frame timestamps), or `PAUSED`.
+fn min_frame_interval(rate: Option<CaptureRate>, refresh_rate: Option<u32>) -> i64 {
+    match rate {
+        Some(rate) => (rate.min_frame_interval(refresh_rate).as_nanos() / 100) as i64,
+        None => PAUSED,
+    }
+}
+
+/// Call `f` until it succeeds, fails with an error which is not transient, or the attempts of
+/// `retry` run out.
+fn retry_transient<T>(
+    retry: Option<TransientRetry>,
+    operation: &'static str,
+    mut f: impl FnMut() -> Result<T>,
+) -> crate::Result<T> {
+    let retry = match retry {
+        Some(retry) => retry,
+        None => return f().context(operation),
+    };
+    let mut attempt = 0;
+    loop {
+        match f() {
+            Err(error) if is_transient(&error) && attempt + 1 < retry.attempts => {
+                sleep(retry.delay(attempt));
+                attempt += 1;
+            }
+            Err(source) if is_transient(&source) => {
+                return Err(Error::RetriesExhausted {
+                    operation,
+                    attempts: retry.attempts,
+                    source,
+                })
+            }
+            result => return result.context(operation),
+        }
+    }
+}
+
+/// State of a capture shared with the handlers of its sessions, which outlives each session.
+#[derive(Clone)]
+struct SessionHandlers {
+    frame_signal: Arc<FrameSignal>,
+    observers: Observers,
+    /// See `min_frame_interval()`.
+    min_frame_interval: Arc<AtomicI64>,
+    frame_callback: FrameCallbackSlot,
+}
+
+impl Session {
+    fn new(
+        direct3d_device: &IDirect3DDevice,
+        capture_item: GraphicsCaptureItem,
+        capturable: &dyn Capturable,
+        config: &CaptureConfig,
+        handlers: &SessionHandlers,
+    ) -> Result<Self> {
+        let capture_item_size = capture_item.Size()?;
+
+        let refresh_rate = capturable.refresh_rate();
+        let frame_pool_size = frame_pool_size(refresh_rate);
+        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+            direct3d_device,
+            frame_pool_format(config.output_format),
+            frame_pool_size,
+            capture_item_size,
+        )?;
+
+        let session = frame_pool.CreateCaptureSession(&capture_item)?;
+        session.SetIsCursorCaptureEnabled(config.capture_cursor)?;
+
+        let (sender, receiver) = sync_channel(1 << 5);
+        let every_nth_frame = config.every_nth_frame.max(1) as u64;
+        let frame_counter = AtomicU64::new(0);
+        let last_frame_ts = AtomicI64::new(i64::MIN);
+        let SessionHandlers {
+            frame_signal: signal,
+            observers,
+            min_frame_interval,
+            frame_callback,
+        } = handlers.clone();
+        let frame_arrived_handler =
+            TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new(
+                move |frame_pool, _| {
+                    // not retried, so as not to hold up the thread delivering frames
+                    let frame = match frame_pool.as_ref().unwrap().TryGetNextFrame() {
+                        Ok(frame) => frame,
+                        Err(error) => {
+                            observers.error(&error);
+                            return Err(error);
+                        }
+                    };
+                    if frame_counter.fetch_add(1, Ordering::Relaxed) % every_nth_frame != 0 {
+                        // return the buffer to the pool right away
+                        observers.dropped(DropReason::Decimated);
+                        return frame.Close();
+                    }
+                    let min_frame_interval = min_frame_interval.load(Ordering::Relaxed);
+                    if min_frame_interval == PAUSED {
+                        observers.dropped(DropReason::Paused);
+                        return frame.Close();
+                    }
+                    let ts = frame.SystemRelativeTime()?;
+                    if min_frame_interval > 0 {
+                        let last_ts = last_frame_ts.load(Ordering::Relaxed);
+                        // allow some jitter, otherwise frames arriving slightly early would halve the rate
+                        if ts.Duration.saturating_sub(last_ts) < min_frame_interval * 9 / 10 {
+                            observers.dropped(DropReason::RateLimited);
+                            return frame.Close();
+                        }
+                        last_frame_ts.store(ts.Duration, Ordering::Relaxed);
+                    }
+                    if !frame_callback.deliver(&frame)? {
+                        observers.dropped(DropReason::Consumed);
+                        return frame.Close();
+                    }
+                    match sender.try_send(Some(frame)) {
+                        Err(TrySendError::Full(_)) => observers.dropped(DropReason::QueueFull),
+                        Err(TrySendError::Disconnected(_)) => {
+                            observers.dropped(DropReason::Disconnected)
+                        }
+                        _ => signal.frame_arrived(),
+                    }
+                    Ok(())
+                },
+            );
+        let frame_arrived_token = frame_pool.FrameArrived(&frame_arrived_handler)?;
+
+        // wake up whoever waits for frames, so that they can notice the capture is over
+        let signal = handlers.frame_signal.clone();
+        let closed_handler =
+            TypedEventHandler::<GraphicsCaptureItem, IInspectable>::new(move |_, _| {
+                signal.set();
+                Ok(())
+            });
+        let closed_token = capture_item.Closed(&closed_handler)?;
+
+        Ok(Self {
+            capture_item,
+            frame_pool,
+            frame_pool_size,
+            session,
+            frame_source: receiver,
+            frame_arrived_token,
+            closed_token,
+            closed: Cell::new(false),
+        })
+    }
+
+    /// Stop the capture, making sure the handlers are not invoked anymore. Does nothing if the
+    /// session has been closed already.
+    ///
+    /// Everything is closed even if a step fails, the first error being returned.
+    fn close(&self) -> Result<()> {
+        if self.closed.replace(true) {
+            return Ok(());
+        }
+        // closing the pool alone does not release the handlers until the pool itself is released
+        let frame_arrived = self.frame_pool.RemoveFrameArrived(self.frame_arrived_token);
+        let closed = self.capture_item.RemoveClosed(self.closed_token);
+        let session = self.session.Close();
+        let frame_pool = self.frame_pool.Close();
+        frame_arrived.and(closed).and(session).and(frame_pool)
+    }
+}
+
+/// Captures frames of a `Capturable`.
+///
+/// # Threading
+///
+/// A capture is neither `Send` nor `Sync`: it owns a D3D11 immediate context, and its stages hold
+/// GPU resources, none of which may be used from several threads. Build it on the thread which
+/// grabs the frames, or let `CaptureThread` do that and receive the frames elsewhere. `Frame`s
+/// borrow the capture's staging texture and stay on its thread as well; copy them with
+/// `Frame::to_image()` to send them.
+///
+/// What may cross threads: capturables (which are `Send`, so they can be moved to the capture
+/// thread), observers and frame arrived callbacks (which are called from the thread delivering
+/// frames), the receivers returned by `events()`, and `Image`s.
 pub struct Capture {
     device: ID3D11Device,
     direct3d_device: IDirect3DDevice,
     context: ID3D11DeviceContext,
     capturable: Box<dyn Capturable>,
+    config: CaptureConfig,
     capture_box: D3D11_BOX,
     capture_done_signal: Receiver<()>,
-    frame_pool: Direct3D11CaptureFramePool,
-    frame_source: Receiver<Option<Direct3D11CaptureFrame>>,
-    session: GraphicsCaptureSession,
+    session: Session,
+    handlers: SessionHandlers,
     staging_texture: Option<StagingTexture>,
+    /// Staging texture holding a frame which has been copied but not returned yet, or a spare one
+    /// (pipelined mode only).
+    pending_staging_texture: Option<StagingTexture>,
+    has_pending_frame: bool,
+    /// Timestamps of the frames in `staging_texture` and `pending_staging_texture`.
+    frame_timestamp: i64,
+    pending_frame_timestamp: i64,
+    /// Statistics of the frames in `staging_texture` and `pending_staging_texture`.
+    frame_stats: Option<FrameStats>,
+    pending_frame_stats: Option<FrameStats>,
+    /// Position of the tracked window in the frames in `staging_texture` and
+    /// `pending_staging_texture`.
+    tracked_window: Option<Rect>,
+    pending_tracked_window: Option<Rect>,
+    /// Destination of `sample_pixels()`, one pixel per point.
+    sample_texture: Option<StagingTexture>,
+    /// Copy of the last returned frame, for `Frame::changed_tiles`.
+    previous_image: Option<Image>,
+    frame_returned: bool,
+    /// Input events for `Frame::input_events`, and those newer than the current frame.
+    input: Option<Receiver<InputEvent>>,
+    input_events: Vec<InputEvent>,
+    pending_input_events: Vec<InputEvent>,
+    stages: Vec<Box<dyn Stage>>,
+    work_texture: Option<ID3D11Texture2D>,
+    /// Copy of the part of the frame copied by `grab_into`, to compute its statistics.
+    stats_texture: Option<ID3D11Texture2D>,
+    output_texture: Option<ID3D11Texture2D>,
+    /// Final frame in the output format, if that differs from the format frames are processed in.
+    converted_texture: Option<ID3D11Texture2D>,
+    scaler: Option<Scaler>,
+    alpha_conversion: AlphaConversion,
+    format_conversion: FormatConversion,
+    gpu_timer: Option<GpuTimer>,
+    gpu_time: Option<Duration>,
+    frame_analyzer: Option<FrameAnalyzer>,
+    hang_detector: Option<HangDetector>,
     content_size: SizeInt32,
+    power_state: PowerState,
+    power_checked_at: Instant,
+    occluded: bool,
+    /// Set by `set_paused()`.
+    paused: bool,
+    occlusion_checked_at: Instant,
+    /// Desktop of the target window, and whether it is the current one.
+    virtual_desktop: Option<(GUID, bool)>,
+    virtual_desktop_checked_at: Instant,
+    retarget_checked_at: Instant,
+    events: EventSender,
+    last_frame_at: Instant,
+    started: bool,
+    dormant: bool,
     stopped: bool,
 }
 
@@ -45,65 +660,148 @@ impl Capture {
     /// frame pool / capture session.
     ///
     /// Note that this will not start capturing yet. Call `start()` to actually start receiving frames.
-    pub fn new(capturable: Box<dyn Capturable>, capture_cursor: bool) -> Result<Self> {
-        let device = create_d3d_device()?;
+    pub fn new(capturable: Box<dyn Capturable>, capture_cursor: bool) -> crate::Result<Self> {
+        Self::builder(capturable)
+            .capture_cursor(capture_cursor)
+            .build()
+    }
+
+    /// Start building a capture with non-default options.
+    pub fn builder(capturable: Box<dyn Capturable>) -> CaptureBuilder {
+        CaptureBuilder::new(capturable)
+    }
+
+    /// Create a new capture with given options. See `new()`.
+    ///
+    /// The target is validated first (see `Capturable::validate`), so that common problems are
+    /// reported with a specific `Error` rather than a failure somewhere inside capture setup.
+    pub fn with_config(
+        capturable: Box<dyn Capturable>,
+        config: CaptureConfig,
+    ) -> crate::Result<Self> {
+        Self::with_device(capturable, config, None)
+    }
+
+    /// Same as `with_config()`, but captures on `device` (e.g. one shared by several captures, see
+    /// `CaptureBuilder::device`) instead of creating a device for this capture.
+    pub(crate) fn with_device(
+        capturable: Box<dyn Capturable>,
+        config: CaptureConfig,
+        device: Option<ID3D11Device>,
+    ) -> crate::Result<Self> {
+        if !GraphicsCaptureSession::IsSupported()? {
+            return Err(Error::CaptureNotSupported);
+        }
+        config.validate()?;
+        capturable.validate()?;
+
+        let device = match device {
+            Some(device) => device,
+            None => create_d3d_device().context("creating the D3D11 device")?,
+        };
         let context = unsafe {
             let mut d3d_context = None;
             device.GetImmediateContext(&mut d3d_context);
             d3d_context.expect("failed to create d3d_context")
         };
-        let direct3d_device = create_direct3d_device(&device)?;
+        let direct3d_device =
+            create_direct3d_device(&device).context("creating the WinRT Direct3D device")?;
 
-        let capture_item = capturable.create_capture_item()?;
-        let capture_item_size = capture_item.Size()?;
-
-        let frame_pool = Direct3D11CaptureFramePool::CreateFreeThreaded(
+        let power_state = PowerState::current();
+        let rate = match config.power_throttle {
+            Some(throttle) => throttle.rate(power_state, config.rate),
+            None => Some(config.rate),
+        };
+        let handlers = SessionHandlers {
+            frame_signal: Arc::new(FrameSignal::new()?),
+            observers: Observers::default(),
+            min_frame_interval: Arc::new(AtomicI64::new(min_frame_interval(
+                rate,
+                capturable.refresh_rate(),
+            ))),
+            frame_callback: FrameCallbackSlot::default(),
+        };
+        let capture_item =
+            retry_transient(config.transient_retry, "creating the capture item", || {
+                capturable.create_capture_item()
+            })?;
+        let session = Session::new(
             &direct3d_device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
-            1,
-            capture_item_size,
-        )?;
+            capture_item,
+            capturable.as_ref(),
+            &config,
+            &handlers,
+        )
+        .context("creating the capture session")?;
 
-        let session = frame_pool.CreateCaptureSession(&capture_item)?;
-        session.SetIsCursorCaptureEnabled(capture_cursor)?;
-
-        let (sender, receiver) = sync_channel(1 << 5);
-        frame_pool.FrameArrived(
-            &TypedEventHandler::<Direct3D11CaptureFramePool, IInspectable>::new(
-                move |frame_pool, _| {
-                    let frame_pool = frame_pool.as_ref().unwrap();
-                    let frame = frame_pool.TryGetNextFrame()?;
-                    let ts = frame.SystemRelativeTime()?;
-                    match sender.try_send(Some(frame)) {
-                        Err(TrySendError::Full(_)) => {
-                            // TODO keep track of these frames?
-                            println!("dropping frame {}", ts.Duration);
-                        }
-                        Err(TrySendError::Disconnected(_)) => {
-                            println!("frame receiver disconnected");
-                        }
-                        _ => {}
-                    }
-                    Ok(())
-                },
-            ),
-        )?;
-
-        let capture_box = capturable.get_client_box()?;
+        let capture_box = config.insets.apply(
+            capturable
+                .get_client_box()
+                .context("getting the client box of the target")?,
+        );
         let capture_done_signal = capturable.get_close_notification_channel();
+        let alpha_conversion = AlphaConversion::new(FormatConversion::input_alpha(
+            config.output_format,
+            config.alpha,
+        ));
+        let format_conversion = FormatConversion::new(config.output_format, config.alpha);
+        let input = config
+            .annotate_input
+            .then(|| input::subscribe_with_keyboard(INPUT_BUFFER_SIZE))
+            .transpose()
+            .context("installing the input hooks")?;
+        let hang_detector = config.hang_detection.map(HangDetector::new);
 
         Ok(Self {
             device,
             direct3d_device,
             context,
             capturable,
+            config,
             capture_box,
             capture_done_signal,
-            frame_pool,
-            frame_source: receiver,
             session,
+            handlers,
             staging_texture: None,
+            pending_staging_texture: None,
+            has_pending_frame: false,
+            frame_timestamp: 0,
+            pending_frame_timestamp: 0,
+            frame_stats: None,
+            pending_frame_stats: None,
+            tracked_window: None,
+            pending_tracked_window: None,
+            sample_texture: None,
+            previous_image: None,
+            frame_returned: false,
+            input,
+            input_events: Vec::new(),
+            pending_input_events: Vec::new(),
+            stages: Vec::new(),
+            work_texture: None,
+            stats_texture: None,
+            output_texture: None,
+            converted_texture: None,
+            scaler: None,
+            alpha_conversion,
+            format_conversion,
+            gpu_timer: None,
+            gpu_time: None,
+            frame_analyzer: None,
+            hang_detector,
             content_size: Default::default(),
+            power_state,
+            power_checked_at: Instant::now(),
+            occluded: false,
+            paused: false,
+            occlusion_checked_at: Instant::now(),
+            virtual_desktop: None,
+            virtual_desktop_checked_at: Instant::now(),
+            retarget_checked_at: Instant::now(),
+            events: Default::default(),
+            last_frame_at: Instant::now(),
+            started: false,
+            dormant: false,
             stopped: false,
         })
     }
@@ -113,32 +811,394 @@ impl Capture {
         &self.capturable
     }
 
+    /// Get options this capture was created with.
+    pub fn config(&self) -> &CaptureConfig {
+        &self.config
+    }
+
+    /// Append a processing stage, which will be applied to every frame on the GPU before it is
+    /// read back.
+    pub fn add_stage(&mut self, stage: Box<dyn Stage>) {
+        self.stages.push(stage);
+    }
+
+    /// Change the rate frames are delivered at while capturing, replacing `CaptureConfig::rate`.
+    /// Power throttling and occlusion still apply on top of it.
+    pub fn set_rate(&mut self, rate: CaptureRate) {
+        self.config.rate = rate;
+        self.update_min_frame_interval();
+    }
+
+    /// Stop delivering frames until called again with `false`. Frames arriving in the meantime are
+    /// dropped right away (see `DropReason::Paused`), so pausing costs next to nothing.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.update_min_frame_interval();
+    }
+
+    /// Crop the target differently from the next frame on, see `CaptureConfig::insets`.
+    pub fn set_insets(&mut self, insets: Insets) -> Result<()> {
+        self.config.insets = insets;
+        self.capture_box = insets.apply(self.capturable.get_client_box()?);
+        // textures of the new size are created with the next frame
+        self.staging_texture = None;
+        self.pending_staging_texture = None;
+        self.has_pending_frame = false;
+        self.frame_returned = false;
+        Ok(())
+    }
+
+    /// Register an observer to be notified about grabbed and dropped frames, resizes and errors.
+    pub fn add_observer(&mut self, observer: Arc<dyn Observer>) {
+        self.handlers.observers.add(observer);
+    }
+
+    /// GPU time spent on copying and processing the last grabbed frame.
+    ///
+    /// This is only measured if `CaptureConfig::gpu_timing` is enabled. Comparing it to the total
+    /// time spent in `grab()` shows whether the capture overhead is GPU- or CPU-bound.
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.gpu_time
+    }
+
+    /// Estimate the memory this capture currently holds, e.g. to budget resources when running
+    /// many captures at once.
+    pub fn memory_usage(&self) -> Result<MemoryUsage> {
+        let item_size = self.session.capture_item.Size()?;
+        let frame_pool = self.session.frame_pool_size.max(0) as u64
+            * item_size.Width.max(0) as u64
+            * item_size.Height.max(0) as u64
+            * bytes_per_pixel(self.config.output_format.capture_format());
+        let staging: u64 = [
+            &self.staging_texture,
+            &self.pending_staging_texture,
+            &self.sample_texture,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|staging| texture_bytes(&staging.texture))
+        .sum();
+        let processing: u64 = [
+            &self.work_texture,
+            &self.output_texture,
+            &self.converted_texture,
+        ]
+        .into_iter()
+        .flatten()
+        .map(texture_bytes)
+        .sum();
+        let input_events = self.input_events.capacity() + self.pending_input_events.capacity();
+        let cpu = self
+            .previous_image
+            .as_ref()
+            .map_or(0, |image| image.data.capacity() as u64)
+            + (input_events * size_of::<InputEvent>()) as u64;
+        Ok(MemoryUsage {
+            frame_pool,
+            staging,
+            processing,
+            cpu,
+        })
+    }
+
+    /// Subscribe to diagnostic events of this capture.
+    ///
+    /// Events are delivered while frames are being grabbed. A subscriber which does not drain its
+    /// receiver will miss events, but never blocks the capture.
+    pub fn events(&mut self) -> Receiver<CaptureEvent> {
+        self.events.subscribe()
+    }
+
+    /// Emit an event on behalf of something driving the capture, e.g. a `CaptureThread`.
+    pub(crate) fn emit_event(&mut self, event: CaptureEvent) {
+        self.events.emit(event);
+    }
+
+    /// Report a frame which has been grabbed but then dropped by something driving the capture.
+    pub(crate) fn report_drop(&self, reason: DropReason) {
+        self.handlers.observers.dropped(reason);
+    }
+
+    /// Receive frames right as they arrive in the frame pool, on the thread delivering them, or
+    /// stop doing so with `None`.
+    ///
+    /// This is for latency-critical consumers: frames are handed over before they are queued, so
+    /// there is no channel hop and no need to call `grab()`. Frames the callback returns `false`
+    /// for are not queued (and reported to observers as `DropReason::Consumed`), the others are
+    /// grabbed as usual. Rate limiting and decimation apply before the callback.
+    ///
+    /// The callback runs while the frame pool waits for it, so it must be quick. It must not
+    /// call back into this capture or set another callback, which would deadlock. The texture is
+    /// on `d3d_device()`, which is switched to multithread-protected mode here so that its
+    /// immediate context can be used from the callback while another thread grabs.
+    pub fn set_frame_arrived_callback(
+        &mut self,
+        callback: Option<FrameArrivedCallback>,
+    ) -> Result<()> {
+        if callback.is_some() {
+            let multithread: ID3D11Multithread = self.context.cast()?;
+            unsafe { multithread.SetMultithreadProtected(true) };
+        }
+        self.handlers.frame_callback.set(callback);
+        Ok(())
+    }
+
+    /// Get the D3D11 device frames are captured, processed and read back on.
+    ///
+    /// This is for advanced use, e.g. creating resources to process `Frame::texture` with custom
+    /// GPU code. The device is shared with the capture and its stages: it must not be used from
+    /// another thread while the capture is grabbing, and anything bound to the pipeline must be
+    /// unbound again before the next grab.
+    pub fn d3d_device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Get the immediate context of `d3d_device()`. The same rules apply.
+    pub fn d3d_context(&self) -> &ID3D11DeviceContext {
+        &self.context
+    }
+
+    /// Win32 event which is signaled while a frame is available, so that `grab()` will not block.
+    ///
+    /// This allows waiting for frames together with other handles using `WaitForMultipleObjects`
+    /// and friends. The event is also signaled when the target is closed; `grab()` then returns
+    /// `GrabStatus::Closed`. The handle is owned by the capture and must not be closed.
+    pub fn frame_event(&self) -> HANDLE {
+        self.handlers.frame_signal.event
+    }
+
     /// Start capturing frames.
-    pub fn start(&self) -> Result<()> {
-        self.session.StartCapture()
+    pub fn start(&mut self) -> Result<()> {
+        self.session.session.StartCapture()?;
+        self.started = true;
+        self.last_frame_at = Instant::now();
+        self.handlers.observers.started();
+        Ok(())
     }
 
     /// Grab current capture frame.
     ///
-    /// **This method blocks if there is no frames in the frame pool** (happens when application's window
-    /// is minimized, for example).
+    /// **This method blocks until there is a frame in the frame pool**, unless the target is
+    /// minimized or the grab timeout is set.
     ///
     /// Returns:
-    /// * `Ok(Some(...))` if there is a frame and it's been successfully captured;
-    /// * `Ok(None)` if no frames can be received (e.g. when the window was closed).
+    /// * `Ok(GrabStatus::Frame(...))` if there is a frame and it's been successfully captured;
+    /// * `Ok(GrabStatus::Closed)` if no frames can be received (e.g. when the window was closed);
+    /// * `Ok(...)` with another status explaining why there is no frame at the moment;
     /// * `Err(...)` if an error has occured while capturing a frame.
-    pub fn grab(&mut self) -> Result<Option<Frame>> {
-        if self.grab_next()? {
-            let texture = self.staging_texture.as_ref().unwrap();
-            let ptr = self
-                .staging_texture
-                .as_ref()
-                .unwrap()
-                .as_mapped(&self.context)?;
-            Ok(Some(Frame { texture, ptr }))
-        } else {
-            Ok(None)
+    pub fn grab(&mut self) -> crate::Result<GrabStatus> {
+        self.remember_frame()?;
+        let status = self.grab_next();
+        if let Some(status) = self.observe_error(status)? {
+            return Ok(status);
+        }
+        self.collect_input_events();
+        let hang = self.detect_hang().map_err(Error::from);
+        self.observe_error(hang)?;
+        self.frame_returned = true;
+        self.observe_error(self.current_frame().map_err(Error::from))
+    }
+
+    /// Capture a single frame without keeping the capture running.
+    ///
+    /// The session is started just for the duration of this call and closed afterwards, so that no
+    /// GPU work is done between requests. This is intended for polling the target at very low
+    /// rates; use `start()` and `grab()` otherwise. If the capture has been started already, this
+    /// is the same as `grab()`.
+    pub fn request_frame(&mut self) -> crate::Result<GrabStatus> {
+        if self.started {
+            return self.grab();
         }
+        if self.stopped {
+            return Ok(GrabStatus::Closed);
+        }
+        self.remember_frame()?;
+        if self.dormant {
+            // sessions cannot be restarted once closed
+            self.recreate_session()?;
+        }
+
+        self.start()?;
+        let status = self.grab_next();
+        self.started = false;
+        self.dormant = true;
+        if !self.stopped {
+            self.session.close()?;
+        }
+
+        if let Some(status) = self.observe_error(status)? {
+            return Ok(status);
+        }
+        self.collect_input_events();
+        let hang = self.detect_hang().map_err(Error::from);
+        self.observe_error(hang)?;
+        self.frame_returned = true;
+        self.observe_error(self.current_frame().map_err(Error::from))
+    }
+
+    /// Move the input events which happened up to the current frame to `input_events`.
+    fn collect_input_events(&mut self) {
+        let input = match &self.input {
+            Some(input) => input,
+            None => return,
+        };
+        self.pending_input_events.extend(input.try_iter());
+        // mouse and keyboard events are published from different threads
+        self.pending_input_events
+            .sort_by_key(|event| event.timestamp);
+        let frame_timestamp = self.frame_timestamp;
+        let count = self
+            .pending_input_events
+            .partition_point(|event| event.timestamp <= frame_timestamp);
+        self.input_events = self.pending_input_events.drain(..count).collect();
+    }
+
+    /// Read single pixels of the next frame, without reading back the whole frame.
+    ///
+    /// This waits for a frame like `grab()` does, and copies a 1x1 box for every point out of it,
+    /// so that only `points.len()` pixels have to travel back from the GPU. Points are in frame
+    /// coordinates before stages and scaling are applied (neither of which runs for sampled
+    /// frames); points outside of the frame are returned as transparent black. Colors are 8-bit
+    /// sRGB regardless of `CaptureConfig::output_format`. The frame is not returned by the next
+    /// `grab()`.
+    ///
+    /// Returns `None` if there is no frame, see `grab()` for the reasons.
+    pub fn sample_pixels(&mut self, points: &[POINT]) -> crate::Result<Option<Vec<Color>>> {
+        if self.stopped {
+            return Ok(None);
+        }
+        let frame = match self.receive_frame(Instant::now(), false)? {
+            Received::Frame(frame) => frame,
+            _ => return Ok(None),
+        };
+        self.last_frame_at = Instant::now();
+        if points.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+
+        let frame_texture: ID3D11Texture2D = get_dxgi_interface_from_object(&frame.Surface()?)?;
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { frame_texture.GetDesc(&mut desc) };
+        let count = points.len() as u32;
+        let reusable = matches!(
+            &self.sample_texture,
+            Some(texture) if texture.desc.Width == count && texture.desc.Format == desc.Format
+        );
+        if !reusable {
+            self.sample_texture = Some(StagingTexture::new(&self.device, count, 1, desc.Format)?);
+        }
+        let sample_texture = self.sample_texture.as_ref().unwrap();
+
+        let right = self.capture_box.right.min(desc.Width);
+        let bottom = self.capture_box.bottom.min(desc.Height);
+        let sources: Vec<Option<(u32, u32)>> = points
+            .iter()
+            .map(|point| {
+                let x = self.capture_box.left.checked_add_signed(point.x)?;
+                let y = self.capture_box.top.checked_add_signed(point.y)?;
+                (x < right && y < bottom).then_some((x, y))
+            })
+            .collect();
+        let dest = sample_texture.as_resource()?;
+        let src: ID3D11Resource = frame_texture.cast()?;
+        for (i, source) in sources.iter().enumerate() {
+            if let Some((x, y)) = source {
+                let pixel_box = client_box(*x, *y, 1, 1);
+                unsafe {
+                    self.context.CopySubresourceRegion(
+                        Some(&dest),
+                        0,
+                        i as u32,
+                        0,
+                        0,
+                        Some(&src),
+                        0,
+                        Some(&pixel_box as *const _),
+                    );
+                }
+            }
+        }
+        let mapped = sample_texture.as_mapped(&self.context)?;
+        let pixel_size = bytes_per_pixel(desc.Format) as usize;
+        let data = unsafe {
+            std::slice::from_raw_parts(mapped.pData as *const u8, count as usize * pixel_size)
+        };
+        let colors = sources
+            .iter()
+            .zip(data.chunks_exact(pixel_size))
+            .map(|(source, pixel)| match source {
+                Some(_) if desc.Format == DXGI_FORMAT_R16G16B16A16_FLOAT => {
+                    Color::from_scrgb(pixel)
+                }
+                Some(_) => Color::from_bgra(pixel),
+                None => Color::default(),
+            })
+            .collect();
+        Ok(Some(colors))
+    }
+
+    /// Check the frame about to be returned for black or frozen content.
+    fn detect_hang(&mut self) -> Result<()> {
+        let mut detector = match self.hang_detector.take() {
+            Some(detector) => detector,
+            None => return Ok(()),
+        };
+        let events = match self.current_frame()? {
+            GrabStatus::Frame(frame) => detector.check(&frame),
+            _ => Vec::new(),
+        };
+        self.hang_detector = Some(detector);
+        for event in events {
+            self.events.emit(event);
+        }
+        Ok(())
+    }
+
+    /// Keep a copy of the frame returned by the previous grab, before it is overwritten.
+    fn remember_frame(&mut self) -> Result<()> {
+        if !self.config.track_changes || !std::mem::take(&mut self.frame_returned) {
+            return Ok(());
+        }
+        let image = match self.current_frame()? {
+            GrabStatus::Frame(frame) => Image::from_frame_raw(&frame),
+            _ => return Ok(()),
+        };
+        self.previous_image = Some(image);
+        Ok(())
+    }
+
+    /// Notify observers if `result` is an error.
+    fn observe_error<T>(&self, result: crate::Result<T>) -> crate::Result<T> {
+        if let Err(error) = &result {
+            self.handlers.observers.error(&error.to_windows_error());
+        }
+        result
+    }
+
+    pub(crate) fn current_frame(&self) -> Result<GrabStatus> {
+        let texture = self.staging_texture.as_ref().unwrap();
+        let ptr = self.map_staging_texture(texture)?;
+        Ok(GrabStatus::Frame(Frame {
+            texture,
+            ptr,
+            timestamp: self.frame_timestamp,
+            previous: self.previous_image.as_ref(),
+            input_events: &self.input_events,
+            stats: self.frame_stats.as_ref(),
+            tracked_window: self.tracked_window,
+        }))
+    }
+
+    fn map_staging_texture(&self, texture: &StagingTexture) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        if let Some(map_retry) = self.config.map_retry {
+            for attempt in 0..map_retry.attempts {
+                if let Some(ptr) = texture.try_as_mapped(&self.context)? {
+                    return Ok(ptr);
+                }
+                sleep(map_retry.delay(attempt));
+            }
+        }
+        texture.as_mapped(&self.context)
     }
 
     /// Stops the capture.
@@ -147,65 +1207,586 @@ impl Capture {
     /// **not** produce more frames).
     pub fn stop(&mut self) -> Result<()> {
         self.stopped = true;
-        self.session.Close()?;
-        self.frame_pool.Close()?;
-        Ok(())
+        self.handlers.frame_signal.set();
+        self.handlers.observers.stopped();
+        self.session.close()
     }
 
     fn recreate_frame_pool(&mut self) -> Result<()> {
-        let capture_item = self.capturable.create_capture_item()?;
-        let capture_item_size = capture_item.Size()?;
-        self.capture_box = self.capturable.get_client_box()?;
-        self.frame_pool.Recreate(
+        let capture_item_size = self.session.capture_item.Size()?;
+        self.capture_box = self.config.insets.apply(self.capturable.get_client_box()?);
+        self.session.frame_pool.Recreate(
             &self.direct3d_device,
-            DirectXPixelFormat::B8G8R8A8UIntNormalized,
-            1,
+            frame_pool_format(self.config.output_format),
+            self.session.frame_pool_size,
             capture_item_size,
         )?;
+        self.handlers.observers.recreated();
         Ok(())
     }
 
-    fn grab_next(&mut self) -> Result<bool> {
-        if self.stopped {
+    /// Replace the frame pool and session with new ones for the current capturable, restarting the
+    /// capture if it has been started before.
+    fn recreate_session(&mut self) -> crate::Result<()> {
+        self.session.close().ok();
+        self.handlers.frame_signal.clear();
+        let capture_item = retry_transient(
+            self.config.transient_retry,
+            "creating the capture item",
+            || self.capturable.create_capture_item(),
+        )?;
+        self.session = Session::new(
+            &self.direct3d_device,
+            capture_item,
+            self.capturable.as_ref(),
+            &self.config,
+            &self.handlers,
+        )?;
+        // the replacement of a reattached target may be on a display with another refresh rate
+        self.update_min_frame_interval();
+        self.capture_box = self.config.insets.apply(self.capturable.get_client_box()?);
+        self.staging_texture = None;
+        self.pending_staging_texture = None;
+        self.has_pending_frame = false;
+        self.frame_returned = false;
+        self.content_size = Default::default();
+        if self.started {
+            self.session.session.StartCapture()?;
+        }
+        self.last_frame_at = Instant::now();
+        self.handlers.observers.recreated();
+        Ok(())
+    }
+
+    /// Recreate the session if no frames have arrived for longer than the watchdog timeout while
+    /// the target is visible.
+    ///
+    /// Returns `true` if the session has been recreated.
+    fn check_watchdog(&mut self) -> crate::Result<bool> {
+        if let Some(timeout) = self.config.watchdog {
+            let stalled_for = self.last_frame_at.elapsed();
+            let paused = self.current_rate().is_none();
+            if self.started && !paused && stalled_for >= timeout && self.capturable.is_visible() {
+                self.recreate_session()?;
+                self.events
+                    .emit(CaptureEvent::WatchdogRestart { stalled_for });
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Rate frames should currently be delivered at, or `None` if the capture is paused.
+    fn current_rate(&self) -> Option<CaptureRate> {
+        if self.occluded || self.paused {
+            return None;
+        }
+        match self.config.power_throttle {
+            Some(throttle) => throttle.rate(self.power_state, self.config.rate),
+            None => Some(self.config.rate),
+        }
+    }
+
+    fn update_min_frame_interval(&mut self) {
+        let interval = min_frame_interval(self.current_rate(), self.capturable.refresh_rate());
+        self.handlers
+            .min_frame_interval
+            .store(interval, Ordering::Relaxed);
+        // no frames are delivered while paused, which must not trip the watchdog
+        self.last_frame_at = Instant::now();
+    }
+
+    /// Adjust the capture rate if the power state has changed since it was last checked.
+    fn check_power_state(&mut self) {
+        if self.config.power_throttle.is_none()
+            || self.power_checked_at.elapsed() < POWER_POLL_INTERVAL
+        {
+            return;
+        }
+        self.power_checked_at = Instant::now();
+        let state = PowerState::current();
+        if state != self.power_state {
+            self.power_state = state;
+            self.update_min_frame_interval();
+            self.events.emit(CaptureEvent::PowerStateChanged { state });
+        }
+    }
+
+    /// Pause or resume the capture if the target has become occluded or visible.
+    fn check_occlusion(&mut self) {
+        if !self.config.pause_when_occluded
+            || self.occlusion_checked_at.elapsed() < OCCLUSION_POLL_INTERVAL
+        {
+            return;
+        }
+        self.occlusion_checked_at = Instant::now();
+        let occluded = self.capturable.is_occluded();
+        if occluded != self.occluded {
+            self.occluded = occluded;
+            self.update_min_frame_interval();
+            self.events
+                .emit(CaptureEvent::OcclusionChanged { occluded });
+        }
+    }
+
+    /// Report if the target window has moved to another virtual desktop, or the user has switched
+    /// desktops.
+    fn check_virtual_desktop(&mut self) {
+        if !self.config.track_virtual_desktop
+            || self.virtual_desktop_checked_at.elapsed() < VIRTUAL_DESKTOP_POLL_INTERVAL
+        {
+            return;
+        }
+        self.virtual_desktop_checked_at = Instant::now();
+        let window = match self.capturable.as_window() {
+            Some(window) => window,
+            None => return,
+        };
+        let desktop = match (
+            window.virtual_desktop_id(),
+            window.is_on_current_virtual_desktop(),
+        ) {
+            (Ok(id), Ok(is_current)) => (id, is_current),
+            _ => return,
+        };
+        // the desktop the capture starts on is not a change
+        let previous = self.virtual_desktop.replace(desktop);
+        if previous.is_some() && previous != Some(desktop) {
+            self.events.emit(CaptureEvent::VirtualDesktopChanged {
+                desktop_id: format!("{:?}", desktop.0),
+                is_current: desktop.1,
+            });
+        }
+    }
+
+    /// Wait for a replacement of the closed target and switch the capture over to it.
+    ///
+    /// Returns `false` if auto-reattach is disabled or no replacement has appeared in time.
+    fn reattach(&mut self) -> crate::Result<bool> {
+        let timeout = match self.config.auto_reattach {
+            Some(timeout) => timeout,
+            None => return Ok(false),
+        };
+
+        let deadline = Instant::now() + timeout;
+        let mut existing = None;
+        let replacement = loop {
+            if let Some(replacement) = self.capturable.find_replacement(&mut existing) {
+                break replacement;
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            sleep(REATTACH_POLL_INTERVAL);
+        };
+        self.switch_target(replacement)?;
+        Ok(true)
+    }
+
+    /// Switch the capture over to a new target if the current one asks for it, see
+    /// `Capturable::retarget`. Checked at most every `RETARGET_POLL_INTERVAL`, whether frames are
+    /// arriving or not.
+    ///
+    /// Returns `true` if the target has been switched.
+    fn check_retarget(&mut self) -> crate::Result<bool> {
+        if self.retarget_checked_at.elapsed() < RETARGET_POLL_INTERVAL {
             return Ok(false);
         }
-        let frame = loop {
-            match self.frame_source.try_recv() {
-                Ok(Some(f)) => break f,
+        self.retarget_checked_at = Instant::now();
+        match self.capturable.retarget() {
+            Some(target) => {
+                let previous_handle = self.capturable.get_raw_handle();
+                let handle = target.get_raw_handle();
+                self.switch_target(target)?;
+                self.events.emit(CaptureEvent::Retargeted {
+                    previous_handle,
+                    handle,
+                });
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn switch_target(&mut self, target: Box<dyn Capturable>) -> crate::Result<()> {
+        self.capture_done_signal = target.get_close_notification_channel();
+        self.capturable = target;
+        self.recreate_session()
+    }
+
+    /// Reattach to a replacement of the closed target, or stop the capture if there is none.
+    fn target_closed(&mut self) -> crate::Result<GrabStatus<'static>> {
+        if self.reattach()? {
+            return Ok(GrabStatus::Recreated);
+        }
+        self.stop()?;
+        Ok(GrabStatus::Closed)
+    }
+
+    /// Wait for the next frame from the frame pool.
+    ///
+    /// With `use_pending`, a frame copied by a previous pipelined grab counts as the latest one
+    /// if nothing newer is queued.
+    fn receive_frame(&mut self, started_at: Instant, use_pending: bool) -> crate::Result<Received> {
+        loop {
+            self.check_power_state();
+            self.check_occlusion();
+            self.check_virtual_desktop();
+            match self.session.frame_source.try_recv() {
+                Ok(Some(f)) => {
+                    self.handlers.frame_signal.frame_taken();
+                    // frames keep arriving from the previous target while it is being replaced
+                    if self.check_retarget()? {
+                        f.Close().ok();
+                        return Ok(Received::Nothing(GrabStatus::Recreated));
+                    }
+                    return Ok(Received::Frame(f));
+                }
                 Err(TryRecvError::Empty) => {
+                    if use_pending && self.has_pending_frame {
+                        return Ok(Received::PendingIsLatest);
+                    }
                     // TODO busy loop? so uncivilized
                     if let Ok(()) | Err(TryRecvError::Disconnected) =
                         self.capture_done_signal.try_recv()
                     {
-                        self.stop()?;
-                        return Ok(false);
+                        return Ok(Received::Nothing(self.target_closed()?));
+                    }
+                    if self.check_retarget()? || self.check_watchdog()? {
+                        return Ok(Received::Nothing(GrabStatus::Recreated));
+                    }
+                    if self.capturable.is_minimized() {
+                        return Ok(Received::Nothing(GrabStatus::Minimized));
+                    }
+                    if let Some(timeout) = self.config.grab_timeout {
+                        if started_at.elapsed() >= timeout {
+                            return Ok(Received::Nothing(GrabStatus::Timeout));
+                        }
                     }
                 }
-                Ok(None) | Err(TryRecvError::Disconnected) => return Ok(false),
+                Ok(None) | Err(TryRecvError::Disconnected) => {
+                    return Ok(Received::Nothing(GrabStatus::Closed))
+                }
             }
+        }
+    }
+
+    /// Receive the next frame and copy it into the staging texture.
+    ///
+    /// Returns `None` if the staging texture has been updated, or the status explaining why it
+    /// has not.
+    fn grab_next(&mut self) -> crate::Result<Option<GrabStatus<'static>>> {
+        if self.stopped {
+            return Ok(Some(GrabStatus::Closed));
+        }
+        if self.config.revalidate_target && !self.capturable.is_alive() {
+            return Ok(Some(self.target_closed()?));
+        }
+        let grab_started_at = Instant::now();
+        loop {
+            let frame = match self.receive_frame(grab_started_at, true)? {
+                Received::Frame(frame) => frame,
+                Received::PendingIsLatest => {
+                    // nothing newer has arrived, so the pending frame is the latest one
+                    self.swap_staging_textures();
+                    self.has_pending_frame = false;
+                    return Ok(None);
+                }
+                Received::Nothing(status) => return Ok(Some(status)),
+            };
+
+            self.copy_frame(frame)?;
+            if !self.config.pipelined {
+                return Ok(None);
+            }
+            // hand out the frame copied by the previous call, which the GPU has most likely
+            // finished by now, and keep this one pending until the next call
+            self.swap_staging_textures();
+            if self.has_pending_frame {
+                return Ok(None);
+            }
+            self.has_pending_frame = true;
+        }
+    }
+
+    fn swap_staging_textures(&mut self) {
+        std::mem::swap(&mut self.staging_texture, &mut self.pending_staging_texture);
+        std::mem::swap(&mut self.frame_timestamp, &mut self.pending_frame_timestamp);
+        std::mem::swap(&mut self.frame_stats, &mut self.pending_frame_stats);
+        std::mem::swap(&mut self.tracked_window, &mut self.pending_tracked_window);
+    }
+
+    /// Frame bounds of the tracked window relative to the capture box, see `Frame::tracked_window`.
+    fn locate_tracked_window(&self) -> Option<Rect> {
+        let window = HWND(self.config.track_window?);
+        if !is_shown(window) {
+            return None;
+        }
+        let origin = self.capturable.get_screen_origin()?;
+        let (x, y) = (
+            origin.x + self.config.insets.left as i32,
+            origin.y + self.config.insets.top as i32,
+        );
+        let bounds = get_frame_bounds(window);
+        Some(Rect {
+            left: bounds.left - x,
+            top: bounds.top - y,
+            right: bounds.right - x,
+            bottom: bounds.bottom - y,
+        })
+    }
+
+    /// Receive the next frame and copy the capture box straight into `dest` at `(x, y)`, cropped
+    /// to `max_width` x `max_height`. Nothing is read back, and stages, scaling and format
+    /// conversion do not apply. Used by `SpriteSheet`.
+    ///
+    /// Observers are notified as for `grab()`, and statistics are computed for the copied part if
+    /// enabled. Of hangs, only black frames are detected, as frozen ones are told by the pixels.
+    pub(crate) fn grab_into(
+        &mut self,
+        dest: &ID3D11Texture2D,
+        (x, y): (u32, u32),
+        max_size: (u32, u32),
+    ) -> crate::Result<GpuGrab> {
+        let dest: ID3D11Resource = dest.cast()?;
+        self.grab_with(max_size, |context, source, source_box| {
+            unsafe {
+                context.CopySubresourceRegion(
+                    Some(&dest),
+                    0,
+                    x,
+                    y,
+                    0,
+                    Some(source),
+                    0,
+                    Some(source_box as *const _),
+                );
+            }
+            Ok(())
+        })
+    }
+
+    /// Receive the next frame and hand `copy` the part of its texture to use (the capture box,
+    /// cropped to `max_width` x `max_height`), together with the immediate context. Otherwise the
+    /// same as `grab_into()`. Used by `Mosaic`, which scales the frames instead of copying them.
+    pub(crate) fn grab_with(
+        &mut self,
+        max_size: (u32, u32),
+        copy: impl FnOnce(&ID3D11DeviceContext, &ID3D11Resource, &D3D11_BOX) -> Result<()>,
+    ) -> crate::Result<GpuGrab> {
+        let grabbed = self.copy_frame_with(max_size, copy);
+        self.observe_error(grabbed)
+    }
+
+    fn copy_frame_with(
+        &mut self,
+        (max_width, max_height): (u32, u32),
+        copy: impl FnOnce(&ID3D11DeviceContext, &ID3D11Resource, &D3D11_BOX) -> Result<()>,
+    ) -> crate::Result<GpuGrab> {
+        if self.stopped {
+            return Ok(GpuGrab::Nothing(GrabStatus::Closed));
+        }
+        let frame = match self.receive_frame(Instant::now(), false)? {
+            Received::Frame(frame) => frame,
+            Received::Nothing(status) => return Ok(GpuGrab::Nothing(status)),
+            Received::PendingIsLatest => return Ok(GpuGrab::Nothing(GrabStatus::Timeout)),
         };
+        let (frame_texture, resized) = self.begin_frame(&frame)?;
+        let timestamp = self.frame_timestamp;
 
-        let frame_texture: ID3D11Texture2D = get_dxgi_interface_from_object(&frame.Surface()?)?;
-        let content_size = frame.ContentSize()?;
+        let width = self.capture_box.right - self.capture_box.left;
+        let height = self.capture_box.bottom - self.capture_box.top;
+        if resized {
+            self.handlers.observers.resized(width, height);
+        }
+        // the frame may still have the previous size right after a resize
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { frame_texture.GetDesc(&mut desc) };
+        let source_box = D3D11_BOX {
+            right: (self.capture_box.left + width.min(max_width)).min(desc.Width),
+            bottom: (self.capture_box.top + height.min(max_height)).min(desc.Height),
+            ..self.capture_box
+        };
+        if source_box.right <= source_box.left || source_box.bottom <= source_box.top {
+            return Ok(GpuGrab::Nothing(GrabStatus::Timeout));
+        }
+        let source: ID3D11Resource = frame_texture.cast()?;
+        copy(&self.context, &source, &source_box)?;
+        let (copied_width, copied_height) = (
+            source_box.right - source_box.left,
+            source_box.bottom - source_box.top,
+        );
 
-        if self.content_size.Width != content_size.Width
-            || self.content_size.Height != content_size.Height
-            || self.staging_texture.is_none()
-        {
+        if self.config.computes_frame_stats() {
+            self.analyze_box(&frame_texture, &source_box)?;
+        }
+        if let Some(detector) = self.hang_detector.as_mut() {
+            for event in detector.check_stats(timestamp, self.frame_stats.as_ref()) {
+                self.events.emit(event);
+            }
+        }
+        self.handlers.observers.frame(&FrameInfo {
+            timestamp,
+            width: copied_width,
+            height: copied_height,
+            process_time: self.last_frame_at.elapsed(),
+            gpu_time: None,
+        });
+        Ok(GpuGrab::Copied {
+            width,
+            height,
+            copied_width,
+            copied_height,
+            timestamp,
+        })
+    }
+
+    /// Compute the statistics of `source_box` of `frame_texture`, for frames which do not go
+    /// through the work texture.
+    fn analyze_box(
+        &mut self,
+        frame_texture: &ID3D11Texture2D,
+        source_box: &D3D11_BOX,
+    ) -> Result<()> {
+        let (width, height) = (
+            source_box.right - source_box.left,
+            source_box.bottom - source_box.top,
+        );
+        let reusable = matches!(&self.stats_texture, Some(texture) if {
             let mut desc = D3D11_TEXTURE2D_DESC::default();
-            unsafe { frame_texture.GetDesc(&mut desc) };
+            unsafe { texture.GetDesc(&mut desc) };
+            (desc.Width, desc.Height) == (width, height)
+        });
+        if !reusable {
+            let format = self.config.output_format.capture_format();
+            self.stats_texture = Some(create_work_texture(&self.device, width, height, format)?);
+        }
+        let stats_texture = self.stats_texture.as_ref().unwrap();
+        let dest: ID3D11Resource = stats_texture.cast()?;
+        let source: ID3D11Resource = frame_texture.cast()?;
+        unsafe {
+            self.context.CopySubresourceRegion(
+                Some(&dest),
+                0,
+                0,
+                0,
+                0,
+                Some(&source),
+                0,
+                Some(source_box as *const _),
+            );
+        }
+        let ctx = StageContext {
+            device: &self.device,
+            context: &self.context,
+            width,
+            height,
+            screen_origin: None,
+            timestamp: self.frame_timestamp,
+        };
+        if self.frame_analyzer.is_none() {
+            self.frame_analyzer = Some(FrameAnalyzer::new(&self.device)?);
+        }
+        let analyzer = self.frame_analyzer.as_mut().unwrap();
+        analyzer.analyze(&ctx, stats_texture)?;
+        self.frame_stats = analyzer.resolve(&self.context)?;
+        Ok(())
+    }
+
+    /// Take the texture out of `frame`, and recreate the frame pool if the size of the content
+    /// has changed. Shared by all ways of copying frames.
+    ///
+    /// Returns the texture, and whether the size has changed.
+    fn begin_frame(&mut self, frame: &Direct3D11CaptureFrame) -> Result<(ID3D11Texture2D, bool)> {
+        self.last_frame_at = Instant::now();
+        let frame_texture: ID3D11Texture2D = get_dxgi_interface_from_object(&frame.Surface()?)?;
+        self.frame_timestamp = frame.SystemRelativeTime()?.Duration;
+        let content_size = frame.ContentSize()?;
+        let resized = self.content_size.Width != content_size.Width
+            || self.content_size.Height != content_size.Height;
+        if resized {
             self.recreate_frame_pool()?;
-            let new_staging_texture = StagingTexture::new(
-                &self.device,
+            self.content_size = content_size;
+        }
+        Ok((frame_texture, resized))
+    }
+
+    /// Copy `frame` into the staging texture, applying all stages on the way.
+    fn copy_frame(&mut self, frame: Direct3D11CaptureFrame) -> Result<()> {
+        let (frame_texture, resized) = self.begin_frame(&frame)?;
+        let timestamp = self.frame_timestamp;
+
+        if resized || self.staging_texture.is_none() {
+            let format = self.config.output_format.dxgi_format();
+            let (width, height) = self.config.output_size.unwrap_or((
                 self.capture_box.right - self.capture_box.left,
                 self.capture_box.bottom - self.capture_box.top,
-                desc.Format,
-            )?;
+            ));
+            let new_staging_texture = StagingTexture::new(&self.device, width, height, format)?;
+            let previous_size = self
+                .staging_texture
+                .as_ref()
+                .map(|texture| (texture.desc.Width, texture.desc.Height));
+            if previous_size != Some((width, height)) {
+                self.handlers.observers.resized(width, height);
+            }
             self.staging_texture = Some(new_staging_texture);
-            self.content_size = content_size;
+            self.pending_staging_texture = if self.config.pipelined {
+                Some(StagingTexture::new(&self.device, width, height, format)?)
+            } else {
+                None
+            };
+            self.has_pending_frame = false;
+            self.work_texture = None;
+            self.output_texture = None;
+            self.converted_texture = None;
         }
+        self.tracked_window = self.locate_tracked_window();
 
-        let copy_dest = self.staging_texture.as_ref().unwrap().as_resource()?;
+        let format = self.config.output_format.capture_format();
+        let converts_format = self.config.output_format != OutputFormat::Bgra8;
+        let needs_work_texture = !self.stages.is_empty()
+            || self.config.output_size.is_some()
+            || self.config.alpha != AlphaMode::Premultiplied
+            || converts_format
+            // the staging texture cannot be bound as a shader resource
+            || self.config.computes_frame_stats();
+        if needs_work_texture && self.work_texture.is_none() {
+            self.work_texture = Some(create_work_texture(
+                &self.device,
+                self.capture_box.right - self.capture_box.left,
+                self.capture_box.bottom - self.capture_box.top,
+                format,
+            )?);
+        }
+        if let Some((width, height)) = self.config.output_size {
+            if self.output_texture.is_none() {
+                self.output_texture =
+                    Some(create_work_texture(&self.device, width, height, format)?);
+            }
+        }
+        if converts_format && self.converted_texture.is_none() {
+            let staging_desc = &self.staging_texture.as_ref().unwrap().desc;
+            self.converted_texture = Some(create_work_texture(
+                &self.device,
+                staging_desc.Width,
+                staging_desc.Height,
+                self.config.output_format.dxgi_format(),
+            )?);
+        }
+
+        if self.config.gpu_timing && self.gpu_timer.is_none() {
+            self.gpu_timer = Some(GpuTimer::new(&self.device)?);
+        }
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            gpu_timer.begin(&self.context);
+        }
+
+        let staging_resource = self.staging_texture.as_ref().unwrap().as_resource()?;
+        let copy_dest = match &self.work_texture {
+            Some(work_texture) => work_texture.cast()?,
+            None => staging_resource.clone(),
+        };
         let copy_src = frame_texture.cast()?;
         unsafe {
             self.context.CopySubresourceRegion(
@@ -220,9 +1801,127 @@ impl Capture {
             );
         }
 
+        if let Some(work_texture) = &self.work_texture {
+            let ctx = StageContext {
+                device: &self.device,
+                context: &self.context,
+                width: self.capture_box.right - self.capture_box.left,
+                height: self.capture_box.bottom - self.capture_box.top,
+                screen_origin: self.capturable.get_screen_origin().map(|origin| POINT {
+                    x: origin.x + self.config.insets.left as i32,
+                    y: origin.y + self.config.insets.top as i32,
+                }),
+                timestamp,
+            };
+            for stage in self.stages.iter_mut() {
+                stage.process(&ctx, work_texture)?;
+            }
+            let (final_ctx, final_texture) = match (&self.output_texture, self.config.output_size) {
+                (Some(output_texture), Some((width, height))) => {
+                    let output_ctx = StageContext {
+                        width,
+                        height,
+                        ..ctx
+                    };
+                    if self.scaler.is_none() {
+                        // scRGB frames are linear already, so plain bilinear filtering is right
+                        let gamma_space = self.config.gamma_space_scaling || converts_format;
+                        self.scaler = Some(Scaler::new(&output_ctx, gamma_space)?);
+                    }
+                    let scaler = self.scaler.as_ref().unwrap();
+                    match self.config.scale_mode {
+                        ScaleMode::Stretch => {
+                            scaler.scale(&output_ctx, work_texture, output_texture)?
+                        }
+                        ScaleMode::Letterbox { padding } => {
+                            scaler.letterbox(&output_ctx, work_texture, output_texture, padding)?
+                        }
+                    }
+                    (output_ctx, output_texture)
+                }
+                _ => (ctx, work_texture),
+            };
+            // after scaling, which has to work on premultiplied colors to avoid fringes
+            self.alpha_conversion.process(&final_ctx, final_texture)?;
+            let final_texture = match &self.converted_texture {
+                Some(converted_texture) => {
+                    self.format_conversion
+                        .convert(&final_ctx, final_texture, converted_texture)?;
+                    converted_texture
+                }
+                None => final_texture,
+            };
+            if self.config.computes_frame_stats() {
+                if self.frame_analyzer.is_none() {
+                    self.frame_analyzer = Some(FrameAnalyzer::new(&self.device)?);
+                }
+                let analyzer = self.frame_analyzer.as_mut().unwrap();
+                analyzer.analyze(&final_ctx, final_texture)?;
+            }
+            let final_resource: ID3D11Resource = final_texture.cast()?;
+            unsafe {
+                self.context
+                    .CopyResource(Some(&staging_resource), Some(&final_resource));
+            }
+        }
+
+        if let Some(gpu_timer) = self.gpu_timer.as_mut() {
+            gpu_timer.end(&self.context);
+            self.gpu_time = gpu_timer.resolve(&self.context)?;
+        }
+        if let Some(analyzer) = self.frame_analyzer.as_mut() {
+            self.frame_stats = analyzer.resolve(&self.context)?;
+        }
+
+        let staging_desc = &self.staging_texture.as_ref().unwrap().desc;
+        self.handlers.observers.frame(&FrameInfo {
+            timestamp,
+            width: staging_desc.Width,
+            height: staging_desc.Height,
+            process_time: self.last_frame_at.elapsed(),
+            gpu_time: self.gpu_time,
+        });
+
         // TODO queue a fence here? currently we ensure buffer is copied by map-unmap texture outside of this method,
         // which is probably not the best way to do this
 
-        Ok(true)
+        Ok(())
+    }
+}
+
+impl FrameSource for Capture {
+    fn start(&mut self) -> Result<()> {
+        Capture::start(self)
+    }
+
+    fn grab(&mut self) -> crate::Result<GrabStatus> {
+        Capture::grab(self)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        Capture::stop(self)
+    }
+
+    fn add_stage(&mut self, stage: Box<dyn Stage>) {
+        Capture::add_stage(self, stage)
+    }
+
+    fn d3d_device(&self) -> &ID3D11Device {
+        Capture::d3d_device(self)
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        // an open frame pool keeps invoking the handler (and holding on to the device) until it is
+        // closed, regardless of whether anyone is still grabbing frames
+        self.session.close().ok();
+    }
+}
+
+impl AsRawHandle for Capture {
+    /// Same as `frame_event()`.
+    fn as_raw_handle(&self) -> RawHandle {
+        self.handlers.frame_signal.event.0 as RawHandle
     }
 }
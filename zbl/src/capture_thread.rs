@@ -0,0 +1,384 @@
+//! Capturing on a dedicated thread, see `CaptureThread`.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, Sender, TrySendError},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    capture::GrabStatus,
+    config::{CaptureBuilder, CaptureRate, Insets, MemoryCap, OverflowPolicy},
+    event::CaptureEvent,
+    observer::DropReason,
+    ro_initialize_once,
+    util::Rect,
+    Capture, Image,
+};
+
+/// Grab timeout of captures built without one, so that the thread notices `stop()` while no
+/// frames arrive.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Change to the capture requested through a `CaptureThread`.
+enum Command {
+    SetPaused(bool),
+    SetRate(CaptureRate),
+    SetInsets(Insets),
+}
+
+impl Command {
+    fn apply(self, capture: &mut Capture) -> crate::Result<()> {
+        match self {
+            Command::SetPaused(paused) => capture.set_paused(paused),
+            Command::SetRate(rate) => capture.set_rate(rate),
+            Command::SetInsets(insets) => capture.set_insets(insets)?,
+        }
+        Ok(())
+    }
+}
+
+/// Memory held by the frames a `CaptureThread` has handed out, see `CaptureConfig::memory_cap`.
+#[derive(Debug, Default)]
+struct HeldBytes {
+    bytes: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Share of `HeldBytes` taken by a frame, given back once the last clone of the frame is dropped.
+#[derive(Debug)]
+struct Reservation {
+    bytes: u64,
+    held: Arc<HeldBytes>,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        *self.held.bytes.lock().unwrap() -= self.bytes;
+        self.held.released.notify_all();
+    }
+}
+
+/// Enforces a `MemoryCap` on the frames handed out by a `CaptureThread`.
+struct MemoryBudget {
+    cap: MemoryCap,
+    held: Arc<HeldBytes>,
+}
+
+impl MemoryBudget {
+    /// Reserve memory for a frame of `bytes`, waiting for frames to be released if the policy
+    /// says so. Returns the reservation, or `None` if the frame has to be dropped, and whether the
+    /// cap has been reached.
+    fn reserve(&self, bytes: u64, stop: &AtomicBool) -> (Option<Reservation>, bool) {
+        let mut held = self.held.bytes.lock().unwrap();
+        let mut reached = false;
+        while *held > 0 && *held + bytes > self.cap.max_bytes {
+            reached = true;
+            if self.cap.policy == OverflowPolicy::Drop || stop.load(Ordering::SeqCst) {
+                return (None, reached);
+            }
+            held = self
+                .held
+                .released
+                .wait_timeout(held, STOP_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        *held += bytes;
+        let reservation = Reservation {
+            bytes,
+            held: self.held.clone(),
+        };
+        (Some(reservation), reached)
+    }
+}
+
+/// A frame copied off a `CaptureThread`.
+///
+/// Frames carry a private share of the thread's memory cap, so they cannot be built with a struct
+/// literal; use `new()` instead, e.g. to feed consumers frames in tests.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ThreadFrame {
+    pub image: Image,
+    /// See `Frame::timestamp`.
+    pub timestamp: i64,
+    /// See `Frame::tracked_window`.
+    pub tracked_window: Option<Rect>,
+    /// Counts the frame against `CaptureConfig::memory_cap`, if set.
+    _reservation: Option<Arc<Reservation>>,
+}
+
+impl ThreadFrame {
+    /// A frame which does not count against any memory cap.
+    pub fn new(image: Image, timestamp: i64, tracked_window: Option<Rect>) -> Self {
+        Self {
+            image,
+            timestamp,
+            tracked_window,
+            _reservation: None,
+        }
+    }
+}
+
+/// A capture running on a thread of its own, delivering frames over a channel.
+///
+/// `Capture` is not `Send`: it owns a D3D11 immediate context and stages holding GPU resources,
+/// which must stay on the thread that created them. Instead of moving a capture, build it on the
+/// thread that uses it. This does that for the common case of consuming frames elsewhere: the
+/// handle is `Send`, and frames arrive as `Image`s, which are.
+///
+/// Frames are copied to CPU memory on the capture thread. When the consumer falls behind by more
+/// than the queue size, new frames are dropped until it catches up. For services which run for a
+/// long time, `CaptureConfig::memory_cap` additionally bounds the memory of the frames handed out,
+/// including those kept by the consumer.
+///
+/// The capture can be controlled from any thread through the handle (e.g. `pause()`,
+/// `set_rate()`). Commands are queued and applied by the capture thread in order, before its next
+/// grab.
+pub struct CaptureThread {
+    frames: Receiver<ThreadFrame>,
+    commands: Sender<Command>,
+    events: Receiver<CaptureEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<crate::Result<()>>>,
+}
+
+impl CaptureThread {
+    /// Spawn a thread, build a capture on it with the builder returned by `make_builder`, and start
+    /// grabbing. Returns once the capture has started, or with the error that prevented it.
+    ///
+    /// `make_builder` runs on the new thread, so the builder (and e.g. its stages) does not need
+    /// to be `Send`. Captures without a grab timeout get a short one, so that `stop()` does not
+    /// have to wait for the next frame.
+    pub fn spawn<F>(make_builder: F, queue_size: usize) -> crate::Result<Self>
+    where
+        F: FnOnce() -> crate::Result<CaptureBuilder> + Send + 'static,
+    {
+        let (frame_sender, frames) = sync_channel(queue_size.max(1));
+        let (ready_sender, ready) = channel();
+        let (commands, command_receiver) = channel::<Command>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            ro_initialize_once();
+            let started = make_builder().and_then(|mut builder| {
+                let config = builder.config_mut();
+                config.grab_timeout = config.grab_timeout.or(Some(STOP_POLL_INTERVAL));
+                let mut capture = builder.build()?;
+                let events = capture.events();
+                capture.start()?;
+                Ok((capture, events))
+            });
+            let mut capture = match started {
+                Ok((capture, events)) => {
+                    ready_sender.send(Ok(events)).ok();
+                    capture
+                }
+                Err(error) => {
+                    ready_sender.send(Err(error)).ok();
+                    return Ok(());
+                }
+            };
+            let budget = capture.config().memory_cap.map(|cap| MemoryBudget {
+                cap,
+                held: Default::default(),
+            });
+            let mut cap_reached = false;
+            while !thread_stop.load(Ordering::SeqCst) {
+                for command in command_receiver.try_iter() {
+                    command.apply(&mut capture)?;
+                }
+                let (frame, reached) = match capture.grab()? {
+                    GrabStatus::Frame(frame) => {
+                        let bytes = frame.width() as u64 * frame.height() as u64 * 4;
+                        let (reservation, reached) = match &budget {
+                            Some(budget) => budget.reserve(bytes, &thread_stop),
+                            None => (None, false),
+                        };
+                        let admitted = budget.is_none() || reservation.is_some();
+                        let frame = admitted.then(|| ThreadFrame {
+                            image: frame.to_image(),
+                            timestamp: frame.timestamp,
+                            tracked_window: frame.tracked_window,
+                            _reservation: reservation.map(Arc::new),
+                        });
+                        (frame, reached)
+                    }
+                    GrabStatus::Closed => break,
+                    _ => continue,
+                };
+                if reached != cap_reached {
+                    cap_reached = reached;
+                    capture.emit_event(CaptureEvent::MemoryCapChanged { reached });
+                }
+                let frame = match frame {
+                    Some(frame) => frame,
+                    None => {
+                        capture.report_drop(DropReason::MemoryCap);
+                        continue;
+                    }
+                };
+                if let Err(TrySendError::Disconnected(_)) = frame_sender.try_send(frame) {
+                    break;
+                }
+            }
+            capture.stop()?;
+            Ok(())
+        });
+        let events = match ready.recv() {
+            Ok(started) => started?,
+            // nothing is sent only if the thread panicked, which is passed on by joining it
+            Err(_) => {
+                join(thread)?;
+                unreachable!()
+            }
+        };
+        Ok(Self {
+            frames,
+            commands,
+            events,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Frames in the order they were grabbed. The channel disconnects once the capture has
+    /// stopped, e.g. because the target was closed.
+    pub fn frames(&self) -> &Receiver<ThreadFrame> {
+        &self.frames
+    }
+
+    /// Events of the capture, see `Capture::events()`.
+    pub fn events(&self) -> &Receiver<CaptureEvent> {
+        &self.events
+    }
+
+    /// Stop delivering frames until `resume()`, see `Capture::set_paused`.
+    pub fn pause(&self) {
+        self.send(Command::SetPaused(true));
+    }
+
+    pub fn resume(&self) {
+        self.send(Command::SetPaused(false));
+    }
+
+    /// See `Capture::set_rate`.
+    pub fn set_rate(&self, rate: CaptureRate) {
+        self.send(Command::SetRate(rate));
+    }
+
+    /// Change the crop of the target, see `Capture::set_insets`. A failure to apply it ends the
+    /// capture, and is returned by `stop()`.
+    pub fn set_insets(&self, insets: Insets) {
+        self.send(Command::SetInsets(insets));
+    }
+
+    fn send(&self, command: Command) {
+        // commands to a capture which has ended are moot
+        self.commands.send(command).ok();
+    }
+
+    /// Stop capturing and wait for the thread to finish, returning the error which ended the
+    /// capture, if any.
+    pub fn stop(mut self) -> crate::Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => join(thread),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for CaptureThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Wait for `thread`, turning a panic into a panic of the caller.
+fn join(thread: JoinHandle<crate::Result<()>>) -> crate::Result<()> {
+    match thread.join() {
+        Ok(result) => result,
+        Err(panic) => std::panic::resume_unwind(panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_bytes: u64, policy: OverflowPolicy) -> MemoryBudget {
+        MemoryBudget {
+            cap: MemoryCap { max_bytes, policy },
+            held: Default::default(),
+        }
+    }
+
+    fn held(budget: &MemoryBudget) -> u64 {
+        *budget.held.bytes.lock().unwrap()
+    }
+
+    #[test]
+    fn reserves_within_the_cap() {
+        let budget = budget(100, OverflowPolicy::Drop);
+        let stop = AtomicBool::new(false);
+        let (first, reached) = budget.reserve(60, &stop);
+        assert!(first.is_some() && !reached);
+        let (second, reached) = budget.reserve(40, &stop);
+        assert!(second.is_some() && !reached);
+        assert_eq!(held(&budget), 100);
+        drop((first, second));
+        assert_eq!(held(&budget), 0);
+    }
+
+    #[test]
+    fn drops_over_the_cap() {
+        let budget = budget(100, OverflowPolicy::Drop);
+        let stop = AtomicBool::new(false);
+        let (first, _) = budget.reserve(60, &stop);
+        let (second, reached) = budget.reserve(60, &stop);
+        assert!(second.is_none() && reached);
+        assert_eq!(held(&budget), 60);
+        drop(first);
+        let (third, reached) = budget.reserve(60, &stop);
+        assert!(third.is_some() && !reached);
+    }
+
+    #[test]
+    fn admits_a_frame_larger_than_the_cap_while_none_is_held() {
+        let budget = budget(100, OverflowPolicy::Drop);
+        let (reservation, reached) = budget.reserve(500, &AtomicBool::new(false));
+        assert!(reservation.is_some() && !reached);
+    }
+
+    #[test]
+    fn blocks_until_frames_are_released() {
+        let budget = budget(100, OverflowPolicy::Block);
+        let stop = AtomicBool::new(false);
+        let (first, _) = budget.reserve(60, &stop);
+        let release = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(first);
+        });
+        let (second, reached) = budget.reserve(60, &stop);
+        assert!(second.is_some() && reached);
+        assert_eq!(held(&budget), 60);
+        release.join().unwrap();
+    }
+
+    #[test]
+    fn stops_blocking_when_stopped() {
+        let budget = budget(100, OverflowPolicy::Block);
+        let (_first, _) = budget.reserve(60, &AtomicBool::new(false));
+        let (second, reached) = budget.reserve(60, &AtomicBool::new(true));
+        assert!(second.is_none() && reached);
+    }
+}
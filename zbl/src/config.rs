@@ -0,0 +1,954 @@
+use std::{sync::Arc, time::Duration};
+
+use windows::Win32::{
+    Foundation::HWND,
+    Graphics::{
+        Direct3D11::{ID3D11Device, D3D11_BOX},
+        Dxgi::Common::{
+            DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+            DXGI_FORMAT_R16G16B16A16_FLOAT,
+        },
+    },
+};
+
+use crate::{
+    frame_callback::{ArrivedFrame, FrameArrivedCallback},
+    observer::Observer,
+    pipeline::Stage,
+    power::PowerState,
+    Capturable, Capture, Error,
+};
+
+/// How often frames should be delivered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CaptureRate {
+    /// Deliver every frame produced by the system.
+    #[default]
+    Unlimited,
+    /// Deliver at most one frame per refresh of the monitor showing the target.
+    Display,
+    /// Deliver at most one frame per `n` refreshes of the monitor showing the target, e.g. `2` for
+    /// half the display rate.
+    DisplayDivisor(u32),
+    /// Deliver at most the given number of frames per second.
+    Fps(f64),
+}
+
+impl CaptureRate {
+    /// Minimal time between two delivered frames, given the refresh rate of the source monitor.
+    ///
+    /// If the refresh rate is unknown, 60 Hz is assumed.
+    pub fn min_frame_interval(&self, refresh_rate: Option<u32>) -> Duration {
+        let refresh_rate = refresh_rate.unwrap_or(60).max(1) as f64;
+        match *self {
+            CaptureRate::Unlimited => Duration::ZERO,
+            CaptureRate::Display => Duration::from_secs_f64(1.0 / refresh_rate),
+            CaptureRate::DisplayDivisor(n) => {
+                Duration::from_secs_f64(n.max(1) as f64 / refresh_rate)
+            }
+            CaptureRate::Fps(fps) if fps > 0.0 => Duration::from_secs_f64(1.0 / fps),
+            CaptureRate::Fps(_) => Duration::ZERO,
+        }
+    }
+}
+
+/// How the alpha channel of frames should be delivered.
+///
+/// Windows.Graphics.Capture produces premultiplied alpha, which for most windows is either opaque
+/// or meaningless (e.g. windows drawn with GDI leave garbage in it).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AlphaMode {
+    /// Deliver alpha as captured, with premultiplied colors.
+    #[default]
+    Premultiplied,
+    /// Set alpha to fully opaque, keeping colors as they are.
+    Opaque,
+    /// Divide colors by alpha, for consumers expecting straight (non-premultiplied) alpha.
+    Straight,
+}
+
+/// Pixel format of the frames returned by a capture.
+///
+/// Formats other than `Bgra8` capture in 16-bit floating point (scRGB, i.e. linear Rec. 709 with
+/// 1.0 being SDR white), so that HDR and wide-gamut content is not clipped before it reaches the
+/// stages, and pack the result into 32 bits per pixel as the last step before readback.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OutputFormat {
+    /// `DXGI_FORMAT_B8G8R8A8_UNORM`, sRGB-encoded. HDR content is tone mapped by the system.
+    #[default]
+    Bgra8,
+    /// `DXGI_FORMAT_R10G10B10A2_UNORM`, sRGB-encoded Rec. 709. Brighter than SDR white is clipped,
+    /// but gradients keep 10 bits of precision.
+    Rgb10A2,
+    /// `DXGI_FORMAT_R10G10B10A2_UNORM`, HDR10, i.e. PQ-encoded (SMPTE ST 2084) Rec. 2020 up to
+    /// 10000 nits.
+    Hdr10,
+}
+
+impl OutputFormat {
+    /// Format of the returned frames.
+    pub fn dxgi_format(self) -> DXGI_FORMAT {
+        match self {
+            OutputFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+            OutputFormat::Rgb10A2 | OutputFormat::Hdr10 => DXGI_FORMAT_R10G10B10A2_UNORM,
+        }
+    }
+
+    /// Format frames are captured and processed in.
+    pub(crate) fn capture_format(self) -> DXGI_FORMAT {
+        match self {
+            OutputFormat::Bgra8 => DXGI_FORMAT_B8G8R8A8_UNORM,
+            OutputFormat::Rgb10A2 | OutputFormat::Hdr10 => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        }
+    }
+}
+
+/// Pixels to crop from each side of the target's client box (see `Capturable::get_client_box`),
+/// e.g. to cut off an in-app toolbar. For windows the client box already excludes the title bar
+/// and borders.
+///
+/// Cropping happens as part of the copy out of the frame pool, so it costs nothing. Insets larger
+/// than the target leave a single pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Insets {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl Insets {
+    /// The same inset on all sides.
+    pub fn uniform(inset: u32) -> Self {
+        Self {
+            left: inset,
+            top: inset,
+            right: inset,
+            bottom: inset,
+        }
+    }
+
+    /// Shrink `client_box` by these insets.
+    pub fn apply(&self, client_box: D3D11_BOX) -> D3D11_BOX {
+        let left = (client_box.left + self.left).min(client_box.right.saturating_sub(1));
+        let top = (client_box.top + self.top).min(client_box.bottom.saturating_sub(1));
+        D3D11_BOX {
+            left,
+            top,
+            right: client_box.right.saturating_sub(self.right).max(left + 1),
+            bottom: client_box.bottom.saturating_sub(self.bottom).max(top + 1),
+            ..client_box
+        }
+    }
+}
+
+/// How frames are fitted into `CaptureConfig::output_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ScaleMode {
+    /// Stretch frames over the whole output, ignoring their aspect ratio.
+    #[default]
+    Stretch,
+    /// Scale frames to fit into the output preserving their aspect ratio, and fill the bars at
+    /// the sides (or top and bottom) with `padding`, as straight RGBA.
+    Letterbox { padding: [f32; 4] },
+}
+
+/// Exponential backoff between the attempts of an operation, see `MapRetry` and
+/// `TransientRetry`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Backoff {
+    /// Sleep before the second attempt. Every following sleep is twice as long as the previous
+    /// one, up to `max_delay`.
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub delay: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "secs"))]
+    pub max_delay: Duration,
+    /// Number of attempts, including the first one.
+    pub attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::TRANSIENT
+    }
+}
+
+impl Backoff {
+    /// Suited to polling the GPU: 32 attempts, starting 100µs and at most 2ms apart.
+    pub const POLL: Self = Self {
+        delay: Duration::from_micros(100),
+        max_delay: Duration::from_millis(2),
+        attempts: 32,
+    };
+
+    /// Suited to retrying Windows calls: 5 attempts, starting 10ms and at most 250ms apart. This
+    /// is the default, also for fields left out when deserializing.
+    pub const TRANSIENT: Self = Self {
+        delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(250),
+        attempts: 5,
+    };
+
+    /// Delay before the `attempt`-th retry (starting at 0).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay)
+    }
+
+    fn validate(&self, name: &str) -> crate::Result<()> {
+        if self.max_delay < self.delay {
+            return Err(Error::InvalidConfig(format!(
+                "{}: max_delay must not be less than delay",
+                name
+            )));
+        }
+        if self.attempts == 0 {
+            return Err(Error::InvalidConfig(format!(
+                "{}: attempts must be at least 1",
+                name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// How to wait for the GPU when reading a frame back.
+///
+/// Instead of blocking inside the driver until the copy has finished, the staging texture is
+/// polled, sleeping between attempts, so that the thread can be scheduled to do other work. Once
+/// all attempts have failed, the capture falls back to waiting inside the driver.
+/// `Backoff::POLL` is a good starting point.
+pub type MapRetry = Backoff;
+
+/// How to retry Windows.Graphics.Capture calls which fail transiently, e.g. creating the capture
+/// item of a window which is just being shown.
+///
+/// Only errors which may go away by themselves are retried, such as `E_PENDING` or
+/// `DXGI_ERROR_WAS_STILL_DRAWING`; e.g. access being denied to an elevated target, an invalid
+/// handle, or the generic `E_FAIL`, are reported right away. Retried calls sleep in between
+/// attempts, on the thread making them. Once all attempts have failed, the last error is reported
+/// as `Error::RetriesExhausted`.
+///
+/// Frames are not retried, as taking them out of the frame pool happens on the thread delivering
+/// them: one which cannot be taken out is reported to observers (see `Observer::on_error`), and
+/// stays in the frame pool until the next frame arrives.
+pub type TransientRetry = Backoff;
+
+/// What a `CaptureThread` does with a frame which does not fit under `MemoryCap::max_bytes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OverflowPolicy {
+    /// Drop the frame, reported as `DropReason::MemoryCap`.
+    #[default]
+    Drop,
+    /// Stop grabbing until the consumer has released enough frames. Frames arriving meanwhile are
+    /// dropped by the frame pool (`DropReason::QueueFull`), so the frame delivered afterwards is a
+    /// recent one.
+    Block,
+}
+
+/// Hard limit on the memory held by frames which a `CaptureThread` has copied but the consumer
+/// has not dropped yet, see `CaptureConfig::memory_cap`.
+///
+/// Frames count from the moment they are queued until the last clone of them is dropped, so
+/// frames kept around by the consumer count as well. A single frame larger than the cap is let
+/// through when no other frames are held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct MemoryCap {
+    pub max_bytes: u64,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for MemoryCap {
+    fn default() -> Self {
+        Self {
+            max_bytes: 256 << 20,
+            policy: OverflowPolicy::Drop,
+        }
+    }
+}
+
+/// Detection of targets which have stopped rendering properly, see `CaptureEvent::BlackChanged`
+/// and `CaptureEvent::FrozenChanged`.
+///
+/// Both are judged by the frames returned by `Capture::grab()`, so they only work while frames
+/// keep arriving. Targets which stop producing frames altogether are caught by the watchdog.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct HangDetection {
+    /// Frames are black if the luminance (0-255) of all their pixels is at most this level.
+    pub black_level: u8,
+    /// Report black frames once they have lasted this long. Detecting them computes frame
+    /// statistics on the GPU, as `CaptureConfig::frame_stats` does.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "secs::option", skip_serializing_if = "Option::is_none")
+    )]
+    pub black_for: Option<Duration>,
+    /// Report frozen content once frames have been identical for this long. This hashes every
+    /// frame on the CPU.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "secs::option", skip_serializing_if = "Option::is_none")
+    )]
+    pub frozen_for: Option<Duration>,
+}
+
+impl Default for HangDetection {
+    fn default() -> Self {
+        Self {
+            black_level: 16,
+            black_for: Some(Duration::from_secs(2)),
+            frozen_for: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+/// Capture rates to switch to when the system runs on battery.
+///
+/// A rate of `None` pauses the capture: frames are dropped as soon as they arrive, and `grab()`
+/// waits (or times out) as if the target was not producing any.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct PowerThrottle {
+    /// Rate while running on battery, replacing `CaptureConfig::rate`.
+    pub on_battery: Option<CaptureRate>,
+    /// Rate while battery saver is on.
+    pub battery_saver: Option<CaptureRate>,
+}
+
+impl Default for PowerThrottle {
+    fn default() -> Self {
+        Self {
+            on_battery: Some(CaptureRate::Fps(10.0)),
+            battery_saver: None,
+        }
+    }
+}
+
+impl PowerThrottle {
+    /// Rate to capture at in the given power state, if any, given the rate used on AC power.
+    pub fn rate(&self, state: PowerState, rate: CaptureRate) -> Option<CaptureRate> {
+        match state {
+            PowerState::AcPower => Some(rate),
+            PowerState::Battery => self.on_battery,
+            PowerState::BatterySaver => self.battery_saver,
+        }
+    }
+}
+
+/// Options controlling a `Capture`.
+///
+/// With the `serde` feature, durations are (de)serialized as seconds, and missing fields take
+/// their default values.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct CaptureConfig {
+    /// Whether the mouse cursor should be drawn into frames.
+    pub capture_cursor: bool,
+    /// Only deliver every n-th frame produced by the system. Skipped frames are dropped as soon as
+    /// they arrive, before being queued or copied. `0` and `1` mean every frame.
+    pub every_nth_frame: u32,
+    /// Pacing of delivered frames. Frames arriving too early are dropped as soon as they arrive.
+    pub rate: CaptureRate,
+    /// If set, when the target is closed the capture waits up to this long for a replacement (see
+    /// `Capturable::find_replacement`) and re-establishes the session instead of terminating.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "secs::option", skip_serializing_if = "Option::is_none")
+    )]
+    pub auto_reattach: Option<Duration>,
+    /// If set, the frame pool and session are recreated when no frames have arrived for this long
+    /// while the target is visible.
+    ///
+    /// Windows.Graphics.Capture only delivers frames when the content changes, so this should be
+    /// well above the longest period the target is expected to stay static.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "secs::option", skip_serializing_if = "Option::is_none")
+    )]
+    pub watchdog: Option<Duration>,
+    /// If set, `Capture::grab()` gives up waiting for a frame after this long and returns
+    /// `GrabStatus::Timeout`. Otherwise it waits indefinitely.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "secs::option", skip_serializing_if = "Option::is_none")
+    )]
+    pub grab_timeout: Option<Duration>,
+    /// If set, frames are scaled to this size (width, height) on the GPU after all stages have
+    /// been applied, see `scale_mode`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub output_size: Option<(u32, u32)>,
+    /// How frames are fitted into `output_size`. By default they are stretched.
+    pub scale_mode: ScaleMode,
+    /// Whether to filter directly on sRGB values when scaling to `output_size`. By default
+    /// filtering is done in linear light, which keeps thin text from darkening in downscaled
+    /// frames, but takes more texture reads per pixel.
+    pub gamma_space_scaling: bool,
+    /// Pixels cropped from each side of the target's client box.
+    pub insets: Insets,
+    /// Whether to measure the GPU time spent on copying and processing every frame (see
+    /// `Capture::gpu_time`). This makes `grab()` wait for the GPU to finish.
+    pub gpu_timing: bool,
+    /// Whether to compute a luminance histogram and the average color of every frame on the GPU
+    /// (see `Frame::stats`). Like `gpu_timing`, this makes `grab()` wait for the GPU to finish.
+    pub frame_stats: bool,
+    /// Whether `Capture::grab()` should return the frame received by the previous call while the
+    /// current one is still being copied, so that reading the frame back does not wait for the
+    /// GPU.
+    ///
+    /// When frames arrive faster than they are grabbed, this adds one frame of latency. When no
+    /// newer frame is queued, the pending frame is returned right away instead of waiting.
+    pub pipelined: bool,
+    /// If set, frames are read back without blocking inside the driver, see `MapRetry`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub map_retry: Option<MapRetry>,
+    /// If set, calls which may fail transiently are retried, see `TransientRetry`.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub transient_retry: Option<TransientRetry>,
+    /// Whether to keep a copy of the last grabbed frame, so that `Frame::changed_tiles` can tell
+    /// what has changed since. This costs a copy of every frame in CPU memory.
+    pub track_changes: bool,
+    /// How the alpha channel is delivered. Conversions are done on the GPU after all stages and
+    /// scaling.
+    pub alpha: AlphaMode,
+    /// Pixel format of the returned frames.
+    pub output_format: OutputFormat,
+    /// If set, the capture rate is lowered (or the capture is paused) while the system runs on
+    /// battery. The power status is polled about once a second while grabbing.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub power_throttle: Option<PowerThrottle>,
+    /// Whether to pause the capture while the target is occluded (see `Capturable::is_occluded`).
+    /// Occlusion is polled a few times a second while grabbing.
+    pub pause_when_occluded: bool,
+    /// Whether to report `CaptureEvent::VirtualDesktopChanged` when a target window moves between
+    /// virtual desktops. The desktop is polled about once a second while grabbing.
+    pub track_virtual_desktop: bool,
+    /// Whether to check that the target still exists (see `Capturable::is_alive`) before every
+    /// grab, so that a destroyed window is reported as `GrabStatus::Closed` right away instead of
+    /// timing out until its close notification arrives.
+    pub revalidate_target: bool,
+    /// Whether to record global mouse and keyboard input with low-level hooks, and attach it to
+    /// the frames it preceded (see `Frame::input_events`). Keys are recorded system-wide, so only
+    /// enable this where users expect it.
+    pub annotate_input: bool,
+    /// If set, events are emitted when frames stay black or identical for a while, e.g. because
+    /// the target hangs or has lost its renderer (see `HangDetection`).
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub hang_detection: Option<HangDetection>,
+    /// Raw `HWND` of a window whose position is attached to every frame (see
+    /// `Frame::tracked_window`), e.g. to crop it out of a monitor capture.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub track_window: Option<isize>,
+    /// If set, the memory held by frames delivered through a `CaptureThread` is capped, and
+    /// `CaptureEvent::MemoryCapChanged` is emitted when the cap is reached. Without it, only the
+    /// number of queued frames is limited, which does not bound frames kept by the consumer.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub memory_cap: Option<MemoryCap>,
+}
+
+impl CaptureConfig {
+    /// Check that the options make sense together. This is done by `Capture::with_config`, but
+    /// can be useful to report problems in e.g. a config file early.
+    pub fn validate(&self) -> crate::Result<()> {
+        let invalid = |message: &str| Err(Error::InvalidConfig(message.to_string()));
+        let check_rate = |name: &str, rate: CaptureRate| match rate {
+            CaptureRate::Fps(fps) if !fps.is_finite() || fps < 0.0 => {
+                invalid(&format!("{}: fps must be a non-negative number", name))
+            }
+            CaptureRate::DisplayDivisor(0) => {
+                invalid(&format!("{}: display divisor must be at least 1", name))
+            }
+            _ => Ok(()),
+        };
+        check_rate("rate", self.rate)?;
+        if let Some(throttle) = self.power_throttle {
+            for (name, rate) in [
+                ("power_throttle.on_battery", throttle.on_battery),
+                ("power_throttle.battery_saver", throttle.battery_saver),
+            ] {
+                if let Some(rate) = rate {
+                    check_rate(name, rate)?;
+                }
+            }
+        }
+        if let Some((width, height)) = self.output_size {
+            if width == 0 || height == 0 {
+                return invalid("output_size: width and height must be positive");
+            }
+        }
+        if let ScaleMode::Letterbox { padding } = self.scale_mode {
+            if padding.iter().any(|c| !(0.0..=1.0).contains(c)) {
+                return invalid("scale_mode: padding channels must be between 0 and 1");
+            }
+        }
+        if let Some(map_retry) = self.map_retry {
+            map_retry.validate("map_retry")?;
+        }
+        if let Some(transient_retry) = self.transient_retry {
+            transient_retry.validate("transient_retry")?;
+        }
+        if let Some(memory_cap) = self.memory_cap {
+            if memory_cap.max_bytes == 0 {
+                return invalid("memory_cap: max_bytes must be positive");
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether frame statistics have to be computed, for `Frame::stats` or for detecting black
+    /// frames.
+    pub(crate) fn computes_frame_stats(&self) -> bool {
+        self.frame_stats
+            || matches!(self.hang_detection, Some(detection) if detection.black_for.is_some())
+    }
+}
+
+/// Durations as (fractional) seconds, which read better in config files than `{ secs, nanos }`.
+#[cfg(feature = "serde")]
+pub(crate) mod secs {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use std::time::Duration;
+
+        use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            duration: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match duration {
+                Some(duration) => serializer.serialize_some(&duration.as_secs_f64()),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            Option::<f64>::deserialize(deserializer)?
+                .map(|secs| Duration::try_from_secs_f64(secs).map_err(D::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+/// Builder for `Capture`.
+pub struct CaptureBuilder {
+    capturable: Box<dyn Capturable>,
+    config: CaptureConfig,
+    stages: Vec<Box<dyn Stage>>,
+    observers: Vec<Arc<dyn Observer>>,
+    frame_arrived_callback: Option<FrameArrivedCallback>,
+    device: Option<ID3D11Device>,
+}
+
+impl CaptureBuilder {
+    pub fn new(capturable: Box<dyn Capturable>) -> Self {
+        Self {
+            capturable,
+            config: Default::default(),
+            stages: Vec::new(),
+            observers: Vec::new(),
+            frame_arrived_callback: None,
+            device: None,
+        }
+    }
+
+    /// Replace all options at once.
+    pub fn config(mut self, config: CaptureConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn capture_cursor(mut self, capture_cursor: bool) -> Self {
+        self.config.capture_cursor = capture_cursor;
+        self
+    }
+
+    /// Only deliver every `n`-th frame, dropping the rest as early as possible.
+    pub fn capture_every_n_frames(mut self, n: u32) -> Self {
+        self.config.every_nth_frame = n;
+        self
+    }
+
+    /// Limit the rate at which frames are delivered.
+    pub fn rate(mut self, rate: CaptureRate) -> Self {
+        self.config.rate = rate;
+        self
+    }
+
+    /// Wait up to `timeout` for the target to reappear when it gets closed.
+    pub fn auto_reattach(mut self, timeout: Duration) -> Self {
+        self.config.auto_reattach = Some(timeout);
+        self
+    }
+
+    /// Recreate the session when no frames have arrived for `timeout` while the target is visible.
+    pub fn watchdog(mut self, timeout: Duration) -> Self {
+        self.config.watchdog = Some(timeout);
+        self
+    }
+
+    /// Make `Capture::grab()` return `GrabStatus::Timeout` if no frame has arrived for `timeout`.
+    pub fn grab_timeout(mut self, timeout: Duration) -> Self {
+        self.config.grab_timeout = Some(timeout);
+        self
+    }
+
+    /// Scale frames to `width` x `height` on the GPU before they are read back.
+    pub fn output_size(mut self, width: u32, height: u32) -> Self {
+        self.config.output_size = Some((width, height));
+        self
+    }
+
+    /// Crop the target's client box, see `Insets`.
+    pub fn insets(mut self, insets: Insets) -> Self {
+        self.config.insets = insets;
+        self
+    }
+
+    /// Preserve the aspect ratio when scaling to `output_size`, see `ScaleMode`.
+    pub fn scale_mode(mut self, scale_mode: ScaleMode) -> Self {
+        self.config.scale_mode = scale_mode;
+        self
+    }
+
+    /// Filter in sRGB space when scaling, see `CaptureConfig::gamma_space_scaling`.
+    pub fn gamma_space_scaling(mut self, gamma_space_scaling: bool) -> Self {
+        self.config.gamma_space_scaling = gamma_space_scaling;
+        self
+    }
+
+    /// Measure the GPU time spent on every frame.
+    pub fn gpu_timing(mut self, gpu_timing: bool) -> Self {
+        self.config.gpu_timing = gpu_timing;
+        self
+    }
+
+    /// Compute a histogram and the average color of every frame, see `CaptureConfig::frame_stats`.
+    pub fn frame_stats(mut self, frame_stats: bool) -> Self {
+        self.config.frame_stats = frame_stats;
+        self
+    }
+
+    /// Report frames which stay black or identical, see `HangDetection`.
+    pub fn hang_detection(mut self, hang_detection: HangDetection) -> Self {
+        self.config.hang_detection = Some(hang_detection);
+        self
+    }
+
+    /// Return frames one grab late to avoid waiting for the GPU, see `CaptureConfig::pipelined`.
+    pub fn pipelined(mut self, pipelined: bool) -> Self {
+        self.config.pipelined = pipelined;
+        self
+    }
+
+    /// Poll for the frame copy to finish instead of blocking inside the driver.
+    pub fn map_retry(mut self, map_retry: MapRetry) -> Self {
+        self.config.map_retry = Some(map_retry);
+        self
+    }
+
+    /// Keep the last grabbed frame to compare against, see `Frame::changed_tiles`.
+    pub fn track_changes(mut self, track_changes: bool) -> Self {
+        self.config.track_changes = track_changes;
+        self
+    }
+
+    /// Convert the alpha channel of frames, see `AlphaMode`.
+    pub fn alpha(mut self, alpha: AlphaMode) -> Self {
+        self.config.alpha = alpha;
+        self
+    }
+
+    /// Retry calls which may fail transiently, see `TransientRetry`.
+    pub fn transient_retry(mut self, transient_retry: TransientRetry) -> Self {
+        self.config.transient_retry = Some(transient_retry);
+        self
+    }
+
+    /// Cap the memory held by frames delivered through a `CaptureThread`, see `MemoryCap`.
+    pub fn memory_cap(mut self, memory_cap: MemoryCap) -> Self {
+        self.config.memory_cap = Some(memory_cap);
+        self
+    }
+
+    /// Return frames in another pixel format, e.g. 10-bit for HDR content, see `OutputFormat`.
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.config.output_format = output_format;
+        self
+    }
+
+    /// Lower the capture rate while running on battery, see `PowerThrottle`.
+    pub fn power_throttle(mut self, power_throttle: PowerThrottle) -> Self {
+        self.config.power_throttle = Some(power_throttle);
+        self
+    }
+
+    /// Pause the capture while the target is not visible to the user.
+    pub fn pause_when_occluded(mut self, pause_when_occluded: bool) -> Self {
+        self.config.pause_when_occluded = pause_when_occluded;
+        self
+    }
+
+    /// Report when the target window moves between virtual desktops.
+    pub fn track_virtual_desktop(mut self, track_virtual_desktop: bool) -> Self {
+        self.config.track_virtual_desktop = track_virtual_desktop;
+        self
+    }
+
+    /// Check that the target still exists before every grab.
+    pub fn revalidate_target(mut self, revalidate_target: bool) -> Self {
+        self.config.revalidate_target = revalidate_target;
+        self
+    }
+
+    /// Attach the position of `window` within the captured area to every frame.
+    pub fn track_window(mut self, window: HWND) -> Self {
+        self.config.track_window = Some(window.0);
+        self
+    }
+
+    /// Attach the mouse and keyboard events since the previous frame to every frame.
+    pub fn annotate_input(mut self, annotate_input: bool) -> Self {
+        self.config.annotate_input = annotate_input;
+        self
+    }
+
+    /// Append a GPU processing stage. Stages are applied in the order they were added.
+    pub fn stage(mut self, stage: Box<dyn Stage>) -> Self {
+        self.stages.push(stage);
+        self
+    }
+
+    /// Register an observer, see `Capture::add_observer`.
+    pub fn observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Receive frames as soon as they arrive, see `Capture::set_frame_arrived_callback`.
+    pub fn on_frame_arrived(
+        mut self,
+        callback: impl FnMut(&ArrivedFrame) -> bool + Send + 'static,
+    ) -> Self {
+        self.frame_arrived_callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Capture on `device` instead of creating a new device, e.g. to share one device between many
+    /// captures. All captures on a device must be grabbed from the same thread.
+    pub fn device(mut self, device: ID3D11Device) -> Self {
+        self.device = Some(device);
+        self
+    }
+
+    pub(crate) fn config_mut(&mut self) -> &mut CaptureConfig {
+        &mut self.config
+    }
+
+    pub fn build(self) -> crate::Result<Capture> {
+        let mut capture = Capture::with_device(self.capturable, self.config, self.device)?;
+        for stage in self.stages {
+            capture.add_stage(stage);
+        }
+        for observer in self.observers {
+            capture.add_observer(observer);
+        }
+        if let Some(callback) = self.frame_arrived_callback {
+            capture.set_frame_arrived_callback(Some(callback))?;
+        }
+        Ok(capture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_box(left: u32, top: u32, right: u32, bottom: u32) -> D3D11_BOX {
+        D3D11_BOX {
+            left,
+            top,
+            front: 0,
+            right,
+            bottom,
+            back: 1,
+        }
+    }
+
+    fn edges(b: D3D11_BOX) -> (u32, u32, u32, u32) {
+        (b.left, b.top, b.right, b.bottom)
+    }
+
+    #[test]
+    fn insets_shrink_the_client_box() {
+        let insets = Insets {
+            left: 1,
+            top: 2,
+            right: 3,
+            bottom: 4,
+        };
+        let applied = insets.apply(client_box(10, 20, 110, 220));
+        assert_eq!(edges(applied), (11, 22, 107, 216));
+        assert_eq!((applied.front, applied.back), (0, 1));
+        assert_eq!(
+            edges(Insets::default().apply(client_box(0, 0, 5, 5))),
+            (0, 0, 5, 5)
+        );
+    }
+
+    #[test]
+    fn insets_keep_at_least_one_pixel() {
+        let applied = Insets::uniform(100).apply(client_box(10, 20, 50, 60));
+        assert_eq!(edges(applied), (49, 59, 50, 60));
+        let applied = Insets::uniform(100).apply(client_box(0, 0, 0, 0));
+        assert_eq!(edges(applied), (0, 0, 1, 1));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let backoff = Backoff {
+            delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            attempts: 5,
+        };
+        let delays: Vec<_> = (0..5).map(|attempt| backoff.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            [10, 20, 40, 50, 50].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let backoff = Backoff {
+            delay: Duration::MAX,
+            max_delay: Duration::MAX,
+            attempts: 1,
+        };
+        assert_eq!(backoff.delay(40), Duration::MAX);
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(CaptureConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_backoff_is_rejected() {
+        let backoff = Backoff {
+            delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(1),
+            attempts: 1,
+        };
+        let config = CaptureConfig {
+            map_retry: Some(backoff),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(Error::InvalidConfig(message)) if message.starts_with("map_retry")
+        ));
+        let config = CaptureConfig {
+            transient_retry: Some(Backoff {
+                attempts: 0,
+                ..Backoff::TRANSIENT
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(Error::InvalidConfig(message)) if message.starts_with("transient_retry")
+        ));
+    }
+
+    #[test]
+    fn invalid_rates_are_rejected() {
+        for rate in [
+            CaptureRate::Fps(-1.0),
+            CaptureRate::Fps(f64::NAN),
+            CaptureRate::DisplayDivisor(0),
+        ] {
+            let config = CaptureConfig {
+                rate,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{:?}", rate);
+        }
+    }
+
+    #[test]
+    fn empty_output_size_is_rejected() {
+        let config = CaptureConfig {
+            output_size: Some((0, 720)),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! Moving frames to a D3D11 device on another GPU.
+//!
+//! Textures can only be used on the device that created them. On machines with several GPUs (e.g.
+//! Optimus laptops), the device a consumer renders with may live on a different adapter than the
+//! capture's, and passing textures between them fails with interop errors. `CrossAdapterCopy`
+//! picks a way to get frames across: devices on the same adapter share a texture, synchronized
+//! with a keyed mutex, and devices on different adapters go through system memory.
+
+use windows::{
+    core::{Interface, Result},
+    Win32::{
+        Foundation::LUID,
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D,
+                D3D11_BIND_RENDER_TARGET, D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_FLAG,
+                D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX, D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+            },
+            Dxgi::{IDXGIDevice, IDXGIKeyedMutex, IDXGIResource},
+        },
+        System::Threading::INFINITE,
+    },
+};
+
+use crate::{pipeline::create_work_texture, staging_texture::StagingTexture};
+
+/// Identifier of the adapter (GPU) `device` was created on.
+pub fn adapter_luid(device: &ID3D11Device) -> Result<LUID> {
+    let dxgi_device: IDXGIDevice = device.cast()?;
+    unsafe { Ok(dxgi_device.GetAdapter()?.GetDesc()?.AdapterLuid) }
+}
+
+/// How `CrossAdapterCopy` moves textures between devices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyPath {
+    /// Both devices are on the same adapter and share a texture. This is a GPU copy.
+    SharedTexture,
+    /// The devices are on different adapters, so textures are read back to system memory and
+    /// uploaded again. This stalls the source device on every copy.
+    CpuBounce,
+}
+
+fn immediate_context(device: &ID3D11Device) -> ID3D11DeviceContext {
+    let mut context = None;
+    unsafe { device.GetImmediateContext(&mut context) };
+    context.expect("failed to get immediate context")
+}
+
+fn texture_desc(texture: &ID3D11Texture2D) -> D3D11_TEXTURE2D_DESC {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { texture.GetDesc(&mut desc) };
+    desc
+}
+
+fn same_size(a: &D3D11_TEXTURE2D_DESC, b: &D3D11_TEXTURE2D_DESC) -> bool {
+    a.Width == b.Width && a.Height == b.Height && a.Format == b.Format
+}
+
+/// Key of the keyed mutex of the shared texture while the source device may write to it.
+const SOURCE_KEY: u64 = 0;
+/// Key of the keyed mutex of the shared texture while the target device may read it.
+const TARGET_KEY: u64 = 1;
+
+/// A texture shared between the source and the target device.
+struct SharedTexture {
+    source: ID3D11Texture2D,
+    source_mutex: IDXGIKeyedMutex,
+    target: ID3D11Texture2D,
+    target_mutex: IDXGIKeyedMutex,
+    /// Whether the target device holds the mutex, i.e. the texture has been handed out.
+    target_holds: bool,
+}
+
+impl SharedTexture {
+    /// Give the texture back to the source device.
+    fn release_target(&mut self) -> Result<()> {
+        if std::mem::take(&mut self.target_holds) {
+            unsafe { self.target_mutex.ReleaseSync(SOURCE_KEY)? };
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SharedTexture {
+    fn drop(&mut self) {
+        self.release_target().ok();
+    }
+}
+
+/// Copies textures from one device to another, possibly on a different adapter.
+///
+/// The returned textures are owned by this object and overwritten by the next copy, so consumers
+/// should copy them into their own resources (or use them right away). A shared texture is held
+/// by the target device until the next copy.
+///
+/// If sharing fails (e.g. WARP devices cannot open shared resources), the copy falls back to
+/// going through system memory, see `path()`.
+pub struct CrossAdapterCopy {
+    source_device: ID3D11Device,
+    source_context: ID3D11DeviceContext,
+    target_device: ID3D11Device,
+    target_context: ID3D11DeviceContext,
+    path: CopyPath,
+    shared: Option<SharedTexture>,
+    staging: Option<StagingTexture>,
+    upload: Option<ID3D11Texture2D>,
+}
+
+impl CrossAdapterCopy {
+    /// Prepare copying textures created on `source` (e.g. `Capture::d3d_device()`) to `target`.
+    pub fn new(source: &ID3D11Device, target: &ID3D11Device) -> Result<Self> {
+        let path = if adapter_luid(source)? == adapter_luid(target)? {
+            CopyPath::SharedTexture
+        } else {
+            CopyPath::CpuBounce
+        };
+        Ok(Self {
+            source_device: source.clone(),
+            source_context: immediate_context(source),
+            target_device: target.clone(),
+            target_context: immediate_context(target),
+            path,
+            shared: None,
+            staging: None,
+            upload: None,
+        })
+    }
+
+    /// The way textures are copied.
+    pub fn path(&self) -> CopyPath {
+        self.path
+    }
+
+    /// Copy `texture` (created on the source device) to the target device.
+    pub fn copy(&mut self, texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        if self.path == CopyPath::SharedTexture {
+            match self.copy_shared(texture) {
+                Ok(copy) => return Ok(copy),
+                Err(_) => {
+                    self.path = CopyPath::CpuBounce;
+                    self.shared = None;
+                }
+            }
+        }
+        self.copy_through_cpu(texture)
+    }
+
+    fn copy_shared(&mut self, texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        let desc = texture_desc(texture);
+        let outdated = match &self.shared {
+            Some(shared) => !same_size(&texture_desc(&shared.source), &desc),
+            None => true,
+        };
+        if outdated {
+            self.shared = None;
+            let shared_desc = D3D11_TEXTURE2D_DESC {
+                BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+                MiscFlags: D3D11_RESOURCE_MISC_SHARED_KEYEDMUTEX,
+                Usage: D3D11_USAGE_DEFAULT,
+                CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+                MipLevels: 1,
+                ArraySize: 1,
+                ..desc
+            };
+            let shared = unsafe { self.source_device.CreateTexture2D(&shared_desc, None)? };
+            let dxgi_resource: IDXGIResource = shared.cast()?;
+            let handle = unsafe { dxgi_resource.GetSharedHandle()? };
+            let opened: ID3D11Texture2D = unsafe { self.target_device.OpenSharedResource(handle)? };
+            self.shared = Some(SharedTexture {
+                source_mutex: shared.cast()?,
+                source: shared,
+                target_mutex: opened.cast()?,
+                target: opened,
+                target_holds: false,
+            });
+        }
+        let shared = self.shared.as_mut().unwrap();
+        shared.release_target()?;
+        let dest: ID3D11Resource = shared.source.cast()?;
+        let src: ID3D11Resource = texture.cast()?;
+        // the keyed mutex orders the copy on the source device before reads on the target device,
+        // which a flush alone does not
+        unsafe {
+            shared.source_mutex.AcquireSync(SOURCE_KEY, INFINITE)?;
+            self.source_context.CopyResource(Some(&dest), Some(&src));
+            shared.source_mutex.ReleaseSync(TARGET_KEY)?;
+            shared.target_mutex.AcquireSync(TARGET_KEY, INFINITE)?;
+        }
+        shared.target_holds = true;
+        Ok(shared.target.clone())
+    }
+
+    fn copy_through_cpu(&mut self, texture: &ID3D11Texture2D) -> Result<ID3D11Texture2D> {
+        let desc = texture_desc(texture);
+        if !matches!(&self.staging, Some(staging) if same_size(&staging.desc, &desc)) {
+            self.staging = Some(StagingTexture::new(
+                &self.source_device,
+                desc.Width,
+                desc.Height,
+                desc.Format,
+            )?);
+        }
+        if !matches!(&self.upload, Some(upload) if same_size(&texture_desc(upload), &desc)) {
+            self.upload = Some(create_work_texture(
+                &self.target_device,
+                desc.Width,
+                desc.Height,
+                desc.Format,
+            )?);
+        }
+        let staging = self.staging.as_ref().unwrap();
+        let upload = self.upload.as_ref().unwrap();
+
+        let src: ID3D11Resource = texture.cast()?;
+        unsafe {
+            self.source_context
+                .CopyResource(Some(&staging.as_resource()?), Some(&src))
+        };
+        let mapped = staging.as_mapped(&self.source_context)?;
+        let dest: ID3D11Resource = upload.cast()?;
+        unsafe {
+            self.target_context.UpdateSubresource(
+                Some(&dest),
+                0,
+                None,
+                mapped.pData,
+                mapped.RowPitch,
+                0,
+            )
+        };
+        Ok(upload.clone())
+    }
+}
@@ -0,0 +1,145 @@
+//! The mouse cursor as an image, for drawing it onto frames in software (see
+//! `pipeline::CursorOverlay`) instead of letting Windows.Graphics.Capture do it.
+
+use std::{ffi::c_void, mem::size_of};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::POINT,
+        Graphics::Gdi::{DeleteObject, GetObjectW, BITMAP, HBITMAP, HBRUSH},
+        UI::WindowsAndMessaging::{
+            CopyIcon, DestroyIcon, DrawIconEx, GetCursorInfo, GetIconInfo, CURSORINFO,
+            CURSOR_SHOWING, DI_NORMAL, HICON, ICONINFO,
+        },
+    },
+};
+
+use crate::{image::draw_dib, Image};
+
+/// Appearance of the mouse cursor.
+#[derive(Clone, Debug)]
+pub struct CursorShape {
+    /// The cursor with premultiplied alpha.
+    pub image: Image,
+    /// The pixel of `image` which is at the cursor position.
+    pub hotspot: POINT,
+}
+
+/// State of the mouse cursor, see `cursor_state()`.
+#[derive(Clone, Copy, Debug)]
+pub struct CursorState {
+    /// Cursor position on the virtual screen.
+    pub position: POINT,
+    /// Whether the cursor is shown at all.
+    pub visible: bool,
+    /// Raw `HCURSOR`, which changes whenever the cursor shape does.
+    pub handle: isize,
+}
+
+/// Current position and shape handle of the cursor.
+pub fn cursor_state() -> Result<CursorState> {
+    let mut info = CURSORINFO {
+        cbSize: size_of::<CURSORINFO>() as u32,
+        ..Default::default()
+    };
+    unsafe { GetCursorInfo(&mut info).ok()? };
+    Ok(CursorState {
+        position: info.ptScreenPos,
+        visible: info.flags.0 & CURSOR_SHOWING.0 != 0,
+        handle: info.hCursor.0,
+    })
+}
+
+impl CursorShape {
+    /// Shape of the cursor currently shown, or `None` if the cursor is hidden.
+    pub fn current() -> Result<Option<Self>> {
+        let state = cursor_state()?;
+        if !state.visible || state.handle == 0 {
+            return Ok(None);
+        }
+        Self::from_handle(state.handle).map(Some)
+    }
+
+    /// Render the cursor (or icon) with the given raw `HCURSOR`.
+    ///
+    /// Cursors are drawn on black and on white, and the alpha channel is recovered from the
+    /// difference. This works the same for color, monochrome and animated cursors (of which the
+    /// first frame is taken), but pixels which invert the background come out white.
+    pub fn from_handle(handle: isize) -> Result<Self> {
+        unsafe {
+            // the cursor may be destroyed by its owner at any time
+            let icon = CopyIcon(HICON(handle))?;
+            let result = render_icon(icon);
+            DestroyIcon(icon);
+            result
+        }
+    }
+}
+
+unsafe fn render_icon(icon: HICON) -> Result<CursorShape> {
+    let mut info = ICONINFO::default();
+    GetIconInfo(icon, &mut info).ok()?;
+    let (width, height) = if !info.hbmColor.is_invalid() {
+        bitmap_size(info.hbmColor)
+    } else {
+        // monochrome cursors stack the AND and XOR masks vertically
+        let (width, height) = bitmap_size(info.hbmMask);
+        (width, height / 2)
+    };
+    if !info.hbmColor.is_invalid() {
+        DeleteObject(info.hbmColor);
+    }
+    DeleteObject(info.hbmMask);
+
+    let on_black = draw_icon(icon, width, height, 0x00)?;
+    let on_white = draw_icon(icon, width, height, 0xff)?;
+    let mut data = on_black;
+    for (black, white) in data.chunks_exact_mut(4).zip(on_white.chunks_exact(4)) {
+        // over black, each channel is c * a; over white it is c * a + (1 - a)
+        let transparency = (0..3)
+            .map(|c| white[c].saturating_sub(black[c]) as u32)
+            .sum::<u32>()
+            / 3;
+        black[3] = 255 - transparency as u8;
+    }
+    Ok(CursorShape {
+        image: Image {
+            width,
+            height,
+            data,
+        },
+        hotspot: POINT {
+            x: info.xHotspot as i32,
+            y: info.yHotspot as i32,
+        },
+    })
+}
+
+unsafe fn bitmap_size(bitmap: HBITMAP) -> (u32, u32) {
+    let mut info = BITMAP::default();
+    GetObjectW(
+        bitmap,
+        size_of::<BITMAP>() as i32,
+        Some(&mut info as *mut _ as *mut c_void),
+    );
+    (info.bmWidth.max(0) as u32, info.bmHeight.max(0) as u32)
+}
+
+/// Draw `icon` onto a `width` x `height` 32bpp top-down bitmap filled with `background`.
+unsafe fn draw_icon(icon: HICON, width: u32, height: u32, background: u8) -> Result<Vec<u8>> {
+    draw_dib(width, height, |dc, bits| {
+        bits.fill(background);
+        DrawIconEx(
+            dc,
+            0,
+            0,
+            icon,
+            width as i32,
+            height as i32,
+            0,
+            HBRUSH::default(),
+            DI_NORMAL,
+        )
+    })
+}
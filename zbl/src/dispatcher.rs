@@ -0,0 +1,49 @@
+//! Giving threads creating captures a `DispatcherQueue`, see `init_dispatcher_queue`.
+
+use std::{cell::RefCell, mem::size_of};
+
+use windows::{
+    System::{DispatcherQueue, DispatcherQueueController},
+    Win32::System::WinRT::{
+        CreateDispatcherQueueController, DispatcherQueueOptions, DQTAT_COM_NONE,
+        DQTYPE_THREAD_CURRENT,
+    },
+};
+
+use crate::error::Context;
+
+/// Controller of the queue created for the current thread, shut down when the thread exits.
+struct OwnedQueue(DispatcherQueueController);
+
+impl Drop for OwnedQueue {
+    fn drop(&mut self) {
+        // nothing is dispatched anymore, so there is no need to wait for the shutdown
+        self.0.ShutdownQueueAsync().ok();
+    }
+}
+
+thread_local! {
+    static QUEUE: RefCell<Option<OwnedQueue>> = RefCell::new(None);
+}
+
+/// Create a `DispatcherQueue` for the current thread unless it already has one. It is kept until
+/// the thread exits.
+///
+/// Some parts of Windows.Graphics.Capture look for a `DispatcherQueue` on the calling thread, and
+/// e.g. deliver events differently (or fail) without one. Threads created by the application
+/// usually have none; call this on them before creating captures if that is an issue. UI threads
+/// (e.g. of WinUI or a `DispatcherQueueController` of the application) already have one.
+pub fn init_dispatcher_queue() -> crate::Result<()> {
+    if DispatcherQueue::GetForCurrentThread().is_ok() {
+        return Ok(());
+    }
+    let options = DispatcherQueueOptions {
+        dwSize: size_of::<DispatcherQueueOptions>() as u32,
+        threadType: DQTYPE_THREAD_CURRENT,
+        apartmentType: DQTAT_COM_NONE,
+    };
+    let controller = unsafe { CreateDispatcherQueueController(options) }
+        .context("creating a DispatcherQueue for the current thread")?;
+    QUEUE.with(|queue| *queue.borrow_mut() = Some(OwnedQueue(controller)));
+    Ok(())
+}
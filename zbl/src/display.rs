@@ -1,31 +1,40 @@
-use std::{
-    collections::HashMap,
-    sync::{
-        mpsc::{sync_channel, Receiver, SyncSender},
-        RwLock,
-    },
-};
+use std::sync::mpsc::Receiver;
 
-use lazy_static::lazy_static;
 use windows::{
-    core::Result,
+    core::{Result, PCWSTR},
     Graphics::Capture::GraphicsCaptureItem,
     Win32::{
-        Foundation::{BOOL, LPARAM, RECT},
+        Devices::Display::{
+            DisplayConfigGetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig,
+            DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+            DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_DEVICE_INFO_TYPE,
+            DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_VIRTUAL,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_WIRED,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL, DISPLAYCONFIG_OUTPUT_TECHNOLOGY_MIRACAST,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_OTHER, DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED,
+            DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_SOURCE_DEVICE_NAME,
+            DISPLAYCONFIG_TARGET_DEVICE_NAME, DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY,
+            QDC_ONLY_ACTIVE_PATHS,
+        },
+        Foundation::{BOOL, ERROR_SUCCESS, HWND, LPARAM, LUID, POINT, RECT},
         Graphics::{
             Direct3D11::D3D11_BOX,
-            Gdi::{EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFOEXW},
+            Gdi::{
+                EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, MonitorFromWindow,
+                DEVMODEW, ENUM_CURRENT_SETTINGS, HDC, HMONITOR, MONITORINFOEXW,
+                MONITOR_DEFAULTTONEAREST,
+            },
         },
-        System::WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
+        UI::WindowsAndMessaging::MONITORINFOF_PRIMARY,
     },
 };
 
-use crate::{util::convert_u16_string, Capturable};
-
-lazy_static! {
-    static ref OBJECT_DESTROYED_USER_DATA: RwLock<HashMap<isize, (isize, SyncSender<()>)>> =
-        Default::default();
-}
+use crate::{
+    capturable::{client_box, create_capture_item_for_monitor, never_closing_notification_channel},
+    util::{convert_u16_string, Rect},
+    Capturable, Error,
+};
 
 fn get_monitor_info(handle: HMONITOR) -> Result<MONITORINFOEXW> {
     let mut info = MONITORINFOEXW::default();
@@ -52,6 +61,161 @@ fn enumerate_displays() -> Result<Box<Vec<Result<Display>>>> {
     }
 }
 
+/// How a display is attached to the system.
+///
+/// Virtual display drivers (e.g. the ones used to run GPU workloads on headless machines) and
+/// remote desktop sessions show up as indirect displays. Windows.Graphics.Capture works on those as
+/// long as the driver renders the desktop through the GPU; see `Display::verify_capture`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DisplayConnection {
+    /// A panel built into the device, e.g. a laptop screen.
+    Internal,
+    /// A monitor plugged into a video output (HDMI, DisplayPort, ...).
+    External,
+    /// A wireless display (Miracast).
+    Wireless,
+    /// A display driven by an indirect display driver over some other connection, e.g. USB.
+    IndirectWired,
+    /// A display which does not exist physically, created by an indirect display driver.
+    Virtual,
+    /// The connection could not be determined, e.g. because the display is being reconfigured.
+    Unknown,
+}
+
+impl From<DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY> for DisplayConnection {
+    fn from(technology: DISPLAYCONFIG_VIDEO_OUTPUT_TECHNOLOGY) -> Self {
+        match technology {
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL
+            | DISPLAYCONFIG_OUTPUT_TECHNOLOGY_DISPLAYPORT_EMBEDDED
+            | DISPLAYCONFIG_OUTPUT_TECHNOLOGY_UDI_EMBEDDED => Self::Internal,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_MIRACAST => Self::Wireless,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_WIRED => Self::IndirectWired,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_VIRTUAL => Self::Virtual,
+            DISPLAYCONFIG_OUTPUT_TECHNOLOGY_OTHER => Self::Unknown,
+            _ => Self::External,
+        }
+    }
+}
+
+impl DisplayConnection {
+    /// Whether the display is driven by an indirect display driver rather than a GPU output.
+    /// Miracast displays are not: they are driven by the GPU driver's own wireless output.
+    pub fn is_indirect(&self) -> bool {
+        matches!(self, Self::IndirectWired | Self::Virtual)
+    }
+}
+
+/// A monitor showing a display. Displays in clone mode are shown on several monitors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayOutput {
+    pub connection: DisplayConnection,
+    /// Name of the monitor as reported by its driver (e.g. "DELL U2720Q"), if any.
+    pub friendly_name: Option<String>,
+}
+
+/// Monitors showing the active display with the given GDI device name (e.g. `\\.\DISPLAY1`),
+/// from the display configuration database.
+fn query_display_config(device_name: &str) -> Vec<DisplayOutput> {
+    let (mut path_count, mut mode_count) = (0u32, 0u32);
+    let mut paths;
+    let mut modes;
+    unsafe {
+        if GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count)
+            != ERROR_SUCCESS
+        {
+            return Vec::new();
+        }
+        paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+        modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+        if QueryDisplayConfig(
+            QDC_ONLY_ACTIVE_PATHS,
+            &mut path_count,
+            paths.as_mut_ptr(),
+            &mut mode_count,
+            modes.as_mut_ptr(),
+            None,
+        ) != ERROR_SUCCESS
+        {
+            return Vec::new();
+        }
+    }
+    paths.truncate(path_count as usize);
+
+    // in clone mode, several paths share a source
+    let mut outputs = Vec::new();
+    for path in paths {
+        let mut source = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+            header: device_info_header::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>(
+                DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+                path.sourceInfo.adapterId,
+                path.sourceInfo.id,
+            ),
+            ..Default::default()
+        };
+        if unsafe { DisplayConfigGetDeviceInfo(&mut source.header) } != 0
+            || convert_u16_string(&source.viewGdiDeviceName) != device_name
+        {
+            continue;
+        }
+        let mut target = DISPLAYCONFIG_TARGET_DEVICE_NAME {
+            header: device_info_header::<DISPLAYCONFIG_TARGET_DEVICE_NAME>(
+                DISPLAYCONFIG_DEVICE_INFO_GET_TARGET_NAME,
+                path.targetInfo.adapterId,
+                path.targetInfo.id,
+            ),
+            ..Default::default()
+        };
+        let friendly_name = if unsafe { DisplayConfigGetDeviceInfo(&mut target.header) } == 0 {
+            convert_u16_string(&target.monitorFriendlyDeviceName)
+        } else {
+            String::new()
+        };
+        outputs.push(DisplayOutput {
+            connection: path.targetInfo.outputTechnology.into(),
+            friendly_name: Some(friendly_name).filter(|name| !name.is_empty()),
+        });
+    }
+    outputs
+}
+
+fn device_info_header<T>(
+    r#type: DISPLAYCONFIG_DEVICE_INFO_TYPE,
+    adapter_id: LUID,
+    id: u32,
+) -> DISPLAYCONFIG_DEVICE_INFO_HEADER {
+    DISPLAYCONFIG_DEVICE_INFO_HEADER {
+        r#type,
+        size: std::mem::size_of::<T>() as u32,
+        adapterId: adapter_id,
+        id,
+    }
+}
+
+/// Snapshot of the properties of a `Display`, which unlike the display itself can be sent to other
+/// processes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplayInfo {
+    /// Raw `HMONITOR`, valid only on the same desktop session.
+    pub handle: isize,
+    pub name: String,
+    pub monitor_rect: Rect,
+    pub work_area: Rect,
+    pub is_primary: bool,
+    pub refresh_rate: Option<u32>,
+    pub connection: DisplayConnection,
+    /// Name of the monitor as reported by its driver (e.g. "DELL U2720Q"), if any.
+    pub friendly_name: Option<String>,
+    /// All monitors showing the display, see `Display::outputs`.
+    pub outputs: Vec<DisplayOutput>,
+    /// Raw `HMONITOR` of another display showing the same content, which
+    /// `Display::enumerate_distinct` returns instead of this one.
+    pub duplicate_of: Option<isize>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Display {
     pub handle: HMONITOR,
@@ -70,6 +234,16 @@ impl Display {
         })
     }
 
+    /// Display which contains the largest part of the given window.
+    pub fn for_window(handle: HWND) -> Result<Self> {
+        Self::new(unsafe { MonitorFromWindow(handle, MONITOR_DEFAULTTONEAREST) })
+    }
+
+    /// All displays attached to the desktop.
+    pub fn enumerate() -> Result<Vec<Self>> {
+        (*enumerate_displays()?).into_iter().collect()
+    }
+
     pub fn find_by_id(id: usize) -> Result<Self> {
         let displays = *enumerate_displays()?;
         displays[id].clone()
@@ -79,37 +253,198 @@ impl Display {
         let rect = self.display_info.monitorInfo.rcMonitor;
         (rect.right - rect.left, rect.bottom - rect.top)
     }
+
+    /// Rectangle of the display on the virtual screen.
+    pub fn get_monitor_rect(&self) -> RECT {
+        self.display_info.monitorInfo.rcMonitor
+    }
+
+    /// Part of the display not covered by the taskbar and docked toolbars, on the virtual screen.
+    pub fn get_work_area(&self) -> RECT {
+        self.display_info.monitorInfo.rcWork
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.display_info.monitorInfo.dwFlags & MONITORINFOF_PRIMARY != 0
+    }
+
+    /// How the display is attached to the system.
+    pub fn connection(&self) -> DisplayConnection {
+        query_display_config(&self.display_name)
+            .first()
+            .map_or(DisplayConnection::Unknown, |output| output.connection)
+    }
+
+    /// Name of the monitor as reported by its driver, if any.
+    pub fn friendly_name(&self) -> Option<String> {
+        query_display_config(&self.display_name)
+            .into_iter()
+            .next()
+            .and_then(|output| output.friendly_name)
+    }
+
+    /// Monitors showing the display. In clone mode ("Duplicate these displays"), Windows shows
+    /// the same display on several monitors, so capturing it once covers all of them.
+    pub fn outputs(&self) -> Vec<DisplayOutput> {
+        query_display_config(&self.display_name)
+    }
+
+    /// Other displays showing the same content as this one, i.e. covering the same rectangle of
+    /// the virtual screen. This happens when displays on different adapters are duplicated, which
+    /// Windows sets up as separate displays rather than one display with several outputs.
+    pub fn duplicates(&self) -> Result<Vec<Self>> {
+        Ok(Self::enumerate()?
+            .into_iter()
+            .filter(|other| {
+                other.handle != self.handle && other.get_monitor_rect() == self.get_monitor_rect()
+            })
+            .collect())
+    }
+
+    /// All displays attached to the desktop, leaving out duplicates (see `duplicates()`), so that
+    /// identical content is only captured once. Of a set of duplicates, the primary display (or
+    /// else the first one enumerated) is kept.
+    pub fn enumerate_distinct() -> Result<Vec<Self>> {
+        let displays = Self::enumerate()?;
+        Ok(displays
+            .iter()
+            .filter(|display| kept_duplicate(&displays, display).handle == display.handle)
+            .cloned()
+            .collect())
+    }
+
+    /// Displays driven by indirect display drivers, i.e. virtual and remote displays.
+    pub fn enumerate_indirect() -> Result<Vec<Self>> {
+        Ok(Self::enumerate()?
+            .into_iter()
+            .filter(|display| display.connection().is_indirect())
+            .collect())
+    }
+
+    /// Check that Windows.Graphics.Capture actually produces frames for this display, by capturing a
+    /// small thumbnail.
+    ///
+    /// Some virtual display drivers present the desktop without going through the GPU, in which
+    /// case capture items can be created for their displays, but no frames ever arrive. This takes
+    /// up to a second in that case.
+    pub fn verify_capture(&self) -> crate::Result<bool> {
+        Ok(self.thumbnail(64)?.is_some())
+    }
+
+    /// Current properties of the display.
+    pub fn info(&self) -> DisplayInfo {
+        let outputs = query_display_config(&self.display_name);
+        let (connection, friendly_name) = match outputs.first() {
+            Some(output) => (output.connection, output.friendly_name.clone()),
+            None => (DisplayConnection::Unknown, None),
+        };
+        let duplicate_of = Self::enumerate()
+            .ok()
+            .map(|displays| kept_duplicate(&displays, self).handle)
+            .filter(|&handle| handle != self.handle)
+            .map(|handle| handle.0);
+        DisplayInfo {
+            handle: self.handle.0,
+            name: self.display_name.clone(),
+            monitor_rect: self.get_monitor_rect().into(),
+            work_area: self.get_work_area().into(),
+            is_primary: self.is_primary(),
+            refresh_rate: self.get_refresh_rate(),
+            connection,
+            friendly_name,
+            outputs,
+            duplicate_of,
+        }
+    }
+
+    /// Current refresh rate of the display in Hz, if it can be determined.
+    pub fn get_refresh_rate(&self) -> Option<u32> {
+        let mut mode = DEVMODEW {
+            dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+            ..Default::default()
+        };
+        let found = unsafe {
+            EnumDisplaySettingsW(
+                PCWSTR::from_raw(self.display_info.szDevice.as_ptr()),
+                ENUM_CURRENT_SETTINGS,
+                &mut mode as *mut _,
+            )
+        };
+        // 0 and 1 both mean "hardware default"
+        if found.as_bool() && mode.dmDisplayFrequency > 1 {
+            Some(mode.dmDisplayFrequency)
+        } else {
+            None
+        }
+    }
+}
+
+/// The display `enumerate_distinct()` keeps out of `display` and its duplicates in `displays`.
+fn kept_duplicate<'a>(displays: &'a [Display], display: &'a Display) -> &'a Display {
+    let rect = display.get_monitor_rect();
+    let mut duplicates = displays
+        .iter()
+        .filter(|other| other.get_monitor_rect() == rect);
+    let first = duplicates.clone().next().unwrap_or(display);
+    duplicates.find(|other| other.is_primary()).unwrap_or(first)
 }
 
 impl Capturable for Display {
     fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
-        let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
-        unsafe { interop.CreateForMonitor(self.handle) }
+        create_capture_item_for_monitor(self.handle)
     }
 
     fn get_client_box(&self) -> Result<D3D11_BOX> {
         let (w, h) = self.get_virtual_size();
-
-        let mut client_box = D3D11_BOX::default();
-        client_box.left = 0;
-        client_box.right = w as u32;
-        client_box.top = 0;
-        client_box.bottom = h as u32;
-        client_box.front = 0;
-        client_box.back = 1;
-        Ok(client_box)
+        Ok(client_box(0, 0, w as u32, h as u32))
     }
 
     fn get_close_notification_channel(&self) -> Receiver<()> {
-        let (sender, receiver) = sync_channel(1);
-        OBJECT_DESTROYED_USER_DATA
-            .write()
-            .unwrap()
-            .insert(self.handle.0, (self.handle.0, sender));
-        receiver
+        never_closing_notification_channel()
     }
 
     fn get_raw_handle(&self) -> isize {
         self.handle.0
     }
+
+    fn get_screen_origin(&self) -> Option<POINT> {
+        let rect = self.display_info.monitorInfo.rcMonitor;
+        Some(POINT {
+            x: rect.left,
+            y: rect.top,
+        })
+    }
+
+    fn refresh_rate(&self) -> Option<u32> {
+        self.get_refresh_rate()
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        let (w, h) = self.get_virtual_size();
+        if w <= 0 || h <= 0 {
+            return Err(Error::ZeroSized);
+        }
+        Ok(())
+    }
+
+    fn as_display(&self) -> Option<&Display> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_indirect_display_drivers_are_indirect() {
+        let connection = |technology| DisplayConnection::from(technology);
+        assert!(connection(DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_WIRED).is_indirect());
+        assert!(connection(DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INDIRECT_VIRTUAL).is_indirect());
+        let miracast = connection(DISPLAYCONFIG_OUTPUT_TECHNOLOGY_MIRACAST);
+        assert_eq!(miracast, DisplayConnection::Wireless);
+        assert!(!miracast.is_indirect());
+        assert!(!connection(DISPLAYCONFIG_OUTPUT_TECHNOLOGY_INTERNAL).is_indirect());
+        assert!(!connection(DISPLAYCONFIG_OUTPUT_TECHNOLOGY_OTHER).is_indirect());
+    }
 }
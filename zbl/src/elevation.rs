@@ -0,0 +1,56 @@
+//! Whether processes run elevated (as administrator).
+//!
+//! Windows.Graphics.Capture cannot capture windows of elevated processes from a process which is
+//! not elevated: depending on the system, creating the capture fails with `E_ACCESSDENIED` or the
+//! frames stay black.
+
+use std::{ffi::c_void, mem::size_of};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::{CloseHandle, E_ACCESSDENIED, HANDLE},
+        Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
+        System::Threading::{
+            GetCurrentProcess, OpenProcess, OpenProcessToken, PROCESS_QUERY_LIMITED_INFORMATION,
+        },
+    },
+};
+
+fn is_token_elevated(process: HANDLE) -> Result<bool> {
+    let mut token = HANDLE::default();
+    unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token).ok()? };
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut size = 0u32;
+    let result = unsafe {
+        GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut _ as *mut c_void),
+            size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        )
+        .ok()
+    };
+    unsafe { CloseHandle(token) };
+    result.map(|_| elevation.TokenIsElevated != 0)
+}
+
+/// Whether the current process is elevated.
+pub fn is_current_process_elevated() -> bool {
+    is_token_elevated(unsafe { GetCurrentProcess() }).unwrap_or(false)
+}
+
+/// Whether the process with the given id is elevated.
+///
+/// A process which is not elevated is not allowed to look at the token of an elevated one, so
+/// being denied access counts as elevated.
+pub fn is_process_elevated(process_id: u32) -> Result<bool> {
+    let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id)? };
+    let result = is_token_elevated(process);
+    unsafe { CloseHandle(process) };
+    match result {
+        Err(error) if error.code() == E_ACCESSDENIED => Ok(true),
+        result => result,
+    }
+}
@@ -0,0 +1,219 @@
+use std::fmt;
+
+use windows::{
+    core::HRESULT,
+    Win32::{
+        Foundation::{
+            ERROR_BUSY, ERROR_INVALID_WINDOW_HANDLE, E_ACCESSDENIED, E_FAIL, E_INVALIDARG,
+            E_NOTIMPL, E_OUTOFMEMORY, E_PENDING, REGDB_E_CLASSNOTREG, RO_E_CLOSED,
+            RPC_E_CALL_REJECTED, RPC_E_SERVERCALL_RETRYLATER,
+        },
+        Graphics::Dxgi::{
+            DXGI_ERROR_DEVICE_HUNG, DXGI_ERROR_DEVICE_REMOVED, DXGI_ERROR_DEVICE_RESET,
+            DXGI_ERROR_UNSUPPORTED, DXGI_ERROR_WAS_STILL_DRAWING,
+        },
+    },
+};
+
+/// Errors which can be diagnosed before a capture is created.
+///
+/// Everything else (e.g. a failing D3D11 call) is reported as `WindowsError`, or as
+/// `WindowsCallFailed` where the failing operation is known. Both display the HRESULT, and a hint
+/// at the likely cause for common ones.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Windows.Graphics.Capture is not supported on this system")]
+    CaptureNotSupported,
+    #[error("window handle is not valid (the window may have been closed)")]
+    InvalidWindow,
+    #[error("window is cloaked (it may be on another virtual desktop or suspended)")]
+    WindowCloaked,
+    #[error(
+        "window belongs to an elevated process, which can only be captured from an elevated one"
+    )]
+    ElevatedTarget,
+    #[error("capture target has zero size")]
+    ZeroSized,
+    #[error("capture target not found: {0}")]
+    TargetNotFound(String),
+    #[error("invalid capture config: {0}")]
+    InvalidConfig(String),
+    #[error("failed to encode or decode image: {0}")]
+    EncodingError(String),
+    #[error("{operation} failed after {attempts} attempts: {}", Details(.source))]
+    RetriesExhausted {
+        operation: &'static str,
+        attempts: u32,
+        source: windows::core::Error,
+    },
+    #[error("{operation} failed: {}", Details(.source))]
+    WindowsCallFailed {
+        operation: &'static str,
+        source: windows::core::Error,
+    },
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("windows api error: {}", Details(.0))]
+    WindowsError(#[from] windows::core::Error),
+}
+
+impl Error {
+    /// HRESULT of the underlying Windows error, if any.
+    pub fn hresult(&self) -> Option<HRESULT> {
+        match self {
+            Error::RetriesExhausted { source, .. } | Error::WindowsCallFailed { source, .. } => {
+                Some(source.code())
+            }
+            Error::WindowsError(error) => Some(error.code()),
+            _ => None,
+        }
+    }
+
+    /// Likely cause of the underlying Windows error, for HRESULTs commonly seen while capturing.
+    pub fn hint(&self) -> Option<&'static str> {
+        self.hresult().and_then(known_hresult).map(|(_, hint)| hint)
+    }
+
+    /// The underlying Windows error, or `E_FAIL` with the message of other errors.
+    pub(crate) fn to_windows_error(&self) -> windows::core::Error {
+        match self {
+            Error::RetriesExhausted { source, .. } | Error::WindowsCallFailed { source, .. } => {
+                source.clone()
+            }
+            Error::WindowsError(error) => error.clone(),
+            error => windows::core::Error::new(E_FAIL, error.to_string().as_str().into()),
+        }
+    }
+}
+
+/// For code reporting `windows::core::Error`s, e.g. stages, which calls into the capture.
+impl From<Error> for windows::core::Error {
+    fn from(error: Error) -> Self {
+        error.to_windows_error()
+    }
+}
+
+/// Whether a Windows error may go away by itself, so that the failing call is worth retrying, see
+/// `TransientRetry`.
+pub(crate) fn is_transient(error: &windows::core::Error) -> bool {
+    // not E_FAIL, which is returned by persistent failures just as well
+    [
+        E_PENDING,
+        RPC_E_CALL_REJECTED,
+        RPC_E_SERVERCALL_RETRYLATER,
+        ERROR_BUSY.to_hresult(),
+        DXGI_ERROR_WAS_STILL_DRAWING,
+    ]
+    .contains(&error.code())
+}
+
+/// Name and likely cause of HRESULTs commonly returned by capture calls.
+fn known_hresult(code: HRESULT) -> Option<(&'static str, &'static str)> {
+    let known = [
+        (
+            E_ACCESSDENIED,
+            "E_ACCESSDENIED",
+            "capturing an elevated window from a non-elevated process, or a protected window",
+        ),
+        (
+            E_INVALIDARG,
+            "E_INVALIDARG",
+            "the window or monitor handle may no longer be valid",
+        ),
+        (
+            ERROR_INVALID_WINDOW_HANDLE.to_hresult(),
+            "ERROR_INVALID_WINDOW_HANDLE",
+            "the window has been closed",
+        ),
+        (
+            E_NOTIMPL,
+            "E_NOTIMPL",
+            "this version of Windows does not support the requested capture feature",
+        ),
+        (
+            REGDB_E_CLASSNOTREG,
+            "REGDB_E_CLASSNOTREG",
+            "Windows.Graphics.Capture is not available (Windows 10 1803 or later is required)",
+        ),
+        (
+            E_OUTOFMEMORY,
+            "E_OUTOFMEMORY",
+            "out of (video) memory, e.g. because of too many or too large captures",
+        ),
+        (
+            RO_E_CLOSED,
+            "RO_E_CLOSED",
+            "the capture session or frame pool has already been closed",
+        ),
+        (
+            DXGI_ERROR_DEVICE_REMOVED,
+            "DXGI_ERROR_DEVICE_REMOVED",
+            "the GPU was removed, or its driver was updated or crashed",
+        ),
+        (
+            DXGI_ERROR_DEVICE_RESET,
+            "DXGI_ERROR_DEVICE_RESET",
+            "the GPU was reset, e.g. after a timeout detection and recovery",
+        ),
+        (
+            DXGI_ERROR_DEVICE_HUNG,
+            "DXGI_ERROR_DEVICE_HUNG",
+            "the GPU stopped responding to the commands of this process",
+        ),
+        (
+            DXGI_ERROR_UNSUPPORTED,
+            "DXGI_ERROR_UNSUPPORTED",
+            "the GPU or its driver does not support the requested format or feature",
+        ),
+        (
+            DXGI_ERROR_WAS_STILL_DRAWING,
+            "DXGI_ERROR_WAS_STILL_DRAWING",
+            "the GPU has not finished with the resource yet",
+        ),
+    ];
+    known
+        .into_iter()
+        .find(|(hresult, _, _)| *hresult == code)
+        .map(|(_, name, hint)| (name, hint))
+}
+
+/// Displays a Windows error with its HRESULT and, if known, its name and likely cause.
+struct Details<'a>(&'a windows::core::Error);
+
+impl fmt::Display for Details<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.0.code();
+        write!(f, "{} (HRESULT 0x{:08X}", self.0.message(), code.0 as u32)?;
+        if let Some((name, hint)) = known_hresult(code) {
+            write!(f, ", {}: {}", name, hint)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Attaches the name of the failing operation to Windows errors.
+pub(crate) trait Context<T> {
+    fn context(self, operation: &'static str) -> Result<T>;
+}
+
+impl<T> Context<T> for windows::core::Result<T> {
+    fn context(self, operation: &'static str) -> Result<T> {
+        self.map_err(|source| Error::WindowsCallFailed { operation, source })
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_errors_which_may_go_away_are_transient() {
+        let error = |code: HRESULT| windows::core::Error::from(code);
+        assert!(is_transient(&error(E_PENDING)));
+        assert!(is_transient(&error(DXGI_ERROR_WAS_STILL_DRAWING)));
+        assert!(!is_transient(&error(E_FAIL)));
+        assert!(!is_transient(&error(E_ACCESSDENIED)));
+    }
+}
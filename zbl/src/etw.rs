@@ -0,0 +1,99 @@
+use windows::{
+    core::{Error, Result, GUID, HSTRING},
+    Win32::{
+        Foundation::{ERROR_SUCCESS, WIN32_ERROR},
+        System::Diagnostics::Etw::{
+            EventProviderEnabled, EventRegister, EventUnregister, EventWriteString,
+        },
+    },
+};
+
+use crate::observer::{DropReason, FrameInfo, Observer};
+
+/// GUID of the `zbl` provider. This is the GUID EventSource and TraceLogging derive from the name,
+/// so the provider can be enabled as `*zbl` as well (e.g. `wpr -start` with a custom profile, or
+/// `tracelog -enable`).
+pub const PROVIDER_ID: GUID = GUID::from_u128(0x43acfa85_944b_5d06_0c20_87468d780970);
+
+const LEVEL_ERROR: u8 = 2;
+const LEVEL_WARNING: u8 = 3;
+const LEVEL_INFO: u8 = 4;
+const LEVEL_VERBOSE: u8 = 5;
+
+/// Emits capture diagnostics as ETW string events, so that captures can be correlated with GPU
+/// activity in Windows Performance Analyzer.
+///
+/// Register it as an observer of every capture which should be traced:
+/// `capture.add_observer(Arc::new(EtwObserver::new()?))`. Lifecycle events are logged at the
+/// information level, frames and copy durations at the verbose level, drops as warnings and
+/// errors as errors. Nothing is formatted unless a session has enabled the provider.
+pub struct EtwObserver {
+    handle: u64,
+}
+
+impl EtwObserver {
+    pub fn new() -> Result<Self> {
+        let mut handle = 0;
+        let status = unsafe { EventRegister(&PROVIDER_ID, None, None, &mut handle) };
+        if status != ERROR_SUCCESS.0 {
+            return Err(WIN32_ERROR(status).to_hresult().into());
+        }
+        Ok(Self { handle })
+    }
+
+    fn write(&self, level: u8, message: impl FnOnce() -> String) {
+        unsafe {
+            if EventProviderEnabled(self.handle, level, 0).0 != 0 {
+                EventWriteString(self.handle, level, 0, &HSTRING::from(message()));
+            }
+        }
+    }
+}
+
+impl Drop for EtwObserver {
+    fn drop(&mut self) {
+        unsafe { EventUnregister(self.handle) };
+    }
+}
+
+impl Observer for EtwObserver {
+    fn on_frame(&self, info: &FrameInfo) {
+        self.write(LEVEL_VERBOSE, || {
+            format!(
+                "frame timestamp={} width={} height={} copy_us={} gpu_us={}",
+                info.timestamp,
+                info.width,
+                info.height,
+                info.process_time.as_micros(),
+                info.gpu_time
+                    .map_or(-1, |gpu_time| gpu_time.as_micros() as i64)
+            )
+        });
+    }
+
+    fn on_drop(&self, reason: DropReason) {
+        self.write(LEVEL_WARNING, || format!("drop reason={:?}", reason));
+    }
+
+    fn on_resize(&self, width: u32, height: u32) {
+        self.write(LEVEL_INFO, || {
+            format!("resize width={} height={}", width, height)
+        });
+    }
+
+    fn on_error(&self, error: &Error) {
+        self.write(LEVEL_ERROR, || format!("error {}", error));
+    }
+
+    fn on_start(&self) {
+        self.write(LEVEL_INFO, || "start".to_owned());
+    }
+
+    fn on_stop(&self) {
+        self.write(LEVEL_INFO, || "stop".to_owned());
+    }
+
+    fn on_recreate(&self) {
+        self.write(LEVEL_INFO, || "recreate".to_owned());
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+    time::Duration,
+};
+
+use crate::power::PowerState;
+
+/// Diagnostic events emitted by `Capture`. Subscribe with `Capture::events()`.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CaptureEvent {
+    /// No frames have arrived for `stalled_for` while the target was visible, so the frame pool and
+    /// session have been recreated.
+    WatchdogRestart {
+        #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+        stalled_for: Duration,
+    },
+    /// The power state of the system has changed, and the capture rate has been adjusted to it
+    /// (see `CaptureConfig::power_throttle`).
+    PowerStateChanged { state: PowerState },
+    /// The target has become occluded (or visible again), and the capture has been paused (or
+    /// resumed) accordingly (see `CaptureConfig::pause_when_occluded`).
+    OcclusionChanged { occluded: bool },
+    /// The target window has been moved to another virtual desktop, or the user has switched
+    /// from or to its desktop (see `CaptureConfig::track_virtual_desktop`). No frames are produced
+    /// while the window is not on the current desktop.
+    VirtualDesktopChanged {
+        /// Id of the window's desktop, formatted as a GUID.
+        desktop_id: String,
+        is_current: bool,
+    },
+    /// The capture has switched to another target on its own (see `Capturable::retarget`), e.g.
+    /// because the target window has been reparented or replaced, or `FollowMouse` moved to
+    /// another display. Handles are raw `HWND`s or `HMONITOR`s.
+    Retargeted {
+        previous_handle: isize,
+        handle: isize,
+    },
+    /// Frames have been black for `HangDetection::black_for` (or are not black anymore), see
+    /// `CaptureConfig::hang_detection`.
+    BlackChanged { black: bool },
+    /// Frames have been identical for `HangDetection::frozen_for` (or have changed again), see
+    /// `CaptureConfig::hang_detection`.
+    FrozenChanged { frozen: bool },
+    /// Frames delivered by a `CaptureThread` have reached `CaptureConfig::memory_cap` (or have
+    /// been released by the consumer enough to be below it again).
+    MemoryCapChanged { reached: bool },
+}
+
+/// Fan-out of events to any number of subscribers.
+///
+/// Events are dropped for subscribers which do not keep up, and subscribers which went away are
+/// forgotten, so emitting never blocks.
+pub(crate) struct EventSender<T = CaptureEvent> {
+    subscribers: Vec<SyncSender<T>>,
+}
+
+impl<T> Default for EventSender<T> {
+    fn default() -> Self {
+        Self {
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> EventSender<T> {
+    const CAPACITY: usize = 1 << 6;
+
+    pub fn subscribe(&mut self) -> Receiver<T> {
+        let (sender, receiver) = sync_channel(Self::CAPACITY);
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn emit(&mut self, event: T) {
+        self.subscribers
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Err(TrySendError::Disconnected(_)) => false,
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+            });
+    }
+}
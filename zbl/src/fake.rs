@@ -0,0 +1,319 @@
+//! Synthetic frames for testing frame-processing code without a desktop session.
+//!
+//! `Capture` is tied to Windows.Graphics.Capture, which needs an interactive session to produce
+//! anything. `FakeCapture` implements the same `FrameSource` interface instead, producing frames
+//! from a `Pattern` through the same stage and staging texture path. It falls back to the WARP
+//! software rasterizer on machines without a GPU, so it works on CI runners.
+
+use std::time::Duration;
+
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::{
+        Direct3D11::{ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D},
+        Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+    },
+};
+
+use crate::{
+    capture::FrameSource,
+    pipeline::{create_work_texture, Stage, StageContext},
+    staging_texture::StagingTexture,
+    util::create_d3d_device,
+    Frame, GrabStatus, Image,
+};
+
+/// Width of the bar drawn by `Pattern::MovingBar`.
+const BAR_WIDTH: u32 = 16;
+
+/// What the frames of a `FakeCapture` look like. All pixels are BGRA.
+pub enum Pattern {
+    /// Every frame is filled with the same color.
+    Solid([u8; 4]),
+    /// Opaque gradient with blue increasing to the right and green increasing downwards.
+    Gradient,
+    /// A white vertical bar on black, moving right by `speed` pixels every frame and wrapping
+    /// around.
+    MovingBar { speed: u32 },
+    /// The given images, in a loop. Images are cropped or padded (with transparent black) to the
+    /// capture size.
+    Images(Vec<Image>),
+}
+
+impl Pattern {
+    /// Render the `index`-th frame.
+    pub fn render(&self, index: u64, width: u32, height: u32) -> Image {
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+        let pixels = data.chunks_exact_mut(4).enumerate();
+        match self {
+            Pattern::Solid(color) => {
+                for (_, pixel) in pixels {
+                    pixel.copy_from_slice(color);
+                }
+            }
+            Pattern::Gradient => {
+                for (i, pixel) in pixels {
+                    let (x, y) = (i as u32 % width, i as u32 / width);
+                    let b = x * 255 / width.saturating_sub(1).max(1);
+                    let g = y * 255 / height.saturating_sub(1).max(1);
+                    pixel.copy_from_slice(&[b as u8, g as u8, 0, 255]);
+                }
+            }
+            Pattern::MovingBar { speed } => {
+                let left = (index * *speed as u64 % width.max(1) as u64) as u32;
+                for (i, pixel) in pixels {
+                    let x = i as u32 % width;
+                    // distance from the bar's left edge, accounting for wrapping around
+                    let inside = (x + width - left) % width < BAR_WIDTH;
+                    pixel.copy_from_slice(if inside { &[255; 4] } else { &[0, 0, 0, 255] });
+                }
+            }
+            Pattern::Images(images) if !images.is_empty() => {
+                let image = &images[(index % images.len() as u64) as usize];
+                let len = width.min(image.width) as usize * 4;
+                for y in 0..height.min(image.height) {
+                    let start = y as usize * width as usize * 4;
+                    data[start..start + len].copy_from_slice(&image.row(y)[..len]);
+                }
+            }
+            Pattern::Images(_) => {}
+        }
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// A stand-in for `Capture` which synthesizes its frames.
+///
+/// Frames are produced on demand: `grab()` never blocks and always returns the next frame, until
+/// the frame limit (if any) is reached. Frame timestamps advance by the frame interval, starting at
+/// zero.
+pub struct FakeCapture {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    pattern: Pattern,
+    width: u32,
+    height: u32,
+    frame_interval: Duration,
+    frame_limit: Option<u64>,
+    frame_index: u64,
+    stopped: bool,
+    stages: Vec<Box<dyn Stage>>,
+    work_texture: ID3D11Texture2D,
+    staging_texture: StagingTexture,
+}
+
+impl FakeCapture {
+    pub fn new(pattern: Pattern, width: u32, height: u32) -> crate::Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(crate::Error::ZeroSized);
+        }
+        let device = create_d3d_device()?;
+        let context = unsafe {
+            let mut d3d_context = None;
+            device.GetImmediateContext(&mut d3d_context);
+            d3d_context.expect("failed to create d3d_context")
+        };
+        let work_texture = create_work_texture(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+        let staging_texture =
+            StagingTexture::new(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+        Ok(Self {
+            device,
+            context,
+            pattern,
+            width,
+            height,
+            frame_interval: Duration::from_secs(1) / 60,
+            frame_limit: None,
+            frame_index: 0,
+            stopped: false,
+            stages: Vec::new(),
+            work_texture,
+            staging_texture,
+        })
+    }
+
+    /// Time between timestamps of consecutive frames (1/60s by default).
+    pub fn frame_interval(mut self, interval: Duration) -> Self {
+        self.frame_interval = interval;
+        self
+    }
+
+    /// Report `GrabStatus::Closed` after `limit` frames, as if the target had been closed.
+    pub fn frame_limit(mut self, limit: u64) -> Self {
+        self.frame_limit = Some(limit);
+        self
+    }
+
+    /// Add a processing stage, see `Capture::add_stage`.
+    pub fn add_stage(&mut self, stage: Box<dyn Stage>) {
+        self.stages.push(stage);
+    }
+
+    /// Get the D3D11 device frames are produced on.
+    pub fn d3d_device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Number of frames produced so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_index
+    }
+
+    pub fn start(&mut self) -> Result<()> {
+        self.stopped = false;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        self.stopped = true;
+        Ok(())
+    }
+
+    /// Produce the next frame.
+    pub fn grab(&mut self) -> crate::Result<GrabStatus> {
+        let exhausted = matches!(self.frame_limit, Some(limit) if self.frame_index >= limit);
+        if self.stopped || exhausted {
+            return Ok(GrabStatus::Closed);
+        }
+        let index = self.frame_index;
+        self.frame_index += 1;
+        let timestamp = (self.frame_interval.as_nanos() * index as u128 / 100) as i64;
+
+        let image = self.pattern.render(index, self.width, self.height);
+        let work_resource: ID3D11Resource = self.work_texture.cast()?;
+        unsafe {
+            self.context.UpdateSubresource(
+                Some(&work_resource),
+                0,
+                None,
+                image.data.as_ptr() as *const _,
+                self.width * 4,
+                0,
+            )
+        };
+        let ctx = StageContext {
+            device: &self.device,
+            context: &self.context,
+            width: self.width,
+            height: self.height,
+            screen_origin: None,
+            timestamp,
+        };
+        for stage in self.stages.iter_mut() {
+            stage.process(&ctx, &self.work_texture)?;
+        }
+        unsafe {
+            self.context.CopyResource(
+                Some(&self.staging_texture.as_resource()?),
+                Some(&work_resource),
+            )
+        };
+        let ptr = self.staging_texture.as_mapped(&self.context)?;
+        Ok(GrabStatus::Frame(Frame {
+            texture: &self.staging_texture,
+            ptr,
+            timestamp,
+            previous: None,
+            input_events: &[],
+            stats: None,
+            tracked_window: None,
+        }))
+    }
+}
+
+impl FrameSource for FakeCapture {
+    fn start(&mut self) -> Result<()> {
+        FakeCapture::start(self)
+    }
+
+    fn grab(&mut self) -> crate::Result<GrabStatus> {
+        FakeCapture::grab(self)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        FakeCapture::stop(self)
+    }
+
+    fn add_stage(&mut self, stage: Box<dyn Stage>) {
+        FakeCapture::add_stage(self, stage)
+    }
+
+    fn d3d_device(&self) -> &ID3D11Device {
+        FakeCapture::d3d_device(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 4] {
+        let start = (y * image.width + x) as usize * 4;
+        image.data[start..start + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn solid_fills_every_pixel() {
+        let image = Pattern::Solid([1, 2, 3, 4]).render(0, 3, 2);
+        assert_eq!((image.width, image.height), (3, 2));
+        assert!(image
+            .data
+            .chunks_exact(4)
+            .all(|pixel| pixel == [1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn gradient_spans_the_full_range() {
+        let image = Pattern::Gradient.render(0, 5, 3);
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&image, 4, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 0, 2), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 4, 2), [255, 255, 0, 255]);
+        // a single pixel does not divide by zero
+        assert_eq!(Pattern::Gradient.render(0, 1, 1).data, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn moving_bar_wraps_around() {
+        let width = BAR_WIDTH * 2;
+        let bar = Pattern::MovingBar { speed: 10 };
+        let white = |image: &Image| (0..width).filter(|&x| pixel(image, x, 0)[0] == 255).count();
+        let first = bar.render(0, width, 1);
+        assert_eq!(pixel(&first, 0, 0), [255; 4]);
+        assert_eq!(pixel(&first, BAR_WIDTH, 0), [0, 0, 0, 255]);
+        // starts at 30 in the fourth frame, so wraps around to the left edge
+        let wrapped = bar.render(3, width, 1);
+        assert_eq!(pixel(&wrapped, 29, 0), [0, 0, 0, 255]);
+        assert_eq!(pixel(&wrapped, 30, 0), [255; 4]);
+        assert_eq!(pixel(&wrapped, 0, 0), [255; 4]);
+        assert_eq!(white(&first), BAR_WIDTH as usize);
+        assert_eq!(white(&wrapped), BAR_WIDTH as usize);
+    }
+
+    #[test]
+    fn images_loop_and_are_cropped_or_padded() {
+        let image = |value, width, height| Image {
+            width,
+            height,
+            data: vec![value; width as usize * height as usize * 4],
+        };
+        let pattern = Pattern::Images(vec![image(1, 4, 1), image(2, 1, 4)]);
+        let first = pattern.render(0, 2, 2);
+        assert_eq!(pixel(&first, 1, 0), [1; 4]);
+        assert_eq!(pixel(&first, 0, 1), [0; 4]);
+        let second = pattern.render(1, 2, 2);
+        assert_eq!(pixel(&second, 0, 1), [2; 4]);
+        assert_eq!(pixel(&second, 1, 1), [0; 4]);
+        assert_eq!(pattern.render(2, 2, 2).data, first.data);
+    }
+
+    #[test]
+    fn no_images_render_transparent_black() {
+        let image = Pattern::Images(Vec::new()).render(0, 2, 2);
+        assert!(image.data.iter().all(|&channel| channel == 0));
+    }
+}
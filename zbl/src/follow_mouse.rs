@@ -0,0 +1,91 @@
+use std::sync::mpsc::Receiver;
+
+use windows::{
+    core::Result,
+    Graphics::Capture::GraphicsCaptureItem,
+    Win32::{
+        Foundation::POINT,
+        Graphics::{
+            Direct3D11::D3D11_BOX,
+            Gdi::{MonitorFromPoint, HMONITOR, MONITOR_DEFAULTTONEAREST},
+        },
+        UI::WindowsAndMessaging::GetCursorPos,
+    },
+};
+
+use crate::{Capturable, Display};
+
+fn monitor_under_cursor() -> Result<HMONITOR> {
+    let mut cursor = POINT::default();
+    unsafe {
+        GetCursorPos(&mut cursor as *mut _).ok()?;
+        Ok(MonitorFromPoint(cursor, MONITOR_DEFAULTTONEAREST))
+    }
+}
+
+/// The display the mouse cursor is on.
+///
+/// Captures of this target switch to another display as soon as the cursor moves there, returning
+/// `GrabStatus::Recreated` once. Displays may have different sizes, so unless `output_size` is
+/// set, frames change their size along with the display.
+#[derive(Clone, Debug)]
+pub struct FollowMouse {
+    display: Display,
+}
+
+impl FollowMouse {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            display: Display::new(monitor_under_cursor()?)?,
+        })
+    }
+
+    /// The display currently captured.
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+}
+
+impl Capturable for FollowMouse {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        self.display.create_capture_item()
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        self.display.get_client_box()
+    }
+
+    fn get_close_notification_channel(&self) -> Receiver<()> {
+        self.display.get_close_notification_channel()
+    }
+
+    fn get_raw_handle(&self) -> isize {
+        self.display.get_raw_handle()
+    }
+
+    fn get_screen_origin(&self) -> Option<POINT> {
+        self.display.get_screen_origin()
+    }
+
+    fn refresh_rate(&self) -> Option<u32> {
+        self.display.refresh_rate()
+    }
+
+    fn retarget(&self) -> Option<Box<dyn Capturable>> {
+        let monitor = monitor_under_cursor().ok()?;
+        if monitor == self.display.handle {
+            return None;
+        }
+        Some(Box::new(Self {
+            display: Display::new(monitor).ok()?,
+        }))
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        self.display.validate()
+    }
+
+    fn as_display(&self) -> Option<&Display> {
+        Some(&self.display)
+    }
+}
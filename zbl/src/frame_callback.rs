@@ -0,0 +1,54 @@
+//! Delivering frames straight from the frame pool, see `Capture::set_frame_arrived_callback`.
+
+use std::sync::{Arc, Mutex};
+
+use windows::{
+    core::Result, Graphics::Capture::Direct3D11CaptureFrame,
+    Win32::Graphics::Direct3D11::ID3D11Texture2D,
+};
+
+use crate::util::get_dxgi_interface_from_object;
+
+/// A frame as it arrives in the frame pool, before it is queued for `Capture::grab()`.
+pub struct ArrivedFrame<'a> {
+    /// Surface of the frame pool holding the frame. Only valid during the callback, and neither
+    /// cropped to the capture box nor processed by stages.
+    pub texture: &'a ID3D11Texture2D,
+    /// Time at which the frame was produced, in 100ns units (same as `Frame::timestamp`).
+    pub timestamp: i64,
+    /// Size of the content in `texture`, which may be smaller than the texture itself.
+    pub content_size: (u32, u32),
+}
+
+/// Called on the thread delivering frames. Returns whether the frame should still be queued for
+/// `Capture::grab()`.
+pub type FrameArrivedCallback = Box<dyn FnMut(&ArrivedFrame) -> bool + Send>;
+
+/// Callback slot of a capture, shared with its frame handler.
+#[derive(Clone, Default)]
+pub(crate) struct FrameCallbackSlot(Arc<Mutex<Option<FrameArrivedCallback>>>);
+
+impl FrameCallbackSlot {
+    pub fn set(&self, callback: Option<FrameArrivedCallback>) {
+        *self.0.lock().unwrap() = callback;
+    }
+
+    /// Hand `frame` to the callback, if there is one. Returns whether the frame should be queued.
+    pub fn deliver(&self, frame: &Direct3D11CaptureFrame) -> Result<bool> {
+        let mut callback = self.0.lock().unwrap();
+        let callback = match callback.as_mut() {
+            Some(callback) => callback,
+            None => return Ok(true),
+        };
+        let texture: ID3D11Texture2D = get_dxgi_interface_from_object(&frame.Surface()?)?;
+        let content_size = frame.ContentSize()?;
+        Ok(callback(&ArrivedFrame {
+            texture: &texture,
+            timestamp: frame.SystemRelativeTime()?.Duration,
+            content_size: (
+                content_size.Width.max(0) as u32,
+                content_size.Height.max(0) as u32,
+            ),
+        }))
+    }
+}
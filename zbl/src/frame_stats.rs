@@ -0,0 +1,307 @@
+//! Luminance histograms and average colors of frames, computed on the GPU.
+//!
+//! Only a few hundred bytes per frame are read back, so these are cheap enough to compute for every
+//! frame, e.g. to detect loading screens, black frames or flashing content without looking at the
+//! pixels on the CPU.
+
+use std::mem::size_of;
+
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::{
+        Direct3D11::{
+            ID3D11Buffer, ID3D11ClassLinkage, ID3D11ComputeShader, ID3D11Device,
+            ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, ID3D11UnorderedAccessView,
+            D3D11_BIND_FLAG, D3D11_BIND_UNORDERED_ACCESS, D3D11_BUFFER_DESC, D3D11_BUFFER_UAV,
+            D3D11_CPU_ACCESS_FLAG, D3D11_CPU_ACCESS_READ, D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG,
+            D3D11_UAV_DIMENSION_BUFFER, D3D11_UNORDERED_ACCESS_VIEW_DESC,
+            D3D11_UNORDERED_ACCESS_VIEW_DESC_0, D3D11_USAGE_DEFAULT, D3D11_USAGE_STAGING,
+        },
+        Dxgi::Common::DXGI_FORMAT_R32_UINT,
+    },
+};
+
+use crate::{
+    image::Color,
+    pipeline::{
+        shader::{compile_shader, create_constant_buffer, update_buffer},
+        StageContext,
+    },
+};
+
+/// Number of luminance levels in `FrameStats::histogram`.
+pub const HISTOGRAM_BINS: usize = 256;
+
+/// Histogram bins, followed by the sum of every channel as (low, high) 32-bit halves.
+const RESULT_LEN: usize = HISTOGRAM_BINS + 8;
+
+const THREAD_GROUP_SIZE: u32 = 16;
+
+const STATS_CS: &str = r#"
+Texture2D<float4> frame : register(t0);
+RWBuffer<uint> stats : register(u0);
+cbuffer Frame : register(b0) {
+    uint2 frame_size;
+};
+
+groupshared uint bins[256];
+groupshared uint sums[4];
+
+[numthreads(16, 16, 1)]
+void main(uint3 id : SV_DispatchThreadID, uint index : SV_GroupIndex) {
+    bins[index] = 0;
+    if (index < 4) {
+        sums[index] = 0;
+    }
+    GroupMemoryBarrierWithGroupSync();
+
+    if (all(id.xy < frame_size)) {
+        float4 color = saturate(frame.Load(int3(id.xy, 0)));
+        uint4 channels = uint4(round(color * 255));
+        float luma = dot(color.rgb, float3(0.2126, 0.7152, 0.0722));
+        InterlockedAdd(bins[min(uint(round(luma * 255)), 255)], 1);
+        InterlockedAdd(sums[0], channels.r);
+        InterlockedAdd(sums[1], channels.g);
+        InterlockedAdd(sums[2], channels.b);
+        InterlockedAdd(sums[3], channels.a);
+    }
+    GroupMemoryBarrierWithGroupSync();
+
+    if (bins[index] != 0) {
+        InterlockedAdd(stats[index], bins[index]);
+    }
+    if (index < 4) {
+        // large frames overflow 32 bits, so carry into the high half
+        uint previous;
+        InterlockedAdd(stats[256 + index * 2], sums[index], previous);
+        if (previous > 0xffffffff - sums[index]) {
+            InterlockedAdd(stats[257 + index * 2], 1);
+        }
+    }
+}
+"#;
+
+/// Statistics of a single frame, see `CaptureConfig::frame_stats`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameStats {
+    /// Number of pixels per luminance level (Rec. 709 luma of the gamma-encoded colors), from
+    /// black to white. Always has `HISTOGRAM_BINS` entries.
+    pub histogram: Vec<u32>,
+    /// Average color of all pixels, rounded to the nearest level.
+    pub average: Color,
+}
+
+impl FrameStats {
+    /// Number of pixels the statistics were computed from.
+    pub fn pixel_count(&self) -> u64 {
+        self.histogram.iter().map(|&count| count as u64).sum()
+    }
+
+    /// Average luminance, from 0 (black) to 1 (white).
+    pub fn mean_luminance(&self) -> f32 {
+        let count = self.pixel_count();
+        if count == 0 {
+            return 0.0;
+        }
+        let total: u64 = self
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(level, &pixels)| level as u64 * pixels as u64)
+            .sum();
+        total as f32 / count as f32 / (HISTOGRAM_BINS - 1) as f32
+    }
+
+    /// Fraction of pixels darker than `level`, e.g. `fraction_below(16) > 0.99` for a black frame.
+    pub fn fraction_below(&self, level: u8) -> f32 {
+        let count = self.pixel_count();
+        if count == 0 {
+            return 0.0;
+        }
+        let below: u64 = self.histogram[..level as usize]
+            .iter()
+            .map(|&pixels| pixels as u64)
+            .sum();
+        below as f32 / count as f32
+    }
+
+    fn from_result(result: &[u32]) -> Self {
+        let histogram = result[..HISTOGRAM_BINS].to_vec();
+        let count: u64 = histogram.iter().map(|&pixels| pixels as u64).sum();
+        let channel = |index: usize| {
+            let low = result[HISTOGRAM_BINS + index * 2] as u64;
+            let high = result[HISTOGRAM_BINS + index * 2 + 1] as u64;
+            let sum = low | (high << 32);
+            ((sum + count / 2) / count.max(1)) as u8
+        };
+        Self {
+            histogram,
+            average: Color {
+                r: channel(0),
+                g: channel(1),
+                b: channel(2),
+                a: channel(3),
+            },
+        }
+    }
+}
+
+/// Computes `FrameStats` with a compute shader.
+pub struct FrameAnalyzer {
+    shader: ID3D11ComputeShader,
+    constants: ID3D11Buffer,
+    result: ID3D11Buffer,
+    result_view: ID3D11UnorderedAccessView,
+    readback: ID3D11Buffer,
+    pending: bool,
+}
+
+impl FrameAnalyzer {
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let code = compile_shader(STATS_CS, "main", "cs_5_0")?;
+        let shader = unsafe { device.CreateComputeShader(&code, None::<&ID3D11ClassLinkage>)? };
+        let result_desc = D3D11_BUFFER_DESC {
+            ByteWidth: (RESULT_LEN * size_of::<u32>()) as u32,
+            Usage: D3D11_USAGE_DEFAULT,
+            BindFlags: D3D11_BIND_UNORDERED_ACCESS,
+            CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+            MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+            StructureByteStride: 0,
+        };
+        let readback_desc = D3D11_BUFFER_DESC {
+            Usage: D3D11_USAGE_STAGING,
+            BindFlags: D3D11_BIND_FLAG(0),
+            CPUAccessFlags: D3D11_CPU_ACCESS_READ,
+            ..result_desc
+        };
+        let view_desc = D3D11_UNORDERED_ACCESS_VIEW_DESC {
+            Format: DXGI_FORMAT_R32_UINT,
+            ViewDimension: D3D11_UAV_DIMENSION_BUFFER,
+            Anonymous: D3D11_UNORDERED_ACCESS_VIEW_DESC_0 {
+                Buffer: D3D11_BUFFER_UAV {
+                    FirstElement: 0,
+                    NumElements: RESULT_LEN as u32,
+                    Flags: 0,
+                },
+            },
+        };
+        unsafe {
+            let result = device.CreateBuffer(&result_desc, None)?;
+            let resource: ID3D11Resource = result.cast()?;
+            let result_view =
+                device.CreateUnorderedAccessView(Some(&resource), Some(&view_desc as *const _))?;
+            Ok(Self {
+                shader,
+                constants: create_constant_buffer(device, 16)?,
+                result,
+                result_view,
+                readback: device.CreateBuffer(&readback_desc, None)?,
+                pending: false,
+            })
+        }
+    }
+
+    /// Queue the computation for `texture`, which must be bindable as a shader resource. The
+    /// result is read by `resolve()`.
+    pub fn analyze(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        update_buffer(ctx, &self.constants, &[ctx.width, ctx.height, 0, 0])?;
+        update_buffer(ctx, &self.result, &[0u32; RESULT_LEN])?;
+        let resource: ID3D11Resource = texture.cast()?;
+        unsafe {
+            let input = ctx.device.CreateShaderResourceView(Some(&resource), None)?;
+            let dc = ctx.context;
+            dc.CSSetShader(&self.shader, None);
+            dc.CSSetShaderResources(0, Some(&[Some(input)]));
+            dc.CSSetConstantBuffers(0, Some(&[Some(self.constants.clone())]));
+            dc.CSSetUnorderedAccessViews(0, 1, Some(&Some(self.result_view.clone())), None);
+            dc.Dispatch(
+                (ctx.width + THREAD_GROUP_SIZE - 1) / THREAD_GROUP_SIZE,
+                (ctx.height + THREAD_GROUP_SIZE - 1) / THREAD_GROUP_SIZE,
+                1,
+            );
+            dc.CSSetUnorderedAccessViews(0, 1, Some(&None), None);
+            dc.CSSetShaderResources(0, Some(&[None]));
+
+            let readback: ID3D11Resource = self.readback.cast()?;
+            let result: ID3D11Resource = self.result.cast()?;
+            dc.CopyResource(Some(&readback), Some(&result));
+        }
+        self.pending = true;
+        Ok(())
+    }
+
+    /// Wait for the GPU to finish the last `analyze()` and get its result.
+    ///
+    /// Returns `None` if nothing has been analyzed since the last call.
+    pub fn resolve(&mut self, context: &ID3D11DeviceContext) -> Result<Option<FrameStats>> {
+        if !self.pending {
+            return Ok(None);
+        }
+        self.pending = false;
+        let readback: ID3D11Resource = self.readback.cast()?;
+        unsafe {
+            let mapped = context.Map(Some(&readback), 0, D3D11_MAP_READ, 0)?;
+            let result = std::slice::from_raw_parts(mapped.pData as *const u32, RESULT_LEN);
+            let stats = FrameStats::from_result(result);
+            context.Unmap(Some(&readback), 0);
+            Ok(Some(stats))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(histogram: &[(usize, u32)], sums: [u64; 4]) -> Vec<u32> {
+        let mut result = vec![0; RESULT_LEN];
+        for &(level, pixels) in histogram {
+            result[level] = pixels;
+        }
+        for (index, sum) in sums.into_iter().enumerate() {
+            result[HISTOGRAM_BINS + index * 2] = sum as u32;
+            result[HISTOGRAM_BINS + index * 2 + 1] = (sum >> 32) as u32;
+        }
+        result
+    }
+
+    #[test]
+    fn averages_round_to_the_nearest_level() {
+        let stats = FrameStats::from_result(&result(&[(0, 1), (255, 3)], [5, 6, 0, 1020]));
+        assert_eq!(stats.pixel_count(), 4);
+        assert_eq!(
+            stats.average,
+            Color {
+                r: 1,
+                g: 2,
+                b: 0,
+                a: 255
+            }
+        );
+        assert_eq!(stats.mean_luminance(), 0.75);
+        assert_eq!(stats.fraction_below(16), 0.25);
+    }
+
+    #[test]
+    fn sums_use_both_words() {
+        // 2^32 pixels of level 3, counted in two bins to fit into 32 bits
+        let pixels = 1u64 << 32;
+        let result = result(
+            &[(3, u32::MAX), (4, 1)],
+            [pixels * 3, pixels * 200, 0, pixels * 255],
+        );
+        let stats = FrameStats::from_result(&result);
+        assert_eq!(stats.pixel_count(), pixels);
+        assert_eq!((stats.average.r, stats.average.g), (3, 200));
+        assert_eq!(stats.average.a, 255);
+    }
+
+    #[test]
+    fn empty_frames_average_to_zero() {
+        let stats = FrameStats::from_result(&result(&[], [0; 4]));
+        assert_eq!(stats.average, Color::default());
+        assert_eq!(stats.mean_luminance(), 0.0);
+        assert_eq!(stats.histogram.len(), HISTOGRAM_BINS);
+    }
+}
@@ -0,0 +1,340 @@
+//! Comparing frames against reference images, for regression tests of processing stages.
+//!
+//! Reference ("golden") images are stored as PNG files. A typical test renders a frame with
+//! `FakeCapture`, runs it through the stages under test and checks the result:
+//!
+//! ```ignore
+//! # use zbl::{golden::{assert_golden, Tolerance}, FakeCapture, Pattern};
+//! let mut capture = FakeCapture::new(Pattern::Gradient, 256, 256).unwrap();
+//! // capture.add_stage(...);
+//! let image = capture.grab().unwrap().frame().unwrap().to_image();
+//! assert_golden(&image, "tests/golden/gradient.png", &Tolerance::default());
+//! ```
+//!
+//! Setting the `ZBL_UPDATE_GOLDEN` environment variable makes `assert_golden` (re)write the
+//! reference images instead of checking them, which is also how missing ones are created.
+
+#[cfg(feature = "png")]
+use std::path::{Path, PathBuf};
+
+use crate::Image;
+
+/// Environment variable which makes `assert_golden` overwrite the reference images.
+pub const UPDATE_GOLDEN_VAR: &str = "ZBL_UPDATE_GOLDEN";
+
+/// Size of the (non-overlapping) windows SSIM is computed over.
+const SSIM_WINDOW: u32 = 8;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+/// How much an image may differ from its reference.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tolerance {
+    /// Channels differing by at most this much are considered equal.
+    pub channel_delta: u8,
+    /// Fraction (0..1) of pixels which may differ by more than `channel_delta`.
+    pub max_differing_pixels: f64,
+    /// Minimum structural similarity (-1..1, 1 meaning identical), if checked at all.
+    pub min_ssim: Option<f64>,
+}
+
+impl Default for Tolerance {
+    /// Allows for rounding differences between GPUs, but nothing else.
+    fn default() -> Self {
+        Self {
+            channel_delta: 2,
+            max_differing_pixels: 0.0,
+            min_ssim: None,
+        }
+    }
+}
+
+/// Result of comparing two images of the same size.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// Largest difference of any channel of any pixel.
+    pub max_delta: u8,
+    /// Number of pixels with any channel differing by more than the channel tolerance.
+    pub differing_pixels: usize,
+    /// Fraction (0..1) of pixels which differ.
+    pub differing_fraction: f64,
+    /// Mean structural similarity of the luma of both images.
+    pub ssim: f64,
+}
+
+impl Comparison {
+    pub fn is_within(&self, tolerance: &Tolerance) -> bool {
+        self.differing_fraction <= tolerance.max_differing_pixels
+            && tolerance
+                .min_ssim
+                .map_or(true, |min_ssim| self.ssim >= min_ssim)
+    }
+}
+
+/// Compare `actual` with `expected`, counting pixels which differ by more than `channel_delta`.
+///
+/// Returns `None` if the images have different sizes.
+pub fn compare(actual: &Image, expected: &Image, channel_delta: u8) -> Option<Comparison> {
+    if actual.width != expected.width || actual.height != expected.height {
+        return None;
+    }
+    let mut max_delta = 0;
+    let mut differing_pixels = 0;
+    for (a, b) in actual
+        .data
+        .chunks_exact(4)
+        .zip(expected.data.chunks_exact(4))
+    {
+        let delta = (0..4).map(|c| a[c].abs_diff(b[c])).max().unwrap();
+        max_delta = max_delta.max(delta);
+        if delta > channel_delta {
+            differing_pixels += 1;
+        }
+    }
+    let pixel_count = (actual.width as usize * actual.height as usize).max(1);
+    Some(Comparison {
+        max_delta,
+        differing_pixels,
+        differing_fraction: differing_pixels as f64 / pixel_count as f64,
+        ssim: ssim(actual, expected),
+    })
+}
+
+/// Mean structural similarity index of the luma of two images of the same size.
+///
+/// Panics if the images have different sizes.
+pub fn ssim(a: &Image, b: &Image) -> f64 {
+    assert_same_size(a, b);
+    let (luma_a, luma_b) = (luma(a), luma(b));
+    let width = a.width as usize;
+    let mut total = 0.0;
+    let mut windows = 0;
+    for wy in (0..a.height).step_by(SSIM_WINDOW as usize) {
+        for wx in (0..a.width).step_by(SSIM_WINDOW as usize) {
+            let mut pixels = Vec::with_capacity((SSIM_WINDOW * SSIM_WINDOW) as usize);
+            for y in wy..(wy + SSIM_WINDOW).min(a.height) {
+                for x in wx..(wx + SSIM_WINDOW).min(a.width) {
+                    let i = y as usize * width + x as usize;
+                    pixels.push((luma_a[i], luma_b[i]));
+                }
+            }
+            let n = pixels.len() as f64;
+            let mean_a = pixels.iter().map(|p| p.0).sum::<f64>() / n;
+            let mean_b = pixels.iter().map(|p| p.1).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (pa, pb) in pixels {
+                var_a += (pa - mean_a) * (pa - mean_a);
+                var_b += (pb - mean_b) * (pb - mean_b);
+                covariance += (pa - mean_a) * (pb - mean_b);
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            windows += 1;
+        }
+    }
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+fn assert_same_size(a: &Image, b: &Image) {
+    assert!(
+        a.width == b.width && a.height == b.height,
+        "images of different sizes cannot be compared: {}x{} and {}x{}",
+        a.width,
+        a.height,
+        b.width,
+        b.height
+    );
+}
+
+fn luma(image: &Image) -> Vec<f64> {
+    image
+        .data
+        .chunks_exact(4)
+        .map(|p| 0.114 * p[0] as f64 + 0.587 * p[1] as f64 + 0.299 * p[2] as f64)
+        .collect()
+}
+
+/// Visualize the differences between two images of the same size: pixels which differ by more
+/// than `channel_delta` are red, the rest are dimmed copies of `expected`.
+///
+/// Panics if the images have different sizes.
+pub fn diff_image(actual: &Image, expected: &Image, channel_delta: u8) -> Image {
+    assert_same_size(actual, expected);
+    let data = actual
+        .data
+        .chunks_exact(4)
+        .zip(expected.data.chunks_exact(4))
+        .flat_map(|(a, b)| {
+            if (0..4).any(|c| a[c].abs_diff(b[c]) > channel_delta) {
+                [0, 0, 255, 255]
+            } else {
+                [b[0] / 4, b[1] / 4, b[2] / 4, 255]
+            }
+        })
+        .collect();
+    Image {
+        width: expected.width,
+        height: expected.height,
+        data,
+    }
+}
+
+/// Check `image` against the reference PNG at `path`, panicking if it does not match. Requires the
+/// `png` feature.
+///
+/// On mismatch, the image and a visualization of the differences are written next to the
+/// reference as `<name>.actual.png` and `<name>.diff.png`. If `ZBL_UPDATE_GOLDEN` is set, the
+/// reference is written instead. A missing reference fails the check otherwise, so that a test
+/// cannot pass by accident, e.g. on CI where the reference has not been checked in.
+#[cfg(feature = "png")]
+pub fn assert_golden(image: &Image, path: impl AsRef<Path>, tolerance: &Tolerance) {
+    let update = std::env::var_os(UPDATE_GOLDEN_VAR).is_some();
+    check_golden(image, path.as_ref(), tolerance, update);
+}
+
+#[cfg(feature = "png")]
+fn check_golden(image: &Image, path: &Path, tolerance: &Tolerance, update: bool) {
+    if update {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).ok();
+        }
+        image
+            .save_png(path)
+            .unwrap_or_else(|e| panic!("failed to write {}: {}", path.display(), e));
+        return;
+    }
+    if !path.exists() {
+        image.save_png(sibling(path, "actual")).ok();
+        panic!(
+            "reference image {} does not exist, set {} to create it",
+            path.display(),
+            UPDATE_GOLDEN_VAR
+        );
+    }
+    let expected = Image::load_png(path)
+        .unwrap_or_else(|e| panic!("failed to load {}: {}", path.display(), e));
+    let comparison = compare(image, &expected, tolerance.channel_delta);
+    if matches!(&comparison, Some(comparison) if comparison.is_within(tolerance)) {
+        return;
+    }
+    image.save_png(sibling(path, "actual")).ok();
+    let message = match comparison {
+        Some(comparison) => {
+            diff_image(image, &expected, tolerance.channel_delta)
+                .save_png(sibling(path, "diff"))
+                .ok();
+            format!("{:?} exceeds {:?}", comparison, tolerance)
+        }
+        None => format!(
+            "size is {}x{}, expected {}x{}",
+            image.width, image.height, expected.width, expected.height
+        ),
+    };
+    panic!("image does not match {}: {}", path.display(), message);
+}
+
+/// `dir/name.png` -> `dir/name.<suffix>.png`
+#[cfg(feature = "png")]
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> Image {
+        let data = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, (x + y) as u8, 255]))
+            .collect();
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn solid(width: u32, height: u32, value: u8) -> Image {
+        Image {
+            width,
+            height,
+            data: vec![value; width as usize * height as usize * 4],
+        }
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = gradient(20, 12);
+        let comparison = compare(&image, &image, 0).unwrap();
+        assert_eq!(comparison.max_delta, 0);
+        assert_eq!(comparison.differing_pixels, 0);
+        assert!((comparison.ssim - 1.0).abs() < 1e-9);
+        assert!(comparison.is_within(&Tolerance {
+            min_ssim: Some(0.99),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn differences_are_counted_beyond_the_channel_delta() {
+        let expected = solid(4, 4, 100);
+        let mut actual = expected.clone();
+        actual.data[0] = 102;
+        actual.data[4] = 110;
+        let comparison = compare(&actual, &expected, 2).unwrap();
+        assert_eq!(comparison.max_delta, 10);
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.differing_fraction, 1.0 / 16.0);
+        assert!(!comparison.is_within(&Tolerance::default()));
+    }
+
+    #[test]
+    fn compare_rejects_different_sizes() {
+        assert!(compare(&solid(4, 4, 0), &solid(4, 5, 0), 0).is_none());
+    }
+
+    #[test]
+    fn ssim_drops_for_different_structure() {
+        let image = gradient(32, 32);
+        let flat = solid(32, 32, 64);
+        assert!(ssim(&image, &flat) < 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "images of different sizes cannot be compared: 8x8 and 8x9")]
+    fn ssim_rejects_different_sizes() {
+        ssim(&solid(8, 8, 0), &solid(8, 9, 0));
+    }
+
+    #[test]
+    fn diff_image_marks_differing_pixels() {
+        let expected = solid(2, 1, 200);
+        let mut actual = expected.clone();
+        actual.data[4] = 0;
+        let diff = diff_image(&actual, &expected, 2);
+        assert_eq!(diff.data, [50, 50, 50, 255, 0, 0, 255, 255]);
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn missing_reference_fails() {
+        let dir = std::env::temp_dir().join(format!("zbl-golden-{}", std::process::id()));
+        let path = dir.join("missing.png");
+        let image = solid(4, 4, 0);
+        let result =
+            std::panic::catch_unwind(|| check_golden(&image, &path, &Tolerance::default(), false));
+        let message = *result.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.contains("does not exist"), "{}", message);
+        assert!(!path.exists());
+
+        check_golden(&image, &path, &Tolerance::default(), true);
+        check_golden(&image, &path, &Tolerance::default(), false);
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,105 @@
+use std::{hint::spin_loop, mem::size_of, time::Duration};
+
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::Direct3D11::{
+        ID3D11Asynchronous, ID3D11Device, ID3D11DeviceContext, D3D11_QUERY_DATA_TIMESTAMP_DISJOINT,
+        D3D11_QUERY_DESC, D3D11_QUERY_TIMESTAMP, D3D11_QUERY_TIMESTAMP_DISJOINT,
+    },
+};
+
+/// Measures the time the GPU spends on a sequence of commands using timestamp queries.
+pub struct GpuTimer {
+    disjoint: ID3D11Asynchronous,
+    start: ID3D11Asynchronous,
+    end: ID3D11Asynchronous,
+    pending: bool,
+}
+
+fn create_query(device: &ID3D11Device, desc: &D3D11_QUERY_DESC) -> Result<ID3D11Asynchronous> {
+    unsafe { device.CreateQuery(desc)?.cast() }
+}
+
+impl GpuTimer {
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let disjoint_desc = D3D11_QUERY_DESC {
+            Query: D3D11_QUERY_TIMESTAMP_DISJOINT,
+            MiscFlags: 0,
+        };
+        let timestamp_desc = D3D11_QUERY_DESC {
+            Query: D3D11_QUERY_TIMESTAMP,
+            MiscFlags: 0,
+        };
+        Ok(Self {
+            disjoint: create_query(device, &disjoint_desc)?,
+            start: create_query(device, &timestamp_desc)?,
+            end: create_query(device, &timestamp_desc)?,
+            pending: false,
+        })
+    }
+
+    /// Start measuring. Everything submitted to `context` until `end()` is timed.
+    pub fn begin(&mut self, context: &ID3D11DeviceContext) {
+        unsafe {
+            context.Begin(Some(&self.disjoint));
+            context.End(Some(&self.start));
+        }
+    }
+
+    pub fn end(&mut self, context: &ID3D11DeviceContext) {
+        unsafe {
+            context.End(Some(&self.end));
+            context.End(Some(&self.disjoint));
+        }
+        self.pending = true;
+    }
+
+    /// Wait for the GPU to finish the measured commands and get their duration.
+    ///
+    /// Returns `None` if nothing has been measured since the last call, or if the GPU clock was
+    /// unreliable in the meantime (e.g. because of a power state change).
+    pub fn resolve(&mut self, context: &ID3D11DeviceContext) -> Result<Option<Duration>> {
+        if !self.pending {
+            return Ok(None);
+        }
+        self.pending = false;
+
+        // `GetData` returns S_FALSE while the data is not available, which is not an error and
+        // thus cannot be told from S_OK here; a resolved frequency is never zero though
+        let mut disjoint = D3D11_QUERY_DATA_TIMESTAMP_DISJOINT::default();
+        while disjoint.Frequency == 0 {
+            unsafe {
+                context.GetData(
+                    Some(&self.disjoint),
+                    Some(&mut disjoint as *mut _ as *mut _),
+                    size_of::<D3D11_QUERY_DATA_TIMESTAMP_DISJOINT>() as u32,
+                    0,
+                )?;
+            }
+            spin_loop();
+        }
+        if disjoint.Disjoint.as_bool() {
+            return Ok(None);
+        }
+
+        // both timestamps precede the end of the disjoint query, so they are available by now
+        let start = read_timestamp(context, &self.start)?;
+        let end = read_timestamp(context, &self.end)?;
+        let ticks = end.saturating_sub(start) as u128;
+        let nanos = ticks * 1_000_000_000 / disjoint.Frequency as u128;
+        Ok(Some(Duration::from_nanos(nanos as u64)))
+    }
+}
+
+fn read_timestamp(context: &ID3D11DeviceContext, query: &ID3D11Asynchronous) -> Result<u64> {
+    let mut timestamp = 0u64;
+    unsafe {
+        context.GetData(
+            Some(query),
+            Some(&mut timestamp as *mut _ as *mut _),
+            size_of::<u64>() as u32,
+            0,
+        )?;
+    }
+    Ok(timestamp)
+}
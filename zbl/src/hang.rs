@@ -0,0 +1,198 @@
+//! Detection of black and frozen frames, see `CaptureConfig::hang_detection`.
+
+use std::time::Duration;
+
+use crate::{capture::Frame, config::HangDetection, event::CaptureEvent, FrameStats};
+
+/// Hash of the pixels of `frame` (FNV-1a over 8-byte words), which is cheap enough to compute for
+/// every frame.
+fn content_hash(frame: &Frame) -> u64 {
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = 0xcbf29ce484222325 ^ (((frame.width() as u64) << 32) | frame.height() as u64);
+    for y in 0..frame.height() {
+        let row = frame.row(y);
+        let words = row.chunks_exact(8);
+        let remainder = words.remainder();
+        for word in words {
+            hash = (hash ^ u64::from_le_bytes(word.try_into().unwrap())).wrapping_mul(PRIME);
+        }
+        for &byte in remainder {
+            hash = (hash ^ byte as u64).wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// Length of `duration` in 100ns units, like frame timestamps.
+fn ticks(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100) as i64
+}
+
+/// Tracks how long frames have been black or identical.
+pub(crate) struct HangDetector {
+    config: HangDetection,
+    /// Timestamp of the first of the current run of black frames.
+    black_since: Option<i64>,
+    black: bool,
+    /// Hash of the last frame, and the timestamp of the first frame with that content.
+    content: Option<(u64, i64)>,
+    frozen: bool,
+}
+
+impl HangDetector {
+    pub fn new(config: HangDetection) -> Self {
+        Self {
+            config,
+            black_since: None,
+            black: false,
+            content: None,
+            frozen: false,
+        }
+    }
+
+    /// Look at the next returned frame, and get the events to emit for it.
+    pub fn check(&mut self, frame: &Frame) -> Vec<CaptureEvent> {
+        let mut events = self.check_stats(frame.timestamp, frame.stats);
+        if self.config.frozen_for.is_some() {
+            events.extend(self.check_content(frame.timestamp, content_hash(frame)));
+        }
+        events
+    }
+
+    /// Look at the statistics of the next frame only, for frames which are not read back (see
+    /// `Capture::grab_into`). Only black frames are detected this way.
+    pub fn check_stats(&mut self, timestamp: i64, stats: Option<&FrameStats>) -> Vec<CaptureEvent> {
+        let mut events = Vec::new();
+        if let (Some(black_for), Some(stats)) = (self.config.black_for, stats) {
+            let is_black = stats.histogram[self.config.black_level as usize + 1..]
+                .iter()
+                .all(|&pixels| pixels == 0);
+            let black = if is_black {
+                let since = *self.black_since.get_or_insert(timestamp);
+                timestamp - since >= ticks(black_for)
+            } else {
+                self.black_since = None;
+                false
+            };
+            if black != self.black {
+                self.black = black;
+                events.push(CaptureEvent::BlackChanged { black });
+            }
+        }
+        events
+    }
+
+    /// Look at the hash of the content of the next frame.
+    fn check_content(&mut self, timestamp: i64, hash: u64) -> Option<CaptureEvent> {
+        let frozen_for = self.config.frozen_for?;
+        let since = match self.content {
+            Some((previous, since)) if previous == hash => since,
+            _ => timestamp,
+        };
+        self.content = Some((hash, since));
+        let frozen = timestamp - since >= ticks(frozen_for);
+        if frozen == self.frozen {
+            return None;
+        }
+        self.frozen = frozen;
+        Some(CaptureEvent::FrozenChanged { frozen })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{frame_stats::HISTOGRAM_BINS, Color};
+
+    const SECOND: i64 = 10_000_000;
+
+    fn detector() -> HangDetector {
+        HangDetector::new(HangDetection {
+            black_level: 16,
+            black_for: Some(Duration::from_secs(2)),
+            frozen_for: Some(Duration::from_secs(10)),
+        })
+    }
+
+    fn stats(level: usize) -> FrameStats {
+        let mut histogram = vec![0; HISTOGRAM_BINS];
+        histogram[level] = 100;
+        FrameStats {
+            histogram,
+            average: Color::default(),
+        }
+    }
+
+    fn black_changes(events: Vec<CaptureEvent>) -> Vec<bool> {
+        events
+            .into_iter()
+            .filter_map(|event| match event {
+                CaptureEvent::BlackChanged { black } => Some(black),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reports_black_frames_once_they_last() {
+        let mut detector = detector();
+        let dark = stats(16);
+        assert!(detector.check_stats(0, Some(&dark)).is_empty());
+        assert!(detector.check_stats(SECOND, Some(&dark)).is_empty());
+        assert_eq!(
+            black_changes(detector.check_stats(2 * SECOND, Some(&dark))),
+            [true]
+        );
+        assert!(detector.check_stats(3 * SECOND, Some(&dark)).is_empty());
+        let bright = stats(17);
+        assert_eq!(
+            black_changes(detector.check_stats(4 * SECOND, Some(&bright))),
+            [false]
+        );
+        // the run starts over
+        assert!(detector.check_stats(5 * SECOND, Some(&dark)).is_empty());
+        assert!(detector.check_stats(6 * SECOND, Some(&dark)).is_empty());
+    }
+
+    #[test]
+    fn ignores_frames_without_stats() {
+        let mut detector = detector();
+        assert!(detector.check_stats(0, None).is_empty());
+        assert!(detector.check_stats(10 * SECOND, None).is_empty());
+        let mut disabled = HangDetector::new(HangDetection {
+            black_for: None,
+            ..Default::default()
+        });
+        assert!(disabled.check_stats(0, Some(&stats(0))).is_empty());
+        assert!(disabled
+            .check_stats(10 * SECOND, Some(&stats(0)))
+            .is_empty());
+    }
+
+    fn frozen_changed(event: Option<CaptureEvent>) -> Option<bool> {
+        match event? {
+            CaptureEvent::FrozenChanged { frozen } => Some(frozen),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reports_frozen_content_once_it_lasts() {
+        let mut detector = detector();
+        assert!(detector.check_content(0, 1).is_none());
+        assert!(detector.check_content(9 * SECOND, 1).is_none());
+        assert_eq!(
+            frozen_changed(detector.check_content(10 * SECOND, 1)),
+            Some(true)
+        );
+        assert!(detector.check_content(11 * SECOND, 1).is_none());
+        assert_eq!(
+            frozen_changed(detector.check_content(12 * SECOND, 2)),
+            Some(false)
+        );
+        // changing content never freezes
+        for (second, hash) in (13..30).zip(3..) {
+            assert!(detector.check_content(second * SECOND, hash).is_none());
+        }
+    }
+}
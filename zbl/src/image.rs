@@ -0,0 +1,498 @@
+use std::{fs::File, io::Write, path::Path, ptr::null_mut};
+
+use windows::{
+    core::{Error, Result},
+    Win32::{
+        Foundation::{BOOL, HANDLE, HWND},
+        Graphics::{
+            Dxgi::Common::{
+                DXGI_FORMAT, DXGI_FORMAT_B8G8R8A8_UNORM, DXGI_FORMAT_R10G10B10A2_UNORM,
+            },
+            Gdi::{
+                CreateCompatibleDC, CreateDIBSection, CreatedHDC, DeleteDC, DeleteObject, GdiFlush,
+                GetDC, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, DIB_RGB_COLORS,
+            },
+        },
+        System::{
+            DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData},
+            Memory::{GlobalAlloc, GlobalFree, GlobalLock, GlobalUnlock, GMEM_MOVEABLE},
+            SystemServices::CF_DIB,
+        },
+    },
+};
+
+use crate::Frame;
+
+#[cfg(feature = "png")]
+fn png_error(error: impl std::fmt::Display) -> crate::Error {
+    crate::Error::EncodingError(error.to_string())
+}
+
+const BITMAP_FILE_HEADER_SIZE: u32 = 14;
+const BITMAP_INFO_HEADER_SIZE: u32 = 40;
+const DDS_HEADER_SIZE: u32 = 124;
+const DDS_PIXEL_FORMAT_SIZE: u32 = 32;
+
+/// Color of a single pixel, as captured (i.e. with premultiplied alpha).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    /// Read a pixel stored as BGRA, the layout of frames and images.
+    pub fn from_bgra(bgra: &[u8]) -> Self {
+        Self {
+            r: bgra[2],
+            g: bgra[1],
+            b: bgra[0],
+            a: bgra[3],
+        }
+    }
+
+    /// Read a pixel stored as `DXGI_FORMAT_R10G10B10A2_UNORM`, keeping the 8 most significant bits
+    /// of every channel.
+    pub fn from_rgb10a2(rgb10a2: &[u8]) -> Self {
+        let pixel = u32::from_le_bytes(rgb10a2[..4].try_into().unwrap());
+        Self {
+            r: (pixel >> 2) as u8,
+            g: (pixel >> 12) as u8,
+            b: (pixel >> 22) as u8,
+            a: ((pixel >> 30) * 0x55) as u8,
+        }
+    }
+
+    /// Read a pixel stored as `DXGI_FORMAT_R16G16B16A16_FLOAT` (scRGB), encoding its colors as
+    /// sRGB. Colors brighter than SDR white are clipped.
+    pub fn from_scrgb(rgba: &[u8]) -> Self {
+        let channel =
+            |index: usize| half_to_f32(u16::from_le_bytes([rgba[index * 2], rgba[index * 2 + 1]]));
+        let encode = |c: f32| {
+            let c = c.clamp(0.0, 1.0);
+            let c = if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            };
+            (c * 255.0).round() as u8
+        };
+        Self {
+            r: encode(channel(0)),
+            g: encode(channel(1)),
+            b: encode(channel(2)),
+            a: (channel(3).clamp(0.0, 1.0) * 255.0).round() as u8,
+        }
+    }
+}
+
+/// Decode an IEEE 754 half-precision float.
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// A frame copied to CPU memory, which unlike `Frame` is not tied to the capture it came from.
+///
+/// Pixels are stored as tightly packed BGRA rows.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Copy `frame`, converting 10-bit frames (see `OutputFormat`) to BGRA with the 8 most
+    /// significant bits of every channel.
+    pub fn from_frame(frame: &Frame) -> Self {
+        let mut image = Self::from_frame_raw(frame);
+        if frame.pixel_format() == DXGI_FORMAT_R10G10B10A2_UNORM {
+            for pixel in image.data.chunks_exact_mut(4) {
+                let color = Color::from_rgb10a2(pixel);
+                pixel.copy_from_slice(&[color.b, color.g, color.r, color.a]);
+            }
+        }
+        image
+    }
+
+    /// Copy `frame` as is, i.e. in the frame's pixel format.
+    pub(crate) fn from_frame_raw(frame: &Frame) -> Self {
+        let mut data = Vec::with_capacity(frame.width() as usize * frame.height() as usize * 4);
+        for y in 0..frame.height() {
+            data.extend_from_slice(frame.row(y));
+        }
+        Self {
+            width: frame.width(),
+            height: frame.height(),
+            data,
+        }
+    }
+
+    /// Pixels of the `y`-th row.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let len = self.width as usize * 4;
+        &self.data[y as usize * len..][..len]
+    }
+
+    /// Shrink the image by an integer `factor`, every pixel being the average of a block of
+    /// `factor` x `factor` pixels (fewer at the right and bottom edges).
+    pub fn downscaled(&self, factor: u32) -> Image {
+        if factor <= 1 {
+            return self.clone();
+        }
+        let width = (self.width + factor - 1) / factor;
+        let height = (self.height + factor - 1) / factor;
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for by in 0..height {
+            let rows = by * factor..((by + 1) * factor).min(self.height);
+            for bx in 0..width {
+                let columns = (bx * factor) as usize..((bx + 1) * factor).min(self.width) as usize;
+                let (mut sum, mut count) = ([0u32; 4], 0);
+                for y in rows.clone() {
+                    for pixel in self.row(y)[columns.start * 4..columns.end * 4].chunks_exact(4) {
+                        for (sum, value) in sum.iter_mut().zip(pixel) {
+                            *sum += *value as u32;
+                        }
+                        count += 1;
+                    }
+                }
+                data.extend(sum.map(|sum| ((sum + count / 2) / count) as u8));
+            }
+        }
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Encode the image as a packed device-independent bitmap, i.e. a `BITMAPINFOHEADER` followed
+    /// by the pixels (the layout of `CF_DIB` clipboard data). Rows are stored bottom-up, as many
+    /// programs reading the clipboard do not handle top-down bitmaps.
+    pub fn to_dib(&self) -> Vec<u8> {
+        let mut dib = Vec::with_capacity(BITMAP_INFO_HEADER_SIZE as usize + self.data.len());
+        self.write_info_header(&mut dib);
+        self.write_rows_bottom_up(&mut dib);
+        dib
+    }
+
+    /// Save the image as a 32-bit BMP file.
+    pub fn save_bmp(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let offset = BITMAP_FILE_HEADER_SIZE + BITMAP_INFO_HEADER_SIZE;
+        let mut bmp = Vec::with_capacity(offset as usize + self.data.len());
+        bmp.extend_from_slice(b"BM");
+        bmp.extend_from_slice(&(offset + self.data.len() as u32).to_le_bytes());
+        bmp.extend_from_slice(&0u32.to_le_bytes());
+        bmp.extend_from_slice(&offset.to_le_bytes());
+        self.write_info_header(&mut bmp);
+        self.write_rows_bottom_up(&mut bmp);
+        File::create(path)?.write_all(&bmp)
+    }
+
+    /// Save the image as an uncompressed DDS texture (`DXGI_FORMAT_B8G8R8A8_UNORM`).
+    pub fn save_dds(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let rows = (0..self.height).map(|y| self.row(y));
+        write_dds(
+            path,
+            self.width,
+            self.height,
+            DXGI_FORMAT_B8G8R8A8_UNORM,
+            rows,
+        )
+    }
+
+    /// Load an 8-bit (or palette) PNG file. Alpha is taken as is, without premultiplying.
+    #[cfg(feature = "png")]
+    pub fn load_png(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let mut decoder = png::Decoder::new(std::io::BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(png_error)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(png_error)?;
+        let pixels = &buffer[..info.buffer_size()];
+        let data = match info.color_type {
+            png::ColorType::Rgba => pixels
+                .chunks_exact(4)
+                .flat_map(|p| [p[2], p[1], p[0], p[3]])
+                .collect(),
+            png::ColorType::Rgb => pixels
+                .chunks_exact(3)
+                .flat_map(|p| [p[2], p[1], p[0], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => pixels
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(crate::Error::EncodingError(
+                    "indexed PNG was not expanded".to_string(),
+                ))
+            }
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+
+    /// Save the image as an 8-bit RGBA PNG file. Alpha is stored as is.
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let file = std::io::BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let rgba: Vec<u8> = self
+            .data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect();
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&rgba))
+            .map_err(png_error)
+    }
+
+    /// Write the `BITMAPINFOHEADER` of a bottom-up DIB of the image.
+    fn write_info_header(&self, out: &mut Vec<u8>) {
+        let header = BITMAPINFOHEADER {
+            // positive height means rows are stored bottom-up
+            biHeight: self.height as i32,
+            ..dib_header(self.width, self.height)
+        };
+        out.extend_from_slice(&header.biSize.to_le_bytes());
+        out.extend_from_slice(&header.biWidth.to_le_bytes());
+        out.extend_from_slice(&header.biHeight.to_le_bytes());
+        out.extend_from_slice(&header.biPlanes.to_le_bytes());
+        out.extend_from_slice(&header.biBitCount.to_le_bytes());
+        out.extend_from_slice(&header.biCompression.to_le_bytes());
+        out.extend_from_slice(&header.biSizeImage.to_le_bytes());
+        // resolution and palette, all unused
+        out.extend_from_slice(&[0; 16]);
+    }
+
+    fn write_rows_bottom_up(&self, out: &mut Vec<u8>) {
+        for y in (0..self.height).rev() {
+            out.extend_from_slice(self.row(y));
+        }
+    }
+
+    /// Place the image on the clipboard as `CF_DIB`, replacing its current contents.
+    pub fn copy_to_clipboard(&self) -> Result<()> {
+        let dib = self.to_dib();
+        unsafe {
+            let memory = GlobalAlloc(GMEM_MOVEABLE, dib.len());
+            if memory == 0 {
+                return Err(Error::from_win32());
+            }
+            let ptr = GlobalLock(memory) as *mut u8;
+            if ptr.is_null() {
+                GlobalFree(memory);
+                return Err(Error::from_win32());
+            }
+            std::ptr::copy_nonoverlapping(dib.as_ptr(), ptr, dib.len());
+            GlobalUnlock(memory);
+
+            if let Err(e) = OpenClipboard(HWND::default()).ok() {
+                GlobalFree(memory);
+                return Err(e);
+            }
+            let result = EmptyClipboard()
+                .ok()
+                .and_then(|_| SetClipboardData(CF_DIB.0, HANDLE(memory)));
+            CloseClipboard();
+            if result.is_err() {
+                // the clipboard only takes ownership of the memory on success
+                GlobalFree(memory);
+            }
+            result.map(|_| ())
+        }
+    }
+}
+
+/// Header of a `width` x `height` 32bpp top-down DIB, the layout of `Image::data`.
+pub(crate) fn dib_header(width: u32, height: u32) -> BITMAPINFOHEADER {
+    BITMAPINFOHEADER {
+        biSize: BITMAP_INFO_HEADER_SIZE,
+        biWidth: width as i32,
+        // negative height means rows are stored top-down
+        biHeight: -(height as i32),
+        biPlanes: 1,
+        biBitCount: 32,
+        // BI_RGB
+        biCompression: 0,
+        biSizeImage: width * height * 4,
+        ..Default::default()
+    }
+}
+
+/// Let GDI `draw` into a memory DC with a `width` x `height` top-down DIB section selected (see
+/// `dib_header()`), whose pixels are also passed to `draw`, and return them if it succeeds.
+pub(crate) unsafe fn draw_dib(
+    width: u32,
+    height: u32,
+    draw: impl FnOnce(CreatedHDC, &mut [u8]) -> BOOL,
+) -> Result<Vec<u8>> {
+    let info = BITMAPINFO {
+        bmiHeader: dib_header(width, height),
+        ..Default::default()
+    };
+    let len = width as usize * height as usize * 4;
+    let mut data = vec![0u8; len];
+
+    let screen_dc = GetDC(HWND::default());
+    let dc = CreateCompatibleDC(screen_dc);
+    ReleaseDC(HWND::default(), screen_dc);
+
+    let mut bits = null_mut();
+    let result = CreateDIBSection(dc, &info, DIB_RGB_COLORS, &mut bits, HANDLE::default(), 0)
+        .and_then(|bitmap| {
+            let previous = SelectObject(dc, bitmap);
+            let drawn = draw(dc, std::slice::from_raw_parts_mut(bits as *mut u8, len));
+            GdiFlush();
+            if drawn.as_bool() {
+                std::ptr::copy_nonoverlapping(bits as *const u8, data.as_mut_ptr(), len);
+            }
+            SelectObject(dc, previous);
+            DeleteObject(bitmap);
+            drawn.ok()
+        });
+    DeleteDC(dc);
+    result?;
+    Ok(data)
+}
+
+/// Write a single 2D texture without mipmaps as a DDS file with a DX10 header, so that any DXGI
+/// format can be stored. `rows` are tightly packed, 32 bits per pixel.
+pub(crate) fn write_dds<'a>(
+    path: impl AsRef<Path>,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+    rows: impl Iterator<Item = &'a [u8]>,
+) -> std::io::Result<()> {
+    let pitch = width * 4;
+    let mut dds = Vec::with_capacity(148 + (pitch * height) as usize);
+    dds.extend_from_slice(b"DDS ");
+    // DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PITCH | DDSD_PIXELFORMAT
+    for field in [DDS_HEADER_SIZE, 0x100f, height, width, pitch, 0, 1] {
+        dds.extend_from_slice(&field.to_le_bytes());
+    }
+    dds.extend_from_slice(&[0; 44]);
+    // pixel format: DDPF_FOURCC with the DX10 header following
+    dds.extend_from_slice(&DDS_PIXEL_FORMAT_SIZE.to_le_bytes());
+    dds.extend_from_slice(&4u32.to_le_bytes());
+    dds.extend_from_slice(b"DX10");
+    dds.extend_from_slice(&[0; 20]);
+    // DDSCAPS_TEXTURE, then caps2-4 and reserved
+    dds.extend_from_slice(&0x1000u32.to_le_bytes());
+    dds.extend_from_slice(&[0; 16]);
+    // DX10 header: format, D3D10_RESOURCE_DIMENSION_TEXTURE2D, no flags, array size 1, alpha mode
+    // unknown (frames may have been converted, see `AlphaMode`)
+    for field in [format.0, 3, 0, 1, 0] {
+        dds.extend_from_slice(&field.to_le_bytes());
+    }
+    for row in rows {
+        dds.extend_from_slice(row);
+    }
+    File::create(path)?.write_all(&dds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dib_rows_are_bottom_up() {
+        let image = Image {
+            width: 1,
+            height: 3,
+            data: vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3],
+        };
+        let dib = image.to_dib();
+        let field = |offset: usize| i32::from_le_bytes(dib[offset..offset + 4].try_into().unwrap());
+        assert_eq!(field(0), BITMAP_INFO_HEADER_SIZE as i32);
+        assert_eq!((field(4), field(8)), (1, 3));
+        assert_eq!(field(20), 12);
+        let pixels = &dib[BITMAP_INFO_HEADER_SIZE as usize..];
+        assert_eq!(pixels, [3, 3, 3, 3, 2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(half_to_f32(0x0000), 0.0);
+        assert_eq!(half_to_f32(0x3c00), 1.0);
+        assert_eq!(half_to_f32(0xc000), -2.0);
+        assert_eq!(half_to_f32(0x3800), 0.5);
+        assert_eq!(half_to_f32(0x7bff), 65504.0);
+        // smallest subnormal
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert_eq!(half_to_f32(0xfc00), f32::NEG_INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn scrgb_is_encoded_as_srgb() {
+        let pixel = |r: u16, a: u16| [r.to_le_bytes(), [0; 2], [0; 2], a.to_le_bytes()].concat();
+        let color = Color::from_scrgb(&pixel(0x3c00, 0x3c00));
+        assert_eq!((color.r, color.g, color.a), (255, 0, 255));
+        // linear 0.5 is sRGB 188, and colors brighter than white are clipped
+        assert_eq!(Color::from_scrgb(&pixel(0x3800, 0x3800)).r, 188);
+        assert_eq!(Color::from_scrgb(&pixel(0x4000, 0x3c00)).r, 255);
+    }
+
+    #[test]
+    fn dds_header() {
+        let path = std::env::temp_dir().join(format!("zbl-test-{}.dds", std::process::id()));
+        let rows = [[1u8; 8], [2u8; 8]];
+        write_dds(
+            &path,
+            2,
+            2,
+            DXGI_FORMAT_B8G8R8A8_UNORM,
+            rows.iter().map(|row| &row[..]),
+        )
+        .unwrap();
+        let dds = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let field = |offset: usize| u32::from_le_bytes(dds[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&dds[..4], b"DDS ");
+        assert_eq!(dds.len(), 148 + 16);
+        assert_eq!(field(4), DDS_HEADER_SIZE);
+        // height, width, pitch
+        assert_eq!((field(12), field(16), field(20)), (2, 2, 8));
+        assert_eq!(field(76), DDS_PIXEL_FORMAT_SIZE);
+        assert_eq!(&dds[84..88], b"DX10");
+        assert_eq!(field(128), DXGI_FORMAT_B8G8R8A8_UNORM.0);
+        assert_eq!(&dds[148..], [[1u8; 8], [2u8; 8]].concat());
+    }
+
+    #[test]
+    fn downscaling_averages_blocks() {
+        let image = Image {
+            width: 3,
+            height: 2,
+            data: [[0; 4], [4; 4], [8; 4], [2; 4], [7; 4], [9; 4]].concat(),
+        };
+        let downscaled = image.downscaled(2);
+        assert_eq!((downscaled.width, downscaled.height), (2, 1));
+        // (0 + 4 + 2 + 7) / 4 rounds to 3, the partial block at the right edge averages 8 and 9
+        assert_eq!(downscaled.data, [[3; 4], [9; 4]].concat());
+        assert_eq!(image.downscaled(1).data, image.data);
+        assert_eq!(image.downscaled(0).data, image.data);
+    }
+}
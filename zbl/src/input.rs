@@ -0,0 +1,217 @@
+use std::{
+    sync::{
+        mpsc::{channel, sync_channel, Receiver, SyncSender, TrySendError},
+        RwLock,
+    },
+    time::Instant,
+};
+
+use lazy_static::lazy_static;
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, POINT, WPARAM},
+        UI::WindowsAndMessaging::{
+            CallNextHookEx, GetCursorPos, GetMessageW, SetWindowsHookExW, HC_ACTION, HHOOK,
+            HOOKPROC, KBDLLHOOKSTRUCT, MSG, MSLLHOOKSTRUCT, WH_KEYBOARD_LL, WH_MOUSE_LL,
+            WINDOWS_HOOK_ID, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN,
+            WM_MBUTTONUP, WM_MOUSEMOVE, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+        },
+    },
+};
+
+use crate::util::system_relative_time;
+
+struct Subscriber {
+    sender: SyncSender<InputEvent>,
+    /// Whether to send events of the given kind, so that unwanted events do not fill the buffer.
+    accepts: fn(&InputEventKind) -> bool,
+}
+
+lazy_static! {
+    static ref INPUT_SUBSCRIBERS: RwLock<Vec<Subscriber>> = Default::default();
+    static ref MOUSE_HOOK: Result<()> = run_hook(WH_MOUSE_LL, Some(low_level_mouse_proc));
+    static ref KEYBOARD_HOOK: Result<()> = run_hook(WH_KEYBOARD_LL, Some(low_level_keyboard_proc));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputEventKind {
+    MouseMove,
+    MouseDown(MouseButton),
+    MouseUp(MouseButton),
+    /// A key was pressed (or auto-repeated), with its virtual-key code.
+    KeyDown(u32),
+    /// A key was released, with its virtual-key code.
+    KeyUp(u32),
+}
+
+impl InputEventKind {
+    pub fn is_keyboard(&self) -> bool {
+        matches!(self, InputEventKind::KeyDown(_) | InputEventKind::KeyUp(_))
+    }
+
+    pub fn is_button(&self) -> bool {
+        matches!(
+            self,
+            InputEventKind::MouseDown(_) | InputEventKind::MouseUp(_)
+        )
+    }
+}
+
+/// Global input event, as seen by a low-level hook.
+#[derive(Clone, Copy, Debug)]
+pub struct InputEvent {
+    pub kind: InputEventKind,
+    /// Cursor position on the virtual screen.
+    pub position: POINT,
+    pub time: Instant,
+    /// Time of the event in 100ns units, on the same clock as `Frame::timestamp`.
+    pub timestamp: i64,
+}
+
+unsafe extern "system" fn low_level_mouse_proc(
+    code: i32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const MSLLHOOKSTRUCT);
+        let kind = match wparam.0 as u32 {
+            WM_MOUSEMOVE => Some(InputEventKind::MouseMove),
+            WM_LBUTTONDOWN => Some(InputEventKind::MouseDown(MouseButton::Left)),
+            WM_LBUTTONUP => Some(InputEventKind::MouseUp(MouseButton::Left)),
+            WM_RBUTTONDOWN => Some(InputEventKind::MouseDown(MouseButton::Right)),
+            WM_RBUTTONUP => Some(InputEventKind::MouseUp(MouseButton::Right)),
+            WM_MBUTTONDOWN => Some(InputEventKind::MouseDown(MouseButton::Middle)),
+            WM_MBUTTONUP => Some(InputEventKind::MouseUp(MouseButton::Middle)),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            publish(InputEvent {
+                kind,
+                position: info.pt,
+                time: Instant::now(),
+                timestamp: system_relative_time(),
+            });
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+unsafe extern "system" fn low_level_keyboard_proc(
+    code: i32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if code == HC_ACTION as i32 {
+        let info = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        let kind = match wparam.0 as u32 {
+            WM_KEYDOWN | WM_SYSKEYDOWN => Some(InputEventKind::KeyDown(info.vkCode)),
+            WM_KEYUP | WM_SYSKEYUP => Some(InputEventKind::KeyUp(info.vkCode)),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let mut position = POINT::default();
+            GetCursorPos(&mut position as *mut _);
+            publish(InputEvent {
+                kind,
+                position,
+                time: Instant::now(),
+                timestamp: system_relative_time(),
+            });
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+fn publish(event: InputEvent) {
+    // both hook threads publish, so pruning has to happen under the same lock as sending
+    let mut subscribers = match INPUT_SUBSCRIBERS.write() {
+        Ok(subscribers) => subscribers,
+        Err(_) => return,
+    };
+    subscribers.retain(|subscriber| {
+        if !(subscriber.accepts)(&event.kind) {
+            return true;
+        }
+        !matches!(
+            subscriber.sender.try_send(event),
+            Err(TrySendError::Disconnected(_))
+        )
+    });
+}
+
+/// Install a hook on a dedicated thread, which pumps messages for it for the rest of the process'
+/// lifetime. Returns once the hook is installed.
+fn run_hook(id: WINDOWS_HOOK_ID, proc: HOOKPROC) -> Result<()> {
+    let (installed_sender, installed) = channel();
+    std::thread::spawn(move || unsafe {
+        let hook = SetWindowsHookExW(id, proc, HINSTANCE::default(), 0);
+        let failed = hook.is_err();
+        installed_sender.send(hook.map(|_| ())).ok();
+        if failed {
+            return;
+        }
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {}
+    });
+    // the thread always sends before doing anything else
+    installed.recv().unwrap()
+}
+
+fn add_subscriber(capacity: usize, accepts: fn(&InputEventKind) -> bool) -> Receiver<InputEvent> {
+    let (sender, receiver) = sync_channel(capacity);
+    INPUT_SUBSCRIBERS
+        .write()
+        .unwrap()
+        .push(Subscriber { sender, accepts });
+    receiver
+}
+
+/// Subscribe to global mouse events.
+///
+/// Events are buffered up to `capacity`, newer events are dropped if the receiver does not keep up.
+/// Fails if the hook cannot be installed.
+pub fn subscribe(capacity: usize) -> Result<Receiver<InputEvent>> {
+    MOUSE_HOOK.clone()?;
+    Ok(add_subscriber(capacity, |kind| !kind.is_keyboard()))
+}
+
+/// Subscribe to mouse button presses and releases only, see `subscribe()`.
+///
+/// Mouse moves are far more frequent than clicks, and would push them out of a buffer which is not
+/// drained often.
+pub fn subscribe_buttons(capacity: usize) -> Result<Receiver<InputEvent>> {
+    MOUSE_HOOK.clone()?;
+    Ok(add_subscriber(capacity, InputEventKind::is_button))
+}
+
+/// Subscribe to global mouse and keyboard events, see `subscribe()`.
+///
+/// The keyboard hook sees every key pressed anywhere on the desktop, so only use this where users
+/// expect their input to be recorded.
+pub fn subscribe_with_keyboard(capacity: usize) -> Result<Receiver<InputEvent>> {
+    MOUSE_HOOK.clone()?;
+    KEYBOARD_HOOK.clone()?;
+    Ok(add_subscriber(capacity, |_| true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn button_subscribers_do_not_see_moves() {
+        assert!(InputEventKind::MouseDown(MouseButton::Left).is_button());
+        assert!(InputEventKind::MouseUp(MouseButton::Middle).is_button());
+        assert!(!InputEventKind::MouseMove.is_button());
+        assert!(!InputEventKind::KeyDown(0x41).is_button());
+    }
+}
@@ -0,0 +1,94 @@
+//! Capturing layered windows (`WS_EX_LAYERED`) with their transparency.
+//!
+//! Windows.Graphics.Capture composes layered windows onto an opaque background, so both per-pixel
+//! alpha (`UpdateLayeredWindow`) and per-window alpha or color keys (`SetLayeredWindowAttributes`)
+//! are lost. This goes through GDI instead, which is much slower, but keeps them.
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::{COLORREF, HWND, RECT},
+        Storage::Xps::{PrintWindow, PRINT_WINDOW_FLAGS},
+        UI::WindowsAndMessaging::{
+            GetLayeredWindowAttributes, GetWindowLongW, GetWindowRect, GWL_EXSTYLE,
+            LAYERED_WINDOW_ATTRIBUTES_FLAGS, LWA_ALPHA, LWA_COLORKEY, WS_EX_LAYERED,
+        },
+    },
+};
+
+use crate::{image::draw_dib, Image};
+
+/// Whether `handle` is a layered window.
+pub fn is_layered(handle: HWND) -> bool {
+    let ex_style = unsafe { GetWindowLongW(handle, GWL_EXSTYLE) } as u32;
+    ex_style & WS_EX_LAYERED.0 != 0
+}
+
+/// Capture the whole window `handle` (including its frame, if any) with premultiplied alpha, the
+/// same as frames produced by `Capture`.
+///
+/// Windows which are not layered are returned fully opaque.
+pub fn capture_layered_window(handle: HWND) -> Result<Image> {
+    let mut rect = RECT::default();
+    unsafe { GetWindowRect(handle, &mut rect as *mut _).ok()? };
+    let width = (rect.right - rect.left).max(0) as u32;
+    let height = (rect.bottom - rect.top).max(0) as u32;
+
+    let mut image = Image {
+        width,
+        height,
+        data: print_window(handle, width, height)?,
+    };
+
+    let mut key = COLORREF(0);
+    let mut alpha = 0u8;
+    let mut flags = LAYERED_WINDOW_ATTRIBUTES_FLAGS(0);
+    // this fails for windows using `UpdateLayeredWindow`, whose contents carry per-pixel alpha
+    let has_attributes = unsafe {
+        GetLayeredWindowAttributes(
+            handle,
+            Some(&mut key as *mut _),
+            Some(&mut alpha as *mut _),
+            Some(&mut flags as *mut _),
+        )
+    }
+    .as_bool();
+    if has_attributes || !is_layered(handle) {
+        // the alpha channel of GDI-drawn contents is meaningless
+        for pixel in image.data.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+    }
+    if has_attributes {
+        apply_layered_attributes(&mut image, flags, key, alpha);
+    }
+    Ok(image)
+}
+
+fn print_window(handle: HWND, width: u32, height: u32) -> Result<Vec<u8>> {
+    unsafe {
+        draw_dib(width, height, |dc, _| {
+            PrintWindow(handle, dc, PRINT_WINDOW_FLAGS(0))
+        })
+    }
+}
+
+/// Apply the transparency set with `SetLayeredWindowAttributes` to opaque contents.
+fn apply_layered_attributes(
+    image: &mut Image,
+    flags: LAYERED_WINDOW_ATTRIBUTES_FLAGS,
+    key: COLORREF,
+    alpha: u8,
+) {
+    // COLORREF is 0x00BBGGRR, pixels are BGRA
+    let key = [(key.0 >> 16) as u8, (key.0 >> 8) as u8, key.0 as u8];
+    for pixel in image.data.chunks_exact_mut(4) {
+        if flags.0 & LWA_COLORKEY.0 != 0 && pixel[..3] == key {
+            pixel.fill(0);
+        } else if flags.0 & LWA_ALPHA.0 != 0 {
+            for channel in pixel.iter_mut() {
+                *channel = (*channel as u32 * alpha as u32 / 255) as u8;
+            }
+        }
+    }
+}
@@ -1,28 +1,87 @@
+pub mod capturable;
 pub mod capture;
+pub mod capture_thread;
+pub mod config;
+pub mod cross_adapter;
+pub mod cursor;
+pub(crate) mod dispatcher;
 pub mod display;
+pub mod elevation;
+pub mod error;
+#[cfg(feature = "etw")]
+pub mod etw;
+pub mod event;
+pub mod fake;
+pub mod follow_mouse;
+pub mod frame_callback;
+pub mod frame_stats;
+pub mod golden;
+pub mod gpu_timer;
+pub mod hang;
+pub mod image;
+pub mod input;
+pub mod layered;
+pub mod manager;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mosaic;
+pub mod observer;
+pub mod pipeline;
+pub mod power;
+#[cfg(feature = "serde")]
+pub mod profile;
+pub mod region;
+pub mod replay;
+pub mod screenshot;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod sink;
+pub mod sprite_sheet;
 pub mod staging_texture;
+pub mod template_tracker;
 pub mod util;
+pub mod virtual_desktop;
 pub mod window;
 
-pub use capture::{Capture, Frame};
-pub use display::Display;
-pub use window::Window;
+pub use capturable::Capturable;
+pub use capture::{Capture, Frame, FrameSource, GrabStatus, MemoryUsage};
+pub use capture_thread::{CaptureThread, ThreadFrame};
+pub use config::{
+    AlphaMode, Backoff, CaptureBuilder, CaptureConfig, CaptureRate, HangDetection, Insets,
+    MapRetry, MemoryCap, OutputFormat, OverflowPolicy, PowerThrottle, ScaleMode, TransientRetry,
+};
+pub use cross_adapter::CrossAdapterCopy;
+pub use cursor::CursorShape;
+pub use dispatcher::init_dispatcher_queue;
+pub use display::{Display, DisplayConnection, DisplayInfo, DisplayOutput};
+pub use error::{Error, Result};
+pub use event::CaptureEvent;
+pub use fake::{FakeCapture, Pattern};
+pub use follow_mouse::FollowMouse;
+pub use frame_stats::FrameStats;
+pub use image::{Color, Image};
+pub use manager::{CaptureManager, SessionId, SessionQos};
+#[cfg(feature = "serde")]
+pub use manager::{ManagerState, RestoredSessions, SavedSession};
+pub use metadata::{FrameMetadata, MetadataValue};
+pub use mosaic::Mosaic;
+pub use observer::Observer;
+pub use power::PowerState;
+pub use region::Region;
+pub use replay::ReplayBuffer;
+pub use screenshot::{screenshot_all_monitors, screenshot_all_monitors_stitched};
+pub use sprite_sheet::{Sprite, SpriteSheet};
+pub use template_tracker::{TemplateTracker, TemplateTracking, TrackedFrame};
+pub use window::{Visibility, Window, WindowInfo};
 
 // re-export winapi
 pub use windows;
 
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    mpsc::Receiver,
-};
-use windows::{
-    core::Result,
-    Graphics::Capture::GraphicsCaptureItem,
-    Win32::{
-        Graphics::Direct3D11::D3D11_BOX,
-        System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
-        UI::HiDpi::{SetProcessDpiAwareness, PROCESS_PER_MONITOR_DPI_AWARE},
-    },
+use std::sync::atomic::{AtomicBool, Ordering};
+use windows::Win32::{
+    System::WinRT::{RoInitialize, RO_INIT_MULTITHREADED},
+    UI::HiDpi::{SetProcessDpiAwareness, PROCESS_PER_MONITOR_DPI_AWARE},
 };
 
 pub fn init() {
@@ -45,13 +104,3 @@ pub fn set_dpi_aware() {
         SetProcessDpiAwareness(PROCESS_PER_MONITOR_DPI_AWARE).ok();
     }
 }
-
-pub trait Capturable {
-    fn create_capture_item(&self) -> Result<GraphicsCaptureItem>;
-
-    fn get_client_box(&self) -> Result<D3D11_BOX>;
-
-    fn get_close_notification_channel(&self) -> Receiver<()>;
-
-    fn get_raw_handle(&self) -> isize;
-}
@@ -0,0 +1,456 @@
+//! Running many captures at once, see `CaptureManager`.
+
+#[cfg(feature = "json")]
+use std::path::Path;
+use std::{
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use windows::Win32::Graphics::Direct3D11::ID3D11Device;
+
+use crate::{
+    error::Context,
+    event::{CaptureEvent, EventSender},
+    util::create_d3d_device,
+    Capture, CaptureBuilder, CaptureRate, GrabStatus,
+};
+#[cfg(feature = "serde")]
+use crate::{profile::CaptureProfile, Error};
+
+/// Identifies a capture added to a `CaptureManager`. Ids are never reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionId(pub u64);
+
+/// How a session is scheduled by its `CaptureManager`, e.g. the focused window with a high
+/// priority at 60 fps and background windows at 1 fps.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct SessionQos {
+    /// Sessions with higher priorities are read back first, and get the bandwidth (see
+    /// `CaptureManager::set_bandwidth_limit`) before sessions with lower priorities.
+    pub priority: i32,
+    /// If set, replaces the rate the session was built with.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub rate: Option<CaptureRate>,
+}
+
+/// A session as saved in a `ManagerState`.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SavedSession {
+    pub id: SessionId,
+    pub profile: CaptureProfile,
+    #[serde(default)]
+    pub qos: SessionQos,
+}
+
+/// The sessions of a `CaptureManager` which were added from profiles, so that a service can
+/// resume its captures after a restart (e.g. after crashing), see `CaptureManager::restore`.
+#[cfg(feature = "serde")]
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct ManagerState {
+    pub sessions: Vec<SavedSession>,
+}
+
+/// Outcome of `CaptureManager::restore`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Default)]
+pub struct RestoredSessions {
+    /// Saved id and id in the manager of every restored session, which differ when restoring
+    /// into a manager which already had sessions.
+    pub restored: Vec<(SessionId, SessionId)>,
+    /// Saved id of every session which could not be restored, with the reason.
+    pub failed: Vec<(SessionId, Error)>,
+}
+
+#[cfg(feature = "json")]
+impl ManagerState {
+    pub fn to_json(&self) -> crate::Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> crate::Result<Self> {
+        serde_json::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    /// Write the state to a JSON file. The file is replaced in one go, so a crash while saving
+    /// leaves the previous state intact.
+    pub fn save(&self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, self.to_json()?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Read a state written by `save`.
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+struct ManagedCapture {
+    id: SessionId,
+    capture: Capture,
+    events: Receiver<CaptureEvent>,
+    closed: bool,
+    qos: SessionQos,
+    /// Rate the capture was built with, restored when the QoS rate is unset.
+    base_rate: CaptureRate,
+    /// Size of the last frame read back, to tell whether the next one fits into the bandwidth.
+    frame_bytes: u64,
+    /// Profile the session was added from, for `CaptureManager::state`.
+    #[cfg(feature = "serde")]
+    profile: Option<CaptureProfile>,
+}
+
+/// Readback bandwidth shared by all sessions, refilled continuously. Up to a second's worth can
+/// be saved up for bursts.
+struct Bandwidth {
+    bytes_per_second: u64,
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl Bandwidth {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.available = (self.available + elapsed * self.bytes_per_second as f64)
+            .min(self.bytes_per_second as f64);
+        self.refilled_at = now;
+    }
+}
+
+/// Owns many simultaneous captures (e.g. dozens of windows) on a single D3D11 device, and serves
+/// their frames and events as one stream keyed by `SessionId`.
+///
+/// Frames are read back one at a time: every `grab()` returns at most one frame, and sessions take
+/// turns, so that a few busy targets cannot starve the others and readbacks do not pile up on the
+/// bus. Sessions can be given priorities and rates, and the total readback bandwidth can be
+/// limited (see `SessionQos`).
+pub struct CaptureManager {
+    device: ID3D11Device,
+    captures: Vec<ManagedCapture>,
+    next_id: u64,
+    /// Index of the capture to poll first on the next grab.
+    next_index: usize,
+    events: EventSender<(SessionId, CaptureEvent)>,
+    bandwidth: Option<Bandwidth>,
+}
+
+impl CaptureManager {
+    pub fn new() -> crate::Result<Self> {
+        Ok(Self {
+            device: create_d3d_device().context("creating the D3D11 device")?,
+            captures: Vec::new(),
+            next_id: 0,
+            next_index: 0,
+            events: Default::default(),
+            bandwidth: None,
+        })
+    }
+
+    /// Get the D3D11 device shared by all captures.
+    pub fn device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Build a capture on the shared device and start it.
+    ///
+    /// The capture's grab timeout is overridden, as `grab()` polls the sessions without waiting.
+    pub fn add(&mut self, builder: CaptureBuilder) -> crate::Result<SessionId> {
+        let id = SessionId(self.next_id);
+        self.insert(id, builder)?;
+        Ok(id)
+    }
+
+    fn insert(&mut self, id: SessionId, builder: CaptureBuilder) -> crate::Result<()> {
+        let mut capture = builder
+            .device(self.device.clone())
+            .grab_timeout(Duration::ZERO)
+            .build()?;
+        let events = capture.events();
+        capture.start()?;
+        self.next_id = self.next_id.max(id.0 + 1);
+        self.captures.push(ManagedCapture {
+            id,
+            base_rate: capture.config().rate,
+            capture,
+            events,
+            closed: false,
+            qos: Default::default(),
+            frame_bytes: 0,
+            #[cfg(feature = "serde")]
+            profile: None,
+        });
+        Ok(())
+    }
+
+    /// Add a capture of the target of `profile`, like `add`. Unlike other sessions, sessions added
+    /// from profiles are part of the `state()`.
+    #[cfg(feature = "serde")]
+    pub fn add_profile(&mut self, profile: CaptureProfile) -> crate::Result<SessionId> {
+        let id = self.add(profile.builder()?)?;
+        self.captures.last_mut().unwrap().profile = Some(profile);
+        Ok(id)
+    }
+
+    /// The sessions added from profiles whose targets have not been closed, along with their
+    /// QoS. Save it (e.g. with `ManagerState::save`) whenever sessions change to be able to
+    /// `restore` them.
+    #[cfg(feature = "serde")]
+    pub fn state(&self) -> ManagerState {
+        let sessions = self
+            .captures
+            .iter()
+            .filter(|managed| !managed.closed)
+            .filter_map(|managed| {
+                Some(SavedSession {
+                    id: managed.id,
+                    profile: managed.profile.clone()?,
+                    qos: managed.qos,
+                })
+            })
+            .collect();
+        ManagerState { sessions }
+    }
+
+    /// Add the sessions of a saved state, e.g. after the process has been restarted.
+    ///
+    /// Restoring into a new manager keeps the saved session ids, otherwise sessions get new ones
+    /// (as ids are never reused); the returned `RestoredSessions` maps the saved ids to the new
+    /// ones. Targets are looked up again through the profiles, so e.g. a window which has been
+    /// reopened in the meantime is found as well. Sessions which cannot be restored (e.g. because
+    /// their target is gone) are skipped, and returned with the error.
+    #[cfg(feature = "serde")]
+    pub fn restore(&mut self, state: &ManagerState) -> RestoredSessions {
+        let keep_ids = self.next_id == 0;
+        let mut result = RestoredSessions::default();
+        for saved in &state.sessions {
+            let id = if keep_ids {
+                saved.id
+            } else {
+                SessionId(self.next_id)
+            };
+            let restored = saved
+                .profile
+                .builder()
+                .and_then(|builder| self.insert(id, builder));
+            match restored {
+                Ok(()) => {
+                    self.captures.last_mut().unwrap().profile = Some(saved.profile.clone());
+                    self.set_qos(id, saved.qos);
+                    result.restored.push((saved.id, id));
+                }
+                Err(error) => result.failed.push((saved.id, error)),
+            }
+        }
+        result
+    }
+
+    /// Change how a session is scheduled. Returns `false` if there is no such session.
+    pub fn set_qos(&mut self, id: SessionId, qos: SessionQos) -> bool {
+        let managed = match self.captures.iter_mut().find(|managed| managed.id == id) {
+            Some(managed) => managed,
+            None => return false,
+        };
+        managed.qos = qos;
+        managed
+            .capture
+            .set_rate(qos.rate.unwrap_or(managed.base_rate));
+        true
+    }
+
+    pub fn qos(&self, id: SessionId) -> Option<SessionQos> {
+        self.captures
+            .iter()
+            .find(|managed| managed.id == id)
+            .map(|managed| managed.qos)
+    }
+
+    /// Limit the bytes read back per second by all sessions together, or lift the limit with
+    /// `None`. Sessions whose next frame does not fit are skipped until enough bandwidth has
+    /// accumulated. Meanwhile, sessions with lower priorities are skipped as well, so that they do
+    /// not use up the bandwidth it is saved up for.
+    pub fn set_bandwidth_limit(&mut self, bytes_per_second: Option<u64>) {
+        self.bandwidth = bytes_per_second.map(|bytes_per_second| Bandwidth {
+            bytes_per_second,
+            available: bytes_per_second as f64,
+            refilled_at: Instant::now(),
+        });
+    }
+
+    /// Remove a session, handing its capture back (it keeps running until dropped or stopped).
+    pub fn remove(&mut self, id: SessionId) -> Option<Capture> {
+        let index = self.captures.iter().position(|managed| managed.id == id)?;
+        Some(self.captures.remove(index).capture)
+    }
+
+    pub fn session(&self, id: SessionId) -> Option<&Capture> {
+        self.captures
+            .iter()
+            .find(|managed| managed.id == id)
+            .map(|managed| &managed.capture)
+    }
+
+    pub fn session_mut(&mut self, id: SessionId) -> Option<&mut Capture> {
+        self.captures
+            .iter_mut()
+            .find(|managed| managed.id == id)
+            .map(|managed| &mut managed.capture)
+    }
+
+    /// Ids of all sessions, in the order they were added.
+    pub fn session_ids(&self) -> impl Iterator<Item = SessionId> + '_ {
+        self.captures.iter().map(|managed| managed.id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.captures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.captures.is_empty()
+    }
+
+    /// Subscribe to the events of all sessions. Events are forwarded while grabbing.
+    pub fn events(&mut self) -> Receiver<(SessionId, CaptureEvent)> {
+        self.events.subscribe()
+    }
+
+    /// Get the next frame of any session.
+    ///
+    /// This never blocks: returns `None` if no session has produced a new frame since the last
+    /// call (or none fits into the bandwidth limit). Sessions are polled by priority, and in turns
+    /// among equal priorities. A session whose target has been closed is reported once with
+    /// `GrabStatus::Closed`, and not polled anymore.
+    ///
+    /// Errors are reported along with the session they occurred in, which takes its turn like any
+    /// other, so that a failing session does not keep the others from being polled.
+    pub fn grab(&mut self) -> Option<(SessionId, crate::Result<GrabStatus>)> {
+        self.forward_events();
+        if let Some(bandwidth) = self.bandwidth.as_mut() {
+            bandwidth.refill();
+        }
+        // priority of the first session which did not fit into the bandwidth, for which the
+        // bandwidth is saved up by not admitting sessions with lower priorities
+        let mut blocked_priority = None;
+        for index in self.polling_order() {
+            let managed = &mut self.captures[index];
+            if managed.closed {
+                continue;
+            }
+            if matches!(blocked_priority, Some(priority) if managed.qos.priority < priority) {
+                break;
+            }
+            if let Some(bandwidth) = &self.bandwidth {
+                // frames larger than the limit go through whenever the full second is available
+                let needed = managed.frame_bytes.min(bandwidth.bytes_per_second);
+                if needed as f64 > bandwidth.available {
+                    blocked_priority.get_or_insert(managed.qos.priority);
+                    continue;
+                }
+            }
+            let frame_bytes = match managed.capture.grab() {
+                Ok(GrabStatus::Frame(frame)) => {
+                    Ok(Some(frame.width() as u64 * frame.height() as u64 * 4))
+                }
+                Ok(GrabStatus::Closed) => Ok(None),
+                Ok(_) => continue,
+                Err(error) => Err(error),
+            };
+            self.next_index = index + 1;
+            let managed = &mut self.captures[index];
+            let frame_bytes = match frame_bytes {
+                Ok(Some(frame_bytes)) => frame_bytes,
+                Ok(None) => {
+                    managed.closed = true;
+                    return Some((managed.id, Ok(GrabStatus::Closed)));
+                }
+                Err(error) => return Some((managed.id, Err(error))),
+            };
+            managed.frame_bytes = frame_bytes;
+            if let Some(bandwidth) = self.bandwidth.as_mut() {
+                bandwidth.available -= frame_bytes as f64;
+            }
+            // the frame is only borrowed from the capture again here, as the borrow checker cannot
+            // tell that it is only returned from this iteration
+            return Some((
+                managed.id,
+                managed.capture.current_frame().map_err(Into::into),
+            ));
+        }
+        None
+    }
+
+    /// Indices of the captures by descending priority, starting after the last returned one
+    /// within each priority.
+    fn polling_order(&self) -> Vec<usize> {
+        let count = self.captures.len();
+        let mut order: Vec<usize> = (0..count)
+            .map(|offset| (self.next_index + offset) % count)
+            .collect();
+        // stable, so that the turns are kept within each priority
+        order.sort_by_key(|&index| std::cmp::Reverse(self.captures[index].qos.priority));
+        order
+    }
+
+    fn forward_events(&mut self) {
+        for managed in &self.captures {
+            for event in managed.events.try_iter() {
+                self.events.emit((managed.id, event));
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "json"))]
+mod tests {
+    use super::*;
+    use crate::profile::Target;
+
+    #[test]
+    fn state_json_round_trip() {
+        let saved = |id, target, priority| SavedSession {
+            id: SessionId(id),
+            profile: CaptureProfile {
+                target,
+                config: Default::default(),
+            },
+            qos: SessionQos {
+                priority,
+                rate: Some(CaptureRate::Fps(1.0)),
+            },
+        };
+        let state = ManagerState {
+            sessions: vec![
+                saved(3, Target::Window("editor".into()), 1),
+                saved(7, Target::Display(0), -1),
+            ],
+        };
+        let json = state.to_json().unwrap();
+        let loaded = ManagerState::from_json(&json).unwrap();
+        assert_eq!(loaded.to_json().unwrap(), json);
+        let ids: Vec<_> = loaded.sessions.iter().map(|saved| saved.id).collect();
+        assert_eq!(ids, [SessionId(3), SessionId(7)]);
+        assert_eq!(
+            loaded.sessions[0].profile.target,
+            Target::Window("editor".into())
+        );
+        assert_eq!(loaded.sessions[1].qos, state.sessions[1].qos);
+    }
+
+    #[test]
+    fn missing_qos_defaults() {
+        let json = r#"{ "sessions": [{ "id": 1, "profile": { "target": { "display": 0 } } }] }"#;
+        let state = ManagerState::from_json(json).unwrap();
+        assert_eq!(state.sessions[0].qos, SessionQos::default());
+    }
+}
@@ -0,0 +1,229 @@
+//! Application-defined data attached to frames, e.g. the results of running a detector on them.
+//!
+//! Metadata is sent along with frames by `Sink::send_with_metadata`, so that consumers on the
+//! other end of a pipe or socket receive it in sync with the frames it describes.
+
+use std::collections::BTreeMap;
+
+/// A single metadata value.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum MetadataValue {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl MetadataValue {
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Int(_) => 0,
+            Self::Float(_) => 1,
+            Self::Text(_) => 2,
+            Self::Bytes(_) => 3,
+        }
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        Self::Text(value.to_string())
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        Self::Text(value)
+    }
+}
+
+impl From<Vec<u8>> for MetadataValue {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Bytes(value)
+    }
+}
+
+/// Named values describing a frame.
+///
+/// Serialized by `to_bytes()` as little-endian binary: the number of entries (`u32`), then for
+/// every entry in key order the length of the key (`u32`), the key (UTF-8), the type of the value
+/// (`u8`: 0 = `i64`, 1 = `f64`, 2 = UTF-8 text, 3 = bytes) and the value. Text and bytes are
+/// preceded by their length (`u32`).
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameMetadata(BTreeMap<String, MetadataValue>);
+
+impl FrameMetadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `key` to `value`, replacing any previous value.
+    pub fn insert(&mut self, key: &str, value: impl Into<MetadataValue>) -> &mut Self {
+        self.0.insert(key.to_string(), value.into());
+        self
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.0.get(key)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<MetadataValue> {
+        self.0.remove(key)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &MetadataValue)> {
+        self.0.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.0.len() as u32).to_le_bytes());
+        for (key, value) in &self.0 {
+            bytes.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(value.tag());
+            match value {
+                MetadataValue::Int(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+                MetadataValue::Float(value) => bytes.extend_from_slice(&value.to_le_bytes()),
+                MetadataValue::Text(text) => {
+                    bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(text.as_bytes());
+                }
+                MetadataValue::Bytes(data) => {
+                    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                    bytes.extend_from_slice(data);
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Parse metadata serialized by `to_bytes()`. Returns `None` if `bytes` are malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let count = reader.u32()?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let key_len = reader.u32()? as usize;
+            let key = String::from_utf8(reader.take(key_len)?.to_vec()).ok()?;
+            let value = match reader.take(1)?[0] {
+                0 => MetadataValue::Int(i64::from_le_bytes(reader.take(8)?.try_into().ok()?)),
+                1 => MetadataValue::Float(f64::from_le_bytes(reader.take(8)?.try_into().ok()?)),
+                2 => {
+                    let len = reader.u32()? as usize;
+                    MetadataValue::Text(String::from_utf8(reader.take(len)?.to_vec()).ok()?)
+                }
+                3 => {
+                    let len = reader.u32()? as usize;
+                    MetadataValue::Bytes(reader.take(len)?.to_vec())
+                }
+                _ => return None,
+            };
+            entries.insert(key, value);
+        }
+        Some(Self(entries))
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> FrameMetadata {
+        let mut metadata = FrameMetadata::new();
+        metadata
+            .insert("count", 3i64)
+            .insert("visible", true)
+            .insert("score", 0.75)
+            .insert("label", "player")
+            .insert("mask", vec![1u8, 2, 3]);
+        metadata
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let metadata = sample();
+        assert_eq!(
+            FrameMetadata::from_bytes(&metadata.to_bytes()),
+            Some(metadata)
+        );
+        let empty = FrameMetadata::new();
+        assert_eq!(empty.to_bytes(), [0; 4]);
+        assert_eq!(FrameMetadata::from_bytes(&empty.to_bytes()), Some(empty));
+    }
+
+    #[test]
+    fn bytes_layout() {
+        let mut metadata = FrameMetadata::new();
+        metadata.insert("a", "xy");
+        let expected = [
+            &1u32.to_le_bytes()[..],
+            &1u32.to_le_bytes(),
+            b"a",
+            &[2],
+            &2u32.to_le_bytes(),
+            b"xy",
+        ]
+        .concat();
+        assert_eq!(metadata.to_bytes(), expected);
+    }
+
+    #[test]
+    fn malformed_bytes_are_rejected() {
+        let bytes = sample().to_bytes();
+        for len in 0..bytes.len() {
+            assert_eq!(FrameMetadata::from_bytes(&bytes[..len]), None);
+        }
+        let mut unknown_type = FrameMetadata::new();
+        unknown_type.insert("a", 1i64);
+        let mut bytes = unknown_type.to_bytes();
+        bytes[9] = 4;
+        assert_eq!(FrameMetadata::from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,130 @@
+//! Capture statistics as `metrics` counters, gauges and histograms, so that capture agents can be
+//! monitored with Prometheus (through `metrics-exporter-prometheus`) or any other backend with a
+//! `metrics` recorder. Nothing is recorded until the application installs a recorder.
+//!
+//! All metrics are labeled with the `capture` name given to `MetricsObserver::new`:
+//!
+//! - `zbl_frames_total` (counter): grabbed frames
+//! - `zbl_frame_drops_total` (counter): frames dropped before being grabbed, labeled with the
+//!   `reason` (see `DropReason`)
+//! - `zbl_errors_total` (counter): errors reported to observers
+//! - `zbl_fps` (gauge): grabbed frames per second, over about the last second
+//! - `zbl_frame_latency_seconds` (histogram): time from the system producing a frame to it being
+//!   grabbed
+//! - `zbl_process_seconds` (histogram): time spent in `grab()` copying and processing a frame
+//! - `zbl_gpu_seconds` (histogram): GPU time spent on a frame, with `CaptureConfig::gpu_timing`
+//! - `zbl_frame_width`, `zbl_frame_height` (gauges): size of grabbed frames
+//! - `zbl_running` (gauge): 1 while the capture is started
+//! - `zbl_memory_bytes` (gauge): memory held by the capture, labeled with the `kind` of memory
+//!   (see `MetricsObserver::record_memory_usage`)
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use ::metrics::{gauge, histogram, increment_counter};
+use windows::core::Error;
+
+use crate::{
+    observer::{DropReason, FrameInfo, Observer},
+    util::system_relative_time,
+    MemoryUsage,
+};
+
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+fn drop_reason_label(reason: DropReason) -> &'static str {
+    match reason {
+        DropReason::Decimated => "decimated",
+        DropReason::RateLimited => "rate_limited",
+        DropReason::QueueFull => "queue_full",
+        DropReason::Paused => "paused",
+        DropReason::Consumed => "consumed",
+        DropReason::MemoryCap => "memory_cap",
+        DropReason::Disconnected => "disconnected",
+    }
+}
+
+/// Records the statistics of a capture as metrics, see the module documentation.
+///
+/// Register it as an observer: `capture.add_observer(Arc::new(MetricsObserver::new("game")))`.
+pub struct MetricsObserver {
+    name: String,
+    /// Start of the current fps window, and the frames grabbed since.
+    fps_window: Mutex<(Instant, u32)>,
+}
+
+impl MetricsObserver {
+    /// `name` is the value of the `capture` label, telling the captures of a process apart.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            fps_window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Record the memory held by the capture, e.g. every few seconds from the grabbing thread:
+    /// `observer.record_memory_usage(&capture.memory_usage()?)`.
+    pub fn record_memory_usage(&self, usage: &MemoryUsage) {
+        for (kind, bytes) in [
+            ("frame_pool", usage.frame_pool),
+            ("staging", usage.staging),
+            ("processing", usage.processing),
+            ("cpu", usage.cpu),
+        ] {
+            gauge!(
+                "zbl_memory_bytes",
+                bytes as f64,
+                "capture" => self.name.clone(),
+                "kind" => kind
+            );
+        }
+    }
+}
+
+impl Observer for MetricsObserver {
+    fn on_frame(&self, info: &FrameInfo) {
+        let capture = self.name.clone();
+        increment_counter!("zbl_frames_total", "capture" => capture.clone());
+        let latency = (system_relative_time() - info.timestamp).max(0) as f64 / 1e7;
+        histogram!("zbl_frame_latency_seconds", latency, "capture" => capture.clone());
+        histogram!("zbl_process_seconds", info.process_time, "capture" => capture.clone());
+        if let Some(gpu_time) = info.gpu_time {
+            histogram!("zbl_gpu_seconds", gpu_time, "capture" => capture.clone());
+        }
+        gauge!("zbl_frame_width", info.width as f64, "capture" => capture.clone());
+        gauge!("zbl_frame_height", info.height as f64, "capture" => capture.clone());
+
+        let mut fps_window = self.fps_window.lock().unwrap();
+        let (started_at, frames) = &mut *fps_window;
+        *frames += 1;
+        let elapsed = started_at.elapsed();
+        if elapsed >= FPS_WINDOW {
+            gauge!("zbl_fps", *frames as f64 / elapsed.as_secs_f64(), "capture" => capture);
+            *fps_window = (Instant::now(), 0);
+        }
+    }
+
+    fn on_drop(&self, reason: DropReason) {
+        increment_counter!(
+            "zbl_frame_drops_total",
+            "capture" => self.name.clone(),
+            "reason" => drop_reason_label(reason)
+        );
+    }
+
+    fn on_error(&self, _error: &Error) {
+        increment_counter!("zbl_errors_total", "capture" => self.name.clone());
+    }
+
+    fn on_start(&self) {
+        *self.fps_window.lock().unwrap() = (Instant::now(), 0);
+        gauge!("zbl_running", 1.0, "capture" => self.name.clone());
+    }
+
+    fn on_stop(&self) {
+        gauge!("zbl_running", 0.0, "capture" => self.name.clone());
+        gauge!("zbl_fps", 0.0, "capture" => self.name.clone());
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::{
+        Direct3D11::{
+            ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D,
+            D3D11_TEXTURE2D_DESC,
+        },
+        Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+    },
+};
+
+use crate::{
+    capture::GpuGrab,
+    pipeline::{create_work_texture, scale::Scaler, StageContext},
+    staging_texture::StagingTexture,
+    util::create_d3d_device,
+    Capturable, Capture, Frame, GrabStatus,
+};
+
+struct Cell {
+    capture: Capture,
+    /// Copy of the source's latest frame, at its size.
+    texture: Option<ID3D11Texture2D>,
+    closed: bool,
+}
+
+/// Combines several capture sources into a single frame, laid out in a grid (e.g. 2x2 monitors).
+///
+/// Every source is scaled on the GPU to fit its cell, preserving the aspect ratio, and the
+/// composed frame is read back once. Cells are filled row by row; cells of closed sources, and
+/// cells whose source has not produced a frame yet, are left black. Stages and the other
+/// processing options of `Capture` do not apply to the sources.
+pub struct Mosaic {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    scaler: Scaler,
+    cells: Vec<Cell>,
+    columns: u32,
+    cell_width: u32,
+    cell_height: u32,
+    target: ID3D11Texture2D,
+    staging_texture: StagingTexture,
+}
+
+impl Mosaic {
+    /// Start capturing `capturables` into a grid with `columns` columns of `cell_width` x
+    /// `cell_height` cells.
+    pub fn new(
+        capturables: Vec<Box<dyn Capturable>>,
+        columns: u32,
+        cell_width: u32,
+        cell_height: u32,
+    ) -> crate::Result<Self> {
+        let columns = columns.max(1);
+        let rows = (capturables.len() as u32 + columns - 1) / columns;
+        let width = columns * cell_width;
+        let height = rows.max(1) * cell_height;
+
+        let device = create_d3d_device()?;
+        let context = unsafe {
+            let mut d3d_context = None;
+            device.GetImmediateContext(&mut d3d_context);
+            d3d_context.expect("failed to create d3d_context")
+        };
+        let target = create_work_texture(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+        let staging_texture =
+            StagingTexture::new(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+        let scaler = Scaler::new(
+            &StageContext {
+                device: &device,
+                context: &context,
+                width,
+                height,
+                screen_origin: None,
+                timestamp: 0,
+            },
+            false,
+        )?;
+
+        let mut cells = Vec::with_capacity(capturables.len());
+        for capturable in capturables {
+            let mut capture = Capture::builder(capturable)
+                .device(device.clone())
+                // the mosaic is produced as soon as any of the sources has a new frame
+                .grab_timeout(Duration::ZERO)
+                .build()?;
+            capture.start()?;
+            cells.push(Cell {
+                capture,
+                texture: None,
+                closed: false,
+            });
+        }
+
+        Ok(Self {
+            device,
+            context,
+            scaler,
+            cells,
+            columns,
+            cell_width,
+            cell_height,
+            target,
+            staging_texture,
+        })
+    }
+
+    /// Get the capture of the `index`-th cell.
+    pub fn source(&self, index: usize) -> Option<&Capture> {
+        self.cells.get(index).map(|cell| &cell.capture)
+    }
+
+    /// Get the D3D11 device the mosaic is composed on.
+    pub fn device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Poll all sources and compose a new mosaic frame.
+    ///
+    /// This never blocks: `GrabStatus::Timeout` is returned if none of the sources has produced a
+    /// new frame since the last call, and `GrabStatus::Closed` once all of them have been closed.
+    pub fn grab(&mut self) -> Result<GrabStatus> {
+        let mut updated = false;
+        let mut timestamp = 0;
+        for cell in self.cells.iter_mut().filter(|cell| !cell.closed) {
+            let device = &self.device;
+            let texture = &mut cell.texture;
+            let format = cell.capture.config().output_format.capture_format();
+            let grabbed = cell
+                .capture
+                .grab_with((u32::MAX, u32::MAX), |context, source, area| {
+                    let size = (area.right - area.left, area.bottom - area.top);
+                    let reusable =
+                        matches!(texture, Some(texture) if texture_size(texture) == size);
+                    if !reusable {
+                        *texture = Some(create_work_texture(device, size.0, size.1, format)?);
+                    }
+                    let dest: ID3D11Resource = texture.as_ref().unwrap().cast()?;
+                    unsafe {
+                        context.CopySubresourceRegion(
+                            Some(&dest),
+                            0,
+                            0,
+                            0,
+                            0,
+                            Some(source),
+                            0,
+                            Some(area as *const _),
+                        )
+                    };
+                    Ok(())
+                });
+            match grabbed? {
+                GpuGrab::Copied {
+                    timestamp: frame_timestamp,
+                    ..
+                } => {
+                    timestamp = timestamp.max(frame_timestamp);
+                    updated = true;
+                }
+                GpuGrab::Nothing(GrabStatus::Closed) => {
+                    cell.closed = true;
+                    cell.texture = None;
+                    updated = true;
+                }
+                GpuGrab::Nothing(_) => {}
+            }
+        }
+        if self.cells.iter().all(|cell| cell.closed) {
+            return Ok(GrabStatus::Closed);
+        }
+        if !updated {
+            return Ok(GrabStatus::Timeout);
+        }
+
+        let target: ID3D11Resource = self.target.cast()?;
+        unsafe {
+            let render_target = self.device.CreateRenderTargetView(Some(&target), None)?;
+            self.context
+                .ClearRenderTargetView(&render_target, [0.0, 0.0, 0.0, 1.0].as_ptr());
+        }
+        let (width, height) = texture_size(&self.target);
+        let ctx = StageContext {
+            device: &self.device,
+            context: &self.context,
+            width,
+            height,
+            screen_origin: None,
+            timestamp,
+        };
+        for (i, cell) in self.cells.iter().enumerate() {
+            let texture = match &cell.texture {
+                Some(texture) => texture,
+                None => continue,
+            };
+            let x = (i as u32 % self.columns) * self.cell_width;
+            let y = (i as u32 / self.columns) * self.cell_height;
+            let area = (x, y, self.cell_width, self.cell_height);
+            self.scaler.fit(&ctx, texture, &self.target, area)?;
+        }
+
+        let dest = self.staging_texture.as_resource()?;
+        unsafe { self.context.CopyResource(Some(&dest), Some(&target)) };
+        let ptr = self.staging_texture.as_mapped(&self.context)?;
+        Ok(GrabStatus::Frame(Frame {
+            texture: &self.staging_texture,
+            ptr,
+            timestamp,
+            previous: None,
+            input_events: &[],
+            stats: None,
+            tracked_window: None,
+        }))
+    }
+}
+
+fn texture_size(texture: &ID3D11Texture2D) -> (u32, u32) {
+    let mut desc = D3D11_TEXTURE2D_DESC::default();
+    unsafe { texture.GetDesc(&mut desc) };
+    (desc.Width, desc.Height)
+}
@@ -0,0 +1,124 @@
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use windows::core::Error;
+
+/// Why a frame produced by the system did not reach `Capture::grab()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum DropReason {
+    /// Skipped because of `CaptureConfig::every_nth_frame`.
+    Decimated,
+    /// Skipped to keep to `CaptureConfig::rate`.
+    RateLimited,
+    /// The frame queue was full, i.e. frames are not grabbed as fast as they arrive.
+    QueueFull,
+    /// The capture is paused, see `CaptureConfig::power_throttle` and
+    /// `CaptureConfig::pause_when_occluded`.
+    Paused,
+    /// Handled by the frame arrived callback, see `Capture::set_frame_arrived_callback`.
+    Consumed,
+    /// Frames delivered by a `CaptureThread` have reached `CaptureConfig::memory_cap`.
+    MemoryCap,
+    /// The frame arrived while the session was being closed, after the capture stopped receiving
+    /// frames.
+    Disconnected,
+}
+
+/// Information about a grabbed frame.
+///
+/// With the `serde` feature, durations are (de)serialized as seconds.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameInfo {
+    /// System-relative time the frame was produced at, in 100ns units.
+    pub timestamp: i64,
+    pub width: u32,
+    pub height: u32,
+    /// Time spent in `grab()` between receiving the frame and submitting all work for it.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+    pub process_time: Duration,
+    /// Time the GPU spent copying and processing the frame, if `CaptureConfig::gpu_timing` is
+    /// enabled.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs::option"))]
+    pub gpu_time: Option<Duration>,
+}
+
+/// Receives capture statistics as they happen, e.g. to forward them to a metrics system.
+///
+/// All methods do nothing by default. `on_drop` is invoked on the thread delivering frames, so
+/// implementations must be cheap and must not block; the other methods are invoked from `grab()`.
+pub trait Observer: Send + Sync {
+    /// A frame has been grabbed.
+    fn on_frame(&self, _info: &FrameInfo) {}
+
+    /// A frame has been dropped before it could be grabbed.
+    fn on_drop(&self, _reason: DropReason) {}
+
+    /// Grabbed frames will have a new size from now on.
+    fn on_resize(&self, _width: u32, _height: u32) {}
+
+    /// `grab()` is about to return an error, or a frame could not be taken out of the frame pool
+    /// (see `TransientRetry`).
+    fn on_error(&self, _error: &Error) {}
+
+    /// The capture has been started.
+    fn on_start(&self) {}
+
+    /// The capture has been stopped.
+    fn on_stop(&self) {}
+
+    /// The frame pool has been recreated, e.g. after a resize, by the watchdog or after
+    /// reattaching to a new target.
+    fn on_recreate(&self) {}
+}
+
+/// Observers of a capture, shared with its frame handler.
+#[derive(Clone, Default)]
+pub(crate) struct Observers(Arc<RwLock<Vec<Arc<dyn Observer>>>>);
+
+impl Observers {
+    pub fn add(&self, observer: Arc<dyn Observer>) {
+        self.0.write().unwrap().push(observer);
+    }
+
+    fn for_each(&self, f: impl Fn(&dyn Observer)) {
+        for observer in self.0.read().unwrap().iter() {
+            f(observer.as_ref());
+        }
+    }
+
+    pub fn frame(&self, info: &FrameInfo) {
+        self.for_each(|observer| observer.on_frame(info));
+    }
+
+    pub fn dropped(&self, reason: DropReason) {
+        self.for_each(|observer| observer.on_drop(reason));
+    }
+
+    pub fn resized(&self, width: u32, height: u32) {
+        self.for_each(|observer| observer.on_resize(width, height));
+    }
+
+    pub fn error(&self, error: &Error) {
+        self.for_each(|observer| observer.on_error(error));
+    }
+
+    pub fn started(&self) {
+        self.for_each(|observer| observer.on_start());
+    }
+
+    pub fn stopped(&self) {
+        self.for_each(|observer| observer.on_stop());
+    }
+
+    pub fn recreated(&self) {
+        self.for_each(|observer| observer.on_recreate());
+    }
+}
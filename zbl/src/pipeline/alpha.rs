@@ -0,0 +1,55 @@
+use windows::{
+    core::Result,
+    Win32::Graphics::Direct3D11::{ID3D11PixelShader, ID3D11Texture2D},
+};
+
+use crate::{
+    config::AlphaMode,
+    pipeline::{shader::FullscreenPass, Stage, StageContext},
+};
+
+const ALPHA_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+float4 opaque(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return float4(frame.Load(int3(pos.xy, 0)).rgb, 1);
+}
+
+float4 straight(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    float4 c = frame.Load(int3(pos.xy, 0));
+    return c.a > 0 ? float4(saturate(c.rgb / c.a), c.a) : float4(0, 0, 0, 0);
+}
+"#;
+
+/// Converts the premultiplied alpha produced by Windows.Graphics.Capture into the given mode.
+///
+/// This is applied by `Capture` itself according to `CaptureConfig::alpha`, as the very last step
+/// before frames are read back.
+pub struct AlphaConversion {
+    mode: AlphaMode,
+    state: Option<(FullscreenPass, ID3D11PixelShader)>,
+}
+
+impl AlphaConversion {
+    pub fn new(mode: AlphaMode) -> Self {
+        Self { mode, state: None }
+    }
+}
+
+impl Stage for AlphaConversion {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let entry_point = match self.mode {
+            AlphaMode::Premultiplied => return Ok(()),
+            AlphaMode::Opaque => "opaque",
+            AlphaMode::Straight => "straight",
+        };
+        if self.state.is_none() {
+            self.state = Some((
+                FullscreenPass::new(ctx.device)?,
+                FullscreenPass::create_pixel_shader(ctx.device, ALPHA_PS, entry_point)?,
+            ));
+        }
+        let (pass, shader) = self.state.as_mut().unwrap();
+        pass.run(ctx, texture, shader, &[])
+    }
+}
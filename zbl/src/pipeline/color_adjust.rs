@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+
+use windows::{
+    core::Result,
+    Win32::Graphics::Direct3D11::{ID3D11Buffer, ID3D11PixelShader, ID3D11Texture2D},
+};
+
+use crate::pipeline::{
+    shader::{create_constant_buffer, update_buffer, FullscreenPass},
+    Stage, StageContext,
+};
+
+const COLOR_ADJUST_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+cbuffer Adjustment : register(b0) {
+    float gamma;
+    float brightness;
+    float contrast;
+    float saturation;
+};
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    float4 c = frame.Load(int3(pos.xy, 0));
+    if (c.a <= 0) {
+        return c;
+    }
+    float3 rgb = c.rgb / c.a;
+    rgb = (rgb - 0.5) * contrast + 0.5 + brightness;
+    float luma = dot(rgb, float3(0.2126, 0.7152, 0.0722));
+    rgb = saturate(lerp(luma, rgb, saturation));
+    rgb = pow(rgb, 1 / gamma);
+    return float4(rgb * c.a, c.a);
+}
+"#;
+
+/// Color adjustments applied by a `ColorAdjust` stage. The default leaves frames unchanged.
+///
+/// Adjustments are applied to the gamma-encoded colors, in the order of the fields except for
+/// gamma, which is applied last.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ColorAdjustment {
+    /// Values above 1 brighten the midtones, e.g. 1.8 to make dark game footage readable.
+    pub gamma: f32,
+    /// Added to every channel, from -1 (black) to 1 (white).
+    pub brightness: f32,
+    /// Multiplies the distance of every channel from mid-gray, 0 giving a flat gray frame.
+    pub contrast: f32,
+    /// 0 gives a grayscale frame, values above 1 exaggerate colors.
+    pub saturation: f32,
+}
+
+impl Default for ColorAdjustment {
+    fn default() -> Self {
+        Self {
+            gamma: 1.0,
+            brightness: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl ColorAdjustment {
+    /// Whether the adjustment leaves frames unchanged.
+    pub fn is_identity(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Adjustment applied by a `ColorAdjust` stage, which can be modified while the stage is attached
+/// to a capture.
+#[derive(Clone, Default)]
+pub struct ColorControls(Arc<Mutex<ColorAdjustment>>);
+
+impl ColorControls {
+    /// Replace the adjustment. It applies from the next frame on.
+    pub fn set(&self, adjustment: ColorAdjustment) {
+        *self.0.lock().unwrap() = adjustment;
+    }
+
+    pub fn get(&self) -> ColorAdjustment {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Adjusts the gamma, brightness, contrast and saturation of every frame on the GPU, e.g. to
+/// normalize dark footage before it is streamed or fed to a model.
+pub struct ColorAdjust {
+    controls: ColorControls,
+    state: Option<(FullscreenPass, ID3D11PixelShader, ID3D11Buffer)>,
+}
+
+impl Default for ColorAdjust {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl ColorAdjust {
+    pub fn new(adjustment: ColorAdjustment) -> Self {
+        let controls = ColorControls::default();
+        controls.set(adjustment);
+        Self {
+            controls,
+            state: None,
+        }
+    }
+
+    /// Handle to the applied adjustment.
+    pub fn controls(&self) -> ColorControls {
+        self.controls.clone()
+    }
+}
+
+impl Stage for ColorAdjust {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let adjustment = self.controls.get();
+        if adjustment.is_identity() {
+            return Ok(());
+        }
+        if self.state.is_none() {
+            self.state = Some((
+                FullscreenPass::new(ctx.device)?,
+                FullscreenPass::create_pixel_shader(ctx.device, COLOR_ADJUST_PS, "main")?,
+                create_constant_buffer(ctx.device, 16)?,
+            ));
+        }
+        let (pass, shader, buffer) = self.state.as_mut().unwrap();
+        let data = [
+            adjustment.gamma.max(0.01),
+            adjustment.brightness,
+            adjustment.contrast,
+            adjustment.saturation,
+        ];
+        update_buffer(ctx, buffer, &data)?;
+        pass.run(ctx, texture, shader, &[Some(buffer.clone())])
+    }
+}
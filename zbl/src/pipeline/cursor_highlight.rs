@@ -0,0 +1,140 @@
+use std::{
+    sync::mpsc::Receiver,
+    time::{Duration, Instant},
+};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::POINT,
+        Graphics::{
+            Direct2D::{Common::D2D_POINT_2F, D2D1_ELLIPSE},
+            Direct3D11::ID3D11Texture2D,
+        },
+        UI::WindowsAndMessaging::GetCursorPos,
+    },
+};
+
+use crate::{
+    input::{self, InputEvent, InputEventKind},
+    pipeline::{d2d::D2DTarget, Stage, StageContext},
+};
+
+struct Ripple {
+    position: POINT,
+    started_at: Instant,
+}
+
+/// Draws a translucent circle around the mouse cursor, and an expanding ripple on every click.
+///
+/// Clicks are observed through a global low-level mouse hook, so they are visualized regardless of
+/// which window receives them.
+pub struct CursorHighlight {
+    /// Radius of the highlight circle in pixels.
+    pub radius: f32,
+    /// Color of the highlight circle, as straight RGBA.
+    pub color: [f32; 4],
+    /// Color of click ripples, as straight RGBA.
+    pub click_color: [f32; 4],
+    /// How long a click ripple stays visible.
+    pub ripple_duration: Duration,
+    /// Radius a click ripple expands to.
+    pub ripple_radius: f32,
+    /// `None` if the mouse hook could not be installed, in which case clicks are not shown.
+    clicks: Option<Receiver<InputEvent>>,
+    ripples: Vec<Ripple>,
+    d2d: Option<D2DTarget>,
+}
+
+impl Default for CursorHighlight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CursorHighlight {
+    pub fn new() -> Self {
+        Self {
+            radius: 24.0,
+            color: [1.0, 0.9, 0.0, 0.35],
+            click_color: [1.0, 0.2, 0.2, 0.8],
+            ripple_duration: Duration::from_millis(400),
+            ripple_radius: 40.0,
+            clicks: input::subscribe_buttons(1 << 6).ok(),
+            ripples: Vec::new(),
+            d2d: None,
+        }
+    }
+
+    fn collect_clicks(&mut self) {
+        let clicks = self.clicks.iter().flat_map(|clicks| clicks.try_iter());
+        for event in clicks {
+            if let InputEventKind::MouseDown(_) = event.kind {
+                self.ripples.push(Ripple {
+                    position: event.position,
+                    started_at: event.time,
+                });
+            }
+        }
+        let ripple_duration = self.ripple_duration;
+        self.ripples
+            .retain(|ripple| ripple.started_at.elapsed() < ripple_duration);
+    }
+}
+
+fn with_opacity([r, g, b, a]: [f32; 4], opacity: f32) -> [f32; 4] {
+    [r, g, b, a * opacity]
+}
+
+fn ellipse(x: f32, y: f32, radius: f32) -> D2D1_ELLIPSE {
+    D2D1_ELLIPSE {
+        point: D2D_POINT_2F { x, y },
+        radiusX: radius,
+        radiusY: radius,
+    }
+}
+
+impl Stage for CursorHighlight {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        self.collect_clicks();
+
+        let mut cursor = POINT::default();
+        unsafe { GetCursorPos(&mut cursor as *mut _).ok()? };
+        let cursor = match ctx.screen_to_frame(cursor) {
+            Some(cursor) => cursor,
+            // no way to tell where the cursor is relative to the frame
+            None => return Ok(()),
+        };
+
+        if self.d2d.is_none() {
+            self.d2d = Some(D2DTarget::new(ctx.device)?);
+        }
+        let d2d = self.d2d.as_ref().unwrap();
+
+        let highlight_brush = d2d.solid_brush(self.color)?;
+        let ripples = self
+            .ripples
+            .iter()
+            .filter_map(|ripple| {
+                let (x, y) = ctx.screen_to_frame(ripple.position)?;
+                let progress =
+                    ripple.started_at.elapsed().as_secs_f32() / self.ripple_duration.as_secs_f32();
+                Some((x, y, progress))
+            })
+            .collect::<Vec<_>>();
+
+        d2d.draw(texture, |dc| unsafe {
+            dc.FillEllipse(&ellipse(cursor.0, cursor.1, self.radius), &highlight_brush);
+            for (x, y, progress) in ripples {
+                let brush = d2d.solid_brush(with_opacity(self.click_color, 1.0 - progress))?;
+                dc.DrawEllipse(
+                    &ellipse(x, y, self.ripple_radius * progress),
+                    &brush,
+                    3.0,
+                    None,
+                );
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use windows::{
+    core::Result,
+    Win32::Graphics::{
+        Direct2D::{Common::D2D_RECT_F, ID2D1Bitmap, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR},
+        Direct3D11::ID3D11Texture2D,
+    },
+};
+
+use crate::{
+    cursor::{cursor_state, CursorShape},
+    pipeline::{d2d::D2DTarget, Stage, StageContext},
+};
+
+/// Draws the mouse cursor onto every frame.
+///
+/// Meant for captures with `capture_cursor(false)`: the cursor then ends up in frames only through
+/// this stage, and can be scaled, faded or replaced by a custom shape. Targets which do not know
+/// their position on the screen (see `Capturable::get_screen_origin`) are left untouched.
+pub struct CursorOverlay {
+    /// Size of the cursor relative to its native size.
+    pub scale: f32,
+    pub opacity: f32,
+    custom_shape: Option<CursorShape>,
+    /// System cursor shape, along with the `HCURSOR` it was rendered from.
+    shape: Option<(isize, CursorShape)>,
+    bitmap: Option<ID2D1Bitmap>,
+    /// Whether `bitmap` holds the current shape.
+    uploaded: bool,
+    d2d: Option<D2DTarget>,
+}
+
+impl Default for CursorOverlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CursorOverlay {
+    pub fn new() -> Self {
+        Self {
+            scale: 1.0,
+            opacity: 1.0,
+            custom_shape: None,
+            shape: None,
+            bitmap: None,
+            uploaded: false,
+            d2d: None,
+        }
+    }
+
+    /// Always draw `shape` instead of the shape the system currently shows.
+    pub fn with_shape(mut self, shape: CursorShape) -> Self {
+        self.custom_shape = Some(shape);
+        self.uploaded = false;
+        self
+    }
+
+    /// Render the system cursor again if its `HCURSOR` changed.
+    fn update_shape(&mut self, handle: isize) -> Result<()> {
+        if self.custom_shape.is_none() && !matches!(&self.shape, Some((h, _)) if *h == handle) {
+            self.shape = Some((handle, CursorShape::from_handle(handle)?));
+            self.uploaded = false;
+        }
+        Ok(())
+    }
+}
+
+impl Stage for CursorOverlay {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let state = cursor_state()?;
+        if !state.visible || state.handle == 0 {
+            return Ok(());
+        }
+        let (x, y) = match ctx.screen_to_frame(state.position) {
+            Some(position) => position,
+            // no way to tell where the cursor is relative to the frame
+            None => return Ok(()),
+        };
+
+        if self.d2d.is_none() {
+            self.d2d = Some(D2DTarget::new(ctx.device)?);
+        }
+        self.update_shape(state.handle)?;
+        let shape = match &self.custom_shape {
+            Some(shape) => shape,
+            None => &self.shape.as_ref().unwrap().1,
+        };
+        if shape.image.width == 0 || shape.image.height == 0 {
+            return Ok(());
+        }
+        let d2d = self.d2d.as_ref().unwrap();
+        if !self.uploaded {
+            d2d.upload_premultiplied_image(&mut self.bitmap, &shape.image)?;
+            self.uploaded = true;
+        }
+        let bitmap = self.bitmap.clone().unwrap();
+
+        let left = x - shape.hotspot.x as f32 * self.scale;
+        let top = y - shape.hotspot.y as f32 * self.scale;
+        let rect = D2D_RECT_F {
+            left,
+            top,
+            right: left + shape.image.width as f32 * self.scale,
+            bottom: top + shape.image.height as f32 * self.scale,
+        };
+        let opacity = self.opacity;
+        d2d.draw(texture, |dc| unsafe {
+            dc.DrawBitmap(
+                &bitmap,
+                Some(&rect as *const _),
+                opacity,
+                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                None,
+            );
+            Ok(())
+        })
+    }
+}
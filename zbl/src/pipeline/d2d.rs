@@ -0,0 +1,137 @@
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::{
+        Direct2D::{
+            Common::{
+                D2D1_ALPHA_MODE, D2D1_ALPHA_MODE_IGNORE, D2D1_ALPHA_MODE_PREMULTIPLIED,
+                D2D1_COLOR_F, D2D1_PIXEL_FORMAT, D2D_SIZE_U,
+            },
+            D2D1CreateFactory, ID2D1Bitmap, ID2D1Device, ID2D1DeviceContext, ID2D1Factory1,
+            ID2D1Image, ID2D1SolidColorBrush, D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+            D2D1_BITMAP_OPTIONS_TARGET, D2D1_BITMAP_PROPERTIES, D2D1_BITMAP_PROPERTIES1,
+            D2D1_DEVICE_CONTEXT_OPTIONS_NONE, D2D1_FACTORY_TYPE_SINGLE_THREADED,
+        },
+        Direct3D11::{ID3D11Device, ID3D11Texture2D, D3D11_TEXTURE2D_DESC},
+        Dxgi::{Common::DXGI_FORMAT_B8G8R8A8_UNORM, IDXGIDevice, IDXGISurface},
+    },
+};
+
+use crate::Image;
+
+/// Direct2D device context sharing the capture's D3D11 device, for stages drawing onto frames.
+pub struct D2DTarget {
+    context: ID2D1DeviceContext,
+}
+
+impl D2DTarget {
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let factory: ID2D1Factory1 =
+            unsafe { D2D1CreateFactory(D2D1_FACTORY_TYPE_SINGLE_THREADED, None)? };
+        let dxgi_device: IDXGIDevice = device.cast()?;
+        let d2d_device: ID2D1Device = unsafe { factory.CreateDevice(&dxgi_device)? };
+        let context = unsafe { d2d_device.CreateDeviceContext(D2D1_DEVICE_CONTEXT_OPTIONS_NONE)? };
+        Ok(Self { context })
+    }
+
+    pub fn context(&self) -> &ID2D1DeviceContext {
+        &self.context
+    }
+
+    /// Create a brush of the given straight (not premultiplied) RGBA color.
+    pub fn solid_brush(&self, color: [f32; 4]) -> Result<ID2D1SolidColorBrush> {
+        let [r, g, b, a] = color;
+        unsafe {
+            self.context
+                .CreateSolidColorBrush(&D2D1_COLOR_F { r, g, b, a }, None)
+        }
+    }
+
+    /// Upload `image` into a bitmap which can be drawn by this target, ignoring its alpha channel.
+    ///
+    /// `cached` is reused if it has the same size as the image, and replaced otherwise.
+    pub fn upload_image(
+        &self,
+        cached: &mut Option<ID2D1Bitmap>,
+        image: &Image,
+    ) -> Result<ID2D1Bitmap> {
+        self.upload(cached, image, D2D1_ALPHA_MODE_IGNORE)
+    }
+
+    /// Same as `upload_image()`, but for images with premultiplied alpha which should be blended
+    /// when drawn.
+    pub fn upload_premultiplied_image(
+        &self,
+        cached: &mut Option<ID2D1Bitmap>,
+        image: &Image,
+    ) -> Result<ID2D1Bitmap> {
+        self.upload(cached, image, D2D1_ALPHA_MODE_PREMULTIPLIED)
+    }
+
+    fn upload(
+        &self,
+        cached: &mut Option<ID2D1Bitmap>,
+        image: &Image,
+        alpha_mode: D2D1_ALPHA_MODE,
+    ) -> Result<ID2D1Bitmap> {
+        let size = D2D_SIZE_U {
+            width: image.width,
+            height: image.height,
+        };
+        let reusable = match cached {
+            Some(bitmap) => {
+                let cached_size = unsafe { bitmap.GetPixelSize() };
+                cached_size.width == size.width && cached_size.height == size.height
+            }
+            None => false,
+        };
+        if !reusable {
+            let props = D2D1_BITMAP_PROPERTIES {
+                pixelFormat: D2D1_PIXEL_FORMAT {
+                    format: DXGI_FORMAT_B8G8R8A8_UNORM,
+                    alphaMode: alpha_mode,
+                },
+                dpiX: 96.0,
+                dpiY: 96.0,
+            };
+            *cached = Some(unsafe { self.context.CreateBitmap(size, None, 0, &props)? });
+        }
+        let bitmap = cached.as_ref().unwrap();
+        unsafe { bitmap.CopyFromMemory(None, image.data.as_ptr() as *const _, image.width * 4)? };
+        Ok(bitmap.clone())
+    }
+
+    /// Run `draw` with the given texture bound as the render target.
+    ///
+    /// The texture may be in any format Direct2D can render to, in particular
+    /// `DXGI_FORMAT_R16G16B16A16_FLOAT` which frames are processed in for 10-bit output formats.
+    pub fn draw<F>(&self, texture: &ID3D11Texture2D, draw: F) -> Result<()>
+    where
+        F: FnOnce(&ID2D1DeviceContext) -> Result<()>,
+    {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        let surface: IDXGISurface = texture.cast()?;
+        let props = D2D1_BITMAP_PROPERTIES1 {
+            pixelFormat: D2D1_PIXEL_FORMAT {
+                format: desc.Format,
+                alphaMode: D2D1_ALPHA_MODE_PREMULTIPLIED,
+            },
+            dpiX: 96.0,
+            dpiY: 96.0,
+            bitmapOptions: D2D1_BITMAP_OPTIONS_TARGET | D2D1_BITMAP_OPTIONS_CANNOT_DRAW,
+            ..Default::default()
+        };
+        unsafe {
+            let bitmap = self
+                .context
+                .CreateBitmapFromDxgiSurface(&surface, Some(&props as *const _))?;
+            self.context.SetTarget(&bitmap.cast::<ID2D1Image>()?);
+            self.context.BeginDraw();
+            let result = draw(&self.context);
+            let end_result = self.context.EndDraw(None, None);
+            // do not keep the texture alive through the device context
+            self.context.SetTarget(None::<&ID2D1Image>);
+            result.and(end_result)
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Hiding windows from monitor captures.
+//!
+//! Windows.Graphics.Capture cannot leave windows out of a monitor capture (only the capturing
+//! process can exclude its own windows, with `SetWindowDisplayAffinity`). This is a best-effort
+//! replacement: the area of every excluded window is filled with a solid color after the frame has
+//! been captured.
+//!
+//! Windows are filled as a whole, including the parts covered by other windows, and their
+//! position is looked up when the frame is processed, so a window being dragged may peek out of
+//! its fill for a frame.
+
+use std::sync::{Arc, Mutex};
+
+use windows::{
+    core::Result,
+    Win32::{Foundation::HWND, Graphics::Direct3D11::ID3D11Texture2D},
+};
+
+use crate::{
+    pipeline::{
+        redaction::{RedactedRegion, RedactionStyle, RegionSpace},
+        Redaction, Stage, StageContext,
+    },
+    window::{get_frame_bounds, is_shown},
+};
+
+/// Set of windows hidden by an `ExcludeWindows` stage, which can be modified while the stage is
+/// attached to a capture.
+#[derive(Clone, Default)]
+pub struct ExcludedWindows(Arc<Mutex<Vec<HWND>>>);
+
+impl ExcludedWindows {
+    /// Exclude a window. At most 32 windows are hidden at a time, the rest are ignored.
+    pub fn add(&self, window: HWND) {
+        let mut windows = self.0.lock().unwrap();
+        if !windows.contains(&window) {
+            windows.push(window);
+        }
+    }
+
+    pub fn remove(&self, window: HWND) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|excluded| *excluded != window);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    pub fn get(&self) -> Vec<HWND> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Fills the area of some windows with a solid color, for captures of monitors (or other targets
+/// with a known screen origin). Frames of targets without one are left untouched.
+pub struct ExcludeWindows {
+    windows: ExcludedWindows,
+    color: [f32; 4],
+    redaction: Redaction,
+}
+
+impl ExcludeWindows {
+    pub fn new(windows: impl IntoIterator<Item = HWND>) -> Self {
+        let excluded = ExcludedWindows::default();
+        for window in windows {
+            excluded.add(window);
+        }
+        Self {
+            windows: excluded,
+            color: [0.0, 0.0, 0.0, 1.0],
+            redaction: Redaction::new(),
+        }
+    }
+
+    /// Straight RGBA color the windows are filled with, see `RedactionStyle::Fill`. Defaults to
+    /// opaque black.
+    pub fn color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Handle to the set of excluded windows.
+    pub fn windows(&self) -> ExcludedWindows {
+        self.windows.clone()
+    }
+}
+
+impl Stage for ExcludeWindows {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let regions = self.redaction.regions();
+        regions.clear();
+        for window in self.windows.get() {
+            if is_shown(window) {
+                regions.add(RedactedRegion {
+                    rect: get_frame_bounds(window),
+                    space: RegionSpace::Screen,
+                    style: RedactionStyle::Fill(self.color),
+                });
+            }
+        }
+        self.redaction.process(ctx, texture)
+    }
+}
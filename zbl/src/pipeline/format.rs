@@ -0,0 +1,119 @@
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::Direct3D11::{ID3D11PixelShader, ID3D11Resource, ID3D11Texture2D},
+};
+
+use crate::{
+    config::{AlphaMode, OutputFormat},
+    pipeline::{shader::FullscreenPass, StageContext},
+};
+
+const FORMAT_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+float3 srgb(float3 c) {
+    c = saturate(c);
+    return c <= 0.0031308 ? c * 12.92 : 1.055 * pow(c, 1 / 2.4) - 0.055;
+}
+
+float3 pq(float3 nits) {
+    float3 y = pow(saturate(nits / 10000), 0.1593017578125);
+    return pow((0.8359375 + 18.8515625 * y) / (1 + 18.6875 * y), 78.84375);
+}
+
+// unlike the alpha conversion, without clipping colors brighter than SDR white
+float4 straight(float4 c) {
+    return c.a > 0 ? float4(c.rgb / c.a, c.a) : float4(0, 0, 0, 0);
+}
+
+float4 encode_rgb10a2(float4 c) {
+    return float4(srgb(c.rgb), c.a);
+}
+
+float4 encode_hdr10(float4 c) {
+    static const float3x3 rec709_to_rec2020 = {
+        0.6274, 0.3293, 0.0433,
+        0.0691, 0.9195, 0.0114,
+        0.0164, 0.0880, 0.8956,
+    };
+    // scRGB 1.0 is 80 nits
+    return float4(pq(mul(rec709_to_rec2020, c.rgb) * 80), c.a);
+}
+
+float4 rgb10a2(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return encode_rgb10a2(frame.Load(int3(pos.xy, 0)));
+}
+
+float4 rgb10a2_straight(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return encode_rgb10a2(straight(frame.Load(int3(pos.xy, 0))));
+}
+
+float4 hdr10(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return encode_hdr10(frame.Load(int3(pos.xy, 0)));
+}
+
+float4 hdr10_straight(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return encode_hdr10(straight(frame.Load(int3(pos.xy, 0))));
+}
+"#;
+
+/// Packs processed scRGB frames into the 10-bit `OutputFormat`s.
+///
+/// This is applied by `Capture` itself according to `CaptureConfig::output_format`, after alpha
+/// conversion. Straight alpha (see `AlphaMode`) is produced here rather than by the alpha
+/// conversion, as colors have to be divided by alpha before the transfer function is applied, and
+/// without clipping them to SDR white.
+pub struct FormatConversion {
+    format: OutputFormat,
+    alpha: AlphaMode,
+    state: Option<(FullscreenPass, ID3D11PixelShader)>,
+}
+
+impl FormatConversion {
+    pub fn new(format: OutputFormat, alpha: AlphaMode) -> Self {
+        Self {
+            format,
+            alpha,
+            state: None,
+        }
+    }
+
+    /// The alpha mode the alpha conversion has to produce before the format conversion to `format`
+    /// finishes converting into `alpha`.
+    pub fn input_alpha(format: OutputFormat, alpha: AlphaMode) -> AlphaMode {
+        match (format, alpha) {
+            (OutputFormat::Rgb10A2 | OutputFormat::Hdr10, AlphaMode::Straight) => {
+                AlphaMode::Premultiplied
+            }
+            (_, alpha) => alpha,
+        }
+    }
+
+    /// Convert `input` into `output`, which must be of the same size (described by `ctx`) and of
+    /// the output format.
+    pub fn convert(
+        &mut self,
+        ctx: &StageContext,
+        input: &ID3D11Texture2D,
+        output: &ID3D11Texture2D,
+    ) -> Result<()> {
+        let straight = self.alpha == AlphaMode::Straight;
+        let entry_point = match (self.format, straight) {
+            (OutputFormat::Bgra8, _) => return Ok(()),
+            (OutputFormat::Rgb10A2, false) => "rgb10a2",
+            (OutputFormat::Rgb10A2, true) => "rgb10a2_straight",
+            (OutputFormat::Hdr10, false) => "hdr10",
+            (OutputFormat::Hdr10, true) => "hdr10_straight",
+        };
+        if self.state.is_none() {
+            self.state = Some((
+                FullscreenPass::new(ctx.device)?,
+                FullscreenPass::create_pixel_shader(ctx.device, FORMAT_PS, entry_point)?,
+            ));
+        }
+        let (pass, shader) = self.state.as_ref().unwrap();
+        let resource: ID3D11Resource = input.cast()?;
+        let view = unsafe { ctx.device.CreateShaderResourceView(Some(&resource), None)? };
+        pass.draw(ctx, &view, output, shader, &[])
+    }
+}
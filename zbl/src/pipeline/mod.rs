@@ -0,0 +1,104 @@
+//! GPU processing of captured frames.
+//!
+//! When a capture has stages attached, every frame is first copied into a work texture, which is
+//! then handed to each stage in order before being read back. Stages modify the work texture in
+//! place, so they never see the frame pool's surfaces and do not need to care about the capture box.
+
+pub mod alpha;
+pub mod color_adjust;
+pub mod cursor_highlight;
+pub mod cursor_overlay;
+pub mod d2d;
+pub mod exclude_windows;
+pub mod format;
+pub mod picture_in_picture;
+pub mod redaction;
+pub mod scale;
+pub mod shader;
+#[cfg(feature = "spout")]
+pub mod spout;
+pub mod text_overlay;
+pub mod zoom;
+
+pub use color_adjust::{ColorAdjust, ColorAdjustment};
+pub use cursor_highlight::CursorHighlight;
+pub use cursor_overlay::CursorOverlay;
+pub use exclude_windows::ExcludeWindows;
+pub use picture_in_picture::PictureInPicture;
+pub use redaction::Redaction;
+pub use shader::ShaderStage;
+#[cfg(feature = "spout")]
+pub use spout::SpoutSender;
+pub use text_overlay::{Anchor, TextOverlay};
+pub use zoom::{FollowCursor, Zoom, ZoomView};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::POINT,
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11DeviceContext, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+                D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_FLAG, D3D11_RESOURCE_MISC_FLAG,
+                D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+            },
+            Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+        },
+    },
+};
+
+/// Everything a stage may need to know about the frame being processed.
+pub struct StageContext<'a> {
+    pub device: &'a ID3D11Device,
+    pub context: &'a ID3D11DeviceContext,
+    /// Width of the work texture in pixels.
+    pub width: u32,
+    /// Height of the work texture in pixels.
+    pub height: u32,
+    /// Position of the top-left pixel of the frame on the virtual screen, if the target knows it.
+    pub screen_origin: Option<POINT>,
+    /// Time at which the frame was produced, in 100ns units (`Direct3D11CaptureFrame::SystemRelativeTime`).
+    pub timestamp: i64,
+}
+
+impl<'a> StageContext<'a> {
+    /// Convert a point on the virtual screen to frame coordinates.
+    pub fn screen_to_frame(&self, point: POINT) -> Option<(f32, f32)> {
+        self.screen_origin
+            .map(|origin| ((point.x - origin.x) as f32, (point.y - origin.y) as f32))
+    }
+}
+
+/// A processing step applied to every captured frame on the GPU.
+pub trait Stage {
+    /// Process the frame in place.
+    ///
+    /// `texture` is a default-usage texture of the frame size, bindable as a render target and as a
+    /// shader resource.
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()>;
+}
+
+/// Create a texture suitable for being processed by stages.
+pub fn create_work_texture(
+    device: &ID3D11Device,
+    width: u32,
+    height: u32,
+    format: DXGI_FORMAT,
+) -> Result<ID3D11Texture2D> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: width,
+        Height: height,
+        Format: format,
+        MipLevels: 1,
+        ArraySize: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+        MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+        Usage: D3D11_USAGE_DEFAULT,
+        CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+    };
+    unsafe { device.CreateTexture2D(&desc, None) }
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use windows::{
+    core::Result,
+    Win32::Graphics::{
+        Direct2D::{Common::D2D_RECT_F, ID2D1Bitmap, D2D1_BITMAP_INTERPOLATION_MODE_LINEAR},
+        Direct3D11::ID3D11Texture2D,
+    },
+};
+
+use crate::{
+    pipeline::{d2d::D2DTarget, text_overlay::Anchor, Stage, StageContext},
+    Capturable, Capture, GrabStatus, Image,
+};
+
+/// Overlays a secondary capture (e.g. a webcam preview window) onto a corner of every frame.
+///
+/// The secondary capture runs on its own device, so its frames are read back and uploaded again;
+/// this is fine for small insets, but not free. Until the secondary capture produces its first
+/// frame, and after its target is closed, frames pass through unchanged.
+pub struct PictureInPicture {
+    pub anchor: Anchor,
+    /// Distance from the anchored corner in pixels.
+    pub margin: f32,
+    /// Width of the inset relative to the frame width. The height follows the source aspect ratio.
+    pub scale: f32,
+    pub opacity: f32,
+    source: Capture,
+    latest: Option<Image>,
+    bitmap: Option<ID2D1Bitmap>,
+    d2d: Option<D2DTarget>,
+}
+
+impl PictureInPicture {
+    /// Start capturing `capturable` to be shown as an inset.
+    pub fn new(capturable: Box<dyn Capturable>) -> crate::Result<Self> {
+        let mut source = Capture::builder(capturable)
+            // never wait for the secondary source, the primary one dictates the pace
+            .grab_timeout(Duration::ZERO)
+            .build()?;
+        source.start()?;
+        Ok(Self {
+            anchor: Anchor::BottomRight,
+            margin: 16.0,
+            scale: 0.25,
+            opacity: 1.0,
+            source,
+            latest: None,
+            bitmap: None,
+            d2d: None,
+        })
+    }
+
+    /// Get the secondary capture.
+    pub fn source(&self) -> &Capture {
+        &self.source
+    }
+
+    fn update_source(&mut self) -> Result<()> {
+        match self.source.grab()? {
+            GrabStatus::Frame(frame) => self.latest = Some(frame.to_image()),
+            GrabStatus::Closed => self.latest = None,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl Stage for PictureInPicture {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        self.update_source()?;
+        let image = match &self.latest {
+            Some(image) if image.width > 0 && image.height > 0 => image,
+            _ => return Ok(()),
+        };
+
+        if self.d2d.is_none() {
+            self.d2d = Some(D2DTarget::new(ctx.device)?);
+        }
+        let d2d = self.d2d.as_ref().unwrap();
+
+        let bitmap = d2d.upload_image(&mut self.bitmap, image)?;
+
+        let w = ctx.width as f32 * self.scale;
+        let h = w * image.height as f32 / image.width as f32;
+        let x = match self.anchor {
+            Anchor::TopLeft | Anchor::BottomLeft => self.margin,
+            Anchor::TopRight | Anchor::BottomRight => ctx.width as f32 - w - self.margin,
+        };
+        let y = match self.anchor {
+            Anchor::TopLeft | Anchor::TopRight => self.margin,
+            Anchor::BottomLeft | Anchor::BottomRight => ctx.height as f32 - h - self.margin,
+        };
+        let opacity = self.opacity;
+        d2d.draw(texture, |dc| unsafe {
+            dc.DrawBitmap(
+                &bitmap,
+                Some(&D2D_RECT_F {
+                    left: x,
+                    top: y,
+                    right: x + w,
+                    bottom: y + h,
+                } as *const _),
+                opacity,
+                D2D1_BITMAP_INTERPOLATION_MODE_LINEAR,
+                None,
+            );
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::RECT,
+        Graphics::Direct3D11::{ID3D11Buffer, ID3D11PixelShader, ID3D11Texture2D},
+    },
+};
+
+use crate::pipeline::{
+    shader::{create_constant_buffer, update_buffer, FullscreenPass},
+    Stage, StageContext,
+};
+
+const MAX_REGIONS: usize = 32;
+
+const REDACTION_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+struct Region {
+    int4 rect;
+    float4 color;
+    uint4 params;
+};
+
+cbuffer Regions : register(b0) {
+    uint4 count;
+    Region regions[32];
+};
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    int2 p = int2(pos.xy);
+    for (uint i = 0; i < count.x; ++i) {
+        Region r = regions[i];
+        if (p.x >= r.rect.x && p.y >= r.rect.y && p.x < r.rect.z && p.y < r.rect.w) {
+            if (r.params.x == 0) {
+                // premultiplied "over"
+                return r.color + frame.Load(int3(p, 0)) * (1.0 - r.color.a);
+            }
+            int block = max(int(r.params.y), 1);
+            int2 center = (p - r.rect.xy) / block * block + r.rect.xy + block / 2;
+            return frame.Load(int3(min(center, r.rect.zw - 1), 0));
+        }
+    }
+    return frame.Load(int3(p, 0));
+}
+"#;
+
+/// How a redacted region is hidden.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RedactionStyle {
+    /// Fill the region with a solid straight RGBA color, blended over the frame if it is not
+    /// opaque.
+    Fill([f32; 4]),
+    /// Pixelate the region with square blocks of the given size in pixels.
+    Pixelate(u32),
+}
+
+/// Coordinate space of a redacted region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionSpace {
+    /// Virtual screen coordinates, i.e. the region stays in place when the captured window moves.
+    Screen,
+    /// Frame coordinates.
+    Frame,
+}
+
+/// Rectangle hidden from every frame.
+#[derive(Clone, Copy, Debug)]
+pub struct RedactedRegion {
+    pub rect: RECT,
+    pub space: RegionSpace,
+    pub style: RedactionStyle,
+}
+
+/// Set of regions hidden by a `Redaction` stage, which can be modified while the stage is attached
+/// to a capture.
+#[derive(Clone, Default)]
+pub struct RedactedRegions(Arc<Mutex<Vec<RedactedRegion>>>);
+
+impl RedactedRegions {
+    /// Add a region. At most 32 regions are applied, the rest are ignored.
+    pub fn add(&self, region: RedactedRegion) {
+        self.0.lock().unwrap().push(region);
+    }
+
+    /// Black out a rectangle on the screen.
+    pub fn black_out(&self, rect: RECT) {
+        self.add(RedactedRegion {
+            rect,
+            space: RegionSpace::Screen,
+            style: RedactionStyle::Fill([0.0, 0.0, 0.0, 1.0]),
+        });
+    }
+
+    /// Pixelate a rectangle on the screen.
+    pub fn pixelate(&self, rect: RECT, block_size: u32) {
+        self.add(RedactedRegion {
+            rect,
+            space: RegionSpace::Screen,
+            style: RedactionStyle::Pixelate(block_size),
+        });
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    pub fn get(&self) -> Vec<RedactedRegion> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Premultiply a straight RGBA color, as frames are premultiplied at this point.
+fn premultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r * a, g * a, b * a, a]
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct RegionData {
+    rect: [i32; 4],
+    color: [f32; 4],
+    params: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RegionsData {
+    count: [u32; 4],
+    regions: [RegionData; MAX_REGIONS],
+}
+
+/// Hides parts of every frame by filling or pixelating them on the GPU.
+pub struct Redaction {
+    regions: RedactedRegions,
+    state: Option<(FullscreenPass, ID3D11PixelShader, ID3D11Buffer)>,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Redaction {
+    pub fn new() -> Self {
+        Self {
+            regions: Default::default(),
+            state: None,
+        }
+    }
+
+    /// Handle to the set of redacted regions.
+    pub fn regions(&self) -> RedactedRegions {
+        self.regions.clone()
+    }
+
+    fn collect(&self, ctx: &StageContext) -> RegionsData {
+        let mut data = RegionsData {
+            count: [0; 4],
+            regions: [Default::default(); MAX_REGIONS],
+        };
+        let mut count = 0;
+        for region in self.regions.get().iter().take(MAX_REGIONS) {
+            let RECT {
+                left,
+                top,
+                right,
+                bottom,
+            } = region.rect;
+            let rect = match (region.space, ctx.screen_origin) {
+                // no way to tell where the region is relative to the frame
+                (RegionSpace::Screen, None) => continue,
+                (RegionSpace::Screen, Some(origin)) => [
+                    left - origin.x,
+                    top - origin.y,
+                    right - origin.x,
+                    bottom - origin.y,
+                ],
+                (RegionSpace::Frame, _) => [left, top, right, bottom],
+            };
+            data.regions[count] = match region.style {
+                RedactionStyle::Fill(color) => RegionData {
+                    rect,
+                    color: premultiply(color),
+                    params: [0; 4],
+                },
+                RedactionStyle::Pixelate(block_size) => RegionData {
+                    rect,
+                    color: [0.0; 4],
+                    params: [1, block_size, 0, 0],
+                },
+            };
+            count += 1;
+        }
+        data.count[0] = count as u32;
+        data
+    }
+}
+
+impl Stage for Redaction {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let data = self.collect(ctx);
+        if data.count[0] == 0 {
+            return Ok(());
+        }
+        if self.state.is_none() {
+            self.state = Some((
+                FullscreenPass::new(ctx.device)?,
+                FullscreenPass::create_pixel_shader(ctx.device, REDACTION_PS, "main")?,
+                create_constant_buffer(ctx.device, std::mem::size_of::<RegionsData>())?,
+            ));
+        }
+        let (pass, shader, buffer) = self.state.as_mut().unwrap();
+        update_buffer(ctx, buffer, std::slice::from_ref(&data))?;
+        pass.run(ctx, texture, shader, &[Some(buffer.clone())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_colors_are_premultiplied() {
+        assert_eq!(premultiply([1.0, 0.5, 0.0, 1.0]), [1.0, 0.5, 0.0, 1.0]);
+        assert_eq!(premultiply([1.0, 0.5, 0.0, 0.5]), [0.5, 0.25, 0.0, 0.5]);
+        assert_eq!(premultiply([1.0, 1.0, 1.0, 0.0]), [0.0; 4]);
+    }
+}
@@ -0,0 +1,164 @@
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::Direct3D11::{
+        ID3D11PixelShader, ID3D11Resource, ID3D11Texture2D, D3D11_TEXTURE2D_DESC, D3D11_VIEWPORT,
+    },
+};
+
+use crate::pipeline::{shader::FullscreenPass, StageContext};
+
+const SCALE_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+SamplerState frame_sampler : register(s0);
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return frame.Sample(frame_sampler, uv);
+}
+"#;
+
+/// Filters in linear light: every output pixel averages bilinear taps spread over the input
+/// texels it covers (up to 8x8), decoding sRGB before and encoding it after. Colors are
+/// premultiplied, so they are unpremultiplied around the conversions.
+const LINEAR_SCALE_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+float3 to_linear(float3 c) {
+    return c <= 0.04045 ? c / 12.92 : pow((c + 0.055) / 1.055, 2.4);
+}
+
+float3 to_srgb(float3 c) {
+    return c <= 0.0031308 ? c * 12.92 : 1.055 * pow(c, 1.0 / 2.4) - 0.055;
+}
+
+float4 load_linear(int2 texel, int2 size) {
+    float4 c = frame.Load(int3(clamp(texel, 0, size - 1), 0));
+    if (c.a <= 0) {
+        return 0;
+    }
+    return float4(to_linear(saturate(c.rgb / c.a)) * c.a, c.a);
+}
+
+float4 bilinear(float2 uv, int2 size) {
+    float2 p = uv * size - 0.5;
+    int2 i = int2(floor(p));
+    float2 f = p - i;
+    float4 top = lerp(load_linear(i, size), load_linear(i + int2(1, 0), size), f.x);
+    float4 bottom = lerp(load_linear(i + int2(0, 1), size), load_linear(i + int2(1, 1), size), f.x);
+    return lerp(top, bottom, f.y);
+}
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    uint width, height;
+    frame.GetDimensions(width, height);
+    int2 size = int2(width, height);
+    // input texels covered by this output pixel
+    float2 footprint = float2(abs(ddx(uv.x)), abs(ddy(uv.y))) * size;
+    int2 taps = clamp(int2(ceil(footprint)), 1, 8);
+    float4 sum = 0;
+    for (int y = 0; y < taps.y; y++) {
+        for (int x = 0; x < taps.x; x++) {
+            float2 offset = ((float2(x, y) + 0.5) / taps - 0.5) * footprint / size;
+            sum += bilinear(uv + offset, size);
+        }
+    }
+    float4 c = sum / (taps.x * taps.y);
+    if (c.a <= 0) {
+        return 0;
+    }
+    return float4(to_srgb(saturate(c.rgb / c.a)) * c.a, c.a);
+}
+"#;
+
+/// Resizes textures on the GPU.
+///
+/// By default, filtering is done in linear light. Filtering sRGB values directly (as plain
+/// bilinear sampling does) darkens thin bright features against dark backgrounds and vice versa,
+/// which is most visible on small text in downscaled frames.
+pub struct Scaler {
+    pass: FullscreenPass,
+    shader: ID3D11PixelShader,
+}
+
+impl Scaler {
+    /// Create a scaler filtering in linear light, or directly on sRGB values (a single bilinear
+    /// sample per pixel, which is cheaper) if `gamma_space` is set.
+    pub fn new(ctx: &StageContext, gamma_space: bool) -> Result<Self> {
+        let source = if gamma_space {
+            SCALE_PS
+        } else {
+            LINEAR_SCALE_PS
+        };
+        Ok(Self {
+            pass: FullscreenPass::new(ctx.device)?,
+            shader: FullscreenPass::create_pixel_shader(ctx.device, source, "main")?,
+        })
+    }
+
+    /// Stretch `input` over the whole `output`.
+    ///
+    /// `ctx` describes the output, i.e. its `width` and `height` must be the size of `output`.
+    pub fn scale(
+        &self,
+        ctx: &StageContext,
+        input: &ID3D11Texture2D,
+        output: &ID3D11Texture2D,
+    ) -> Result<()> {
+        let resource: ID3D11Resource = input.cast()?;
+        let view = unsafe { ctx.device.CreateShaderResourceView(Some(&resource), None)? };
+        self.pass.draw(ctx, &view, output, &self.shader, &[])
+    }
+
+    /// Scale `input` to fit into `output` while preserving its aspect ratio, centered, and fill the
+    /// remaining bars with `padding` (straight RGBA).
+    ///
+    /// `ctx` describes the output, as for `scale()`.
+    pub fn letterbox(
+        &self,
+        ctx: &StageContext,
+        input: &ID3D11Texture2D,
+        output: &ID3D11Texture2D,
+        padding: [f32; 4],
+    ) -> Result<()> {
+        // frames are premultiplied at this point
+        let [r, g, b, a] = padding;
+        let padding = [r * a, g * a, b * a, a];
+        let resource: ID3D11Resource = output.cast()?;
+        unsafe {
+            let render_target = ctx.device.CreateRenderTargetView(Some(&resource), None)?;
+            ctx.context
+                .ClearRenderTargetView(&render_target, padding.as_ptr());
+        }
+        self.fit(ctx, input, output, (0, 0, ctx.width, ctx.height))
+    }
+
+    /// Scale `input` to fit into the `(x, y, width, height)` area of `output` while preserving
+    /// its aspect ratio, centered. The rest of `output` is left as it is.
+    ///
+    /// `ctx` describes the output, as for `scale()`.
+    pub fn fit(
+        &self,
+        ctx: &StageContext,
+        input: &ID3D11Texture2D,
+        output: &ID3D11Texture2D,
+        (x, y, width, height): (u32, u32, u32, u32),
+    ) -> Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { input.GetDesc(&mut desc) };
+        let (out_w, out_h) = (width as f32, height as f32);
+        let scale = (out_w / desc.Width.max(1) as f32).min(out_h / desc.Height.max(1) as f32);
+        let (w, h) = (desc.Width as f32 * scale, desc.Height as f32 * scale);
+        let viewport = D3D11_VIEWPORT {
+            // whole pixels, so that the edges of the picture are not blended with the padding
+            TopLeftX: x as f32 + ((out_w - w) / 2.0).round(),
+            TopLeftY: y as f32 + ((out_h - h) / 2.0).round(),
+            Width: w.round(),
+            Height: h.round(),
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+        let resource: ID3D11Resource = input.cast()?;
+        let view = unsafe { ctx.device.CreateShaderResourceView(Some(&resource), None)? };
+        self.pass
+            .draw_viewport(ctx, &view, output, &viewport, &self.shader, &[])
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    ffi::CString,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use windows::{
+    core::{Error, Interface, Result, HSTRING, PCSTR},
+    Win32::Graphics::{
+        Direct3D::{
+            Fxc::{D3DCompile, ID3DInclude, D3DCOMPILE_OPTIMIZATION_LEVEL3},
+            ID3DBlob, D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+        },
+        Direct3D11::{
+            ID3D11Buffer, ID3D11ClassLinkage, ID3D11ComputeShader, ID3D11DepthStencilView,
+            ID3D11Device, ID3D11InputLayout, ID3D11PixelShader, ID3D11RenderTargetView,
+            ID3D11Resource, ID3D11SamplerState, ID3D11ShaderResourceView, ID3D11Texture2D,
+            ID3D11UnorderedAccessView, ID3D11VertexShader, D3D11_BIND_CONSTANT_BUFFER,
+            D3D11_BIND_SHADER_RESOURCE, D3D11_BIND_UNORDERED_ACCESS, D3D11_BUFFER_DESC,
+            D3D11_COMPARISON_NEVER, D3D11_CPU_ACCESS_FLAG, D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+            D3D11_RESOURCE_MISC_FLAG, D3D11_SAMPLER_DESC, D3D11_TEXTURE2D_DESC,
+            D3D11_TEXTURE_ADDRESS_CLAMP, D3D11_USAGE_DEFAULT, D3D11_VIEWPORT,
+        },
+        Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC},
+    },
+};
+
+use crate::pipeline::{create_work_texture, Stage, StageContext};
+
+const FULLSCREEN_VS: &str = r#"
+struct VsOut {
+    float4 pos : SV_Position;
+    float2 uv : TEXCOORD0;
+};
+
+VsOut main(uint id : SV_VertexID) {
+    VsOut o;
+    o.uv = float2((id << 1) & 2, id & 2);
+    o.pos = float4(o.uv * float2(2, -2) + float2(-1, 1), 0, 1);
+    return o;
+}
+"#;
+
+const BLIT_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return frame.Load(int3(pos.xy, 0));
+}
+"#;
+
+/// Compile HLSL source into bytecode, e.g. `compile_shader(src, "main", "ps_5_0")`.
+///
+/// Compilation errors are reported with the compiler's output as the error message.
+pub fn compile_shader(source: &str, entry_point: &str, target: &str) -> Result<Vec<u8>> {
+    let entry_point = CString::new(entry_point).unwrap();
+    let target = CString::new(target).unwrap();
+    let mut code: Option<ID3DBlob> = None;
+    let mut errors: Option<ID3DBlob> = None;
+    let result = unsafe {
+        D3DCompile(
+            source.as_ptr() as *const _,
+            source.len(),
+            PCSTR::null(),
+            None,
+            None::<&ID3DInclude>,
+            PCSTR::from_raw(entry_point.as_ptr() as *const u8),
+            PCSTR::from_raw(target.as_ptr() as *const u8),
+            D3DCOMPILE_OPTIMIZATION_LEVEL3,
+            0,
+            &mut code,
+            Some(&mut errors),
+        )
+    };
+    if let Err(e) = result {
+        let message = errors
+            .map(|errors| String::from_utf8_lossy(blob_bytes(&errors)).into_owned())
+            .unwrap_or_else(|| e.message().to_string());
+        return Err(Error::new(e.code(), HSTRING::from(message)));
+    }
+    Ok(blob_bytes(&code.unwrap()).to_vec())
+}
+
+fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+    }
+}
+
+/// Create a constant buffer of `size` bytes (rounded up to 16).
+pub fn create_constant_buffer(device: &ID3D11Device, size: usize) -> Result<ID3D11Buffer> {
+    let desc = D3D11_BUFFER_DESC {
+        ByteWidth: ((size.max(1) + 15) / 16 * 16) as u32,
+        Usage: D3D11_USAGE_DEFAULT,
+        BindFlags: D3D11_BIND_CONSTANT_BUFFER,
+        CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+        MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+        StructureByteStride: 0,
+    };
+    unsafe { device.CreateBuffer(&desc, None) }
+}
+
+/// Upload `data` into a constant buffer. `data` must be exactly as large as the buffer.
+pub fn update_buffer<T: Copy>(ctx: &StageContext, buffer: &ID3D11Buffer, data: &[T]) -> Result<()> {
+    let resource: ID3D11Resource = buffer.cast()?;
+    unsafe {
+        ctx.context
+            .UpdateSubresource(Some(&resource), 0, None, data.as_ptr() as *const _, 0, 0)
+    };
+    Ok(())
+}
+
+/// Helper for stages which render a full-screen pixel shader over the frame.
+///
+/// The frame is copied into a scratch texture which is bound as `t0` (with a linear clamping sampler
+/// at `s0`), and the work texture itself is the render target.
+pub struct FullscreenPass {
+    vertex_shader: ID3D11VertexShader,
+    sampler: ID3D11SamplerState,
+    scratch: Option<(ID3D11Texture2D, ID3D11ShaderResourceView)>,
+}
+
+impl FullscreenPass {
+    pub fn new(device: &ID3D11Device) -> Result<Self> {
+        let vs_code = compile_shader(FULLSCREEN_VS, "main", "vs_5_0")?;
+        let vertex_shader =
+            unsafe { device.CreateVertexShader(&vs_code, None::<&ID3D11ClassLinkage>)? };
+        let sampler_desc = D3D11_SAMPLER_DESC {
+            Filter: D3D11_FILTER_MIN_MAG_MIP_LINEAR,
+            AddressU: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressV: D3D11_TEXTURE_ADDRESS_CLAMP,
+            AddressW: D3D11_TEXTURE_ADDRESS_CLAMP,
+            ComparisonFunc: D3D11_COMPARISON_NEVER,
+            MaxLOD: f32::MAX,
+            ..Default::default()
+        };
+        let sampler = unsafe { device.CreateSamplerState(&sampler_desc)? };
+        Ok(Self {
+            vertex_shader,
+            sampler,
+            scratch: None,
+        })
+    }
+
+    /// Compile a pixel shader for use with this pass.
+    pub fn create_pixel_shader(
+        device: &ID3D11Device,
+        source: &str,
+        entry_point: &str,
+    ) -> Result<ID3D11PixelShader> {
+        let code = compile_shader(source, entry_point, "ps_5_0")?;
+        unsafe { device.CreatePixelShader(&code, None::<&ID3D11ClassLinkage>) }
+    }
+
+    /// Copy `texture` into the scratch texture and get a view of the copy.
+    fn scratch(
+        &mut self,
+        ctx: &StageContext,
+        texture: &ID3D11Texture2D,
+    ) -> Result<ID3D11ShaderResourceView> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        let outdated = match &self.scratch {
+            Some((scratch, _)) => {
+                let mut scratch_desc = D3D11_TEXTURE2D_DESC::default();
+                unsafe { scratch.GetDesc(&mut scratch_desc) };
+                scratch_desc.Width != desc.Width
+                    || scratch_desc.Height != desc.Height
+                    || scratch_desc.Format != desc.Format
+            }
+            None => true,
+        };
+        if outdated {
+            let scratch = create_work_texture(ctx.device, desc.Width, desc.Height, desc.Format)?;
+            let resource: ID3D11Resource = scratch.cast()?;
+            let view = unsafe { ctx.device.CreateShaderResourceView(Some(&resource), None)? };
+            self.scratch = Some((scratch, view));
+        }
+        let (scratch, view) = self.scratch.as_ref().unwrap();
+        let dest: ID3D11Resource = scratch.cast()?;
+        let src: ID3D11Resource = texture.cast()?;
+        unsafe { ctx.context.CopyResource(Some(&dest), Some(&src)) };
+        Ok(view.clone())
+    }
+
+    /// Render `pixel_shader` over `texture`, with a copy of `texture` as the input.
+    ///
+    /// `constant_buffers` are bound to the pixel shader starting from `b0`.
+    pub fn run(
+        &mut self,
+        ctx: &StageContext,
+        texture: &ID3D11Texture2D,
+        pixel_shader: &ID3D11PixelShader,
+        constant_buffers: &[Option<ID3D11Buffer>],
+    ) -> Result<()> {
+        let input = self.scratch(ctx, texture)?;
+        self.draw(ctx, &input, texture, pixel_shader, constant_buffers)
+    }
+
+    /// Render `pixel_shader` over `target` with `input` bound as `t0`.
+    pub fn draw(
+        &self,
+        ctx: &StageContext,
+        input: &ID3D11ShaderResourceView,
+        target: &ID3D11Texture2D,
+        pixel_shader: &ID3D11PixelShader,
+        constant_buffers: &[Option<ID3D11Buffer>],
+    ) -> Result<()> {
+        let viewport = D3D11_VIEWPORT {
+            TopLeftX: 0.0,
+            TopLeftY: 0.0,
+            Width: ctx.width as f32,
+            Height: ctx.height as f32,
+            MinDepth: 0.0,
+            MaxDepth: 1.0,
+        };
+        self.draw_viewport(
+            ctx,
+            input,
+            target,
+            &viewport,
+            pixel_shader,
+            constant_buffers,
+        )
+    }
+
+    /// Same as `draw()`, but only renders to `viewport` of `target`. The rest of `target` is left
+    /// untouched.
+    pub fn draw_viewport(
+        &self,
+        ctx: &StageContext,
+        input: &ID3D11ShaderResourceView,
+        target: &ID3D11Texture2D,
+        viewport: &D3D11_VIEWPORT,
+        pixel_shader: &ID3D11PixelShader,
+        constant_buffers: &[Option<ID3D11Buffer>],
+    ) -> Result<()> {
+        let target: ID3D11Resource = target.cast()?;
+        let render_target: ID3D11RenderTargetView =
+            unsafe { ctx.device.CreateRenderTargetView(Some(&target), None)? };
+        unsafe {
+            let dc = ctx.context;
+            dc.IASetInputLayout(None::<&ID3D11InputLayout>);
+            dc.IASetPrimitiveTopology(D3D11_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            dc.VSSetShader(&self.vertex_shader, None);
+            dc.PSSetShader(pixel_shader, None);
+            dc.PSSetShaderResources(0, Some(&[Some(input.clone())]));
+            dc.PSSetSamplers(0, Some(&[Some(self.sampler.clone())]));
+            if !constant_buffers.is_empty() {
+                dc.PSSetConstantBuffers(0, Some(constant_buffers));
+            }
+            dc.OMSetRenderTargets(
+                Some(&[Some(render_target)]),
+                None::<&ID3D11DepthStencilView>,
+            );
+            dc.RSSetViewports(Some(&[*viewport]));
+            dc.Draw(3, 0);
+            // unbind everything, so that the textures can be used as copy sources/destinations again
+            dc.OMSetRenderTargets(None, None::<&ID3D11DepthStencilView>);
+            dc.PSSetShaderResources(0, Some(&[None]));
+        }
+        Ok(())
+    }
+}
+
+/// Constant buffer contents shared between a `ShaderStage` and its owner, so they can be updated
+/// while the stage is attached to a capture.
+#[derive(Clone, Default)]
+pub struct ShaderConstants(Arc<Mutex<(Vec<u8>, bool)>>);
+
+impl ShaderConstants {
+    /// Replace the contents of the constant buffer. They are uploaded before the next frame.
+    pub fn set(&self, data: &[u8]) {
+        let mut guard = self.0.lock().unwrap();
+        guard.0 = data.to_vec();
+        guard.1 = true;
+    }
+
+    /// Take the contents if they have changed since the last call.
+    fn take_changed(&self) -> Option<Vec<u8>> {
+        let mut guard = self.0.lock().unwrap();
+        if guard.1 {
+            guard.1 = false;
+            Some(guard.0.clone())
+        } else {
+            None
+        }
+    }
+}
+
+enum ShaderKind {
+    Pixel,
+    Compute { thread_group_size: (u32, u32) },
+}
+
+enum CompiledShader {
+    Pixel(ID3D11PixelShader),
+    Compute {
+        shader: ID3D11ComputeShader,
+        blit: ID3D11PixelShader,
+        output: Option<(
+            ID3D11Texture2D,
+            ID3D11UnorderedAccessView,
+            ID3D11ShaderResourceView,
+        )>,
+    },
+}
+
+struct ShaderState {
+    pass: FullscreenPass,
+    shader: CompiledShader,
+    user_constants: Option<ID3D11Buffer>,
+    frame_constants: ID3D11Buffer,
+}
+
+/// Runs a user-supplied HLSL shader on every frame.
+///
+/// Shaders have access to the following resources:
+/// * `Texture2D<float4> frame : register(t0)` - the frame before this stage;
+/// * `SamplerState frame_sampler : register(s0)` - linear clamping sampler (pixel shaders only);
+/// * `cbuffer Constants : register(b0)` - user data, see `constants()`;
+/// * `cbuffer Frame : register(b1) { uint2 frame_size; float time; }` - frame size in pixels and
+///   seconds since the stage started.
+///
+/// Pixel shaders are run over the whole frame with the signature
+/// `float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target`.
+///
+/// Compute shaders write their result into `RWTexture2D<unorm float4> output : register(u0)`
+/// (RGBA channel order), and are dispatched with one thread per pixel.
+pub struct ShaderStage {
+    source: String,
+    entry_point: String,
+    kind: ShaderKind,
+    constants: ShaderConstants,
+    started_at: Instant,
+    state: Option<ShaderState>,
+}
+
+impl ShaderStage {
+    pub fn pixel(source: &str, entry_point: &str) -> Self {
+        Self::new(source, entry_point, ShaderKind::Pixel)
+    }
+
+    /// `thread_group_size` must match the `[numthreads(x, y, 1)]` attribute of the entry point.
+    pub fn compute(source: &str, entry_point: &str, thread_group_size: (u32, u32)) -> Self {
+        Self::new(
+            source,
+            entry_point,
+            ShaderKind::Compute { thread_group_size },
+        )
+    }
+
+    fn new(source: &str, entry_point: &str, kind: ShaderKind) -> Self {
+        Self {
+            source: source.to_string(),
+            entry_point: entry_point.to_string(),
+            kind,
+            constants: Default::default(),
+            started_at: Instant::now(),
+            state: None,
+        }
+    }
+
+    /// Handle to the user constant buffer (`b0`) of this stage.
+    pub fn constants(&self) -> ShaderConstants {
+        self.constants.clone()
+    }
+
+    fn init(&self, device: &ID3D11Device) -> Result<ShaderState> {
+        let pass = FullscreenPass::new(device)?;
+        let shader = match self.kind {
+            ShaderKind::Pixel => CompiledShader::Pixel(FullscreenPass::create_pixel_shader(
+                device,
+                &self.source,
+                &self.entry_point,
+            )?),
+            ShaderKind::Compute { .. } => {
+                let code = compile_shader(&self.source, &self.entry_point, "cs_5_0")?;
+                CompiledShader::Compute {
+                    shader: unsafe {
+                        device.CreateComputeShader(&code, None::<&ID3D11ClassLinkage>)?
+                    },
+                    blit: FullscreenPass::create_pixel_shader(device, BLIT_PS, "main")?,
+                    output: None,
+                }
+            }
+        };
+        Ok(ShaderState {
+            pass,
+            shader,
+            user_constants: None,
+            frame_constants: create_constant_buffer(device, 16)?,
+        })
+    }
+}
+
+fn create_compute_output(
+    device: &ID3D11Device,
+    width: u32,
+    height: u32,
+) -> Result<(
+    ID3D11Texture2D,
+    ID3D11UnorderedAccessView,
+    ID3D11ShaderResourceView,
+)> {
+    let desc = D3D11_TEXTURE2D_DESC {
+        Width: width,
+        Height: height,
+        Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+        MipLevels: 1,
+        ArraySize: 1,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        BindFlags: D3D11_BIND_UNORDERED_ACCESS | D3D11_BIND_SHADER_RESOURCE,
+        MiscFlags: D3D11_RESOURCE_MISC_FLAG(0),
+        Usage: D3D11_USAGE_DEFAULT,
+        CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+    };
+    unsafe {
+        let texture = device.CreateTexture2D(&desc, None)?;
+        let resource: ID3D11Resource = texture.cast()?;
+        let uav = device.CreateUnorderedAccessView(Some(&resource), None)?;
+        let srv = device.CreateShaderResourceView(Some(&resource), None)?;
+        Ok((texture, uav, srv))
+    }
+}
+
+impl Stage for ShaderStage {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        if self.state.is_none() {
+            self.state = Some(self.init(ctx.device)?);
+        }
+        let state = self.state.as_mut().unwrap();
+
+        if let Some(data) = self.constants.take_changed() {
+            let buffer = create_constant_buffer(ctx.device, data.len())?;
+            let mut padded = data;
+            padded.resize((padded.len().max(1) + 15) / 16 * 16, 0);
+            update_buffer(ctx, &buffer, &padded)?;
+            state.user_constants = Some(buffer);
+        }
+
+        let mut frame_data = [0u32; 4];
+        frame_data[0] = ctx.width;
+        frame_data[1] = ctx.height;
+        frame_data[2] = self.started_at.elapsed().as_secs_f32().to_bits();
+        update_buffer(ctx, &state.frame_constants, &frame_data)?;
+        let constant_buffers = [
+            state.user_constants.clone(),
+            Some(state.frame_constants.clone()),
+        ];
+
+        match &mut state.shader {
+            CompiledShader::Pixel(shader) => {
+                state.pass.run(ctx, texture, shader, &constant_buffers)
+            }
+            CompiledShader::Compute {
+                shader,
+                blit,
+                output,
+            } => {
+                let (group_x, group_y) = match self.kind {
+                    ShaderKind::Compute { thread_group_size } => thread_group_size,
+                    ShaderKind::Pixel => unreachable!(),
+                };
+                let outdated = match output {
+                    Some((output_texture, _, _)) => {
+                        let mut desc = D3D11_TEXTURE2D_DESC::default();
+                        unsafe { output_texture.GetDesc(&mut desc) };
+                        desc.Width != ctx.width || desc.Height != ctx.height
+                    }
+                    None => true,
+                };
+                if outdated {
+                    *output = Some(create_compute_output(ctx.device, ctx.width, ctx.height)?);
+                }
+                let (_, uav, output_view) = output.as_ref().unwrap();
+
+                let input = state.pass.scratch(ctx, texture)?;
+                unsafe {
+                    let dc = ctx.context;
+                    dc.CSSetShader(&*shader, None);
+                    dc.CSSetShaderResources(0, Some(&[Some(input)]));
+                    dc.CSSetConstantBuffers(0, Some(&constant_buffers));
+                    dc.CSSetUnorderedAccessViews(0, 1, Some(&Some(uav.clone())), None);
+                    dc.Dispatch(
+                        (ctx.width + group_x - 1) / group_x.max(1),
+                        (ctx.height + group_y - 1) / group_y.max(1),
+                        1,
+                    );
+                    dc.CSSetUnorderedAccessViews(0, 1, Some(&None), None);
+                    dc.CSSetShaderResources(0, Some(&[None]));
+                }
+                // the output is RGBA, so it cannot be copied into the (BGRA) frame directly
+                state.pass.draw(ctx, output_view, texture, blit, &[])
+            }
+        }
+    }
+}
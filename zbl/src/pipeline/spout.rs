@@ -0,0 +1,311 @@
+//! Publishing frames to other applications through [Spout](https://spout.zeal.co/).
+//!
+//! Spout senders share a D3D11 texture through a legacy share handle, and advertise it through
+//! named shared memory: `SpoutSenderNames` holds the list of active senders, and a mapping named
+//! after each sender describes its texture.
+
+use windows::{
+    core::{Error, Interface, Result, HSTRING},
+    Win32::{
+        Foundation::{
+            CloseHandle, E_FAIL, HANDLE, INVALID_HANDLE_VALUE, WAIT_ABANDONED, WAIT_OBJECT_0,
+        },
+        Graphics::{
+            Direct3D11::{
+                ID3D11Device, ID3D11Resource, ID3D11Texture2D, D3D11_BIND_RENDER_TARGET,
+                D3D11_BIND_SHADER_RESOURCE, D3D11_CPU_ACCESS_FLAG, D3D11_RESOURCE_MISC_SHARED,
+                D3D11_TEXTURE2D_DESC, D3D11_USAGE_DEFAULT,
+            },
+            Dxgi::{
+                Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+                IDXGIResource,
+            },
+        },
+        System::{
+            Memory::{
+                CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
+                PAGE_READWRITE,
+            },
+            Threading::{CreateMutexW, ReleaseMutex, WaitForSingleObject},
+        },
+    },
+};
+
+use crate::pipeline::{Stage, StageContext};
+
+const SENDER_NAMES: &str = "SpoutSenderNames";
+const ACTIVE_SENDER: &str = "ActiveSenderName";
+const MAX_SENDERS: usize = 64;
+const NAME_SIZE: usize = 256;
+const MUTEX_TIMEOUT_MS: u32 = 100;
+
+/// Sender description, as laid out in the sender's shared memory.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SharedTextureInfo {
+    share_handle: u32,
+    width: u32,
+    height: u32,
+    format: u32,
+    usage: u32,
+    description: [u16; 128],
+    partner_id: u32,
+}
+
+/// Named shared memory, guarded by a named mutex.
+struct SharedMemory {
+    mapping: HANDLE,
+    view: *mut u8,
+    size: usize,
+    mutex: HANDLE,
+}
+
+impl SharedMemory {
+    fn open(name: &str, size: usize) -> Result<Self> {
+        unsafe {
+            let mapping = CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                None,
+                PAGE_READWRITE,
+                0,
+                size as u32,
+                &HSTRING::from(name),
+            )?;
+            let view = MapViewOfFile(mapping, FILE_MAP_ALL_ACCESS, 0, 0, size) as *mut u8;
+            if view.is_null() {
+                let error = Error::from_win32();
+                CloseHandle(mapping);
+                return Err(error);
+            }
+            let mutex = match CreateMutexW(None, false, &HSTRING::from(format!("{}_mutex", name))) {
+                Ok(mutex) => mutex,
+                Err(e) => {
+                    UnmapViewOfFile(view as *const _);
+                    CloseHandle(mapping);
+                    return Err(e);
+                }
+            };
+            Ok(Self {
+                mapping,
+                view,
+                size,
+                mutex,
+            })
+        }
+    }
+
+    /// Run `f` on the contents of the memory while holding the mutex.
+    fn with_locked<T>(&self, f: impl FnOnce(&mut [u8]) -> T) -> Option<T> {
+        unsafe {
+            let wait = WaitForSingleObject(self.mutex, MUTEX_TIMEOUT_MS);
+            if wait != WAIT_OBJECT_0 && wait != WAIT_ABANDONED {
+                return None;
+            }
+            let result = f(std::slice::from_raw_parts_mut(self.view, self.size));
+            ReleaseMutex(self.mutex);
+            Some(result)
+        }
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        unsafe {
+            UnmapViewOfFile(self.view as *const _);
+            CloseHandle(self.mapping);
+            CloseHandle(self.mutex);
+        }
+    }
+}
+
+fn encode_name(name: &str) -> [u8; NAME_SIZE] {
+    let mut encoded = [0; NAME_SIZE];
+    let bytes = name.as_bytes();
+    let len = bytes.len().min(NAME_SIZE - 1);
+    encoded[..len].copy_from_slice(&bytes[..len]);
+    encoded
+}
+
+/// Add `name` to the sender list, unless it is already there.
+fn register_sender(names: &mut [u8], name: &[u8; NAME_SIZE]) -> bool {
+    for slot in names.chunks_exact_mut(NAME_SIZE) {
+        if slot == name {
+            return true;
+        }
+        if slot[0] == 0 {
+            slot.copy_from_slice(name);
+            return true;
+        }
+    }
+    false
+}
+
+/// Remove `name` from the sender list, keeping the list contiguous.
+fn unregister_sender(names: &mut [u8], name: &[u8; NAME_SIZE]) {
+    let count = names
+        .chunks_exact(NAME_SIZE)
+        .take_while(|slot| slot[0] != 0)
+        .count();
+    if let Some(index) = names
+        .chunks_exact(NAME_SIZE)
+        .take(count)
+        .position(|slot| slot == name)
+    {
+        names.copy_within(
+            (index + 1) * NAME_SIZE..count * NAME_SIZE,
+            index * NAME_SIZE,
+        );
+        names[(count - 1) * NAME_SIZE..count * NAME_SIZE].fill(0);
+    }
+}
+
+struct Registration {
+    name: [u8; NAME_SIZE],
+    names: SharedMemory,
+    info: SharedMemory,
+    texture: ID3D11Texture2D,
+}
+
+impl Registration {
+    fn new(
+        device: &ID3D11Device,
+        name: &str,
+        width: u32,
+        height: u32,
+        format: DXGI_FORMAT,
+    ) -> Result<Self> {
+        let desc = D3D11_TEXTURE2D_DESC {
+            Width: width,
+            Height: height,
+            Format: format,
+            MipLevels: 1,
+            ArraySize: 1,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                Quality: 0,
+            },
+            BindFlags: D3D11_BIND_RENDER_TARGET | D3D11_BIND_SHADER_RESOURCE,
+            MiscFlags: D3D11_RESOURCE_MISC_SHARED,
+            Usage: D3D11_USAGE_DEFAULT,
+            CPUAccessFlags: D3D11_CPU_ACCESS_FLAG(0),
+        };
+        let texture = unsafe { device.CreateTexture2D(&desc, None)? };
+        let dxgi_resource: IDXGIResource = texture.cast()?;
+        let share_handle = unsafe { dxgi_resource.GetSharedHandle()? };
+
+        let info = SharedMemory::open(name, std::mem::size_of::<SharedTextureInfo>())?;
+        info.with_locked(|memory| {
+            let info = SharedTextureInfo {
+                // share handles are guaranteed to fit in 32 bits, so that 32-bit receivers can use them
+                share_handle: share_handle.0 as u32,
+                width,
+                height,
+                format: format.0,
+                usage: 0,
+                description: [0; 128],
+                partner_id: 0,
+            };
+            unsafe {
+                std::ptr::write_unaligned(memory.as_mut_ptr() as *mut SharedTextureInfo, info)
+            };
+        });
+
+        let encoded_name = encode_name(name);
+        let names = SharedMemory::open(SENDER_NAMES, MAX_SENDERS * NAME_SIZE)?;
+        let registered = names
+            .with_locked(|memory| register_sender(memory, &encoded_name))
+            .unwrap_or(false);
+        if !registered {
+            return Err(Error::new(
+                E_FAIL,
+                HSTRING::from("too many Spout senders are active"),
+            ));
+        }
+
+        // make this sender the active one, unless there is another one already
+        if let Ok(active) = SharedMemory::open(ACTIVE_SENDER, NAME_SIZE) {
+            active.with_locked(|memory| {
+                if memory[0] == 0 {
+                    memory.copy_from_slice(&encoded_name);
+                }
+            });
+        }
+
+        Ok(Self {
+            name: encoded_name,
+            names,
+            info,
+            texture,
+        })
+    }
+
+    fn matches(&self, width: u32, height: u32, format: DXGI_FORMAT) -> bool {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { self.texture.GetDesc(&mut desc) };
+        desc.Width == width && desc.Height == height && desc.Format == format
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let name = self.name;
+        self.names
+            .with_locked(|memory| unregister_sender(memory, &name));
+        // the info memory is kept alive by receivers that have it open, so clear it explicitly
+        self.info.with_locked(|memory| memory.fill(0));
+    }
+}
+
+/// Publishes every frame as a Spout sender, so that Spout-enabled applications (TouchDesigner,
+/// Resolume, OBS with the Spout plugin, etc.) can receive the capture live.
+///
+/// The sender is registered when the first frame is processed and unregistered when the stage is
+/// dropped. The frame is not modified.
+pub struct SpoutSender {
+    name: String,
+    registration: Option<Registration>,
+}
+
+impl SpoutSender {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            registration: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Stage for SpoutSender {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let mut desc = D3D11_TEXTURE2D_DESC::default();
+        unsafe { texture.GetDesc(&mut desc) };
+        let outdated = match &self.registration {
+            Some(registration) => !registration.matches(desc.Width, desc.Height, desc.Format),
+            None => true,
+        };
+        if outdated {
+            // unregister the old sender before registering a new texture under the same name
+            self.registration = None;
+            self.registration = Some(Registration::new(
+                ctx.device,
+                &self.name,
+                desc.Width,
+                desc.Height,
+                desc.Format,
+            )?);
+        }
+        let registration = self.registration.as_ref().unwrap();
+        let dest: ID3D11Resource = registration.texture.cast()?;
+        let src: ID3D11Resource = texture.cast()?;
+        unsafe {
+            ctx.context.CopyResource(Some(&dest), Some(&src));
+            // receivers open the texture on their own devices, so make sure the copy is submitted
+            ctx.context.Flush();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use windows::{
+    core::{Result, HSTRING},
+    Win32::{
+        Graphics::{
+            Direct2D::{
+                Common::{D2D_POINT_2F, D2D_RECT_F},
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            },
+            Direct3D11::ID3D11Texture2D,
+            DirectWrite::{
+                DWriteCreateFactory, IDWriteFactory, IDWriteTextFormat, DWRITE_FACTORY_TYPE_SHARED,
+                DWRITE_FONT_STRETCH_NORMAL, DWRITE_FONT_STYLE_NORMAL, DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_TEXT_METRICS,
+            },
+        },
+        System::SystemInformation::GetLocalTime,
+    },
+};
+
+use crate::pipeline::{d2d::D2DTarget, Stage, StageContext};
+
+/// Corner of the frame the overlay is attached to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Burns text into every frame, e.g. a timestamp or a watermark.
+///
+/// The text is produced from `template`, in which the following placeholders are substituted:
+/// * `{date}` - local date as `YYYY-MM-DD`;
+/// * `{time}` - local time as `HH:MM:SS`;
+/// * `{millis}` - milliseconds of the local time;
+/// * `{frame}` - number of frames processed by this stage so far.
+pub struct TextOverlay {
+    pub template: String,
+    pub anchor: Anchor,
+    /// Distance from the anchored corner in pixels.
+    pub margin: f32,
+    pub font_family: String,
+    /// Font size in pixels.
+    pub font_size: f32,
+    /// Text color, as straight RGBA.
+    pub color: [f32; 4],
+    /// Color of the box drawn behind the text, as straight RGBA. Fully transparent by default.
+    pub background: [f32; 4],
+    frame_counter: u64,
+    text_format: Option<(String, u32, IDWriteTextFormat)>,
+    dwrite: Option<IDWriteFactory>,
+    d2d: Option<D2DTarget>,
+}
+
+impl TextOverlay {
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            anchor: Anchor::default(),
+            margin: 8.0,
+            font_family: "Consolas".to_string(),
+            font_size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            background: [0.0, 0.0, 0.0, 0.0],
+            frame_counter: 0,
+            text_format: None,
+            dwrite: None,
+            d2d: None,
+        }
+    }
+
+    /// Overlay showing the local date and time of each frame.
+    pub fn timestamp() -> Self {
+        let mut overlay = Self::new("{date} {time}.{millis}");
+        overlay.background = [0.0, 0.0, 0.0, 0.5];
+        overlay
+    }
+
+    /// Overlay showing a fixed watermark text.
+    pub fn watermark(text: &str) -> Self {
+        let mut overlay = Self::new(text);
+        overlay.anchor = Anchor::BottomRight;
+        overlay.color = [1.0, 1.0, 1.0, 0.6];
+        overlay
+    }
+
+    fn render_text(&self) -> String {
+        if !self.template.contains('{') {
+            return self.template.clone();
+        }
+        let now = unsafe { GetLocalTime() };
+        self.template
+            .replace(
+                "{date}",
+                &format!("{:04}-{:02}-{:02}", now.wYear, now.wMonth, now.wDay),
+            )
+            .replace(
+                "{time}",
+                &format!("{:02}:{:02}:{:02}", now.wHour, now.wMinute, now.wSecond),
+            )
+            .replace("{millis}", &format!("{:03}", now.wMilliseconds))
+            .replace("{frame}", &self.frame_counter.to_string())
+    }
+
+    /// Get a text format for the current font settings, recreating it if they have changed.
+    fn text_format(&mut self) -> Result<IDWriteTextFormat> {
+        let font_size_bits = self.font_size.to_bits();
+        if let Some((family, size, format)) = &self.text_format {
+            if *family == self.font_family && *size == font_size_bits {
+                return Ok(format.clone());
+            }
+        }
+        let format = unsafe {
+            self.dwrite().CreateTextFormat(
+                &HSTRING::from(self.font_family.as_str()),
+                None,
+                DWRITE_FONT_WEIGHT_NORMAL,
+                DWRITE_FONT_STYLE_NORMAL,
+                DWRITE_FONT_STRETCH_NORMAL,
+                self.font_size,
+                &HSTRING::from("en-us"),
+            )?
+        };
+        self.text_format = Some((self.font_family.clone(), font_size_bits, format.clone()));
+        Ok(format)
+    }
+
+    fn dwrite(&self) -> &IDWriteFactory {
+        self.dwrite.as_ref().unwrap()
+    }
+}
+
+impl Stage for TextOverlay {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        self.frame_counter += 1;
+        if self.d2d.is_none() {
+            self.d2d = Some(D2DTarget::new(ctx.device)?);
+            self.dwrite = Some(unsafe { DWriteCreateFactory(DWRITE_FACTORY_TYPE_SHARED)? });
+        }
+
+        let text: Vec<u16> = self.render_text().encode_utf16().collect();
+        let format = self.text_format()?;
+        let layout = unsafe {
+            self.dwrite()
+                .CreateTextLayout(&text, &format, ctx.width as f32, ctx.height as f32)?
+        };
+        let mut metrics = DWRITE_TEXT_METRICS::default();
+        unsafe { layout.GetMetrics(&mut metrics)? };
+
+        let (w, h) = (metrics.width, metrics.height);
+        let x = match self.anchor {
+            Anchor::TopLeft | Anchor::BottomLeft => self.margin,
+            Anchor::TopRight | Anchor::BottomRight => ctx.width as f32 - w - self.margin,
+        };
+        let y = match self.anchor {
+            Anchor::TopLeft | Anchor::TopRight => self.margin,
+            Anchor::BottomLeft | Anchor::BottomRight => ctx.height as f32 - h - self.margin,
+        };
+
+        let d2d = self.d2d.as_ref().unwrap();
+        let text_brush = d2d.solid_brush(self.color)?;
+        let background_brush = d2d.solid_brush(self.background)?;
+        let draw_background = self.background[3] > 0.0;
+        d2d.draw(texture, |dc| unsafe {
+            if draw_background {
+                let padding = 4.0;
+                dc.FillRectangle(
+                    &D2D_RECT_F {
+                        left: x - padding,
+                        top: y - padding,
+                        right: x + w + padding,
+                        bottom: y + h + padding,
+                    },
+                    &background_brush,
+                );
+            }
+            dc.DrawTextLayout(
+                D2D_POINT_2F { x, y },
+                &layout,
+                &text_brush,
+                D2D1_DRAW_TEXT_OPTIONS_NONE,
+            );
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,346 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use windows::{
+    core::Result,
+    Win32::{
+        Foundation::POINT,
+        Graphics::Direct3D11::{ID3D11Buffer, ID3D11PixelShader, ID3D11Texture2D},
+        UI::WindowsAndMessaging::GetCursorPos,
+    },
+};
+
+use crate::{
+    pipeline::{
+        shader::{create_constant_buffer, update_buffer, FullscreenPass},
+        Stage, StageContext,
+    },
+    util::Rect,
+};
+
+const ZOOM_PS: &str = r#"
+Texture2D<float4> frame : register(t0);
+SamplerState frame_sampler : register(s0);
+
+cbuffer View : register(b0) {
+    float2 origin;
+    float2 size;
+};
+
+float4 main(float4 pos : SV_Position, float2 uv : TEXCOORD0) : SV_Target {
+    return frame.Sample(frame_sampler, origin + uv * size);
+}
+"#;
+
+/// Part of the frame shown by a `Zoom` stage.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct ZoomView {
+    /// Center of the view relative to the frame size, i.e. `(0.5, 0.5)` is the center of the
+    /// frame. Moved inwards as far as needed for the view to stay within the frame.
+    pub center: (f32, f32),
+    /// Magnification, at least 1 (the whole frame).
+    pub zoom: f32,
+}
+
+impl Default for ZoomView {
+    fn default() -> Self {
+        Self {
+            center: (0.5, 0.5),
+            zoom: 1.0,
+        }
+    }
+}
+
+impl ZoomView {
+    /// The smallest view containing `rect` (in frame coordinates) of a frame of `width` x `height`
+    /// pixels.
+    pub fn covering(rect: Rect, width: u32, height: u32) -> Self {
+        let (width, height) = (width.max(1) as f32, height.max(1) as f32);
+        let rect_width = (rect.right - rect.left).max(1) as f32;
+        let rect_height = (rect.bottom - rect.top).max(1) as f32;
+        let zoom = (width / rect_width).min(height / rect_height);
+        Self {
+            center: (
+                (rect.left as f32 + rect_width / 2.0) / width,
+                (rect.top as f32 + rect_height / 2.0) / height,
+            ),
+            zoom: zoom.max(1.0),
+        }
+    }
+
+    /// Whether the view shows the whole frame.
+    pub fn is_identity(&self) -> bool {
+        self.zoom <= 1.0
+    }
+
+    /// Top-left corner and size of the view relative to the frame size, kept within the frame.
+    fn bounds(&self) -> [f32; 4] {
+        let size = 1.0 / self.zoom.max(1.0);
+        let origin = |center: f32| (center - size / 2.0).clamp(0.0, 1.0 - size);
+        [origin(self.center.0), origin(self.center.1), size, size]
+    }
+
+    /// The view with its center moved inwards as far as needed for it to stay within the frame.
+    fn clamped(&self) -> Self {
+        let [x, y, size, _] = self.bounds();
+        Self {
+            center: (x + size / 2.0, y + size / 2.0),
+            zoom: self.zoom.max(1.0),
+        }
+    }
+
+    /// The view `t` (0-1) of the way from `self` to `other`. The zoom is interpolated
+    /// geometrically, so that zooming in and out appears to progress at a constant speed.
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let (from, to) = (self.zoom.max(1.0), other.zoom.max(1.0));
+        Self {
+            center: (
+                lerp(self.center.0, other.center.0),
+                lerp(self.center.1, other.center.1),
+            ),
+            zoom: from * (to / from).powf(t),
+        }
+    }
+}
+
+/// Settings of the mode of a `Zoom` stage which keeps the view centered on the cursor, see
+/// `ZoomControls::follow_cursor`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct FollowCursor {
+    /// Magnification while following the cursor.
+    pub zoom: f32,
+    /// Time constant of the movement of the view: it covers about 63% of the distance to where it
+    /// should be within this time. Zero follows the cursor exactly.
+    #[cfg_attr(feature = "serde", serde(with = "crate::config::secs"))]
+    pub smoothing: Duration,
+    /// Share of the view (0-1) around its center within which the cursor moves without the view
+    /// following, so that small movements do not make it wobble.
+    pub dead_zone: f32,
+}
+
+impl Default for FollowCursor {
+    fn default() -> Self {
+        Self {
+            zoom: 2.0,
+            smoothing: Duration::from_millis(250),
+            dead_zone: 0.3,
+        }
+    }
+}
+
+impl FollowCursor {
+    /// The view `current` moves to in `elapsed` time while the cursor is at `cursor` (relative to
+    /// the frame size), if known.
+    fn step(&self, current: ZoomView, cursor: Option<(f32, f32)>, elapsed: f32) -> ZoomView {
+        let zoom = self.zoom.max(1.0);
+        let mut target = ZoomView {
+            center: current.center,
+            zoom,
+        };
+        if let Some((x, y)) = cursor {
+            // move just far enough for the cursor to be within the dead zone
+            let reach = self.dead_zone.clamp(0.0, 1.0) / zoom / 2.0;
+            target.center = (
+                current.center.0.clamp(x - reach, x + reach),
+                current.center.1.clamp(y - reach, y + reach),
+            );
+        }
+        let smoothing = self.smoothing.as_secs_f32();
+        let t = if smoothing > 0.0 {
+            1.0 - (-elapsed / smoothing).exp()
+        } else {
+            1.0
+        };
+        current.lerp(&target.clamped(), t)
+    }
+}
+
+/// An animation from one view to another, timed by frame timestamps.
+struct Transition {
+    from: ZoomView,
+    to: ZoomView,
+    duration: Duration,
+    /// Timestamp of the first frame of the transition, set once it is rendered.
+    start: Option<i64>,
+}
+
+struct ZoomState {
+    /// View shown in the last frame.
+    current: ZoomView,
+    transition: Transition,
+    follow: Option<FollowCursor>,
+    last_timestamp: Option<i64>,
+}
+
+/// View of a `Zoom` stage, which can be changed while the stage is attached to a capture.
+#[derive(Clone)]
+pub struct ZoomControls(Arc<Mutex<ZoomState>>);
+
+impl ZoomControls {
+    fn new(view: ZoomView) -> Self {
+        let transition = Transition {
+            from: view,
+            to: view,
+            duration: Duration::ZERO,
+            start: None,
+        };
+        Self(Arc::new(Mutex::new(ZoomState {
+            current: view,
+            transition,
+            follow: None,
+            last_timestamp: None,
+        })))
+    }
+
+    /// Jump to `view` from the next frame on. Ends following the cursor.
+    pub fn set(&self, view: ZoomView) {
+        self.animate_to(view, Duration::ZERO);
+    }
+
+    /// Move smoothly from the view currently shown to `view` over `duration`, starting with the
+    /// next frame. Interrupts the running transition, if any, and ends following the cursor.
+    pub fn animate_to(&self, view: ZoomView, duration: Duration) {
+        let mut state = self.0.lock().unwrap();
+        state.follow = None;
+        state.transition = Transition {
+            from: state.current,
+            to: view,
+            duration,
+            start: None,
+        };
+    }
+
+    /// Zoom in and keep the view centered on the cursor, moving it smoothly as the cursor moves,
+    /// e.g. for screencasts. Lasts until the view is set or animated to, or `reset()`.
+    ///
+    /// The cursor is only followed on targets whose position on the screen is known (see
+    /// `Capturable::get_screen_origin`); otherwise the view stays where it is.
+    pub fn follow_cursor(&self, follow: FollowCursor) {
+        self.0.lock().unwrap().follow = Some(follow);
+    }
+
+    /// Whether the view follows the cursor.
+    pub fn is_following_cursor(&self) -> bool {
+        self.0.lock().unwrap().follow.is_some()
+    }
+
+    /// Move smoothly back to the whole frame over `duration`.
+    pub fn reset(&self, duration: Duration) {
+        self.animate_to(ZoomView::default(), duration);
+    }
+
+    /// The view being moved to, or shown if there is no transition running. Not meaningful while
+    /// following the cursor.
+    pub fn target(&self) -> ZoomView {
+        self.0.lock().unwrap().transition.to
+    }
+
+    /// The view shown in the last frame.
+    pub fn current(&self) -> ZoomView {
+        self.0.lock().unwrap().current
+    }
+
+    /// Advance the transition or the following of the cursor (at `cursor`, relative to the frame
+    /// size) to the frame at `timestamp`, returning the view to show.
+    fn advance(&self, timestamp: i64, cursor: Option<(f32, f32)>) -> ZoomView {
+        let mut state = self.0.lock().unwrap();
+        let last_timestamp = state.last_timestamp.replace(timestamp);
+        if let Some(follow) = state.follow {
+            let elapsed = last_timestamp.map_or(0, |last| (timestamp - last).max(0));
+            state.current = follow.step(state.current, cursor, elapsed as f32 / 1e7);
+            return state.current;
+        }
+        let ZoomState {
+            current,
+            transition,
+            ..
+        } = &mut *state;
+        let start = *transition.start.get_or_insert(timestamp);
+        let duration = transition.duration.as_nanos() as f64 / 100.0;
+        let t = if duration > 0.0 {
+            ((timestamp - start) as f64 / duration).clamp(0.0, 1.0) as f32
+        } else {
+            1.0
+        };
+        // ease in and out
+        let t = t * t * (3.0 - 2.0 * t);
+        *current = transition.from.lerp(&transition.to, t);
+        *current
+    }
+}
+
+/// Shows a part of every frame magnified to the full frame size, e.g. for recording tools which
+/// zoom into the region being worked on like presentation tools do. Cropping and scaling happen
+/// on the GPU, with bilinear filtering.
+///
+/// The view can be changed, and smoothly animated, through `controls()` while frames are being
+/// captured, or made to follow the cursor (see `ZoomControls::follow_cursor`). Transitions are
+/// timed by the frame timestamps, so they take the same time in the recording regardless of the
+/// frame rate.
+pub struct Zoom {
+    controls: ZoomControls,
+    state: Option<(FullscreenPass, ID3D11PixelShader, ID3D11Buffer)>,
+}
+
+impl Default for Zoom {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl Zoom {
+    pub fn new(view: ZoomView) -> Self {
+        Self {
+            controls: ZoomControls::new(view),
+            state: None,
+        }
+    }
+
+    /// Handle to the view.
+    pub fn controls(&self) -> ZoomControls {
+        self.controls.clone()
+    }
+}
+
+impl Stage for Zoom {
+    fn process(&mut self, ctx: &StageContext, texture: &ID3D11Texture2D) -> Result<()> {
+        let cursor = if self.controls.is_following_cursor() {
+            let mut cursor = POINT::default();
+            // e.g. while the secure desktop is shown, where the view then stays
+            let known = unsafe { GetCursorPos(&mut cursor as *mut _) }.as_bool();
+            known
+                .then(|| ctx.screen_to_frame(cursor))
+                .flatten()
+                .map(|(x, y)| (x / ctx.width as f32, y / ctx.height as f32))
+        } else {
+            None
+        };
+        let view = self.controls.advance(ctx.timestamp, cursor);
+        if view.is_identity() {
+            return Ok(());
+        }
+        if self.state.is_none() {
+            self.state = Some((
+                FullscreenPass::new(ctx.device)?,
+                FullscreenPass::create_pixel_shader(ctx.device, ZOOM_PS, "main")?,
+                create_constant_buffer(ctx.device, 16)?,
+            ));
+        }
+        let (pass, shader, buffer) = self.state.as_mut().unwrap();
+        update_buffer(ctx, buffer, &view.bounds())?;
+        pass.run(ctx, texture, shader, &[Some(buffer.clone())])
+    }
+}
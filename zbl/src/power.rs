@@ -0,0 +1,38 @@
+//! Power status of the system, for throttling captures on laptops (see
+//! `CaptureConfig::power_throttle`).
+
+use windows::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+/// Where the system currently draws its power from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum PowerState {
+    /// Plugged in, or the power source is unknown (e.g. desktops without a battery).
+    AcPower,
+    /// Running on battery.
+    Battery,
+    /// Running on battery with battery saver turned on.
+    BatterySaver,
+}
+
+impl PowerState {
+    /// Query the current power state.
+    pub fn current() -> Self {
+        let mut status = SYSTEM_POWER_STATUS::default();
+        if !unsafe { GetSystemPowerStatus(&mut status) }.as_bool() {
+            return Self::AcPower;
+        }
+        // `SystemStatusFlag` is 1 while battery saver is on
+        if status.SystemStatusFlag == 1 {
+            Self::BatterySaver
+        } else if status.ACLineStatus == 0 {
+            Self::Battery
+        } else {
+            Self::AcPower
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Capture settings loadable from configuration files.
+//!
+//! A profile names the capture target along with the `CaptureConfig` fields, e.g. in TOML:
+//!
+//! ```toml
+//! target = { window = "visual studio code" }
+//! capture_cursor = true
+//! rate = { fps = 30.0 }
+//! output_size = [1280, 720]
+//! watchdog = 5.0
+//! ```
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Capturable, CaptureBuilder, CaptureConfig, Display, Error, Window};
+
+/// What to capture.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// The first window whose title contains the given string, ignoring case.
+    Window(String),
+    /// The display at the given index of `Display::enumerate()`, i.e. the one
+    /// `Display::find_by_id(index)` returns. Opening fails with `Error::TargetNotFound` if there
+    /// are fewer displays.
+    Display(usize),
+}
+
+impl Target {
+    pub fn open(&self) -> crate::Result<Box<dyn Capturable>> {
+        match self {
+            Target::Window(title) => match Window::find_first(title) {
+                Some(window) => Ok(Box::new(window)),
+                None => Err(Error::TargetNotFound(format!("window {:?}", title))),
+            },
+            Target::Display(id) => match Display::enumerate()?.into_iter().nth(*id) {
+                Some(display) => Ok(Box::new(display)),
+                None => Err(Error::TargetNotFound(format!("display {}", id))),
+            },
+        }
+    }
+}
+
+/// A capture target together with the options to capture it with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CaptureProfile {
+    pub target: Target,
+    #[serde(flatten)]
+    pub config: CaptureConfig,
+}
+
+impl CaptureProfile {
+    /// Parse and validate a profile in TOML format.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(text: &str) -> crate::Result<Self> {
+        let profile: Self =
+            toml::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        profile.config.validate()?;
+        Ok(profile)
+    }
+
+    #[cfg(feature = "toml")]
+    pub fn to_toml(&self) -> crate::Result<String> {
+        toml::to_string(self).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    /// Parse and validate a profile in JSON format.
+    #[cfg(feature = "json")]
+    pub fn from_json(text: &str) -> crate::Result<Self> {
+        let profile: Self =
+            serde_json::from_str(text).map_err(|e| Error::InvalidConfig(e.to_string()))?;
+        profile.config.validate()?;
+        Ok(profile)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> crate::Result<String> {
+        serde_json::to_string_pretty(self).map_err(|e| Error::InvalidConfig(e.to_string()))
+    }
+
+    /// Load a profile from a `.toml` or `.json` file (given the respective feature is enabled).
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|extension| extension.to_str()) {
+            #[cfg(feature = "toml")]
+            Some("toml") => Self::from_toml(&std::fs::read_to_string(path)?),
+            #[cfg(feature = "json")]
+            Some("json") => Self::from_json(&std::fs::read_to_string(path)?),
+            _ => Err(Error::InvalidConfig(format!(
+                "unsupported config file format: {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Find the target and prepare a capture of it. Stages and observers can be added to the
+    /// returned builder before building the capture.
+    pub fn builder(&self) -> crate::Result<CaptureBuilder> {
+        self.config.validate()?;
+        Ok(CaptureBuilder::new(self.target.open()?).config(self.config.clone()))
+    }
+}
@@ -0,0 +1,309 @@
+//! Capturing a rectangle of the screen, and letting the user pick one.
+
+use std::{cell::RefCell, mem::size_of, sync::mpsc::Receiver};
+
+use windows::{
+    core::Result,
+    w,
+    Graphics::Capture::GraphicsCaptureItem,
+    Win32::{
+        Foundation::{COLORREF, HINSTANCE, HWND, LPARAM, LRESULT, POINT, RECT, WPARAM},
+        Graphics::{
+            Direct3D11::D3D11_BOX,
+            Gdi::{
+                BeginPaint, CreateSolidBrush, DeleteObject, EndPaint, FillRect, FrameRect,
+                IntersectRect, InvalidateRect, MonitorFromRect, MONITOR_DEFAULTTONEAREST,
+                PAINTSTRUCT,
+            },
+        },
+        System::LibraryLoader::GetModuleHandleW,
+        UI::{
+            Input::KeyboardAndMouse::{ReleaseCapture, SetCapture, VK_ESCAPE},
+            WindowsAndMessaging::{
+                CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
+                GetSystemMetrics, LoadCursorW, PostQuitMessage, RegisterClassExW,
+                SetForegroundWindow, SetLayeredWindowAttributes, ShowWindow, TranslateMessage,
+                HMENU, IDC_CROSS, LWA_ALPHA, LWA_COLORKEY, MSG, SM_CXVIRTUALSCREEN,
+                SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN, SW_SHOW, WM_DESTROY,
+                WM_ERASEBKGND, WM_KEYDOWN, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MOUSEMOVE, WM_PAINT,
+                WM_RBUTTONDOWN, WNDCLASSEXW, WS_EX_LAYERED, WS_EX_TOOLWINDOW, WS_EX_TOPMOST,
+                WS_POPUP,
+            },
+        },
+    },
+};
+
+use crate::{
+    capturable::{client_box, never_closing_notification_channel},
+    Capturable, Display, Error,
+};
+
+/// Opacity of the dimmed part of the selection overlay.
+const OVERLAY_ALPHA: u8 = 96;
+/// Color keyed out of the overlay, which makes the selected rectangle fully transparent.
+const TRANSPARENT_KEY: COLORREF = COLORREF(0x00ff00ff);
+const BORDER_COLOR: COLORREF = COLORREF(0x00ffffff);
+
+/// A rectangle of a display.
+///
+/// Frames only contain the rectangle, and stages see it at its position on the screen, so e.g.
+/// `pipeline::CursorOverlay` works as with whole displays.
+#[derive(Clone, Debug)]
+pub struct Region {
+    display: Display,
+    rect: RECT,
+}
+
+impl Region {
+    /// The part of `rect` (on the virtual screen) which is on the display containing most of it.
+    /// Captures cannot span displays, so anything beyond that display is cut off.
+    pub fn new(rect: RECT) -> crate::Result<Self> {
+        let display = Display::new(unsafe { MonitorFromRect(&rect, MONITOR_DEFAULTTONEAREST) })?;
+        let mut clipped = RECT::default();
+        let overlaps = unsafe { IntersectRect(&mut clipped, &rect, &display.get_monitor_rect()) };
+        if !overlaps.as_bool() {
+            return Err(Error::ZeroSized);
+        }
+        Ok(Self {
+            display,
+            rect: clipped,
+        })
+    }
+
+    /// Show a translucent overlay over all displays and let the user drag a rectangle, like the
+    /// Snipping Tool does. Blocks until the user is done.
+    ///
+    /// Returns `None` if the selection was cancelled with Escape or a right click.
+    pub fn select() -> crate::Result<Option<Self>> {
+        match select_rect()? {
+            Some(rect) => Self::new(rect).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The captured rectangle on the virtual screen.
+    pub fn rect(&self) -> RECT {
+        self.rect
+    }
+
+    /// The display the rectangle is on.
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+}
+
+impl Capturable for Region {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        self.display.create_capture_item()
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        let monitor = self.display.get_monitor_rect();
+        Ok(client_box(
+            (self.rect.left - monitor.left) as u32,
+            (self.rect.top - monitor.top) as u32,
+            (self.rect.right - self.rect.left) as u32,
+            (self.rect.bottom - self.rect.top) as u32,
+        ))
+    }
+
+    fn get_close_notification_channel(&self) -> Receiver<()> {
+        never_closing_notification_channel()
+    }
+
+    fn get_raw_handle(&self) -> isize {
+        self.display.handle.0
+    }
+
+    fn get_screen_origin(&self) -> Option<POINT> {
+        Some(POINT {
+            x: self.rect.left,
+            y: self.rect.top,
+        })
+    }
+
+    fn refresh_rate(&self) -> Option<u32> {
+        self.display.refresh_rate()
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if self.rect.right <= self.rect.left || self.rect.bottom <= self.rect.top {
+            return Err(Error::ZeroSized);
+        }
+        self.display.validate()
+    }
+}
+
+#[derive(Default)]
+struct Selection {
+    /// Top-left corner of the overlay on the virtual screen.
+    origin: POINT,
+    /// Where the drag started and where the cursor is now, in client coordinates.
+    drag: Option<(POINT, POINT)>,
+    result: Option<RECT>,
+}
+
+impl Selection {
+    fn rect(&self) -> Option<RECT> {
+        self.drag.map(|(start, end)| RECT {
+            left: start.x.min(end.x),
+            top: start.y.min(end.y),
+            right: start.x.max(end.x),
+            bottom: start.y.max(end.y),
+        })
+    }
+}
+
+thread_local! {
+    static SELECTION: RefCell<Selection> = Default::default();
+}
+
+fn point_from_lparam(lparam: LPARAM) -> POINT {
+    POINT {
+        x: (lparam.0 & 0xffff) as i16 as i32,
+        y: ((lparam.0 >> 16) & 0xffff) as i16 as i32,
+    }
+}
+
+unsafe fn paint(window: HWND) {
+    let mut ps = PAINTSTRUCT::default();
+    let dc = BeginPaint(window, &mut ps);
+    let dim = CreateSolidBrush(COLORREF(0));
+    FillRect(dc, &ps.rcPaint, dim);
+    DeleteObject(dim);
+    if let Some(rect) = SELECTION.with(|selection| selection.borrow().rect()) {
+        let hole = CreateSolidBrush(TRANSPARENT_KEY);
+        let border = CreateSolidBrush(BORDER_COLOR);
+        FillRect(dc, &rect, hole);
+        FrameRect(dc, &rect, border);
+        DeleteObject(hole);
+        DeleteObject(border);
+    }
+    EndPaint(window, &ps);
+}
+
+unsafe extern "system" fn selection_window_proc(
+    window: HWND,
+    message: u32,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    match message {
+        WM_LBUTTONDOWN => {
+            let point = point_from_lparam(lparam);
+            SELECTION.with(|selection| selection.borrow_mut().drag = Some((point, point)));
+            // keep receiving mouse messages over the (click-through) transparent hole
+            SetCapture(window);
+        }
+        WM_MOUSEMOVE => {
+            let dragging = SELECTION.with(|selection| {
+                let mut selection = selection.borrow_mut();
+                match &mut selection.drag {
+                    Some((_, end)) => {
+                        *end = point_from_lparam(lparam);
+                        true
+                    }
+                    None => false,
+                }
+            });
+            if dragging {
+                InvalidateRect(window, None, false);
+            }
+        }
+        WM_LBUTTONUP => {
+            ReleaseCapture();
+            SELECTION.with(|selection| {
+                let mut selection = selection.borrow_mut();
+                if let Some((start, _)) = selection.drag {
+                    selection.drag = Some((start, point_from_lparam(lparam)));
+                }
+                let origin = selection.origin;
+                selection.result = selection
+                    .rect()
+                    .filter(|rect| rect.right > rect.left && rect.bottom > rect.top)
+                    .map(|rect| RECT {
+                        left: rect.left + origin.x,
+                        top: rect.top + origin.y,
+                        right: rect.right + origin.x,
+                        bottom: rect.bottom + origin.y,
+                    });
+            });
+            DestroyWindow(window);
+        }
+        WM_KEYDOWN if wparam.0 as u16 == VK_ESCAPE.0 => {
+            DestroyWindow(window);
+        }
+        WM_RBUTTONDOWN => {
+            DestroyWindow(window);
+        }
+        WM_PAINT => paint(window),
+        // everything is painted in WM_PAINT, erasing would only flicker
+        WM_ERASEBKGND => return LRESULT(1),
+        WM_DESTROY => PostQuitMessage(0),
+        _ => return DefWindowProcW(window, message, wparam, lparam),
+    }
+    LRESULT(0)
+}
+
+/// Run the selection overlay on the calling thread, returning the selected rectangle on the
+/// virtual screen.
+fn select_rect() -> Result<Option<RECT>> {
+    unsafe {
+        let instance = GetModuleHandleW(None)?;
+        let class_name = w!("zbl_region_selection");
+        let class = WNDCLASSEXW {
+            cbSize: size_of::<WNDCLASSEXW>() as u32,
+            lpfnWndProc: Some(selection_window_proc),
+            hInstance: instance,
+            hCursor: LoadCursorW(HINSTANCE::default(), IDC_CROSS)?,
+            lpszClassName: class_name,
+            ..Default::default()
+        };
+        // fails harmlessly if the class has been registered by a previous selection
+        RegisterClassExW(&class);
+
+        let origin = POINT {
+            x: GetSystemMetrics(SM_XVIRTUALSCREEN),
+            y: GetSystemMetrics(SM_YVIRTUALSCREEN),
+        };
+        SELECTION.with(|selection| {
+            *selection.borrow_mut() = Selection {
+                origin,
+                ..Default::default()
+            }
+        });
+        let window = CreateWindowExW(
+            WS_EX_LAYERED | WS_EX_TOPMOST | WS_EX_TOOLWINDOW,
+            class_name,
+            w!("Select a region"),
+            WS_POPUP,
+            origin.x,
+            origin.y,
+            GetSystemMetrics(SM_CXVIRTUALSCREEN),
+            GetSystemMetrics(SM_CYVIRTUALSCREEN),
+            HWND::default(),
+            HMENU::default(),
+            instance,
+            None,
+        );
+        if window == HWND::default() {
+            return Err(windows::core::Error::from_win32());
+        }
+        SetLayeredWindowAttributes(
+            window,
+            TRANSPARENT_KEY,
+            OVERLAY_ALPHA,
+            LWA_ALPHA | LWA_COLORKEY,
+        )
+        .ok()?;
+        ShowWindow(window, SW_SHOW);
+        SetForegroundWindow(window);
+
+        let mut msg = MSG::default();
+        while GetMessageW(&mut msg, HWND::default(), 0, 0).as_bool() {
+            TranslateMessage(&msg);
+            DispatchMessageW(&msg);
+        }
+        Ok(SELECTION.with(|selection| selection.borrow_mut().result.take()))
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{Frame, Image};
+
+/// Keeps the most recent frames in memory, so that the last few seconds of a capture can be saved
+/// after the fact ("instant replay").
+///
+/// Frames are stored uncompressed, so a buffer of `length` seconds at `fps` frames per second needs
+/// about `width * height * 4 * fps * length` bytes; use `max_frames` or `max_bytes` to put a hard
+/// limit on that.
+pub struct ReplayBuffer {
+    length: Duration,
+    max_frames: Option<usize>,
+    max_bytes: Option<usize>,
+    /// Size of the pixel data of all buffered frames.
+    bytes: usize,
+    frames: VecDeque<(Instant, Image)>,
+}
+
+impl ReplayBuffer {
+    /// Create a buffer holding frames captured within the last `length`.
+    pub fn new(length: Duration) -> Self {
+        Self {
+            length,
+            max_frames: None,
+            max_bytes: None,
+            bytes: 0,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Limit the number of frames held, regardless of their age.
+    pub fn max_frames(mut self, max_frames: usize) -> Self {
+        self.max_frames = Some(max_frames);
+        self
+    }
+
+    /// Limit the memory taken by the pixel data of the frames held, evicting the oldest frames
+    /// first. The newest frame is kept even if it is larger than that on its own.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Memory taken by the pixel data of the buffered frames.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Copy a frame into the buffer, evicting frames which are too old.
+    pub fn push(&mut self, frame: &Frame) {
+        self.push_image(frame.to_image());
+    }
+
+    pub fn push_image(&mut self, image: Image) {
+        let now = Instant::now();
+        self.bytes += image.data.len();
+        self.frames.push_back((now, image));
+        while let Some((time, _)) = self.frames.front() {
+            let too_many = matches!(self.max_frames, Some(max) if self.frames.len() > max);
+            let too_large =
+                matches!(self.max_bytes, Some(max) if self.bytes > max) && self.frames.len() > 1;
+            if !too_many && !too_large && now.duration_since(*time) <= self.length {
+                break;
+            }
+            if let Some((_, image)) = self.frames.pop_front() {
+                self.bytes -= image.data.len();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    /// Time between the oldest and the newest frame in the buffer.
+    pub fn span(&self) -> Duration {
+        match (self.frames.front(), self.frames.back()) {
+            (Some((first, _)), Some((last, _))) => last.duration_since(*first),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Buffered frames from oldest to newest, along with the time they were pushed at.
+    pub fn frames(&self) -> impl Iterator<Item = &(Instant, Image)> {
+        self.frames.iter()
+    }
+
+    /// Save the buffered frames into `dir` as `frame_00000.bmp`, `frame_00001.bmp`, etc., creating
+    /// the directory if needed. Returns the number of frames saved.
+    pub fn save_image_sequence(&self, dir: impl AsRef<Path>) -> std::io::Result<usize> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        for (i, (_, image)) in self.frames.iter().enumerate() {
+            image.save_bmp(dir.join(format!("frame_{:05}.bmp", i)))?;
+        }
+        Ok(self.frames.len())
+    }
+}
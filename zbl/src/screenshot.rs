@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use crate::{Capture, Display, Image};
+
+/// How long to wait for a display to produce its first frame.
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn screenshot(display: &Display) -> crate::Result<Option<Image>> {
+    let mut capture = Capture::builder(Box::new(display.clone()))
+        .grab_timeout(SCREENSHOT_TIMEOUT)
+        .build()?;
+    let image = capture
+        .request_frame()?
+        .frame()
+        .map(|frame| frame.to_image());
+    Ok(image)
+}
+
+/// Take a screenshot of every display.
+///
+/// A capture session is created for each display in turn and torn down right after its frame has
+/// been taken. Displays which fail to produce a frame in time are left out.
+pub fn screenshot_all_monitors() -> crate::Result<Vec<(Display, Image)>> {
+    let mut screenshots = Vec::new();
+    for display in Display::enumerate()? {
+        if let Some(image) = screenshot(&display)? {
+            screenshots.push((display, image));
+        }
+    }
+    Ok(screenshots)
+}
+
+/// Take a screenshot of every display, and combine them into a single image of the whole virtual
+/// screen, laid out the same way the displays are arranged. Areas not covered by any display are
+/// left transparent black.
+pub fn screenshot_all_monitors_stitched() -> crate::Result<Image> {
+    let screenshots = screenshot_all_monitors()?;
+    let rects: Vec<_> = screenshots
+        .iter()
+        .map(|(display, _)| display.get_monitor_rect())
+        .collect();
+    let left = rects.iter().map(|rect| rect.left).min().unwrap_or(0);
+    let top = rects.iter().map(|rect| rect.top).min().unwrap_or(0);
+    let right = rects.iter().map(|rect| rect.right).max().unwrap_or(0);
+    let bottom = rects.iter().map(|rect| rect.bottom).max().unwrap_or(0);
+
+    let width = (right - left) as u32;
+    let height = (bottom - top) as u32;
+    let mut data = vec![0u8; width as usize * height as usize * 4];
+    for ((_, image), rect) in screenshots.iter().zip(rects) {
+        let x = (rect.left - left) as usize;
+        let y = (rect.top - top) as usize;
+        // the frame can be smaller than the monitor rect, but should never be larger
+        let copy_width = (image.width as usize).min(width as usize - x) * 4;
+        for row in 0..(image.height as usize).min(height as usize - y) {
+            let src = row * image.width as usize * 4;
+            let dst = ((y + row) * width as usize + x) * 4;
+            data[dst..dst + copy_width].copy_from_slice(&image.data[src..src + copy_width]);
+        }
+    }
+    Ok(Image {
+        width,
+        height,
+        data,
+    })
+}
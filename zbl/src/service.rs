@@ -0,0 +1,239 @@
+//! JSON-RPC 2.0 service for orchestrating captures from other machines.
+//!
+//! Requests and responses are single lines of JSON sent over TCP. Supported methods:
+//! * `list_windows` - `WindowInfo` of all capturable windows;
+//! * `list_displays` - `DisplayInfo` of all displays;
+//! * `start_capture` with a `CaptureProfile` as params - id of the new capture;
+//! * `fetch_frame` with `{ "id": ... }` - the next frame as `{ width, height, data }`, where `data`
+//!   is base64-encoded BGRA, or `null` if no new frame has arrived in time;
+//! * `stop_capture` with `{ "id": ... }`.
+//!
+//! Captures are not thread-safe, so all of them live on the thread running `Service::serve()`,
+//! and requests of all connections are handled one after another.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{profile::CaptureProfile, Capture, Display, GrabStatus, Window};
+
+/// How long `fetch_frame` waits for a frame if the profile does not set a grab timeout.
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct CaptureId {
+    id: u64,
+}
+
+type Reply = std::result::Result<Value, (i64, String)>;
+
+/// Captures started through the service.
+#[derive(Default)]
+pub struct Service {
+    captures: HashMap<u64, Capture>,
+    next_id: u64,
+}
+
+impl Service {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle a single call.
+    pub fn call(&mut self, method: &str, params: Value) -> Reply {
+        match method {
+            "list_windows" => to_value(
+                Window::enumerate()
+                    .iter()
+                    .map(Window::info)
+                    .collect::<Vec<_>>(),
+            ),
+            "list_displays" => {
+                let displays = Display::enumerate().map_err(server_error)?;
+                to_value(displays.iter().map(Display::info).collect::<Vec<_>>())
+            }
+            "start_capture" => {
+                let mut profile: CaptureProfile = from_params(params)?;
+                profile
+                    .config
+                    .grab_timeout
+                    .get_or_insert(DEFAULT_FETCH_TIMEOUT);
+                let mut capture = profile
+                    .builder()
+                    .and_then(|builder| builder.build())
+                    .map_err(server_error)?;
+                capture.start().map_err(server_error)?;
+                self.next_id += 1;
+                self.captures.insert(self.next_id, capture);
+                Ok(json!(self.next_id))
+            }
+            "fetch_frame" => {
+                let CaptureId { id } = from_params(params)?;
+                let capture = self.capture(id)?;
+                match capture.grab().map_err(server_error)? {
+                    GrabStatus::Frame(frame) => {
+                        let image = frame.to_image();
+                        Ok(json!({
+                            "width": image.width,
+                            "height": image.height,
+                            "data": base64(&image.data),
+                        }))
+                    }
+                    GrabStatus::Closed => Err((SERVER_ERROR, "capture is closed".to_string())),
+                    _ => Ok(Value::Null),
+                }
+            }
+            "stop_capture" => {
+                let CaptureId { id } = from_params(params)?;
+                self.capture(id)?.stop().map_err(server_error)?;
+                self.captures.remove(&id);
+                Ok(Value::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {:?}", method))),
+        }
+    }
+
+    fn capture(&mut self, id: u64) -> std::result::Result<&mut Capture, (i64, String)> {
+        self.captures
+            .get_mut(&id)
+            .ok_or_else(|| (INVALID_PARAMS, format!("no capture with id {}", id)))
+    }
+
+    /// Accept connections on `address` and handle their requests until the listener fails.
+    pub fn serve(mut self, address: impl ToSocketAddrs) -> std::io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (sender, receiver) = channel::<(Request, Sender<Reply>)>();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                thread::spawn(move || handle_connection(stream, sender));
+            }
+        });
+        for (request, reply) in receiver {
+            reply.send(self.call(&request.method, request.params)).ok();
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, sender: Sender<(Request, Sender<Reply>)>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let (id, reply) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let id = request.id.clone().unwrap_or(Value::Null);
+                let (reply_sender, reply_receiver) = channel();
+                if sender.send((request, reply_sender)).is_err() {
+                    return;
+                }
+                match reply_receiver.recv() {
+                    Ok(reply) => (id, reply),
+                    Err(_) => return,
+                }
+            }
+            Err(e) => (Value::Null, Err((PARSE_ERROR, e.to_string()))),
+        };
+        let response = match reply {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn to_value(value: impl serde::Serialize) -> Reply {
+    serde_json::to_value(value).map_err(server_error)
+}
+
+fn from_params<T: serde::de::DeserializeOwned>(
+    params: Value,
+) -> std::result::Result<T, (i64, String)> {
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+fn server_error(error: impl std::fmt::Display) -> (i64, String) {
+    (SERVER_ERROR, error.to_string())
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(base64(data.as_bytes()), encoded);
+        }
+    }
+
+    #[test]
+    fn base64_uses_the_whole_alphabet() {
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+        assert_eq!(base64(&[0x00, 0x10, 0x83]), "ABCD");
+    }
+}
@@ -0,0 +1,284 @@
+//! Lowering the frame rate, resolution and quality of streaming sinks while their consumers fall
+//! behind, see `AdaptiveQuality`.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    sink::{Compression, Encoding},
+    Frame, Image,
+};
+
+/// A step on the ladder of `AdaptiveQuality`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QualityLevel {
+    /// Send only every n-th frame.
+    pub frame_divisor: u32,
+    /// Divide width and height of frames by this.
+    pub scale_divisor: u32,
+    /// Upper bound of the quality of lossy compression (JPEG or WebP). Has no effect on raw or
+    /// LZ4 frames.
+    pub max_quality: Option<u8>,
+}
+
+impl QualityLevel {
+    /// Share of the data of the best level sent at this one, ignoring the quality bound: every
+    /// `frame_divisor`-th frame, with `scale_divisor`² fewer pixels.
+    fn relative_cost(&self) -> f32 {
+        let scale = self.scale_divisor.max(1) as f32;
+        1.0 / (self.frame_divisor.max(1) as f32 * scale * scale)
+    }
+}
+
+impl Default for QualityLevel {
+    fn default() -> Self {
+        Self {
+            frame_divisor: 1,
+            scale_divisor: 1,
+            max_quality: None,
+        }
+    }
+}
+
+/// Congestion-aware adaptation of a streaming sink.
+///
+/// Sinks write frames synchronously, so a consumer (or link) which cannot keep up shows as sending
+/// taking up much of the time between frames. While that share stays above `congested_load` for
+/// `degrade_after`, the sink moves one level down the ladder; while the share it would take one
+/// level up (estimated from the frame rate and resolution of both levels) stays below
+/// `relaxed_load` for `recover_after`, one level up. The gap between the thresholds and the longer
+/// wait before recovering keep the sink from flapping between levels.
+///
+/// All clients of a sink get the same frames, so the slowest one decides the level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AdaptiveQuality {
+    /// Levels from best to worst. The first is used while the consumers keep up.
+    pub levels: Vec<QualityLevel>,
+    /// Share of the time between frames spent sending (0-1) above which the consumers count as
+    /// falling behind.
+    pub congested_load: f32,
+    /// Share of the time between frames spent sending below which the consumers count as keeping
+    /// up comfortably.
+    pub relaxed_load: f32,
+    pub degrade_after: Duration,
+    pub recover_after: Duration,
+}
+
+impl Default for AdaptiveQuality {
+    fn default() -> Self {
+        let level = |frame_divisor, scale_divisor, max_quality| QualityLevel {
+            frame_divisor,
+            scale_divisor,
+            max_quality,
+        };
+        Self {
+            levels: vec![
+                level(1, 1, None),
+                level(1, 1, Some(60)),
+                level(2, 1, Some(60)),
+                level(2, 2, Some(50)),
+                level(4, 2, Some(40)),
+                level(4, 4, Some(40)),
+            ],
+            congested_load: 0.8,
+            relaxed_load: 0.4,
+            degrade_after: Duration::from_millis(500),
+            recover_after: Duration::from_secs(5),
+        }
+    }
+}
+
+/// State of the adaptation of a sink.
+pub(crate) struct Adapter {
+    config: AdaptiveQuality,
+    level: usize,
+    frames: u64,
+    last_send: Option<Instant>,
+    congested_since: Option<Instant>,
+    relaxed_since: Option<Instant>,
+}
+
+impl Adapter {
+    pub(crate) fn new(mut config: AdaptiveQuality) -> Self {
+        if config.levels.is_empty() {
+            config.levels.push(QualityLevel::default());
+        }
+        Self {
+            config,
+            level: 0,
+            frames: 0,
+            last_send: None,
+            congested_since: None,
+            relaxed_since: None,
+        }
+    }
+
+    /// Index of the current level in `AdaptiveQuality::levels`.
+    pub(crate) fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Apply the current level to `frame`, returning the image and encoding to send it with, or
+    /// `None` if the frame is to be skipped.
+    pub(crate) fn prepare(
+        &mut self,
+        frame: &Frame,
+        encoding: &Encoding,
+    ) -> Option<(Image, Encoding)> {
+        let level = self.config.levels[self.level];
+        self.frames += 1;
+        if (self.frames - 1) % level.frame_divisor.max(1) as u64 != 0 {
+            return None;
+        }
+        let image = frame.to_image().downscaled(level.scale_divisor);
+        let compression = match (encoding.compression, level.max_quality) {
+            (Compression::Jpeg { quality }, Some(max)) => Compression::Jpeg {
+                quality: quality.min(max),
+            },
+            (Compression::WebP { quality, effort }, Some(max)) => Compression::WebP {
+                quality: quality.min(max),
+                effort,
+            },
+            (compression, _) => compression,
+        };
+        let encoding = Encoding {
+            compression,
+            ..*encoding
+        };
+        Some((image, encoding))
+    }
+
+    /// Account for a frame whose sending started at `started` and finished at `now`, and change
+    /// the level if the consumers have been falling behind or keeping up for long enough.
+    pub(crate) fn record(&mut self, started: Instant, now: Instant) {
+        let last_send = self.last_send.replace(started);
+        let interval = match last_send {
+            Some(last_send) => started.duration_since(last_send),
+            None => return,
+        };
+        let load = (now - started).as_secs_f32() / interval.as_secs_f32().max(f32::EPSILON);
+        if load > self.config.congested_load {
+            self.relaxed_since = None;
+            let since = *self.congested_since.get_or_insert(now);
+            let lowest = self.level + 1 == self.config.levels.len();
+            if now - since >= self.config.degrade_after && !lowest {
+                self.change_level(self.level + 1);
+            }
+        } else if self.level > 0 && load * self.recovery_factor() < self.config.relaxed_load {
+            self.congested_since = None;
+            let since = *self.relaxed_since.get_or_insert(now);
+            if now - since >= self.config.recover_after {
+                self.change_level(self.level - 1);
+            }
+        } else {
+            self.congested_since = None;
+            self.relaxed_since = None;
+        }
+    }
+
+    /// How much the load grows when moving one level up, e.g. 2 for twice the frame rate.
+    fn recovery_factor(&self) -> f32 {
+        let levels = &self.config.levels;
+        levels[self.level - 1].relative_cost() / levels[self.level].relative_cost()
+    }
+
+    fn change_level(&mut self, level: usize) {
+        self.level = level;
+        self.congested_since = None;
+        self.relaxed_since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_millis(10);
+
+    /// Send frames every `FRAME` for `duration`, each taking `load` of the time between them at
+    /// the current level.
+    fn send(adapter: &mut Adapter, start: Instant, duration: Duration, load: f32) -> Instant {
+        let mut now = start;
+        while now < start + duration {
+            let level = adapter.config.levels[adapter.level];
+            let interval = FRAME * level.frame_divisor;
+            adapter.record(now, now + interval.mul_f32(load));
+            now += interval;
+        }
+        now
+    }
+
+    fn adapter(levels: Vec<QualityLevel>) -> Adapter {
+        Adapter::new(AdaptiveQuality {
+            levels,
+            ..Default::default()
+        })
+    }
+
+    fn divisor(frame_divisor: u32) -> QualityLevel {
+        QualityLevel {
+            frame_divisor,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn degrades_while_congested() {
+        let mut adapter = adapter(vec![divisor(1), divisor(2), divisor(4)]);
+        let now = send(
+            &mut adapter,
+            Instant::now(),
+            Duration::from_millis(400),
+            0.9,
+        );
+        assert_eq!(adapter.level(), 0);
+        let now = send(&mut adapter, now, Duration::from_millis(200), 0.9);
+        assert_eq!(adapter.level(), 1);
+        send(&mut adapter, now, Duration::from_secs(2), 0.9);
+        assert_eq!(adapter.level(), 2);
+    }
+
+    #[test]
+    fn recovers_while_relaxed() {
+        let mut adapter = adapter(vec![divisor(1), divisor(2)]);
+        let now = send(&mut adapter, Instant::now(), Duration::from_secs(1), 0.9);
+        assert_eq!(adapter.level(), 1);
+        // would be 0.3 at the full frame rate
+        let now = send(&mut adapter, now, Duration::from_secs(4), 0.15);
+        assert_eq!(adapter.level(), 1);
+        send(&mut adapter, now, Duration::from_secs(2), 0.15);
+        assert_eq!(adapter.level(), 0);
+    }
+
+    #[test]
+    fn does_not_recover_into_congestion() {
+        let mut adapter = adapter(vec![divisor(1), divisor(2)]);
+        let now = send(&mut adapter, Instant::now(), Duration::from_secs(1), 0.9);
+        assert_eq!(adapter.level(), 1);
+        // below the relaxed load here, but 0.6 at the full frame rate
+        send(&mut adapter, now, Duration::from_secs(10), 0.3);
+        assert_eq!(adapter.level(), 1);
+    }
+
+    #[test]
+    fn recovery_accounts_for_resolution() {
+        let scaled = QualityLevel {
+            scale_divisor: 2,
+            ..Default::default()
+        };
+        let mut adapter = adapter(vec![divisor(1), scaled]);
+        let now = send(&mut adapter, Instant::now(), Duration::from_secs(1), 0.9);
+        assert_eq!(adapter.level(), 1);
+        // four times the pixels at full resolution
+        let now = send(&mut adapter, now, Duration::from_secs(10), 0.2);
+        assert_eq!(adapter.level(), 1);
+        send(&mut adapter, now, Duration::from_secs(10), 0.05);
+        assert_eq!(adapter.level(), 0);
+    }
+
+    #[test]
+    fn steady_load_keeps_the_level() {
+        let mut adapter = adapter(vec![divisor(1), divisor(2)]);
+        send(&mut adapter, Instant::now(), Duration::from_secs(10), 0.6);
+        assert_eq!(adapter.level(), 0);
+    }
+}
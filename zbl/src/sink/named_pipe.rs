@@ -0,0 +1,246 @@
+//! Serving frames to other local processes through a named pipe.
+//!
+//! The server writes a stream of messages to every connected client, each consisting of a
+//! `FrameHeader` followed by `length` bytes of payload. Clients only ever read from the pipe. For
+//! example, in C#:
+//!
+//! ```cs
+//! using var pipe = new NamedPipeClientStream(".", "zbl", PipeDirection.In);
+//! pipe.Connect();
+//! var reader = new BinaryReader(pipe);
+//! while (true) {
+//!     ulong seq = reader.ReadUInt64();
+//!     long timestamp = reader.ReadInt64();
+//!     uint width = reader.ReadUInt32(), height = reader.ReadUInt32(), stride = reader.ReadUInt32();
+//!     uint format = reader.ReadUInt32(), length = reader.ReadUInt32();
+//!     byte[] payload = reader.ReadBytes((int)length);
+//! }
+//! ```
+//!
+//! A client connecting in the middle of the stream always starts reading at a header.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use windows::{
+    core::{Error, Result, HSTRING},
+    Win32::{
+        Foundation::{CloseHandle, ERROR_PIPE_CONNECTED, HANDLE},
+        Storage::FileSystem::{
+            CreateFileW, WriteFile, FILE_FLAGS_AND_ATTRIBUTES, FILE_FLAG_FIRST_PIPE_INSTANCE,
+            FILE_GENERIC_READ, FILE_SHARE_NONE, OPEN_EXISTING, PIPE_ACCESS_OUTBOUND,
+        },
+        System::Pipes::{
+            ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_TYPE_BYTE,
+            PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+        },
+    },
+};
+
+use crate::{
+    metadata::FrameMetadata,
+    sink::{
+        adaptive::Adapter, encode_image, encode_metadata, AdaptiveQuality, Compression, Encoding,
+        Sink,
+    },
+    Frame,
+};
+
+/// Size of the pipe's output buffer. Writes block once a client falls this far behind.
+const BUFFER_SIZE: u32 = 16 << 20;
+
+/// A connected pipe instance.
+struct Client(HANDLE);
+
+impl Client {
+    fn write_all(&self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let mut written = 0u32;
+            unsafe { WriteFile(self.0, Some(data), Some(&mut written as *mut _), None).ok()? };
+            data = &data[written as usize..];
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        unsafe {
+            DisconnectNamedPipe(self.0);
+            CloseHandle(self.0);
+        }
+    }
+}
+
+/// A named pipe server sending frames to every connected client.
+///
+/// Frames are written synchronously, so a client which stops reading will eventually block
+/// `send()`; clients whose pipe breaks are disconnected. With `adaptive()`, the sink sends less
+/// before that happens.
+pub struct NamedPipeSink {
+    path: HSTRING,
+    clients: Arc<Mutex<Vec<Client>>>,
+    closed: Arc<AtomicBool>,
+    encoding: Encoding,
+    adapter: Option<Adapter>,
+    seq: u64,
+}
+
+impl NamedPipeSink {
+    /// Start serving on `\\.\pipe\<name>`.
+    ///
+    /// Fails (with `ERROR_ACCESS_DENIED`) if another server, possibly of another process, serves
+    /// on that name already, rather than clients ending up connected to either of them.
+    pub fn create(name: &str) -> crate::Result<Self> {
+        let path = HSTRING::from(format!(r"\\.\pipe\{}", name));
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        // create the first instance right away, so that clients can connect as soon as this returns
+        let mut instance = create_instance(&path, true)?;
+        let acceptor_path = path.clone();
+        let acceptor_clients = clients.clone();
+        let acceptor_closed = closed.clone();
+        thread::spawn(move || loop {
+            let connected = unsafe { ConnectNamedPipe(instance, None) }.as_bool()
+                || Error::from_win32().code() == ERROR_PIPE_CONNECTED.to_hresult();
+            if acceptor_closed.load(Ordering::SeqCst) {
+                drop(Client(instance));
+                return;
+            }
+            if connected {
+                acceptor_clients.lock().unwrap().push(Client(instance));
+            } else {
+                drop(Client(instance));
+            }
+            instance = match create_instance(&acceptor_path, false) {
+                Ok(instance) => instance,
+                Err(_) => return,
+            };
+        });
+        Ok(Self {
+            path,
+            clients,
+            closed,
+            encoding: Encoding::default(),
+            adapter: None,
+            seq: 0,
+        })
+    }
+
+    /// Compress frames, see `Compression`.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.encoding.compression = compression;
+        self
+    }
+
+    /// Compress frames as JPEG with the given quality (1-100), or send them raw with `None`.
+    /// Requires the `jpeg` feature.
+    pub fn jpeg_quality(mut self, quality: Option<u8>) -> Self {
+        self.encoding.compression =
+            quality.map_or(Compression::None, |quality| Compression::Jpeg { quality });
+        self
+    }
+
+    /// Prefix payloads with a checksum, see `zbl::sink`. Requires the `checksum` feature.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.encoding.checksum = checksum;
+        self
+    }
+
+    /// Lower the frame rate, resolution and quality while clients fall behind, see
+    /// `AdaptiveQuality`.
+    pub fn adaptive(mut self, adaptive: AdaptiveQuality) -> Self {
+        self.adapter = Some(Adapter::new(adaptive));
+        self
+    }
+
+    /// Index of the current level in `AdaptiveQuality::levels`, if adaptive.
+    pub fn quality_level(&self) -> Option<usize> {
+        self.adapter.as_ref().map(Adapter::level)
+    }
+
+    /// Number of currently connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+impl Sink for NamedPipeSink {
+    fn send(&mut self, frame: &Frame) -> crate::Result<()> {
+        self.send_with_metadata(frame, &FrameMetadata::default())
+    }
+
+    fn send_with_metadata(&mut self, frame: &Frame, metadata: &FrameMetadata) -> crate::Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        let (image, encoding) = match &mut self.adapter {
+            Some(adapter) => match adapter.prepare(frame, &self.encoding) {
+                Some(prepared) => prepared,
+                None => return Ok(()),
+            },
+            None => (frame.to_image(), self.encoding),
+        };
+        let mut message = Vec::new();
+        if !metadata.is_empty() {
+            message = encode_metadata(metadata, self.seq, frame.timestamp, &encoding)?;
+        }
+        // a single write, so that clients connecting in between never see the metadata alone
+        message.extend_from_slice(&encode_image(image, frame.timestamp, self.seq, &encoding)?);
+        self.seq += 1;
+        let started = Instant::now();
+        clients.retain(|client| client.write_all(&message).is_ok());
+        if let Some(adapter) = &mut self.adapter {
+            adapter.record(started, Instant::now());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for NamedPipeSink {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // connect to the pending instance to wake the acceptor thread up
+        if let Ok(handle) = unsafe {
+            CreateFileW(
+                &self.path,
+                FILE_GENERIC_READ,
+                FILE_SHARE_NONE,
+                None,
+                OPEN_EXISTING,
+                FILE_FLAGS_AND_ATTRIBUTES(0),
+                HANDLE::default(),
+            )
+        } {
+            unsafe { CloseHandle(handle) };
+        }
+    }
+}
+
+/// Create an instance of the pipe, the `first` of which must not exist yet.
+fn create_instance(path: &HSTRING, first: bool) -> Result<HANDLE> {
+    let open_mode = if first {
+        PIPE_ACCESS_OUTBOUND | FILE_FLAG_FIRST_PIPE_INSTANCE
+    } else {
+        PIPE_ACCESS_OUTBOUND
+    };
+    unsafe {
+        CreateNamedPipeW(
+            path,
+            open_mode,
+            PIPE_TYPE_BYTE | PIPE_WAIT,
+            PIPE_UNLIMITED_INSTANCES,
+            BUFFER_SIZE,
+            0,
+            0,
+            None,
+        )
+    }
+}
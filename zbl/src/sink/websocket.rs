@@ -0,0 +1,183 @@
+//! Streaming frames to browsers over WebSocket.
+//!
+//! Every frame is sent to all connected clients as a single binary message containing the
+//! `FrameHeader` and the payload. In a browser, it can be drawn like this:
+//!
+//! ```js
+//! const ws = new WebSocket("ws://127.0.0.1:9000");
+//! ws.binaryType = "arraybuffer";
+//! ws.onmessage = async ({ data }) => {
+//!   const header = new DataView(data, 0, 36);
+//!   const [width, height, format] = [16, 20, 28].map((o) => header.getUint32(o, true));
+//!   const payload = new Uint8ClampedArray(data, 36);
+//!   if (format == 2) {
+//!     return; // metadata of the next frame
+//!   } else if (format == 1 || format == 4) {
+//!     // JPEG or WebP
+//!     ctx.drawImage(await createImageBitmap(new Blob([payload])), 0, 0);
+//!   } else if (format == 0) {
+//!     // BGRA -> RGBA
+//!     for (let i = 0; i < payload.length; i += 4) {
+//!       [payload[i], payload[i + 2]] = [payload[i + 2], payload[i]];
+//!     }
+//!     ctx.putImageData(new ImageData(payload, width, height), 0, 0);
+//!   }
+//! };
+//! ```
+
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tungstenite::{Message, WebSocket};
+
+use crate::{
+    metadata::FrameMetadata,
+    sink::{
+        adaptive::Adapter, encode_image, encode_metadata, AdaptiveQuality, Compression, Encoding,
+        Sink,
+    },
+    Frame,
+};
+
+/// How long sending a frame to a single client may block before the client is dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a client may take to complete the WebSocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Clients = Arc<Mutex<Vec<WebSocket<TcpStream>>>>;
+
+/// A WebSocket server broadcasting frames to every connected client.
+///
+/// Clients which fail to keep up (i.e. block sending for longer than a second) are disconnected.
+/// With `adaptive()`, the sink sends less while they fall behind, before it comes to that.
+pub struct WebSocketSink {
+    clients: Clients,
+    address: SocketAddr,
+    encoding: Encoding,
+    adapter: Option<Adapter>,
+    seq: u64,
+}
+
+impl WebSocketSink {
+    /// Start accepting connections on `address`.
+    pub fn bind(address: impl ToSocketAddrs) -> crate::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let clients = Clients::default();
+        let acceptor_clients = clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // handshake on a thread of its own, so that a client which never completes it
+                // does not keep others from connecting
+                let clients = acceptor_clients.clone();
+                thread::spawn(move || {
+                    if let Some(ws) = handshake(stream) {
+                        clients.lock().unwrap().push(ws);
+                    }
+                });
+            }
+        });
+        Ok(Self {
+            clients,
+            address,
+            encoding: Encoding::default(),
+            adapter: None,
+            seq: 0,
+        })
+    }
+
+    /// Compress frames, see `Compression`.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.encoding.compression = compression;
+        self
+    }
+
+    /// Compress frames as JPEG with the given quality (1-100), or send them raw with `None`.
+    /// Requires the `jpeg` feature.
+    pub fn jpeg_quality(mut self, quality: Option<u8>) -> Self {
+        self.encoding.compression =
+            quality.map_or(Compression::None, |quality| Compression::Jpeg { quality });
+        self
+    }
+
+    /// Prefix payloads with a checksum, see `zbl::sink`. Requires the `checksum` feature.
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.encoding.checksum = checksum;
+        self
+    }
+
+    /// Lower the frame rate, resolution and quality while clients fall behind, see
+    /// `AdaptiveQuality`.
+    pub fn adaptive(mut self, adaptive: AdaptiveQuality) -> Self {
+        self.adapter = Some(Adapter::new(adaptive));
+        self
+    }
+
+    /// Index of the current level in `AdaptiveQuality::levels`, if adaptive.
+    pub fn quality_level(&self) -> Option<usize> {
+        self.adapter.as_ref().map(Adapter::level)
+    }
+
+    /// Address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Number of currently connected clients.
+    pub fn client_count(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+fn handshake(stream: TcpStream) -> Option<WebSocket<TcpStream>> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT)).ok()?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).ok()?;
+    let ws = tungstenite::accept(stream).ok()?;
+    ws.get_ref().set_read_timeout(None).ok()?;
+    Some(ws)
+}
+
+impl Sink for WebSocketSink {
+    fn send(&mut self, frame: &Frame) -> crate::Result<()> {
+        self.send_with_metadata(frame, &FrameMetadata::default())
+    }
+
+    fn send_with_metadata(&mut self, frame: &Frame, metadata: &FrameMetadata) -> crate::Result<()> {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return Ok(());
+        }
+        let (image, encoding) = match &mut self.adapter {
+            Some(adapter) => match adapter.prepare(frame, &self.encoding) {
+                Some(prepared) => prepared,
+                None => return Ok(()),
+            },
+            None => (frame.to_image(), self.encoding),
+        };
+        let mut messages = Vec::with_capacity(2);
+        if !metadata.is_empty() {
+            messages.push(encode_metadata(
+                metadata,
+                self.seq,
+                frame.timestamp,
+                &encoding,
+            )?);
+        }
+        messages.push(encode_image(image, frame.timestamp, self.seq, &encoding)?);
+        self.seq += 1;
+        let started = Instant::now();
+        clients.retain_mut(|ws| {
+            messages
+                .iter()
+                .all(|message| ws.send(Message::Binary(message.clone())).is_ok())
+        });
+        if let Some(adapter) = &mut self.adapter {
+            adapter.record(started, Instant::now());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+//! Dumping frames to disk as uncompressed YUV, for standalone encoders and quality-analysis
+//! tools (e.g. `ffmpeg -i capture.y4m`, or `-f rawvideo -pix_fmt nv12 -s 1920x1080` for raw
+//! dumps).
+//!
+//! Colors are converted to 8-bit BT.601 limited range with 4:2:0 subsampling, every chroma sample
+//! being the average of a 2x2 block. Alpha is dropped.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{sink::Sink, Frame, Image};
+
+/// Layout of a file written by `YuvFileSink`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YuvLayout {
+    /// YUV4MPEG2 stream, i.e. a header with the frame size and rate followed by I420 frames.
+    Y4m,
+    /// Planar Y, U and V, frames back to back without any header.
+    I420,
+    /// Planar Y followed by interleaved U and V, frames back to back without any header.
+    Nv12,
+}
+
+/// Writes every frame to a file as YUV 4:2:0.
+///
+/// All frames must have the size of the first one, as none of the layouts can change it midway;
+/// capture with `CaptureConfig::output_size` set for targets which may be resized.
+pub struct YuvFileSink {
+    writer: BufWriter<File>,
+    layout: YuvLayout,
+    frame_rate: (u32, u32),
+    size: Option<(u32, u32)>,
+    buffer: Vec<u8>,
+}
+
+impl YuvFileSink {
+    /// Create (or truncate) the file at `path`.
+    pub fn create(path: impl AsRef<Path>, layout: YuvLayout) -> crate::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            layout,
+            frame_rate: (30, 1),
+            size: None,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Frame rate written to the Y4M header, as a fraction, e.g. `(60000, 1001)`. Defaults to 30
+    /// fps. Must be set before the first frame is sent. Frames are written as they are sent, so
+    /// this should match the capture rate.
+    pub fn frame_rate(mut self, numerator: u32, denominator: u32) -> Self {
+        self.frame_rate = (numerator, denominator.max(1));
+        self
+    }
+
+    /// Write out buffered data.
+    pub fn flush(&mut self) -> crate::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> crate::Result<()> {
+        if self.layout == YuvLayout::Y4m {
+            let (numerator, denominator) = self.frame_rate;
+            writeln!(
+                self.writer,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+                width, height, numerator, denominator
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert a BGRA pixel to BT.601 limited range YUV.
+fn to_yuv(bgra: &[u8]) -> (f32, f32, f32) {
+    let (b, g, r) = (bgra[0] as f32, bgra[1] as f32, bgra[2] as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let u = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let v = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    (y, u, v)
+}
+
+/// Append the planes of `image` to `buffer` in `layout` (`Y4m` being I420).
+fn convert(image: &Image, layout: YuvLayout, buffer: &mut Vec<u8>) {
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+    for y in 0..height {
+        let row = image.row(y as u32);
+        buffer.extend(
+            row.chunks_exact(4)
+                .map(|pixel| to_yuv(pixel).0.round() as u8),
+        );
+    }
+    let mut u_plane = Vec::with_capacity(chroma_width * chroma_height);
+    let mut v_plane = Vec::with_capacity(chroma_width * chroma_height);
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut u, mut v, mut count) = (0.0, 0.0, 0.0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                let row = image.row(y as u32);
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let (_, pixel_u, pixel_v) = to_yuv(&row[x * 4..x * 4 + 4]);
+                    u += pixel_u;
+                    v += pixel_v;
+                    count += 1.0;
+                }
+            }
+            u_plane.push((u / count).round() as u8);
+            v_plane.push((v / count).round() as u8);
+        }
+    }
+    match layout {
+        YuvLayout::Y4m | YuvLayout::I420 => {
+            buffer.extend_from_slice(&u_plane);
+            buffer.extend_from_slice(&v_plane);
+        }
+        YuvLayout::Nv12 => {
+            for (u, v) in u_plane.into_iter().zip(v_plane) {
+                buffer.extend_from_slice(&[u, v]);
+            }
+        }
+    }
+}
+
+impl Sink for YuvFileSink {
+    fn send(&mut self, frame: &Frame) -> crate::Result<()> {
+        let image = frame.to_image();
+        match self.size {
+            None => {
+                self.write_header(image.width, image.height)?;
+                self.size = Some((image.width, image.height));
+            }
+            Some((width, height)) if (width, height) != (image.width, image.height) => {
+                return Err(crate::Error::EncodingError(format!(
+                    "frame size changed from {}x{} to {}x{}",
+                    width, height, image.width, image.height
+                )));
+            }
+            _ => {}
+        }
+        self.buffer.clear();
+        if self.layout == YuvLayout::Y4m {
+            self.buffer.extend_from_slice(b"FRAME\n");
+        }
+        convert(&image, self.layout, &mut self.buffer);
+        self.writer.write_all(&self.buffer)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, bgra: [u8; 4]) -> Image {
+        Image {
+            width,
+            height,
+            data: bgra.repeat(width as usize * height as usize),
+        }
+    }
+
+    #[test]
+    fn limited_range_extremes() {
+        let round = |(y, u, v): (f32, f32, f32)| (y.round(), u.round(), v.round());
+        assert_eq!(round(to_yuv(&[0, 0, 0, 255])), (16.0, 128.0, 128.0));
+        assert_eq!(round(to_yuv(&[255, 255, 255, 255])), (235.0, 128.0, 128.0));
+        assert_eq!(round(to_yuv(&[0, 0, 255, 255])), (81.0, 90.0, 240.0));
+        assert_eq!(round(to_yuv(&[255, 0, 0, 255])), (41.0, 240.0, 110.0));
+    }
+
+    #[test]
+    fn planes_are_subsampled() {
+        let mut buffer = Vec::new();
+        convert(
+            &solid(4, 2, [255, 255, 255, 255]),
+            YuvLayout::I420,
+            &mut buffer,
+        );
+        assert_eq!(buffer.len(), 8 + 2 + 2);
+        assert!(buffer[..8].iter().all(|&y| y == 235));
+        assert!(buffer[8..].iter().all(|&c| c == 128));
+    }
+
+    #[test]
+    fn odd_sizes_round_chroma_up() {
+        let mut buffer = Vec::new();
+        convert(&solid(3, 3, [0, 0, 0, 255]), YuvLayout::I420, &mut buffer);
+        assert_eq!(buffer.len(), 9 + 4 + 4);
+    }
+
+    #[test]
+    fn chroma_averages_blocks() {
+        // red and blue columns, whose chroma averages within each 2x2 block
+        let mut image = solid(2, 2, [0, 0, 255, 255]);
+        for y in 0..2 {
+            image.data[y * 8..y * 8 + 4].copy_from_slice(&[255, 0, 0, 255]);
+        }
+        let (_, red_u, red_v) = to_yuv(&[0, 0, 255, 255]);
+        let (_, blue_u, blue_v) = to_yuv(&[255, 0, 0, 255]);
+        let mut buffer = Vec::new();
+        convert(&image, YuvLayout::I420, &mut buffer);
+        assert_eq!(buffer[4], ((red_u + blue_u) / 2.0).round() as u8);
+        assert_eq!(buffer[5], ((red_v + blue_v) / 2.0).round() as u8);
+    }
+
+    #[test]
+    fn nv12_interleaves_chroma() {
+        let image = solid(2, 2, [0, 0, 255, 255]);
+        let (mut i420, mut nv12) = (Vec::new(), Vec::new());
+        convert(&image, YuvLayout::I420, &mut i420);
+        convert(&image, YuvLayout::Nv12, &mut nv12);
+        assert_eq!(nv12[..4], i420[..4]);
+        assert_eq!(nv12[4..], [i420[4], i420[5]]);
+    }
+}
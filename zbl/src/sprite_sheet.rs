@@ -0,0 +1,231 @@
+//! Capturing many small targets with a single readback, see `SpriteSheet`.
+
+use std::time::Duration;
+
+use windows::{
+    core::{Interface, Result},
+    Win32::Graphics::{
+        Direct3D11::{ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D},
+        Dxgi::Common::DXGI_FORMAT_B8G8R8A8_UNORM,
+    },
+};
+
+use crate::{
+    capture::GpuGrab,
+    pipeline::create_work_texture,
+    staging_texture::StagingTexture,
+    util::{create_d3d_device, Rect},
+    Capturable, Capture, Frame, GrabStatus,
+};
+
+/// Where the latest frame of a source is in the frames of a `SpriteSheet`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Sprite {
+    /// Area of the sheet holding the frame, empty until the source has produced one. Smaller
+    /// than the source's frame if that has been cropped to the slot.
+    pub rect: Rect,
+    /// Size of the source's frame.
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Time at which the frame was produced, in 100ns units (see `Frame::timestamp`).
+    pub timestamp: i64,
+    /// Whether the source has been closed. The sheet keeps its last frame.
+    pub closed: bool,
+}
+
+/// Top-left corner of the slot of the `index`-th source in a sheet with `columns` columns.
+fn slot_origin(index: usize, columns: u32, (slot_width, slot_height): (u32, u32)) -> (u32, u32) {
+    let index = index as u32;
+    (
+        (index % columns) * slot_width,
+        (index / columns) * slot_height,
+    )
+}
+
+/// Size of a sheet holding `count` sources in `columns` columns, at least one slot.
+fn sheet_size(count: usize, columns: u32, (slot_width, slot_height): (u32, u32)) -> (u32, u32) {
+    let rows = (count as u32 + columns - 1) / columns;
+    (columns * slot_width, rows.max(1) * slot_height)
+}
+
+/// Captures many small targets (e.g. dozens of windows) into one texture, which is read back as
+/// a single frame.
+///
+/// Reading back every target separately costs a copy and a synchronization with the GPU each;
+/// here the frames are copied into slots of a shared "sprite sheet" on the GPU instead, and only
+/// the sheet is read back. `layout()` tells where every source's latest frame is.
+///
+/// Slots have a fixed size and are laid out in a grid, row by row, so that the layout stays the
+/// same while sources resize. Frames larger than a slot are cropped to its top-left part; frames
+/// are not scaled, and stages and the other processing options of `Capture` do not apply.
+pub struct SpriteSheet {
+    device: ID3D11Device,
+    context: ID3D11DeviceContext,
+    captures: Vec<Capture>,
+    sprites: Vec<Sprite>,
+    columns: u32,
+    slot_width: u32,
+    slot_height: u32,
+    sheet: ID3D11Texture2D,
+    staging_texture: StagingTexture,
+}
+
+impl SpriteSheet {
+    /// Start capturing `capturables` into a sheet with `columns` columns of `slot_width` x
+    /// `slot_height` slots.
+    pub fn new(
+        capturables: Vec<Box<dyn Capturable>>,
+        columns: u32,
+        slot_width: u32,
+        slot_height: u32,
+    ) -> crate::Result<Self> {
+        let columns = columns.max(1);
+        let (width, height) = sheet_size(capturables.len(), columns, (slot_width, slot_height));
+
+        let device = create_d3d_device()?;
+        let context = unsafe {
+            let mut d3d_context = None;
+            device.GetImmediateContext(&mut d3d_context);
+            d3d_context.expect("failed to create d3d_context")
+        };
+        let sheet = create_work_texture(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+        let staging_texture =
+            StagingTexture::new(&device, width, height, DXGI_FORMAT_B8G8R8A8_UNORM)?;
+
+        let mut captures = Vec::with_capacity(capturables.len());
+        for capturable in capturables {
+            let mut capture = Capture::builder(capturable)
+                .device(device.clone())
+                // the sheet is produced as soon as any of the sources has a new frame
+                .grab_timeout(Duration::ZERO)
+                .build()?;
+            capture.start()?;
+            captures.push(capture);
+        }
+
+        Ok(Self {
+            device,
+            context,
+            sprites: vec![Sprite::default(); captures.len()],
+            captures,
+            columns,
+            slot_width,
+            slot_height,
+            sheet,
+            staging_texture,
+        })
+    }
+
+    /// Get the capture of the `index`-th source.
+    pub fn source(&self, index: usize) -> Option<&Capture> {
+        self.captures.get(index)
+    }
+
+    /// Get the D3D11 device the sources are captured on.
+    pub fn device(&self) -> &ID3D11Device {
+        &self.device
+    }
+
+    /// Where the latest frame of every source is in the last frame returned by `grab()`, in the
+    /// order the sources were given in.
+    pub fn layout(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    /// Poll all sources, copy their new frames into the sheet, and read it back.
+    ///
+    /// This never blocks: `GrabStatus::Timeout` is returned if none of the sources has produced a
+    /// new frame since the last call, and `GrabStatus::Closed` once all of them have been closed.
+    /// The frame's timestamp is the one of the newest source frame.
+    pub fn grab(&mut self) -> Result<GrabStatus> {
+        let mut updated = false;
+        for (index, capture) in self.captures.iter_mut().enumerate() {
+            let sprite = &mut self.sprites[index];
+            if sprite.closed {
+                continue;
+            }
+            let slot_size = (self.slot_width, self.slot_height);
+            let (x, y) = slot_origin(index, self.columns, slot_size);
+            match capture.grab_into(&self.sheet, (x, y), slot_size)? {
+                GpuGrab::Copied {
+                    width,
+                    height,
+                    copied_width,
+                    copied_height,
+                    timestamp,
+                } => {
+                    *sprite = Sprite {
+                        rect: Rect {
+                            left: x as i32,
+                            top: y as i32,
+                            right: (x + copied_width) as i32,
+                            bottom: (y + copied_height) as i32,
+                        },
+                        source_width: width,
+                        source_height: height,
+                        timestamp,
+                        closed: false,
+                    };
+                    updated = true;
+                }
+                GpuGrab::Nothing(GrabStatus::Closed) => {
+                    sprite.closed = true;
+                    updated = true;
+                }
+                GpuGrab::Nothing(_) => {}
+            }
+        }
+        if self.sprites.iter().all(|sprite| sprite.closed) {
+            return Ok(GrabStatus::Closed);
+        }
+        if !updated {
+            return Ok(GrabStatus::Timeout);
+        }
+
+        let dest = self.staging_texture.as_resource()?;
+        let src: ID3D11Resource = self.sheet.cast()?;
+        unsafe { self.context.CopyResource(Some(&dest), Some(&src)) };
+        let ptr = self.staging_texture.as_mapped(&self.context)?;
+        let timestamp = self.sprites.iter().map(|sprite| sprite.timestamp).max();
+        Ok(GrabStatus::Frame(Frame {
+            texture: &self.staging_texture,
+            ptr,
+            timestamp: timestamp.unwrap_or(0),
+            previous: None,
+            input_events: &[],
+            stats: None,
+            tracked_window: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_laid_out_row_by_row() {
+        let origins: Vec<_> = (0..5)
+            .map(|index| slot_origin(index, 2, (30, 20)))
+            .collect();
+        assert_eq!(origins, [(0, 0), (30, 0), (0, 20), (30, 20), (0, 40)]);
+    }
+
+    #[test]
+    fn sheet_fits_all_slots() {
+        assert_eq!(sheet_size(5, 2, (30, 20)), (60, 60));
+        assert_eq!(sheet_size(4, 2, (30, 20)), (60, 40));
+        assert_eq!(sheet_size(1, 4, (30, 20)), (120, 20));
+        for count in 1..20 {
+            let (width, height) = sheet_size(count, 3, (7, 5));
+            let (x, y) = slot_origin(count - 1, 3, (7, 5));
+            assert!(x + 7 <= width && y + 5 <= height);
+        }
+    }
+
+    #[test]
+    fn empty_sheet_has_one_row() {
+        assert_eq!(sheet_size(0, 3, (30, 20)), (90, 20));
+    }
+}
@@ -3,10 +3,13 @@ use windows::{
     Win32::Graphics::{
         Direct3D11::{
             ID3D11Device, ID3D11DeviceContext, ID3D11Resource, ID3D11Texture2D, D3D11_BIND_FLAG,
-            D3D11_CPU_ACCESS_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_READ,
-            D3D11_RESOURCE_MISC_FLAG, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+            D3D11_CPU_ACCESS_READ, D3D11_MAPPED_SUBRESOURCE, D3D11_MAP_FLAG_DO_NOT_WAIT,
+            D3D11_MAP_READ, D3D11_RESOURCE_MISC_FLAG, D3D11_TEXTURE2D_DESC, D3D11_USAGE_STAGING,
+        },
+        Dxgi::{
+            Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+            DXGI_ERROR_WAS_STILL_DRAWING,
         },
-        Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
     },
 };
 
@@ -49,9 +52,26 @@ impl StagingTexture {
     }
 
     pub fn as_mapped(&self, context: &ID3D11DeviceContext) -> Result<D3D11_MAPPED_SUBRESOURCE> {
+        self.map(context, 0)
+    }
+
+    /// Same as `as_mapped()`, but returns `None` instead of waiting if the GPU is still writing
+    /// to the texture.
+    pub fn try_as_mapped(
+        &self,
+        context: &ID3D11DeviceContext,
+    ) -> Result<Option<D3D11_MAPPED_SUBRESOURCE>> {
+        match self.map(context, D3D11_MAP_FLAG_DO_NOT_WAIT.0 as u32) {
+            Ok(mapped_texture) => Ok(Some(mapped_texture)),
+            Err(e) if e.code() == DXGI_ERROR_WAS_STILL_DRAWING => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn map(&self, context: &ID3D11DeviceContext, flags: u32) -> Result<D3D11_MAPPED_SUBRESOURCE> {
         let staging_texture_ptr: ID3D11Resource = self.texture.cast()?;
         let mapped_texture =
-            unsafe { context.Map(Some(&staging_texture_ptr), 0, D3D11_MAP_READ, 0)? };
+            unsafe { context.Map(Some(&staging_texture_ptr), 0, D3D11_MAP_READ, flags)? };
         // we can instantly unmap because the texture is staging, and will be still accessible by CPU
         // TODO there should be a way to do this by queueing a fence (we only need to wait copies) or something like that,
         // which would probably be more correct solution rather than map-unmap
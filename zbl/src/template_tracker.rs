@@ -0,0 +1,443 @@
+//! Keeping the capture box on an element moving within the target, see `TemplateTracker`.
+
+use crate::{config::Insets, util::Rect, Capture, CaptureBuilder, GrabStatus, Image};
+
+/// How a `TemplateTracker` looks for its template.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct TemplateTracking {
+    /// Look for the template every n-th frame, also while it has not been found.
+    pub interval: u32,
+    /// Distance in pixels around the last match within which the template is looked for. The
+    /// whole target is searched until it is found there.
+    pub search_margin: u32,
+    /// Largest mean absolute difference of luma (0-255) between the template and a match.
+    pub max_difference: f32,
+}
+
+impl Default for TemplateTracking {
+    fn default() -> Self {
+        Self {
+            interval: 10,
+            search_margin: 64,
+            max_difference: 12.0,
+        }
+    }
+}
+
+/// A frame of a `TemplateTracker`.
+#[derive(Clone, Debug)]
+pub struct TrackedFrame {
+    /// Pixels of the capture box.
+    pub image: Image,
+    /// See `Frame::timestamp`.
+    pub timestamp: i64,
+    /// Area of the target (relative to its client box, see `Capturable::get_client_box`) shown in
+    /// `image`. Smaller than the capture box if that extends beyond the target, or in a frame in
+    /// which the template has moved farther than `TemplateTracking::search_margin`.
+    pub rect: Rect,
+    /// Whether the template was found by the latest search. While it is not, the box stays where
+    /// the template was last seen.
+    pub locked: bool,
+}
+
+/// Captures a box which follows an element moving within the target (e.g. a panel of a game HUD),
+/// located by matching a small reference image of it.
+///
+/// The capture is cropped (with `CaptureConfig::insets`) to the area around the last match, from
+/// which the capture box is cut out on the CPU. Every `TemplateTracking::interval` frames, the
+/// template is looked for in that area, and the box is re-anchored to where it was found. The
+/// area only changes when the box gets close to its edge, or the template is lost, in which case
+/// the whole target is captured (and searched every `interval` frames) until it is found again.
+///
+/// The tracker changes the insets of its capture, so these should not be set otherwise. Frames
+/// read back before the area changed (e.g. with `CaptureConfig::pipelined`) are skipped.
+pub struct TemplateTracker {
+    capture: Capture,
+    template: Image,
+    anchor: Rect,
+    tracking: TemplateTracking,
+    /// Position of the top-left corner of the template in the client box, once it has been found.
+    position: Option<(i32, i32)>,
+    locked: bool,
+    /// Area of the client box currently captured.
+    region: Rect,
+    /// Client box `region` was set for.
+    client: Rect,
+    frames: u32,
+    closed: bool,
+}
+
+impl TemplateTracker {
+    /// Build and start a capture tracking `template`. `anchor` is the capture box relative to the
+    /// top-left corner of the template, e.g. `Rect { left: -8, top: 0, right: 300, bottom: 120 }`
+    /// for a panel with the template in its top-left corner.
+    pub fn new(
+        builder: CaptureBuilder,
+        template: Image,
+        anchor: Rect,
+        tracking: TemplateTracking,
+    ) -> crate::Result<Self> {
+        if template.width == 0 || template.height == 0 {
+            return Err(crate::Error::InvalidConfig("template is empty".to_string()));
+        }
+        if anchor.right <= anchor.left || anchor.bottom <= anchor.top {
+            return Err(crate::Error::InvalidConfig(
+                "anchored capture box is empty".to_string(),
+            ));
+        }
+        let mut capture = builder.build()?;
+        capture.start()?;
+        Ok(Self {
+            capture,
+            template,
+            anchor,
+            tracking,
+            position: None,
+            locked: false,
+            region: Rect::default(),
+            client: Rect::default(),
+            frames: 0,
+            closed: false,
+        })
+    }
+
+    /// Get the underlying capture.
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+
+    /// Where the template was last found, relative to the client box of the target.
+    pub fn position(&self) -> Option<(i32, i32)> {
+        self.position
+    }
+
+    /// Whether the capture is over, i.e. `grab()` should not be called anymore.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Grab the next frame, and look for the template if it is due.
+    ///
+    /// Returns `None` if no frame has arrived (see `Capture::grab`), and until the template has
+    /// been found for the first time.
+    pub fn grab(&mut self) -> crate::Result<Option<TrackedFrame>> {
+        let client = self.capture.capturable().get_client_box()?;
+        let client = Rect {
+            left: 0,
+            top: 0,
+            right: (client.right - client.left) as i32,
+            bottom: (client.bottom - client.top) as i32,
+        };
+        if client.right <= 0 || client.bottom <= 0 {
+            return Ok(None);
+        }
+        let search = self.frames % self.tracking.interval.max(1) == 0;
+        // keep the area while the template and the box are within it, as every change of the
+        // insets costs new textures
+        let region = match self.tracked_rect(client) {
+            Some(tracked)
+                if self.locked && client == self.client && contains(self.region, tracked) =>
+            {
+                self.region
+            }
+            _ => self.search_region(client),
+        };
+        if region != self.region || client != self.client {
+            self.capture.set_insets(Insets {
+                left: region.left as u32,
+                top: region.top as u32,
+                right: (client.right - region.right) as u32,
+                bottom: (client.bottom - region.bottom) as u32,
+            })?;
+            self.region = region;
+            self.client = client;
+        }
+
+        let (image, timestamp) = match self.capture.grab()? {
+            GrabStatus::Frame(frame) => (frame.to_image(), frame.timestamp),
+            GrabStatus::Closed => {
+                self.closed = true;
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        };
+        let size = (
+            (region.right - region.left) as u32,
+            (region.bottom - region.top) as u32,
+        );
+        if (image.width, image.height) != size {
+            // copied before the area changed
+            return Ok(None);
+        }
+        self.frames = (self.frames + 1) % self.tracking.interval.max(1);
+
+        if search {
+            let found = find_template(&image, &self.template, self.tracking.max_difference);
+            self.locked = found.is_some();
+            if let Some((x, y)) = found {
+                self.position = Some((region.left + x as i32, region.top + y as i32));
+            }
+        }
+        // the template may have moved so far that the box is not entirely within the region
+        let rect = match self.box_rect(client) {
+            Some(rect) => clamp(rect, region),
+            None => return Ok(None),
+        };
+        Ok(Some(TrackedFrame {
+            image: crop(&image, &rect, &region),
+            timestamp,
+            rect,
+            locked: self.locked,
+        }))
+    }
+
+    /// The capture box within `client`, once the template has been found.
+    fn box_rect(&self, client: Rect) -> Option<Rect> {
+        let (x, y) = self.position?;
+        let rect = Rect {
+            left: x + self.anchor.left,
+            top: y + self.anchor.top,
+            right: x + self.anchor.right,
+            bottom: y + self.anchor.bottom,
+        };
+        Some(clamp(rect, client))
+    }
+
+    /// Area of `client` taken up by the capture box and the template, once it has been found.
+    fn tracked_rect(&self, client: Rect) -> Option<Rect> {
+        let (x, y) = self.position?;
+        let rect = Rect {
+            left: x + self.anchor.left.min(0),
+            top: y + self.anchor.top.min(0),
+            right: x + self.anchor.right.max(self.template.width as i32),
+            bottom: y + self.anchor.bottom.max(self.template.height as i32),
+        };
+        Some(clamp(rect, client))
+    }
+
+    /// Area of `client` to capture and look for the template in: around the capture box and the
+    /// template while locked, the whole client box otherwise.
+    fn search_region(&self, client: Rect) -> Rect {
+        let tracked = match self.tracked_rect(client) {
+            Some(tracked) if self.locked => tracked,
+            _ => return client,
+        };
+        let margin = self.tracking.search_margin as i32;
+        let rect = Rect {
+            left: tracked.left - margin,
+            top: tracked.top - margin,
+            right: tracked.right + margin,
+            bottom: tracked.bottom + margin,
+        };
+        clamp(rect, client)
+    }
+}
+
+/// Whether `inner` lies within `outer`.
+fn contains(outer: Rect, inner: Rect) -> bool {
+    inner.left >= outer.left
+        && inner.top >= outer.top
+        && inner.right <= outer.right
+        && inner.bottom <= outer.bottom
+}
+
+/// `rect` clamped to `bounds`, at least a pixel in size.
+fn clamp(rect: Rect, bounds: Rect) -> Rect {
+    let left = rect.left.clamp(bounds.left, bounds.right - 1);
+    let top = rect.top.clamp(bounds.top, bounds.bottom - 1);
+    Rect {
+        left,
+        top,
+        right: rect.right.clamp(left + 1, bounds.right.max(left + 1)),
+        bottom: rect.bottom.clamp(top + 1, bounds.bottom.max(top + 1)),
+    }
+}
+
+/// Cut `rect` out of `image`, which shows `region`. `rect` must be within `region`.
+fn crop(image: &Image, rect: &Rect, region: &Rect) -> Image {
+    let (width, height) = (
+        (rect.right - rect.left) as u32,
+        (rect.bottom - rect.top) as u32,
+    );
+    let x = (rect.left - region.left) as usize;
+    let y = (rect.top - region.top) as u32;
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in y..y + height {
+        data.extend_from_slice(&image.row(row)[x * 4..(x + width as usize) * 4]);
+    }
+    Image {
+        width,
+        height,
+        data,
+    }
+}
+
+fn luma(image: &Image) -> Vec<u8> {
+    image
+        .data
+        .chunks_exact(4)
+        .map(|p| ((p[0] as u32 * 29 + p[1] as u32 * 150 + p[2] as u32 * 77) >> 8) as u8)
+        .collect()
+}
+
+/// Position of the best match of `template` in `image`, if its mean absolute difference of luma
+/// is at most `max_difference`.
+///
+/// Every second position is compared on every second pixel first, then the best of them is refined
+/// at full resolution. Comparisons stop as soon as they exceed the best difference so far.
+fn find_template(image: &Image, template: &Image, max_difference: f32) -> Option<(u32, u32)> {
+    if template.width > image.width || template.height > image.height {
+        return None;
+    }
+    let (image_luma, template_luma) = (luma(image), luma(template));
+    let (width, template_width) = (image.width as usize, template.width as usize);
+    let (last_x, last_y) = (image.width - template.width, image.height - template.height);
+    let difference = |x: u32, y: u32, step: usize, limit: f32| -> Option<f32> {
+        let samples =
+            ((template_width + step - 1) / step) * ((template.height as usize + step - 1) / step);
+        let limit = (limit * samples as f32) as u64;
+        let mut sum = 0u64;
+        for ty in (0..template.height as usize).step_by(step) {
+            let row = &image_luma[(y as usize + ty) * width + x as usize..][..template_width];
+            let template_row = &template_luma[ty * template_width..][..template_width];
+            for tx in (0..template_width).step_by(step) {
+                sum += row[tx].abs_diff(template_row[tx]) as u64;
+            }
+            if sum > limit {
+                return None;
+            }
+        }
+        Some(sum as f32 / samples as f32)
+    };
+    let best_of = |xs: &mut dyn Iterator<Item = (u32, u32)>, step: usize, limit: f32| {
+        let mut best: Option<(f32, u32, u32)> = None;
+        for (x, y) in xs {
+            let limit = best.map_or(limit, |(best, _, _)| best);
+            if let Some(mean) = difference(x, y, step, limit) {
+                best = Some((mean, x, y));
+            }
+        }
+        best
+    };
+
+    // subsampling blurs the differences, so the coarse pass is more lenient
+    let mut coarse = (0..=last_y)
+        .step_by(2)
+        .flat_map(|y| (0..=last_x).step_by(2).map(move |x| (x, y)));
+    let (_, x, y) = best_of(&mut coarse, 2, max_difference * 2.0)?;
+    let mut fine = (y.saturating_sub(1)..=(y + 1).min(last_y))
+        .flat_map(|y| (x.saturating_sub(1)..=(x + 1).min(last_x)).map(move |x| (x, y)));
+    let (_, x, y) = best_of(&mut fine, 1, max_difference)?;
+    Some((x, y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A smooth image in which every patch is different.
+    fn ramp(width: u32, height: u32) -> Image {
+        let data = (0..height)
+            .flat_map(|y| {
+                (0..width).flat_map(move |x| {
+                    let value = (x + 2 * y + x * y / 64) as u8;
+                    [value, value, value, 255]
+                })
+            })
+            .collect();
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn noise(width: u32, height: u32) -> Image {
+        let data = (0..width * height)
+            .flat_map(|i| {
+                let value = (i.wrapping_mul(2654435761) >> 24) as u8;
+                [value, value, value, 255]
+            })
+            .collect();
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn rect(left: i32, top: i32, right: i32, bottom: i32) -> Rect {
+        Rect {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn template_is_found() {
+        let image = ramp(64, 48);
+        for (x, y) in [(0, 0), (17, 9), (51, 37), (48, 32)] {
+            let template = crop(&image, &rect(x, y, x + 13, y + 11), &rect(0, 0, 64, 48));
+            assert_eq!(
+                find_template(&image, &template, 3.0),
+                Some((x as u32, y as u32))
+            );
+        }
+    }
+
+    #[test]
+    fn different_template_is_not_found() {
+        let image = Image {
+            width: 32,
+            height: 32,
+            data: vec![0; 32 * 32 * 4],
+        };
+        assert_eq!(find_template(&image, &noise(8, 8), 12.0), None);
+    }
+
+    #[test]
+    fn larger_template_is_not_found() {
+        assert_eq!(find_template(&noise(8, 8), &noise(9, 4), 255.0), None);
+    }
+
+    #[test]
+    fn clamp_keeps_rects_within_bounds() {
+        let bounds = rect(0, 0, 100, 50);
+        assert_eq!(clamp(rect(10, 10, 20, 20), bounds), rect(10, 10, 20, 20));
+        assert_eq!(clamp(rect(-10, -5, 120, 60), bounds), bounds);
+        // entirely outside, but still a pixel in size
+        assert_eq!(clamp(rect(200, 80, 300, 90), bounds), rect(99, 49, 100, 50));
+        assert_eq!(clamp(rect(-30, -30, -20, -20), bounds), rect(0, 0, 1, 1));
+    }
+
+    #[test]
+    fn crop_cuts_out_rect_relative_to_region() {
+        let region = rect(10, 20, 14, 23);
+        let image = Image {
+            width: 4,
+            height: 3,
+            data: (0..4 * 3 * 4).collect(),
+        };
+        let cropped = crop(&image, &rect(11, 21, 13, 23), &region);
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(
+            cropped.data,
+            [20, 21, 22, 23, 24, 25, 26, 27, 36, 37, 38, 39, 40, 41, 42, 43]
+        );
+    }
+
+    #[test]
+    fn contains_is_inclusive() {
+        let outer = rect(0, 0, 10, 10);
+        assert!(contains(outer, outer));
+        assert!(contains(outer, rect(2, 2, 8, 8)));
+        assert!(!contains(outer, rect(2, 2, 11, 8)));
+    }
+}
@@ -2,6 +2,7 @@ use windows::{
     core::{Interface, Result},
     Graphics::DirectX::Direct3D11::IDirect3DDevice,
     Win32::{
+        Foundation::RECT,
         Graphics::{
             Direct3D::{D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP},
             Direct3D11::{
@@ -10,12 +11,36 @@ use windows::{
             },
             Dxgi::{IDXGIDevice, DXGI_ERROR_UNSUPPORTED},
         },
-        System::WinRT::Direct3D11::{
-            CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+        System::{
+            Performance::{QueryPerformanceCounter, QueryPerformanceFrequency},
+            WinRT::Direct3D11::{
+                CreateDirect3D11DeviceFromDXGIDevice, IDirect3DDxgiInterfaceAccess,
+            },
         },
     },
 };
 
+/// Plain copy of a `RECT`, e.g. for (de)serialization.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl From<RECT> for Rect {
+    fn from(rect: RECT) -> Self {
+        Self {
+            left: rect.left,
+            top: rect.top,
+            right: rect.right,
+            bottom: rect.bottom,
+        }
+    }
+}
+
 pub fn create_d3d_device() -> Result<ID3D11Device> {
     for driver_type in [D3D_DRIVER_TYPE_HARDWARE, D3D_DRIVER_TYPE_WARP] {
         let mut device = None;
@@ -61,3 +86,17 @@ pub fn convert_u16_string(input: &[u16]) -> String {
     }
     s
 }
+
+/// Current time in 100ns units, on the clock of `Direct3D11CaptureFrame::SystemRelativeTime` (the
+/// performance counter).
+pub fn system_relative_time() -> i64 {
+    let (mut counter, mut frequency) = (0, 0);
+    unsafe {
+        QueryPerformanceCounter(&mut counter);
+        QueryPerformanceFrequency(&mut frequency);
+    }
+    if frequency == 0 {
+        return 0;
+    }
+    (counter as i128 * 10_000_000 / frequency as i128) as i64
+}
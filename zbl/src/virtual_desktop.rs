@@ -0,0 +1,32 @@
+//! Virtual desktops of windows.
+//!
+//! Windows on other virtual desktops than the current one are cloaked, so Windows.Graphics.Capture
+//! does not produce frames for them until the user switches to their desktop (or the window is
+//! moved to the current one).
+//!
+//! This goes through `IVirtualDesktopManager`, so COM has to be initialized on the calling thread
+//! (`zbl::init()` takes care of that).
+
+use windows::{
+    core::{Result, GUID},
+    Win32::{
+        Foundation::HWND,
+        System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER},
+        UI::Shell::{IVirtualDesktopManager, VirtualDesktopManager},
+    },
+};
+
+fn manager() -> Result<IVirtualDesktopManager> {
+    unsafe { CoCreateInstance(&VirtualDesktopManager, None, CLSCTX_INPROC_SERVER) }
+}
+
+/// Id of the virtual desktop the top-level window `handle` is on.
+pub fn window_desktop_id(handle: HWND) -> Result<GUID> {
+    unsafe { manager()?.GetWindowDesktopId(handle) }
+}
+
+/// Whether the top-level window `handle` is on the virtual desktop currently shown.
+pub fn is_window_on_current_desktop(handle: HWND) -> Result<bool> {
+    unsafe { manager()?.IsWindowOnCurrentVirtualDesktop(handle) }
+        .map(|on_current| on_current.as_bool())
+}
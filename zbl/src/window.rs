@@ -1,75 +1,44 @@
-use std::{
-    collections::HashMap,
-    sync::{
-        mpsc::{sync_channel, Receiver, SyncSender},
-        RwLock,
-    },
-};
+use std::{collections::HashSet, sync::mpsc::Receiver};
 
-use lazy_static::lazy_static;
 use windows::{
-    core::Result,
+    core::{Result, GUID, HSTRING, PCWSTR},
     Graphics::Capture::GraphicsCaptureItem,
     Win32::{
         Foundation::{BOOL, HWND, LPARAM, POINT, RECT},
         Graphics::{
             Direct3D11::D3D11_BOX,
-            Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED, DWM_CLOAKED_SHELL},
-            Gdi::ClientToScreen,
-        },
-        System::{
-            Console::GetConsoleWindow, WinRT::Graphics::Capture::IGraphicsCaptureItemInterop,
-        },
-        UI::{
-            Accessibility::{SetWinEventHook, UnhookWinEvent, HWINEVENTHOOK},
-            WindowsAndMessaging::{
-                EnumWindows, GetAncestor, GetClassNameW, GetClientRect, GetShellWindow,
-                GetWindowLongW, GetWindowRect, GetWindowTextW, GetWindowThreadProcessId,
-                IsWindowVisible, EVENT_OBJECT_DESTROY, GA_ROOT, GWL_EXSTYLE, GWL_STYLE,
-                WINEVENT_OUTOFCONTEXT, WS_DISABLED, WS_EX_TOOLWINDOW,
+            Dwm::{
+                DwmGetWindowAttribute, DWMWA_CLOAKED, DWMWA_EXTENDED_FRAME_BOUNDS,
+                DWM_CLOAKED_SHELL,
+            },
+            Gdi::{
+                ClientToScreen, CombineRgn, CreateRectRgn, DeleteObject, EqualRgn, NULLREGION,
+                RGN_DIFF, SIMPLEREGION,
             },
         },
+        System::Console::GetConsoleWindow,
+        UI::WindowsAndMessaging::{
+            BringWindowToTop, EnumWindows, FindWindowExW, GetAncestor, GetClassNameW,
+            GetClientRect, GetForegroundWindow, GetLastActivePopup, GetShellWindow, GetWindow,
+            GetWindowLongW, GetWindowRect, GetWindowTextW, GetWindowThreadProcessId, IsIconic,
+            IsWindow, IsWindowVisible, SetForegroundWindow, SetWindowPos, ShowWindow, GA_ROOT,
+            GA_ROOTOWNER, GWL_EXSTYLE, GWL_STYLE, GW_HWNDPREV, SWP_NOACTIVATE, SWP_NOMOVE,
+            SWP_NOZORDER, SW_RESTORE, WS_DISABLED, WS_EX_APPWINDOW, WS_EX_TOOLWINDOW,
+        },
     },
 };
 
-use crate::{util::convert_u16_string, Capturable};
-
-lazy_static! {
-    static ref OBJECT_DESTROYED_USER_DATA: RwLock<HashMap<isize, (isize, SyncSender<()>)>> =
-        Default::default();
-}
-
-extern "system" fn object_destroyed_cb(
-    this: HWINEVENTHOOK,
-    _: u32,
-    handle: HWND,
-    id_object: i32,
-    id_child: i32,
-    _: u32,
-    _: u32,
-) {
-    if id_object == 0 && id_child == 0 && handle != HWND::default() {
-        let has_been_closed = if let Ok(handles) = OBJECT_DESTROYED_USER_DATA.read() {
-            if let Some((window_handle, tx)) = handles.get(&this.0) {
-                if *window_handle == handle.0 {
-                    tx.send(()).ok();
-                    true
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        } else {
-            // TODO is that correct?
-            true
-        };
+use crate::{
+    capturable::{client_box, create_capture_item_for_window, window_close_notification_channel},
+    elevation::{is_current_process_elevated, is_process_elevated},
+    layered::{capture_layered_window, is_layered},
+    util::{convert_u16_string, Rect},
+    virtual_desktop::{is_window_on_current_desktop, window_desktop_id},
+    Capturable, Display, Error, Image,
+};
 
-        if has_been_closed {
-            unsafe { UnhookWinEvent(this) };
-        }
-    }
-}
+const UWP_FRAME_CLASS: &str = "ApplicationFrameWindow";
+const UWP_CORE_WINDOW_CLASS: &str = "Windows.UI.Core.CoreWindow";
 
 extern "system" fn enum_windows_cb(window: HWND, state: LPARAM) -> BOOL {
     let window_info = Window::new(window);
@@ -106,6 +75,87 @@ fn get_window_text(handle: HWND) -> String {
     convert_u16_string(&title)
 }
 
+fn get_window_rect(handle: HWND) -> RECT {
+    let mut rect = RECT::default();
+    unsafe { GetWindowRect(handle, &mut rect as *mut _) };
+    rect
+}
+
+/// Bounds of the window as drawn by DWM, i.e. without the invisible resize borders.
+pub(crate) fn get_frame_bounds(handle: HWND) -> RECT {
+    let mut rect = RECT::default();
+    let result = unsafe {
+        DwmGetWindowAttribute(
+            handle,
+            DWMWA_EXTENDED_FRAME_BOUNDS,
+            &mut rect as *mut _ as *mut _,
+            std::mem::size_of::<RECT>() as u32,
+        )
+    };
+    match result {
+        Ok(()) => rect,
+        Err(_) => get_window_rect(handle),
+    }
+}
+
+pub(crate) fn is_cloaked(handle: HWND) -> bool {
+    let mut cloaked: u32 = 0;
+    let result = unsafe {
+        DwmGetWindowAttribute(
+            handle,
+            DWMWA_CLOAKED,
+            &mut cloaked as *mut _ as *mut _,
+            std::mem::size_of::<u32>() as u32,
+        )
+    };
+    result.is_ok() && cloaked != 0
+}
+
+/// Whether the window exists and is drawn, i.e. visible, not minimized and not cloaked.
+pub(crate) fn is_shown(handle: HWND) -> bool {
+    let drawn = unsafe {
+        IsWindow(handle).as_bool()
+            && IsWindowVisible(handle).as_bool()
+            && !IsIconic(handle).as_bool()
+    };
+    drawn && !is_cloaked(handle)
+}
+
+/// How much of the window is covered by the windows above it in Z-order, ignoring whether it is
+/// drawn at all.
+fn coverage(handle: HWND) -> Visibility {
+    let rect = get_frame_bounds(handle);
+    unsafe {
+        let whole = CreateRectRgn(rect.left, rect.top, rect.right, rect.bottom);
+        let uncovered = CreateRectRgn(rect.left, rect.top, rect.right, rect.bottom);
+        let mut remaining = SIMPLEREGION;
+        let mut above = GetWindow(handle, GW_HWNDPREV);
+        while above.0 != 0 && remaining != NULLREGION {
+            if IsWindowVisible(above).as_bool()
+                && !IsIconic(above).as_bool()
+                && !is_layered(above)
+                && !is_cloaked(above)
+            {
+                let rect = get_frame_bounds(above);
+                let other = CreateRectRgn(rect.left, rect.top, rect.right, rect.bottom);
+                remaining = CombineRgn(uncovered, uncovered, other, RGN_DIFF);
+                DeleteObject(other);
+            }
+            above = GetWindow(above, GW_HWNDPREV);
+        }
+        let visibility = if remaining == NULLREGION {
+            Visibility::Covered
+        } else if EqualRgn(uncovered, whole).as_bool() {
+            Visibility::Visible
+        } else {
+            Visibility::PartiallyCovered
+        };
+        DeleteObject(uncovered);
+        DeleteObject(whole);
+        visibility
+    }
+}
+
 fn get_window_class_name(handle: HWND) -> String {
     let mut class_name = [0u16; 512];
     // TODO: check errors
@@ -113,6 +163,36 @@ fn get_window_class_name(handle: HWND) -> String {
     convert_u16_string(&class_name)
 }
 
+/// How much of a window can be seen on screen, see `Window::visibility`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Visibility {
+    /// Not drawn at all: hidden, minimized, cloaked or on another virtual desktop.
+    Hidden,
+    /// Completely covered by other windows.
+    Covered,
+    /// Partially covered by other windows.
+    PartiallyCovered,
+    /// Not covered by any other window.
+    Visible,
+}
+
+/// Snapshot of the properties of a `Window`, which unlike the window itself can be sent to other
+/// processes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowInfo {
+    /// Raw `HWND`, valid only on the same desktop session.
+    pub handle: isize,
+    pub title: String,
+    pub class_name: String,
+    pub process_id: u32,
+    pub window_rect: Rect,
+    pub client_rect: Rect,
+    pub is_minimized: bool,
+    pub is_cloaked: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct Window {
     pub handle: HWND,
@@ -132,7 +212,28 @@ impl Window {
     }
 
     pub fn find_first(window_name: &str) -> Option<Window> {
-        find_window_by_name(window_name).into_iter().next()
+        let found = find_window_by_name(window_name);
+        // UWP apps may leave empty frames around, which would produce blank captures
+        found
+            .iter()
+            .find(|window| !window.is_empty_uwp_frame())
+            .or_else(|| found.first())
+            .cloned()
+    }
+
+    /// All top-level windows which can be captured (see `is_capturable`).
+    pub fn enumerate() -> Vec<Window> {
+        enumerate_capturable_windows()
+    }
+
+    /// Windows which would be shown in the Alt+Tab switcher (see `is_alt_tab_window`), in Z order.
+    ///
+    /// This is usually what a window picker should offer to the user.
+    pub fn enumerate_alt_tab() -> Vec<Window> {
+        enumerate_capturable_windows()
+            .into_iter()
+            .filter(Window::is_alt_tab_window)
+            .collect()
     }
 
     pub fn matches_title_and_class_name(&self, title: &str, class_name: &str) -> bool {
@@ -161,13 +262,13 @@ impl Window {
         }
 
         let style = unsafe { GetWindowLongW(self.handle, GWL_STYLE) };
-        if style & (WS_DISABLED.0 as i32) == 1 {
+        if style & (WS_DISABLED.0 as i32) != 0 {
             return false;
         }
 
         // No tooltips
         let ex_style = unsafe { GetWindowLongW(self.handle, GWL_EXSTYLE) };
-        if ex_style & (WS_EX_TOOLWINDOW.0 as i32) == 1 {
+        if ex_style & (WS_EX_TOOLWINDOW.0 as i32) != 0 {
             return false;
         }
 
@@ -177,9 +278,7 @@ impl Window {
         }
 
         // Check to see if the self is cloaked if it's a UWP
-        if self.class_name == "Windows.UI.Core.CoreWindow"
-            || self.class_name == "ApplicationFrameWindow"
-        {
+        if self.class_name == UWP_CORE_WINDOW_CLASS || self.class_name == UWP_FRAME_CLASS {
             let mut cloaked: u32 = 0;
             let dwm_attr_cloaked = unsafe {
                 DwmGetWindowAttribute(
@@ -197,67 +296,373 @@ impl Window {
         true
     }
 
+    /// Whether the window would be shown in the Alt+Tab switcher: it is visible and not cloaked,
+    /// it is either an app window or not a tool window, and it is the last active popup of its
+    /// root owner (so that owned dialogs stand in for their owners, like Alt+Tab does).
+    pub fn is_alt_tab_window(&self) -> bool {
+        unsafe {
+            if !IsWindowVisible(self.handle).as_bool() || self.is_cloaked() {
+                return false;
+            }
+
+            let ex_style = GetWindowLongW(self.handle, GWL_EXSTYLE);
+            if ex_style & (WS_EX_APPWINDOW.0 as i32) != 0 {
+                return true;
+            }
+            if ex_style & (WS_EX_TOOLWINDOW.0 as i32) != 0 {
+                return false;
+            }
+
+            // walk from the root owner through its last active popups until a visible one is found,
+            // and accept the window only if the walk ends on it
+            let mut walk = GetAncestor(self.handle, GA_ROOTOWNER);
+            loop {
+                let next = GetLastActivePopup(walk);
+                if next == walk {
+                    break;
+                }
+                walk = next;
+                if IsWindowVisible(walk).as_bool() {
+                    break;
+                }
+            }
+            walk == self.handle
+        }
+    }
+
     pub fn get_process_id(&self) -> u32 {
         let mut process_id = 0u32;
         unsafe { GetWindowThreadProcessId(self.handle, Some(&mut process_id as *mut _)) };
         process_id
     }
-}
 
-impl Capturable for Window {
-    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
-        let interop = windows::core::factory::<GraphicsCaptureItem, IGraphicsCaptureItemInterop>()?;
-        unsafe { interop.CreateForWindow(self.handle) }
+    /// Whether the window belongs to an elevated process. `None` if that cannot be determined
+    /// (e.g. the process has exited).
+    pub fn is_elevated(&self) -> Option<bool> {
+        is_process_elevated(self.get_process_id()).ok()
     }
 
-    fn get_client_box(&self) -> Result<D3D11_BOX> {
-        let mut window_rect = RECT::default();
-        let mut client_rect = RECT::default();
+    /// Whether the window belongs to an elevated process while the current process is not
+    /// elevated, in which case it cannot be captured (see `Error::ElevatedTarget`).
+    pub fn requires_elevation(&self) -> bool {
+        self.is_elevated() == Some(true) && !is_current_process_elevated()
+    }
+
+    /// Whether the window is cloaked by DWM, i.e. not drawn even though it is visible (e.g.
+    /// windows on other virtual desktops, suspended UWP apps).
+    pub fn is_cloaked(&self) -> bool {
+        is_cloaked(self.handle)
+    }
+
+    /// Id of the virtual desktop the window is on.
+    pub fn virtual_desktop_id(&self) -> Result<GUID> {
+        window_desktop_id(self.handle)
+    }
+
+    /// Whether the window is on the virtual desktop currently shown.
+    pub fn is_on_current_virtual_desktop(&self) -> Result<bool> {
+        is_window_on_current_desktop(self.handle)
+    }
+
+    /// Whether Windows.Graphics.Capture is expected to produce frames for the window right now,
+    /// i.e. it is visible, not minimized and on the current virtual desktop.
+    pub fn will_produce_frames(&self) -> bool {
+        self.is_visible()
+            && !self.is_minimized()
+            && self.is_on_current_virtual_desktop().unwrap_or(true)
+    }
+
+    /// Whether the window is completely covered by other windows above it.
+    ///
+    /// Layered windows are never considered to cover anything, since they may be transparent.
+    pub fn is_covered(&self) -> bool {
+        coverage(self.handle) == Visibility::Covered
+    }
+
+    /// Whether the window is the foreground window, i.e. the one receiving keyboard input.
+    pub fn is_foreground(&self) -> bool {
+        let foreground = unsafe { GetForegroundWindow() };
+        foreground.0 != 0 && foreground == self.handle
+    }
+
+    /// How much of the window can currently be seen on screen, i.e. whether the pixels of a
+    /// capture show what the user sees.
+    ///
+    /// Windows.Graphics.Capture keeps capturing covered windows, but applications may stop
+    /// updating (parts of) their content while it cannot be seen.
+    pub fn visibility(&self) -> Visibility {
+        if !self.will_produce_frames() || self.is_cloaked() {
+            return Visibility::Hidden;
+        }
+        coverage(self.handle)
+    }
+
+    /// Restore the window if it is minimized (or maximized), like clicking it in the taskbar.
+    pub fn restore(&self) {
+        unsafe { ShowWindow(self.handle, SW_RESTORE) };
+    }
+
+    /// Restore the window if it is minimized, and move it to the top of the Z-order and into the
+    /// foreground.
+    ///
+    /// Windows only lets processes take the foreground in some situations (e.g. while the user
+    /// interacts with them), otherwise the window just flashes in the taskbar. Returns whether the
+    /// window became the foreground window.
+    pub fn bring_to_front(&self) -> bool {
+        if self.is_minimized() {
+            self.restore();
+        }
+        unsafe {
+            BringWindowToTop(self.handle);
+            SetForegroundWindow(self.handle);
+        }
+        self.is_foreground()
+    }
+
+    /// Resize the window so that its client area is `width` x `height` pixels, keeping its
+    /// position. Minimized and maximized windows should be restored first.
+    pub fn resize(&self, width: u32, height: u32) -> Result<()> {
+        let window = self.get_window_rect();
+        let client = self.get_client_rect();
+        let frame_width = (window.right - window.left) - (client.right - client.left);
+        let frame_height = (window.bottom - window.top) - (client.bottom - client.top);
+        unsafe {
+            SetWindowPos(
+                self.handle,
+                HWND::default(),
+                0,
+                0,
+                width as i32 + frame_width,
+                height as i32 + frame_height,
+                SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE,
+            )
+            .ok()
+        }
+    }
+
+    /// For UWP applications, the `CoreWindow` hosted by this `ApplicationFrameWindow`. The frame
+    /// only draws the title bar, the actual content of the application is in the core window.
+    pub fn get_uwp_core_window(&self) -> Option<HWND> {
+        if self.class_name != UWP_FRAME_CLASS {
+            return None;
+        }
+        let core_window = unsafe {
+            FindWindowExW(
+                self.handle,
+                HWND::default(),
+                &HSTRING::from(UWP_CORE_WINDOW_CLASS),
+                PCWSTR::null(),
+            )
+        };
+        (core_window.0 != 0).then_some(core_window)
+    }
+
+    /// Whether this is a UWP frame which does not host any application (anymore).
+    pub fn is_empty_uwp_frame(&self) -> bool {
+        self.class_name == UWP_FRAME_CLASS && self.get_uwp_core_window().is_none()
+    }
+
+    /// Rectangle of the window contents on the virtual screen: the client area for regular
+    /// windows, and the core window for UWP applications.
+    pub fn get_content_rect(&self) -> RECT {
+        match self.get_uwp_core_window() {
+            Some(core_window) => get_window_rect(core_window),
+            None => self.get_client_rect(),
+        }
+    }
+
+    /// Current title of the window. `title` is the title at the time this `Window` was created.
+    pub fn get_current_title(&self) -> String {
+        get_window_text(self.handle)
+    }
+
+    /// Window rectangle (including the frame) on the virtual screen.
+    pub fn get_window_rect(&self) -> RECT {
+        get_window_rect(self.handle)
+    }
+
+    /// Client area rectangle on the virtual screen.
+    pub fn get_client_rect(&self) -> RECT {
+        let mut rect = RECT::default();
         let mut top_left = POINT::default();
         unsafe {
-            GetWindowRect(self.handle, &mut window_rect as *mut _);
+            GetClientRect(self.handle, &mut rect as *mut _);
             ClientToScreen(self.handle, &mut top_left as *mut _);
-            GetClientRect(self.handle, &mut client_rect as *mut _);
         }
+        RECT {
+            left: top_left.x,
+            top: top_left.y,
+            right: top_left.x + rect.right,
+            bottom: top_left.y + rect.bottom,
+        }
+    }
+
+    /// Display which contains the largest part of the window.
+    pub fn get_display(&self) -> Result<Display> {
+        Display::for_window(self.handle)
+    }
+
+    /// Current properties of the window.
+    pub fn info(&self) -> WindowInfo {
+        WindowInfo {
+            handle: self.handle.0,
+            title: self.get_current_title(),
+            class_name: self.class_name.clone(),
+            process_id: self.get_process_id(),
+            window_rect: self.get_window_rect().into(),
+            client_rect: self.get_client_rect().into(),
+            is_minimized: self.is_minimized(),
+            is_cloaked: self.is_cloaked(),
+        }
+    }
+
+    /// Whether the window is layered (`WS_EX_LAYERED`), i.e. may be transparent.
+    pub fn is_layered(&self) -> bool {
+        is_layered(self.handle)
+    }
+
+    /// Capture the window with its transparency. See `zbl::layered`; use `Capture` for anything
+    /// but occasional snapshots.
+    pub fn capture_layered(&self) -> Result<Image> {
+        capture_layered_window(self.handle)
+    }
+}
+
+impl Capturable for Window {
+    fn create_capture_item(&self) -> Result<GraphicsCaptureItem> {
+        create_capture_item_for_window(self.handle)
+    }
+
+    fn get_client_box(&self) -> Result<D3D11_BOX> {
+        let window_rect = self.get_window_rect();
+        let content_rect = self.get_content_rect();
 
-        let mut client_box = D3D11_BOX::default();
         // TODO
         // 1 seems to work because most window have a 1-pixel gap in the D3D11 texture
         // produced by Windows.Graphics.Capture. Why tho?
-        client_box.left = 1;
-        client_box.right = client_box.left + (client_rect.right - client_rect.left) as u32;
         // TODO there seems to be no reliadble way of getting the taskbar height, so this code is fairly brittle
-        client_box.top = (top_left.y - window_rect.top) as u32;
-        client_box.bottom = client_box.top + (client_rect.bottom - client_rect.top) as u32;
-        client_box.front = 0;
-        client_box.back = 1;
-        Ok(client_box)
+        Ok(client_box(
+            1,
+            (content_rect.top - window_rect.top) as u32,
+            (content_rect.right - content_rect.left) as u32,
+            (content_rect.bottom - content_rect.top) as u32,
+        ))
     }
 
     fn get_close_notification_channel(&self) -> Receiver<()> {
-        let (sender, receiver) = sync_channel(1);
-        let hook_id = unsafe {
-            SetWinEventHook(
-                EVENT_OBJECT_DESTROY,
-                EVENT_OBJECT_DESTROY,
-                None,
-                Some(object_destroyed_cb),
-                // TODO filtering by process id does not always catch the moment when the window is closed
-                // why? aren't windows bound to their process ids?
-                // moreover, for explorer windows even that does not work.
-                // need some more realiable and simpler way to track window closing
-                0,
-                0,
-                WINEVENT_OUTOFCONTEXT,
-            )
-        };
-        if let Ok(mut handles) = OBJECT_DESTROYED_USER_DATA.write() {
-            handles.insert(hook_id.0, (self.handle.0, sender));
-        }
-        receiver
+        window_close_notification_channel(self.handle)
     }
 
     fn get_raw_handle(&self) -> isize {
         self.handle.0
     }
+
+    fn get_screen_origin(&self) -> Option<POINT> {
+        if let Some(core_window) = self.get_uwp_core_window() {
+            let rect = get_window_rect(core_window);
+            return Some(POINT {
+                x: rect.left,
+                y: rect.top,
+            });
+        }
+        let mut top_left = POINT::default();
+        unsafe { ClientToScreen(self.handle, &mut top_left as *mut _) }
+            .as_bool()
+            .then_some(top_left)
+    }
+
+    fn refresh_rate(&self) -> Option<u32> {
+        Display::for_window(self.handle).ok()?.get_refresh_rate()
+    }
+
+    fn is_visible(&self) -> bool {
+        unsafe { IsWindowVisible(self.handle).as_bool() && !IsIconic(self.handle).as_bool() }
+    }
+
+    fn is_minimized(&self) -> bool {
+        unsafe { IsIconic(self.handle).as_bool() }
+    }
+
+    fn is_occluded(&self) -> bool {
+        self.is_cloaked() || self.is_covered()
+    }
+
+    /// Takes over windows with the title and class of the original one (or only the title, since
+    /// some applications use randomized window class names) which have been created after it was
+    /// destroyed.
+    fn find_replacement(
+        &self,
+        existing: &mut Option<HashSet<isize>>,
+    ) -> Option<Box<dyn Capturable>> {
+        if unsafe { IsWindow(self.handle) }.as_bool() {
+            return None;
+        }
+        let windows = enumerate_capturable_windows();
+        let existing = match existing {
+            Some(existing) => existing,
+            None => {
+                // anything shown by now has been around before the window was destroyed
+                *existing = Some(windows.iter().map(|window| window.handle.0).collect());
+                return None;
+            }
+        };
+        let candidates: Vec<Window> = windows
+            .into_iter()
+            .filter(|window| !existing.contains(&window.handle.0))
+            .collect();
+        candidates
+            .iter()
+            .find(|window| window.matches_title_and_class_name(&self.title, &self.class_name))
+            .or_else(|| candidates.iter().find(|window| window.title == self.title))
+            .map(|window| Box::new(window.clone()) as Box<dyn Capturable>)
+    }
+
+    /// Follows the window when it is reparented into another top-level window (e.g. a browser tab
+    /// dropped into another window, or a docked tool window), which Windows.Graphics.Capture
+    /// cannot capture.
+    ///
+    /// Windows which are destroyed and replaced (e.g. by docking UIs) are followed by
+    /// `find_replacement` instead, with `CaptureConfig::auto_reattach`.
+    fn retarget(&self) -> Option<Box<dyn Capturable>> {
+        if !unsafe { IsWindow(self.handle) }.as_bool() {
+            return None;
+        }
+        let root = unsafe { GetAncestor(self.handle, GA_ROOT) };
+        if root != HWND::default() && root != self.handle {
+            return Some(Box::new(Window::new(root)));
+        }
+        None
+    }
+
+    fn is_alive(&self) -> bool {
+        if !unsafe { IsWindow(self.handle) }.as_bool() {
+            return false;
+        }
+        let mut rect = RECT::default();
+        unsafe { GetWindowRect(self.handle, &mut rect) }.as_bool()
+            && rect.right >= rect.left
+            && rect.bottom >= rect.top
+    }
+
+    fn validate(&self) -> crate::Result<()> {
+        if !unsafe { IsWindow(self.handle) }.as_bool() {
+            return Err(Error::InvalidWindow);
+        }
+        if self.is_cloaked() {
+            return Err(Error::WindowCloaked);
+        }
+        if self.requires_elevation() {
+            return Err(Error::ElevatedTarget);
+        }
+        // minimized windows have an empty client area, but are captured once they are restored
+        let rect = self.get_client_rect();
+        if (rect.right <= rect.left || rect.bottom <= rect.top) && !self.is_minimized() {
+            return Err(Error::ZeroSized);
+        }
+        Ok(())
+    }
+
+    fn as_window(&self) -> Option<&Window> {
+        Some(self)
+    }
 }
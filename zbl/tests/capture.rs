@@ -0,0 +1,76 @@
+//! Captures of the primary display. These need an interactive desktop session, so they are
+//! ignored by default; run them with `cargo test -- --ignored`.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::sleep,
+    time::Duration,
+};
+
+use zbl::{Capture, Display};
+
+fn primary_display_capture() -> Capture {
+    zbl::init();
+    let display = Display::enumerate()
+        .unwrap()
+        .into_iter()
+        .find(Display::is_primary)
+        .unwrap();
+    Capture::builder(Box::new(display))
+        .grab_timeout(Duration::from_millis(500))
+        .build()
+        .unwrap()
+}
+
+/// Count the frames the frame pool hands to `capture`.
+fn count_callbacks(capture: &mut Capture) -> Arc<AtomicUsize> {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counter = calls.clone();
+    capture
+        .set_frame_arrived_callback(Some(Box::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            true
+        })))
+        .unwrap();
+    calls
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn no_callbacks_after_stop() {
+    let mut capture = primary_display_capture();
+    let calls = count_callbacks(&mut capture);
+    capture.start().unwrap();
+    for _ in 0..3 {
+        capture.grab().unwrap();
+    }
+    capture.stop().unwrap();
+    let after_stop = calls.load(Ordering::SeqCst);
+    sleep(Duration::from_millis(500));
+    assert_eq!(calls.load(Ordering::SeqCst), after_stop);
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn no_callbacks_after_request_frame() {
+    let mut capture = primary_display_capture();
+    let calls = count_callbacks(&mut capture);
+    capture.request_frame().unwrap();
+    let after_request = calls.load(Ordering::SeqCst);
+    sleep(Duration::from_millis(500));
+    assert_eq!(calls.load(Ordering::SeqCst), after_request);
+    // the session has been closed by `request_frame()` already
+    capture.stop().unwrap();
+}
+
+#[test]
+#[ignore = "needs an interactive desktop"]
+fn stop_twice() {
+    let mut capture = primary_display_capture();
+    capture.start().unwrap();
+    capture.stop().unwrap();
+    capture.stop().unwrap();
+}
@@ -1,6 +1,8 @@
 use ::zbl::windows::Win32::Foundation::HWND;
 use pyo3::{exceptions::PyRuntimeError, prelude::*};
-use std::ffi::c_void;
+use std::{ffi::c_void, thread::sleep, time::Duration};
+
+const MINIMIZED_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -8,6 +10,8 @@ pub enum Error {
     WindowNotFoundError(String),
     #[error("windows api error: {0}")]
     WindowsError(#[from] ::zbl::windows::core::Error),
+    #[error("{0}")]
+    CaptureError(#[from] ::zbl::Error),
     #[error("frame channel error")]
     FrameChannelError(#[from] std::sync::mpsc::RecvError),
     #[error("neither name nor handle is set")]
@@ -85,20 +89,43 @@ impl Capture {
         )
     }
 
-    fn _start(&self) -> Result<()> {
+    fn _start(&mut self) -> Result<()> {
         Ok(self.inner.start()?)
     }
 
     fn _grab(&mut self) -> Result<Option<Frame>> {
-        if let Some(::zbl::Frame { texture, ptr }) = self.inner.grab()? {
-            Ok(Some(Frame {
-                width: texture.desc.Width,
-                height: texture.desc.Height,
-                row_pitch: ptr.RowPitch,
-                ptr: ptr.pData,
-            }))
-        } else {
-            Ok(None)
+        loop {
+            let status = self.inner.grab()?;
+            if let Some(frame) = Self::convert_grab_status(status) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn _request_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            let status = self.inner.request_frame()?;
+            if let Some(frame) = Self::convert_grab_status(status) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Returns `None` if there is no frame yet, but the capture is not over.
+    fn convert_grab_status(status: ::zbl::GrabStatus) -> Option<Option<Frame>> {
+        match status {
+            ::zbl::GrabStatus::Frame(frame) => Some(Some(Frame {
+                width: frame.width(),
+                height: frame.height(),
+                row_pitch: frame.row_pitch(),
+                ptr: frame.ptr.pData,
+            })),
+            ::zbl::GrabStatus::Closed => Some(None),
+            ::zbl::GrabStatus::Minimized => {
+                sleep(MINIMIZED_POLL_INTERVAL);
+                None
+            }
+            ::zbl::GrabStatus::Timeout | ::zbl::GrabStatus::Recreated => None,
         }
     }
 
@@ -136,7 +163,7 @@ impl Capture {
         Ok(self.inner.capturable().get_raw_handle())
     }
 
-    pub fn start(&self) -> PyResult<()> {
+    pub fn start(&mut self) -> PyResult<()> {
         Ok(self._start()?)
     }
 
@@ -144,6 +171,10 @@ impl Capture {
         Ok(self._grab()?)
     }
 
+    pub fn request_frame(&mut self) -> PyResult<Option<Frame>> {
+        Ok(self._request_frame()?)
+    }
+
     pub fn stop(&mut self) -> PyResult<()> {
         Ok(self._stop()?)
     }
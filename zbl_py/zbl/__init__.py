@@ -49,6 +49,15 @@ class Capture:
         for frame in self.raw_frames():
             yield frame_to_numpy_array(frame)
 
+    def request_frame(self) -> Optional[numpy.ndarray]:
+        """
+        Capture a single frame without keeping the capture running. Must not be used inside `with` block.
+        """
+        frame = self._inner.request_frame()
+        if frame is None:
+            return None
+        return frame_to_numpy_array(frame)
+
     def __enter__(self) -> 'Capture':
         self._inner.start()
         return self